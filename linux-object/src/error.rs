@@ -95,16 +95,30 @@ pub enum LxError {
     ENOTEMPTY = 39,
     /// Too many symbolic links encountered
     ELOOP = 40,
+    /// No message of desired type
+    ENOMSG = 42,
     /// Identifier removed
     EIDRM = 43,
     /// Socket operation on non-socket
     ENOTSOCK = 88,
+    /// Destination address required
+    EDESTADDRREQ = 89,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// Protocol wrong type for socket
+    EPROTOTYPE = 91,
     /// Protocol not available
     ENOPROTOOPT = 92,
+    /// Operation not supported on transport endpoint
+    EOPNOTSUPP = 95,
     /// Protocol family not supported
     EPFNOSUPPORT = 96,
     /// Address family not supported by protocol
     EAFNOSUPPORT = 97,
+    /// Address already in use
+    EADDRINUSE = 98,
+    /// Connection reset by peer
+    ECONNRESET = 104,
     /// No buffer space available
     ENOBUFS = 105,
     /// Transport endpoint is already connected
@@ -115,6 +129,9 @@ pub enum LxError {
     ETIMEDOUT = 110,
     /// Connection refused
     ECONNREFUSED = 111,
+    /// Interrupted system call to be restarted if the handler has `SA_RESTART`,
+    /// which becomes `EINTR` otherwise and is never seen by user programs
+    ERESTARTSYS = 512,
 }
 
 #[allow(non_snake_case)]
@@ -162,15 +179,23 @@ impl fmt::Display for LxError {
             ENOSYS => "Function not implemented",
             ENOTEMPTY => "Directory not empty",
             ELOOP => "Too many symbolic links encountered",
+            ENOMSG => "No message of desired type",
             EIDRM => "Identifier removed",
             ENOTSOCK => "Socket operation on non-socket",
+            EDESTADDRREQ => "Destination address required",
+            EMSGSIZE => "Message too long",
+            EPROTOTYPE => "Protocol wrong type for socket",
             ENOPROTOOPT => "Protocol not available",
+            EOPNOTSUPP => "Operation not supported on transport endpoint",
             EPFNOSUPPORT => "Protocol family not supported",
             EAFNOSUPPORT => "Address family not supported by protocol",
+            EADDRINUSE => "Address already in use",
+            ECONNRESET => "Connection reset by peer",
             ENOBUFS => "No buffer space available",
             EISCONN => "Transport endpoint is already connected",
             ENOTCONN => "Transport endpoint is not connected",
             ECONNREFUSED => "Connection refused",
+            ERESTARTSYS => "Interrupted system call should be restarted",
             _ => "Unknown error",
         };
         write!(f, "{}", explain)
@@ -189,6 +214,8 @@ impl From<ZxError> for LxError {
             ZxError::TIMED_OUT => LxError::ETIMEDOUT,
             ZxError::STOP => LxError::ESRCH,
             ZxError::BAD_STATE => LxError::EAGAIN,
+            ZxError::NO_MEMORY => LxError::ENOMEM,
+            ZxError::ACCESS_DENIED => LxError::EACCES,
             _ => unimplemented!("unknown error type: {:?}", e),
         }
     }
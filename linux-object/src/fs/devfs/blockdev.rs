@@ -0,0 +1,111 @@
+use alloc::sync::Arc;
+use core::any::Any;
+use rcore_fs::vfs::{make_rdev, FileType, INode, Metadata, PollStatus, Result, Timespec};
+use rcore_fs_devfs::DevFS;
+use zcore_drivers::scheme::BlockScheme;
+
+use super::convert_error;
+
+/// The size of a block of the device
+const BLOCK_SIZE: usize = 512;
+
+/// Block device, which file systems can be mounted on.
+pub struct BlockDev {
+    index: usize,
+    block: Arc<dyn BlockScheme>,
+    inode_id: usize,
+}
+
+impl BlockDev {
+    pub fn new(index: usize, block: Arc<dyn BlockScheme>) -> Self {
+        Self {
+            index,
+            block,
+            inode_id: DevFS::new_inode_id(),
+        }
+    }
+
+    /// The underlying device
+    pub fn block(&self) -> Arc<dyn BlockScheme> {
+        self.block.clone()
+    }
+}
+
+impl INode for BlockDev {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start).min(buf.len() - done);
+            self.block
+                .read_block(pos / BLOCK_SIZE, &mut block)
+                .map_err(convert_error)?;
+            buf[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+        Ok(done)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - start).min(buf.len() - done);
+            // read the rest of a partially written block
+            if len < BLOCK_SIZE {
+                self.block
+                    .read_block(pos / BLOCK_SIZE, &mut block)
+                    .map_err(convert_error)?;
+            }
+            block[start..start + len].copy_from_slice(&buf[done..done + len]);
+            self.block
+                .write_block(pos / BLOCK_SIZE, &block)
+                .map_err(convert_error)?;
+            done += len;
+        }
+        Ok(done)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        self.block.flush().map_err(convert_error)
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        self.sync_all()
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 1,
+            inode: self.inode_id,
+            size: 0,
+            blk_size: BLOCK_SIZE,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::BlockDevice,
+            mode: 0o660, // owner and group read & write
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+            rdev: make_rdev(8, self.index * 16),
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
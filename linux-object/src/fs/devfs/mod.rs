@@ -1,9 +1,28 @@
+mod blockdev;
 mod fbdev;
 mod input;
 mod random;
 mod uartdev;
 
+pub use blockdev::BlockDev;
 pub use fbdev::FbDev;
 pub use input::{EventDev, MiceDev};
 pub use random::RandomINode;
-pub use uartdev::UartDev;
+pub use uartdev::uart_tty;
+
+use rcore_fs::vfs::FsError;
+use zcore_drivers::DeviceError;
+
+/// Convert the error of a device driver to a file system error
+pub(super) fn convert_error(e: DeviceError) -> FsError {
+    match e {
+        DeviceError::NotSupported => FsError::NotSupported,
+        DeviceError::NotReady => FsError::Busy,
+        DeviceError::InvalidParam => FsError::InvalidParam,
+        DeviceError::BufferTooSmall
+        | DeviceError::DmaError
+        | DeviceError::IoError
+        | DeviceError::AlreadyExists
+        | DeviceError::NoResources => FsError::DeviceError,
+    }
+}
@@ -0,0 +1,786 @@
+//! Implement epoll instance
+#![deny(missing_docs)]
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use bitflags::bitflags;
+use kernel_hal::timer;
+use lazy_static::lazy_static;
+use lock::Mutex;
+use numeric_enum_macro::numeric_enum;
+use zircon_object::object::*;
+
+use super::{FileDesc, FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::error::{LxError, LxResult};
+use crate::sync::{Event, EventBus};
+
+/// The deepest nesting of epoll instances watching each other, as Linux allows
+const EPOLL_MAX_NESTS: usize = 4;
+
+lazy_static! {
+    /// Serializes adding epoll instances to the interest lists,
+    /// so that no loop is formed by concurrent additions
+    static ref EPOLL_NEST_LOCK: Mutex<()> = Mutex::new(());
+}
+
+bitflags! {
+    /// Event types and input flags of `struct epoll_event`
+    pub struct EpollEvents: u32 {
+        /// The associated file is available for read operations
+        const IN = 0x001;
+        /// There is an exceptional condition on the file descriptor
+        const PRI = 0x002;
+        /// The associated file is available for write operations
+        const OUT = 0x004;
+        /// Error condition happened on the associated file descriptor
+        const ERR = 0x008;
+        /// Hang up happened on the associated file descriptor
+        const HUP = 0x010;
+        /// Normal data may be read
+        const RDNORM = 0x040;
+        /// Priority data may be read
+        const RDBAND = 0x080;
+        /// Normal data may be written
+        const WRNORM = 0x100;
+        /// Priority data may be written
+        const WRBAND = 0x200;
+        /// A message is available
+        const MSG = 0x400;
+        /// Stream socket peer closed connection
+        const RDHUP = 0x2000;
+        /// Sets an exclusive wakeup mode for the epoll file descriptor
+        const EXCLUSIVE = 1 << 28;
+        /// Prevent system suspend while the event is pending
+        const WAKEUP = 1 << 29;
+        /// Disable the file descriptor after one event is reported
+        const ONESHOT = 1 << 30;
+        /// Requests edge-triggered notification
+        const ET = 1 << 31;
+    }
+}
+
+/// `struct epoll_event`, which is packed on x86_64
+#[cfg_attr(target_arch = "x86_64", repr(C, packed))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(C))]
+#[derive(Debug, Clone, Copy)]
+pub struct EpollEvent {
+    /// Epoll events
+    pub events: EpollEvents,
+    /// User data variable
+    pub data: u64,
+}
+
+numeric_enum! {
+    #[repr(usize)]
+    #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+    /// Operations of `epoll_ctl`
+    pub enum EpollCtlOp {
+        /// Add an entry to the interest list
+        ADD = 1,
+        /// Remove an entry from the interest list
+        DEL = 2,
+        /// Change the settings of an entry in the interest list
+        MOD = 3,
+    }
+}
+
+/// The waker passed to the file of an entry, which marks the entry to be polled
+/// again and wakes up a waiter of the epoll instance.
+struct EntryWaker {
+    /// set when the file wakes up the entry
+    woken: AtomicBool,
+    /// the epoll instance
+    epoll: Weak<Mutex<EpollInner>>,
+}
+
+impl Wake for EntryWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(inner) = self.epoll.upgrade() {
+            inner.lock().notify();
+        }
+    }
+}
+
+/// An entry in the interest list
+struct EpollEntry {
+    /// the registered file, an entry is dropped after the file is closed
+    file: Weak<dyn FileLike>,
+    /// the registered events
+    events: EpollEvents,
+    /// the user data
+    data: u64,
+    /// events observed last time, used by edge-triggered mode
+    last: EpollEvents,
+    /// an oneshot entry is disabled after reporting, until `EPOLL_CTL_MOD`
+    disabled: bool,
+    /// the file has registered the waker, and is not polled until it is woken up
+    armed: bool,
+    /// the waker registered by the file, replaced by `EPOLL_CTL_MOD`
+    waker: Arc<EntryWaker>,
+}
+
+impl EpollEntry {
+    fn new(
+        epoll: &Arc<Mutex<EpollInner>>,
+        file: Weak<dyn FileLike>,
+        events: EpollEvents,
+        data: u64,
+    ) -> Self {
+        EpollEntry {
+            file,
+            events,
+            data,
+            last: EpollEvents::empty(),
+            disabled: false,
+            armed: false,
+            waker: Arc::new(EntryWaker {
+                woken: AtomicBool::new(false),
+                epoll: Arc::downgrade(epoll),
+            }),
+        }
+    }
+
+    /// Convert the status of the file to the events the entry cares about.
+    ///
+    /// `EPOLLERR` and `EPOLLHUP` are always reported, as Linux does.
+    fn filter(&self, status: &PollStatus) -> EpollEvents {
+        let wanted = self.events | EpollEvents::ERR | EpollEvents::HUP;
+        let mut events = EpollEvents::empty();
+        if status.read {
+            events |= EpollEvents::IN | EpollEvents::RDNORM;
+        }
+        if status.write {
+            events |= EpollEvents::OUT | EpollEvents::WRNORM;
+        }
+        if status.error {
+            events |= EpollEvents::ERR;
+        }
+        events & wanted
+    }
+
+    /// Convert the interested events to `PollEvents` for `async_poll`.
+    ///
+    /// An edge-triggered entry does not ask for the events in `last`, which are
+    /// reported already, so that the file registers the waker while they are ready.
+    fn poll_events(&self, last: EpollEvents) -> PollEvents {
+        let mut wanted = self.events | EpollEvents::ERR | EpollEvents::HUP;
+        if self.events.contains(EpollEvents::ET) {
+            wanted -= last;
+        }
+        let mut events = PollEvents::empty();
+        if wanted.intersects(EpollEvents::IN | EpollEvents::RDNORM) {
+            events |= PollEvents::IN;
+        }
+        if wanted.intersects(EpollEvents::OUT | EpollEvents::WRNORM) {
+            events |= PollEvents::OUT;
+        }
+        if wanted.contains(EpollEvents::ERR) {
+            events |= PollEvents::ERR;
+        }
+        if wanted.contains(EpollEvents::HUP) {
+            events |= PollEvents::HUP;
+        }
+        events
+    }
+}
+
+/// Epoll instance data, shared by all file descriptors refer to it
+struct EpollInner {
+    /// the interest list
+    interests: BTreeMap<FileDesc, EpollEntry>,
+    /// the waiters of `epoll_wait` by their IDs, which are woken up one at a time
+    waiters: VecDeque<(usize, Waker)>,
+    /// event bus to wake up the pollers of the instance, such as an epoll watching it
+    eventbus: EventBus,
+}
+
+impl EpollInner {
+    /// Wake up the first waiter to scan the interest list.
+    fn wake_one(&mut self) {
+        if let Some((_, waker)) = self.waiters.pop_front() {
+            waker.wake();
+        }
+    }
+
+    /// Wake up the first waiter and all pollers subscribed on the event bus
+    fn notify(&mut self) {
+        self.wake_one();
+        self.eventbus.set(Event::READABLE);
+        self.eventbus.clear(Event::READABLE);
+    }
+}
+
+/// Epoll instance
+pub struct EpollInstance {
+    /// Kernel object base
+    base: KObjectBase,
+    /// open flags of the file descriptor
+    flags: Mutex<OpenFlags>,
+    /// the shared data
+    inner: Arc<Mutex<EpollInner>>,
+}
+
+impl_kobject!(EpollInstance);
+
+impl EpollInstance {
+    /// Create a new epoll instance
+    pub fn new(flags: OpenFlags) -> Arc<Self> {
+        Arc::new(EpollInstance {
+            base: KObjectBase::new(),
+            flags: Mutex::new(flags),
+            inner: Arc::new(Mutex::new(EpollInner {
+                interests: BTreeMap::new(),
+                waiters: VecDeque::new(),
+                eventbus: EventBus::default(),
+            })),
+        })
+    }
+
+    /// Add, modify or remove an entry in the interest list
+    pub fn control(
+        &self,
+        op: EpollCtlOp,
+        fd: FileDesc,
+        file: &Arc<dyn FileLike>,
+        event: EpollEvent,
+    ) -> LxResult {
+        let _nest = match file.downcast_ref::<EpollInstance>() {
+            // an epoll instance can not watch itself
+            Some(epoll) if Arc::ptr_eq(&epoll.inner, &self.inner) => {
+                return Err(LxError::EINVAL);
+            }
+            Some(epoll) if op == EpollCtlOp::ADD => {
+                let nest = EPOLL_NEST_LOCK.lock();
+                self.check_nest(epoll)?;
+                Some(nest)
+            }
+            _ => None,
+        };
+        // fields of the packed `EpollEvent` can not be borrowed
+        let (events, data) = (event.events, event.data);
+        let mut inner = self.inner.lock();
+        inner.interests.retain(|_, e| e.file.strong_count() != 0);
+        match op {
+            EpollCtlOp::ADD => {
+                if inner.interests.contains_key(&fd) {
+                    return Err(LxError::EEXIST);
+                }
+                let entry = EpollEntry::new(&self.inner, Arc::downgrade(file), events, data);
+                inner.interests.insert(fd, entry);
+            }
+            EpollCtlOp::MOD => {
+                let entry = inner.interests.get_mut(&fd).ok_or(LxError::ENOENT)?;
+                // the registrations of the old settings wake up a stale waker
+                *entry = EpollEntry::new(&self.inner, entry.file.clone(), events, data);
+            }
+            EpollCtlOp::DEL => {
+                inner.interests.remove(&fd).ok_or(LxError::ENOENT)?;
+            }
+        }
+        // let a waiter scan the interest list again
+        inner.notify();
+        Ok(())
+    }
+
+    /// Returns an error if watching `epoll` would make a loop, or nest the
+    /// instances too deep.
+    fn check_nest(&self, epoll: &EpollInstance) -> LxResult {
+        let mut level = vec![epoll.inner.clone()];
+        for _ in 0..EPOLL_MAX_NESTS {
+            let mut next = Vec::new();
+            for inner in level {
+                if Arc::ptr_eq(&inner, &self.inner) {
+                    return Err(LxError::ELOOP);
+                }
+                let files: Vec<_> = inner
+                    .lock()
+                    .interests
+                    .values()
+                    .filter_map(|e| e.file.upgrade())
+                    .collect();
+                next.extend(
+                    files
+                        .iter()
+                        .filter_map(|f| f.downcast_ref::<EpollInstance>())
+                        .map(|epoll| epoll.inner.clone()),
+                );
+            }
+            if next.is_empty() {
+                return Ok(());
+            }
+            level = next;
+        }
+        Err(LxError::ELOOP)
+    }
+
+    /// Scan the interest list, returns at most `max_events` ready events.
+    ///
+    /// The files are polled with the wakers of the entries, without holding the
+    /// interest list, since a file may be another epoll instance. An entry whose
+    /// file has registered the waker is not polled again until it is woken up.
+    /// Edge-triggered entries only report events which were not observed since
+    /// the last wakeup, and oneshot entries are disabled after reporting.
+    /// Nothing is consumed when `consume` is false.
+    fn scan(&self, max_events: usize, consume: bool) -> Vec<EpollEvent> {
+        let candidates: Vec<_> = {
+            let mut inner = self.inner.lock();
+            inner.interests.retain(|_, e| e.file.strong_count() != 0);
+            inner
+                .interests
+                .iter()
+                .filter(|(_, e)| !e.disabled)
+                .filter(|(_, e)| !e.armed || e.waker.woken.load(Ordering::Acquire))
+                .map(|(&fd, e)| (fd, e.waker.clone()))
+                .collect()
+        };
+        let mut ready = Vec::new();
+        for (fd, entry_waker) in candidates {
+            if ready.len() >= max_events {
+                break;
+            }
+            // clear the flag before polling, so that a wakeup meanwhile is kept
+            let woken = if consume {
+                entry_waker.woken.swap(false, Ordering::AcqRel)
+            } else {
+                entry_waker.woken.load(Ordering::Acquire)
+            };
+            let (file, poll_events) = {
+                let inner = self.inner.lock();
+                let entry = match inner.interests.get(&fd) {
+                    Some(entry) if Arc::ptr_eq(&entry.waker, &entry_waker) => entry,
+                    _ => continue,
+                };
+                // a wakeup starts a new edge
+                let last = if woken {
+                    EpollEvents::empty()
+                } else {
+                    entry.last
+                };
+                match entry.file.upgrade() {
+                    Some(file) => (file, entry.poll_events(last)),
+                    None => continue,
+                }
+            };
+            let waker = Waker::from(entry_waker.clone());
+            let status = {
+                let mut fut = file.async_poll(poll_events);
+                fut.as_mut().poll(&mut Context::from_waker(&waker))
+            };
+            drop(file);
+
+            let mut inner = self.inner.lock();
+            let entry = match inner.interests.get_mut(&fd) {
+                Some(entry) if Arc::ptr_eq(&entry.waker, &entry_waker) => entry,
+                _ => continue,
+            };
+            let last = if woken {
+                EpollEvents::empty()
+            } else {
+                entry.last
+            };
+            let status = match status {
+                Poll::Ready(status) => status.unwrap_or(PollStatus {
+                    read: false,
+                    write: false,
+                    error: true,
+                }),
+                Poll::Pending => {
+                    // the file is not ready, and will wake us up on changes
+                    if consume {
+                        entry.last = last;
+                        entry.armed = true;
+                    }
+                    continue;
+                }
+            };
+            let events = entry.filter(&status);
+            let edge = entry.events.contains(EpollEvents::ET);
+            let report = if edge { events - last } else { events };
+            if consume {
+                entry.last = events;
+                entry.armed = false;
+            }
+            if report.is_empty() {
+                continue;
+            }
+            ready.push(EpollEvent {
+                events: report,
+                data: entry.data,
+            });
+            if consume && entry.events.contains(EpollEvents::ONESHOT) {
+                entry.disabled = true;
+            }
+        }
+        ready
+    }
+
+    /// Wait for at most `max_events` events until `deadline`.
+    ///
+    /// Returns immediately if `deadline` is zero, blocks indefinitely if it is `None`.
+    /// The waiters are woken up one at a time by the entries. A waiter passes the
+    /// wakeup on to the next one when it leaves while events are still ready.
+    pub fn wait(
+        &self,
+        max_events: usize,
+        deadline: Option<Duration>,
+    ) -> impl Future<Output = Vec<EpollEvent>> + '_ {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct EpollFuture<'a> {
+            epoll: &'a EpollInstance,
+            max_events: usize,
+            deadline: Option<Duration>,
+            /// identifies the waiter in the queue of the instance
+            id: usize,
+        }
+
+        impl<'a> Future for EpollFuture<'a> {
+            type Output = Vec<EpollEvent>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // queue up before scanning, so that no wakeup is missed
+                {
+                    let mut inner = self.epoll.inner.lock();
+                    let id = self.id;
+                    inner.waiters.retain(|(waiter, _)| *waiter != id);
+                    inner.waiters.push_back((id, cx.waker().clone()));
+                }
+                let ready = self.epoll.scan(self.max_events, true);
+                let now = timer::timer_now();
+                let timeout = matches!(self.deadline, Some(deadline) if now >= deadline);
+                if !ready.is_empty() || timeout {
+                    return Poll::Ready(ready);
+                }
+                if let Some(deadline) = self.deadline {
+                    let waker = cx.waker().clone();
+                    timer::timer_set(deadline, Box::new(move |_| waker.wake_by_ref()));
+                }
+                Poll::Pending
+            }
+        }
+
+        impl Drop for EpollFuture<'_> {
+            fn drop(&mut self) {
+                let others = {
+                    let mut inner = self.epoll.inner.lock();
+                    let id = self.id;
+                    inner.waiters.retain(|(waiter, _)| *waiter != id);
+                    !inner.waiters.is_empty()
+                };
+                // the wakeup taken by this waiter may be for events it left
+                if others && self.epoll.has_ready() {
+                    self.epoll.inner.lock().wake_one();
+                }
+            }
+        }
+
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        EpollFuture {
+            epoll: self,
+            max_events,
+            deadline,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns whether any entry in the interest list is ready, without consuming it.
+    fn has_ready(&self) -> bool {
+        !self.scan(1, false).is_empty()
+    }
+}
+
+#[async_trait]
+impl FileLike for EpollInstance {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(EpollInstance {
+            base: KObjectBase::new(),
+            flags: Mutex::new(self.flags()),
+            inner: self.inner.clone(),
+        })
+    }
+
+    async fn read(&self, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(PollStatus {
+            read: self.has_ready(),
+            write: false,
+            error: false,
+        })
+    }
+
+    async fn async_poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct ReadyFuture<'a> {
+            epoll: &'a EpollInstance,
+        }
+
+        impl<'a> Future for ReadyFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let status = |read| {
+                    Poll::Ready(Ok(PollStatus {
+                        read,
+                        write: false,
+                        error: false,
+                    }))
+                };
+                if self.epoll.has_ready() {
+                    return status(true);
+                }
+                // the wakers of the entries notify the event bus,
+                // and the list is checked again in case of a wakeup meanwhile
+                let waker = cx.waker().clone();
+                self.epoll
+                    .inner
+                    .lock()
+                    .eventbus
+                    .subscribe(Box::new(move |_| {
+                        waker.wake_by_ref();
+                        true
+                    }));
+                if self.epoll.has_ready() {
+                    return status(true);
+                }
+                Poll::Pending
+            }
+        }
+
+        ReadyFuture { epoll: self }.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{EventFd, EventFdFlags};
+
+    fn fd(fd: usize) -> FileDesc {
+        fd.into()
+    }
+
+    fn event(events: EpollEvents, data: u64) -> EpollEvent {
+        EpollEvent { events, data }
+    }
+
+    /// Returns the ready events without waiting
+    async fn ready(epoll: &EpollInstance) -> Vec<(EpollEvents, u64)> {
+        epoll
+            .wait(16, Some(Duration::ZERO))
+            .await
+            .iter()
+            .map(|e| (e.events, e.data))
+            .collect()
+    }
+
+    fn eventfd() -> (Arc<EventFd>, Arc<dyn FileLike>) {
+        let efd = EventFd::new(0, EventFdFlags::NON_BLOCK);
+        (efd.clone(), efd)
+    }
+
+    async fn drain(efd: &EventFd) {
+        efd.read(&mut [0; 8]).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn ctl() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN, 0);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        assert!(matches!(
+            epoll.control(EpollCtlOp::ADD, fd(3), &file, ev),
+            Err(LxError::EEXIST)
+        ));
+        assert!(matches!(
+            epoll.control(EpollCtlOp::MOD, fd(4), &file, ev),
+            Err(LxError::ENOENT)
+        ));
+        assert!(matches!(
+            epoll.control(EpollCtlOp::DEL, fd(4), &file, ev),
+            Err(LxError::ENOENT)
+        ));
+        epoll.control(EpollCtlOp::DEL, fd(3), &file, ev).unwrap();
+
+        // an entry is dropped with its file
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        drop((efd, file));
+        assert!(ready(&epoll).await.is_empty());
+        assert!(epoll.inner.lock().interests.is_empty());
+    }
+
+    #[async_std::test]
+    async fn level_triggered() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN, 7);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        assert!(ready(&epoll).await.is_empty());
+
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+        drain(&efd).await;
+        assert!(ready(&epoll).await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn edge_triggered() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN | EpollEvents::ET, 7);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+        // the entry waits for a wakeup of the file, which is not polled meanwhile
+        assert!(ready(&epoll).await.is_empty());
+        assert!(epoll.inner.lock().interests[&fd(3)].armed);
+        drain(&efd).await;
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+        assert!(ready(&epoll).await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn oneshot() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN | EpollEvents::ONESHOT, 7);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+        assert!(ready(&epoll).await.is_empty());
+        epoll.control(EpollCtlOp::MOD, fd(3), &file, ev).unwrap();
+        assert_eq!(ready(&epoll).await, [(EpollEvents::IN, 7)]);
+    }
+
+    #[async_std::test]
+    async fn wait_wakeup() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN, 7);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        let writer = async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(10)).await;
+            efd.write(&1u64.to_ne_bytes()).unwrap();
+        });
+        let events = epoll.wait(16, None).await;
+        assert_eq!(events.len(), 1);
+        writer.await;
+    }
+
+    #[async_std::test]
+    async fn exclusive_wakeup() {
+        let epoll = EpollInstance::new(OpenFlags::empty());
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN | EpollEvents::ET, 7);
+        epoll.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        let waiters: Vec<_> = (0..2)
+            .map(|_| {
+                let epoll = epoll.clone();
+                async_std::task::spawn(async move { epoll.wait(16, None).await.len() })
+            })
+            .collect();
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(epoll.inner.lock().waiters.len(), 2);
+
+        // an edge wakes up one waiter only
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        assert_eq!(epoll.inner.lock().waiters.len(), 1);
+        drain(&efd).await;
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        for waiter in waiters {
+            assert_eq!(waiter.await, 1);
+        }
+    }
+
+    #[async_std::test]
+    async fn nested() {
+        let outer = EpollInstance::new(OpenFlags::empty());
+        let inner = EpollInstance::new(OpenFlags::empty());
+        let outer_file: Arc<dyn FileLike> = outer.clone();
+        let inner_file: Arc<dyn FileLike> = inner.clone();
+        let (efd, file) = eventfd();
+        let ev = event(EpollEvents::IN, 7);
+        inner.control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        outer
+            .control(EpollCtlOp::ADD, fd(4), &inner_file, ev)
+            .unwrap();
+        assert!(ready(&outer).await.is_empty());
+
+        // the wakeup of the file goes through the inner instance
+        efd.write(&1u64.to_ne_bytes()).unwrap();
+        assert_eq!(ready(&outer).await, [(EpollEvents::IN, 7)]);
+        assert!(outer_file.poll(PollEvents::IN).unwrap().read);
+
+        // loops are rejected
+        assert!(matches!(
+            outer.control(EpollCtlOp::ADD, fd(5), &outer_file, ev),
+            Err(LxError::EINVAL)
+        ));
+        assert!(matches!(
+            inner.control(EpollCtlOp::ADD, fd(5), &outer_file, ev),
+            Err(LxError::ELOOP)
+        ));
+    }
+
+    #[async_std::test]
+    async fn nest_depth() {
+        let epolls: Vec<_> = (0..=EPOLL_MAX_NESTS + 1)
+            .map(|_| EpollInstance::new(OpenFlags::empty()))
+            .collect();
+        let ev = event(EpollEvents::IN, 0);
+        for pair in epolls[..=EPOLL_MAX_NESTS].windows(2) {
+            let file: Arc<dyn FileLike> = pair[1].clone();
+            pair[0].control(EpollCtlOp::ADD, fd(3), &file, ev).unwrap();
+        }
+        // the chain below the added instance is too deep
+        let file: Arc<dyn FileLike> = epolls[0].clone();
+        assert!(matches!(
+            epolls[EPOLL_MAX_NESTS + 1].control(EpollCtlOp::ADD, fd(3), &file, ev),
+            Err(LxError::ELOOP)
+        ));
+    }
+}
@@ -0,0 +1,286 @@
+//! Implement eventfd
+#![deny(missing_docs)]
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    convert::TryInto,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use lock::Mutex;
+use zircon_object::object::*;
+
+use super::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::error::{LxError, LxResult};
+use crate::sync::{wait_for_event, Event, EventBus};
+
+bitflags::bitflags! {
+    /// Flags of `eventfd2`
+    pub struct EventFdFlags: usize {
+        /// Provide semaphore-like semantics for reads
+        const SEMAPHORE = 1;
+        /// Set the O_NONBLOCK file status flag
+        const NON_BLOCK = 1 << 11;
+        /// Set the close-on-exec flag
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+/// The maximum value of the counter
+const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+/// Event notification file, a counter maintained by the kernel
+pub struct EventFd {
+    /// Kernel object base
+    base: KObjectBase,
+    /// semaphore-like semantics
+    semaphore: bool,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+    /// the counter, shared by the duplicated files
+    count: Arc<Mutex<u64>>,
+    /// event bus to wake up readers and writers
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl_kobject!(EventFd);
+
+impl EventFd {
+    /// Create an eventfd object with the initial value of the counter
+    pub fn new(initval: u64, flags: EventFdFlags) -> Arc<Self> {
+        let mut open_flags = OpenFlags::RDWR;
+        open_flags.set(
+            OpenFlags::NON_BLOCK,
+            flags.contains(EventFdFlags::NON_BLOCK),
+        );
+        open_flags.set(OpenFlags::CLOEXEC, flags.contains(EventFdFlags::CLOEXEC));
+        let eventfd = Arc::new(EventFd {
+            base: KObjectBase::new(),
+            semaphore: flags.contains(EventFdFlags::SEMAPHORE),
+            flags: Mutex::new(open_flags),
+            count: Arc::new(Mutex::new(initval)),
+            eventbus: EventBus::new(),
+        });
+        eventfd.notify(initval);
+        eventfd
+    }
+
+    /// Notify the event bus with the new value of the counter,
+    /// must be called with the counter locked
+    fn notify(&self, count: u64) {
+        let mut set = Event::empty();
+        if count > 0 {
+            set |= Event::READABLE;
+        }
+        if count < EVENTFD_MAX {
+            set |= Event::WRITABLE;
+        }
+        self.eventbus
+            .lock()
+            .change(Event::READABLE | Event::WRITABLE, set);
+    }
+
+    /// Take the value from the counter, returns `EAGAIN` if it is zero
+    fn try_read(&self) -> LxResult<u64> {
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return Err(LxError::EAGAIN);
+        }
+        let value = if self.semaphore { 1 } else { *count };
+        *count -= value;
+        self.notify(*count);
+        Ok(value)
+    }
+
+    fn status(count: u64) -> PollStatus {
+        PollStatus {
+            read: count > 0,
+            write: count < EVENTFD_MAX,
+            error: false,
+        }
+    }
+}
+
+#[async_trait]
+impl FileLike for EventFd {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(EventFd {
+            base: KObjectBase::new(),
+            semaphore: self.semaphore,
+            flags: Mutex::new(self.flags()),
+            count: self.count.clone(),
+            eventbus: self.eventbus.clone(),
+        })
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        if buf.len() < 8 {
+            return Err(LxError::EINVAL);
+        }
+        let value = loop {
+            match self.try_read() {
+                Err(LxError::EAGAIN) if !self.flags().contains(OpenFlags::NON_BLOCK) => {
+                    wait_for_event(self.eventbus.clone(), Event::READABLE).await;
+                }
+                ret => break ret?,
+            }
+        };
+        buf[..8].copy_from_slice(&value.to_ne_bytes());
+        Ok(8)
+    }
+
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        let value = u64::from_ne_bytes(
+            buf.get(..8)
+                .ok_or(LxError::EINVAL)?
+                .try_into()
+                .map_err(|_| LxError::EINVAL)?,
+        );
+        if value == u64::MAX {
+            return Err(LxError::EINVAL);
+        }
+        let mut count = self.count.lock();
+        // the write never blocks, since `write` of `FileLike` is synchronous
+        if EVENTFD_MAX - *count < value {
+            return Err(LxError::EAGAIN);
+        }
+        *count += value;
+        self.notify(*count);
+        Ok(8)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(Self::status(*self.count.lock()))
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct EventFdFuture<'a> {
+            eventfd: &'a EventFd,
+            events: PollEvents,
+        }
+
+        impl<'a> Future for EventFdFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // hold the counter to avoid missing a notification
+                let count = self.eventfd.count.lock();
+                let status = EventFd::status(*count);
+                if (status.read && self.events.contains(PollEvents::IN))
+                    || (status.write && self.events.contains(PollEvents::OUT))
+                {
+                    return Poll::Ready(Ok(status));
+                }
+                let waker = cx.waker().clone();
+                self.eventfd.eventbus.lock().subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        EventFdFuture {
+            eventfd: self,
+            events,
+        }
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(efd: &EventFd) -> LxResult<u64> {
+        let mut buf = [0; 8];
+        efd.read(&mut buf).await?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    fn write(efd: &EventFd, value: u64) -> LxResult<usize> {
+        efd.write(&value.to_ne_bytes())
+    }
+
+    #[async_std::test]
+    async fn counter() {
+        let efd = EventFd::new(3, EventFdFlags::NON_BLOCK);
+        write(&efd, 4).unwrap();
+        assert_eq!(read(&efd).await.unwrap(), 7);
+        assert!(matches!(read(&efd).await, Err(LxError::EAGAIN)));
+
+        assert!(matches!(efd.read(&mut [0; 4]).await, Err(LxError::EINVAL)));
+        assert!(matches!(efd.write(&[0; 4]), Err(LxError::EINVAL)));
+        assert!(matches!(write(&efd, u64::MAX), Err(LxError::EINVAL)));
+
+        // the counter can not go beyond `EVENTFD_MAX`
+        write(&efd, EVENTFD_MAX).unwrap();
+        let status = efd.poll(PollEvents::IN | PollEvents::OUT).unwrap();
+        assert!(status.read && !status.write);
+        assert!(matches!(write(&efd, 1), Err(LxError::EAGAIN)));
+        assert_eq!(read(&efd).await.unwrap(), EVENTFD_MAX);
+        let status = efd.poll(PollEvents::IN | PollEvents::OUT).unwrap();
+        assert!(!status.read && status.write);
+    }
+
+    #[async_std::test]
+    async fn semaphore() {
+        let efd = EventFd::new(2, EventFdFlags::SEMAPHORE | EventFdFlags::NON_BLOCK);
+        write(&efd, 1).unwrap();
+        for _ in 0..3 {
+            assert_eq!(read(&efd).await.unwrap(), 1);
+        }
+        assert!(matches!(read(&efd).await, Err(LxError::EAGAIN)));
+    }
+
+    #[async_std::test]
+    async fn dup() {
+        let efd = EventFd::new(0, EventFdFlags::SEMAPHORE | EventFdFlags::NON_BLOCK);
+        let dup = efd.dup();
+        write(&efd, 2).unwrap();
+        // the duplicated file shares the counter and the semaphore mode
+        let mut buf = [0; 8];
+        dup.read(&mut buf).await.unwrap();
+        assert_eq!(u64::from_ne_bytes(buf), 1);
+        assert_eq!(read(&efd).await.unwrap(), 1);
+        assert!(matches!(dup.read(&mut buf).await, Err(LxError::EAGAIN)));
+        dup.write(&3u64.to_ne_bytes()).unwrap();
+        assert!(efd.poll(PollEvents::IN).unwrap().read);
+    }
+
+    #[async_std::test]
+    async fn blocking_read() {
+        let efd = EventFd::new(0, EventFdFlags::empty());
+        let reader = {
+            let efd = efd.clone();
+            async_std::task::spawn(async move { read(&efd).await.unwrap() })
+        };
+        async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+        write(&efd, 5).unwrap();
+        assert_eq!(reader.await, 5);
+        assert!(!efd.poll(PollEvents::IN).unwrap().read);
+    }
+}
@@ -9,7 +9,9 @@ use rcore_fs::vfs::{FileType, FsError, INode, Metadata, PollStatus};
 use zircon_object::object::*;
 use zircon_object::vm::{pages, VmObject};
 
-use super::FileLike;
+use super::file_lock::LockOwner;
+use super::inotify::{fsnotify_close, fsnotify_modify};
+use super::{FileLike, Mount, PageCache, Pipe};
 use crate::error::{LxError, LxResult};
 
 use zircon_object::vm::PAGE_SIZE_LOG2;
@@ -27,6 +29,8 @@ bitflags::bitflags! {
         const CREATE = 1 << 6;
         /// error if CREATE and the file exists
         const EXCLUSIVE = 1 << 7;
+        /// do not make the terminal opened the controlling terminal
+        const NOCTTY = 1 << 8;
         /// truncate file upon open
         const TRUNCATE = 1 << 9;
         /// append on each write
@@ -108,6 +112,25 @@ pub struct File {
     path: String,
     /// file inner mut data
     inner: RwLock<FileInner>,
+    /// owner of the flock and OFD locks, shared with the duplicated files
+    pub(super) lock_owner: Arc<LockOwner>,
+    /// the mount the file is opened through, `None` for pseudo files
+    mount: Option<Arc<Mount>>,
+    /// the open file description, shared with the duplicated files
+    description: Arc<Description>,
+}
+
+/// An open file description, the close is reported when it is dropped
+/// with the last file sharing it.
+struct Description {
+    inode: Arc<dyn INode>,
+    writable: bool,
+}
+
+impl Drop for Description {
+    fn drop(&mut self) {
+        fsnotify_close(&self.inode, self.writable);
+    }
 }
 
 impl_kobject!(File);
@@ -125,6 +148,9 @@ impl FileInner {
         if !self.flags.readable() {
             return Err(LxError::EBADF);
         }
+        if let Some(cache) = PageCache::get(&self.inode) {
+            return cache.read(offset as usize, buf);
+        }
         if !self.flags.non_block() {
             // block
             loop {
@@ -158,17 +184,52 @@ impl FileInner {
         if !self.flags.writable() {
             return Err(LxError::EBADF);
         }
-        let len = self.inode.write_at(offset as usize, buf)?;
+        let len = match PageCache::get(&self.inode) {
+            Some(cache) => cache.write(offset as usize, buf)?,
+            None => self.inode.write_at(offset as usize, buf)?,
+        };
+        if len > 0 {
+            fsnotify_modify(&self.inode);
+        }
         Ok(len)
     }
 }
 
 impl File {
     /// create a file struct
+    ///
+    /// The file is taken as opened through the latest mount of its file system,
+    /// use `with_mount` if the mount is known.
     pub fn new(inode: Arc<dyn INode>, flags: OpenFlags, path: String) -> Arc<Self> {
+        let mount = Mount::of_inode(&inode);
+        Self::new_inner(inode, flags, path, mount)
+    }
+
+    /// create a file struct opened through `mount`
+    pub fn with_mount(
+        inode: Arc<dyn INode>,
+        flags: OpenFlags,
+        path: String,
+        mount: Arc<Mount>,
+    ) -> Arc<Self> {
+        Self::new_inner(inode, flags, path, Some(mount))
+    }
+
+    fn new_inner(
+        inode: Arc<dyn INode>,
+        flags: OpenFlags,
+        path: String,
+        mount: Option<Arc<Mount>>,
+    ) -> Arc<Self> {
         Arc::new(File {
             base: KObjectBase::new(),
             path,
+            lock_owner: LockOwner::new(inode.clone()),
+            mount,
+            description: Arc::new(Description {
+                inode: inode.clone(),
+                writable: flags.writable(),
+            }),
             inner: RwLock::new(FileInner {
                 offset: 0,
                 flags,
@@ -177,6 +238,20 @@ impl File {
         })
     }
 
+    /// The mount the file is opened through
+    pub fn mount(&self) -> Option<&Arc<Mount>> {
+        self.mount.as_ref()
+    }
+
+    /// Check that the file can be modified, it fails with `EROFS` if it is
+    /// opened through a read-only mount.
+    pub fn check_writable(&self) -> LxResult {
+        match &self.mount {
+            Some(mount) => mount.check_writable(),
+            None => Ok(()),
+        }
+    }
+
     /// Returns the file path.
     pub fn path(&self) -> &String {
         &self.path
@@ -199,7 +274,11 @@ impl File {
         if !inner.flags.writable() {
             return Err(LxError::EBADF);
         }
-        inner.inode.resize(len as usize)?;
+        match PageCache::get(&inner.inode) {
+            Some(cache) => cache.resize(len as usize)?,
+            None => inner.inode.resize(len as usize)?,
+        }
+        fsnotify_modify(&inner.inode);
         Ok(())
     }
 
@@ -241,6 +320,15 @@ impl File {
     pub fn inode(&self) -> Arc<dyn INode> {
         self.inner.read().inode.clone()
     }
+
+    /// Get the page cache of the file for shared mappings, which covers at least `len` bytes
+    pub fn page_cache(&self, len: usize) -> LxResult<Arc<PageCache>> {
+        let inode = self.inode();
+        if inode.metadata()?.type_ != FileType::File {
+            return Err(LxError::ENODEV);
+        }
+        PageCache::get_or_create(&inode, len)
+    }
 }
 
 #[async_trait]
@@ -262,6 +350,9 @@ impl FileLike for File {
             base: KObjectBase::new(),
             path: self.path.clone(),
             inner: RwLock::new(self.inner.read().clone()),
+            lock_owner: self.lock_owner.clone(),
+            mount: self.mount.clone(),
+            description: self.description.clone(),
         })
     }
 
@@ -285,13 +376,22 @@ impl FileLike for File {
         Ok(self.inner.read().inode.poll()?)
     }
 
-    async fn async_poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
-        Ok(self.inner.read().inode.async_poll().await?)
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        let inode = self.inode();
+        // a pipe registers the waker until it is ready for the events asked for
+        if let Some(pipe) = inode.downcast_ref::<Pipe>() {
+            return pipe.async_poll_events(events).await;
+        }
+        Ok(inode.async_poll().await?)
     }
 
     fn ioctl(&self, request: usize, arg1: usize, _arg2: usize, _arg3: usize) -> LxResult<usize> {
         // ioctl syscall
-        self.inner.read().inode.io_control(request as u32, arg1)?;
+        let inode = self.inner.read().inode.clone();
+        if let Some(ret) = super::tty::ioctl(&inode, request, arg1) {
+            return ret;
+        }
+        inode.io_control(request as u32, arg1)?;
         Ok(0)
     }
 
@@ -302,9 +402,14 @@ impl FileLike for File {
             FileType::File => {
                 let vmo = VmObject::new_contiguous(pages(len), PAGE_SIZE_LOG2)?;
                 let (guard, buf) = vmo.as_mut_buf()?;
-                inner.inode.read_at(offset, buf)?;
+                match PageCache::get(&inner.inode) {
+                    Some(cache) => cache.read(offset, buf)?,
+                    None => inner.inode.read_at(offset, buf)?,
+                };
                 drop(guard);
                 vmo.unset_contiguous();
+                // shown in `/proc/[pid]/maps`
+                vmo.set_name(&self.path);
                 Ok(vmo)
             }
             FileType::CharDevice => {
@@ -0,0 +1,730 @@
+//! Advisory file locks, the `flock` locks and the record locks of `fcntl`
+//!
+//! The locks of all files are kept in a global table indexed by the files.
+//!
+//! - A `flock` lock is owned by an open file description, shared by the duplicated
+//!   file descriptors, and released when the description is closed.
+//! - A POSIX record lock is owned by a process, and released when the process closes
+//!   any file descriptor of the file, or exits.
+//! - An open file description lock is a record lock owned by an open file description.
+//!
+//! The flock locks and the record locks of a file do not conflict with each other.
+#![deny(missing_docs)]
+
+use alloc::{collections::BTreeMap, collections::BTreeSet, sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use lazy_static::lazy_static;
+use lock::Mutex;
+use rcore_fs::vfs::INode;
+use zircon_object::object::KoID;
+
+use super::mount::{inode_key, INodeKey};
+use super::{File, SeekFrom};
+use crate::error::{LxError, LxResult};
+
+/// The type of a lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    /// A shared lock, or a read lock
+    Shared,
+    /// An exclusive lock, or a write lock
+    Exclusive,
+}
+
+/// The description of a record lock, `struct flock`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flock {
+    /// the type of the lock, `F_RDLCK`, `F_WRLCK` or `F_UNLCK`
+    pub l_type: i16,
+    /// how to interpret `l_start`, `SEEK_SET`, `SEEK_CUR` or `SEEK_END`
+    pub l_whence: i16,
+    /// the starting offset of the lock
+    pub l_start: i64,
+    /// the number of bytes to lock, 0 for the end of the file
+    pub l_len: i64,
+    /// the process holding the lock, -1 for an open file description lock
+    pub l_pid: i32,
+}
+
+/// Read lock of `struct flock`
+pub const F_RDLCK: i16 = 0;
+/// Write lock of `struct flock`
+pub const F_WRLCK: i16 = 1;
+/// Unlock of `struct flock`
+pub const F_UNLCK: i16 = 2;
+
+lazy_static! {
+    /// The locks of all files
+    static ref LOCKS: Mutex<LockTable> = Mutex::new(LockTable::default());
+}
+
+/// The owner of a lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    /// a process, of the POSIX record locks
+    Process(KoID),
+    /// an open file description, by the address of its [`LockOwner`]
+    File(usize),
+}
+
+/// A record lock on the bytes `[start, end]` of a file
+#[derive(Debug, Clone, Copy)]
+struct RecordLock {
+    owner: Owner,
+    kind: LockKind,
+    start: u64,
+    /// the last byte locked, `u64::MAX` for the end of the file however it grows
+    end: u64,
+}
+
+impl RecordLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start <= end && start <= self.end
+    }
+
+    fn conflicts(&self, other: &RecordLock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other.start, other.end)
+            && (self.kind == LockKind::Exclusive || other.kind == LockKind::Exclusive)
+    }
+}
+
+/// The locks of a file
+#[derive(Default)]
+struct FileLocks {
+    /// the flock locks by the open file descriptions
+    flocks: Vec<(usize, LockKind)>,
+    /// the record locks
+    records: Vec<RecordLock>,
+    /// the tasks waiting for the release of a lock
+    waiters: Vec<Waker>,
+}
+
+impl FileLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.records.is_empty() && self.waiters.is_empty()
+    }
+
+    /// Wake up the waiters to try again after the locks are changed.
+    fn wake(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+
+    fn flock_conflicts(&self, file: usize, kind: LockKind) -> bool {
+        self.flocks
+            .iter()
+            .any(|&(f, k)| f != file && (kind == LockKind::Exclusive || k == LockKind::Exclusive))
+    }
+
+    fn remove_flock(&mut self, file: usize) {
+        let len = self.flocks.len();
+        self.flocks.retain(|&(f, _)| f != file);
+        if self.flocks.len() != len {
+            self.wake();
+        }
+    }
+
+    fn record_conflict(&self, lock: &RecordLock) -> Option<&RecordLock> {
+        self.records.iter().find(|r| r.conflicts(lock))
+    }
+
+    /// Unlock the bytes `[start, end]` owned by `owner`, splitting the locks.
+    fn unlock(&mut self, owner: Owner, start: u64, end: u64) {
+        let mut records = Vec::with_capacity(self.records.len());
+        for r in self.records.drain(..) {
+            if r.owner != owner || !r.overlaps(start, end) {
+                records.push(r);
+                continue;
+            }
+            if r.start < start {
+                records.push(RecordLock {
+                    end: start - 1,
+                    ..r
+                });
+            }
+            if r.end > end {
+                records.push(RecordLock {
+                    start: end + 1,
+                    ..r
+                });
+            }
+        }
+        self.records = records;
+        self.wake();
+    }
+
+    /// Replace the locks of the owner in the range with `lock`,
+    /// merged with the adjacent locks of the same type.
+    fn lock(&mut self, mut lock: RecordLock) {
+        self.unlock(lock.owner, lock.start, lock.end);
+        self.records.retain(|r| {
+            let adjacent = r.owner == lock.owner
+                && r.kind == lock.kind
+                && r.end.saturating_add(1) >= lock.start
+                && lock.end.saturating_add(1) >= r.start;
+            if adjacent {
+                lock.start = lock.start.min(r.start);
+                lock.end = lock.end.max(r.end);
+            }
+            !adjacent
+        });
+        self.records.push(lock);
+    }
+
+    fn release(&mut self, owner: Owner) {
+        self.unlock(owner, 0, u64::MAX);
+    }
+}
+
+/// The table of locks
+#[derive(Default)]
+struct LockTable {
+    /// the locks of each file
+    files: BTreeMap<INodeKey, FileLocks>,
+    /// the record locks which the processes are waiting for, to detect deadlocks,
+    /// keyed by the waiters as the threads of a process may wait at the same time
+    blocked: BTreeMap<usize, (KoID, INodeKey, RecordLock)>,
+}
+
+impl LockTable {
+    /// Drop the entry of the file if there is no lock or waiter.
+    fn tidy(&mut self, key: INodeKey) {
+        if self.files.get(&key).map_or(false, FileLocks::is_empty) {
+            self.files.remove(&key);
+        }
+    }
+
+    /// Whether the process `pid` waiting for `lock` of the file `key` would wait
+    /// for itself, through the processes holding the conflicting locks and the
+    /// locks they are waiting for in turn.
+    fn deadlock(&self, pid: KoID, key: INodeKey, lock: &RecordLock) -> bool {
+        let mut visited = BTreeSet::new();
+        let mut requests = vec![(key, *lock)];
+        while let Some((key, lock)) = requests.pop() {
+            let locks = match self.files.get(&key) {
+                Some(locks) => locks,
+                None => continue,
+            };
+            for r in locks.records.iter().filter(|r| r.conflicts(&lock)) {
+                if let Owner::Process(holder) = r.owner {
+                    if holder == pid {
+                        return true;
+                    }
+                    if visited.insert(holder) {
+                        let waiting = self.blocked.values().filter(|(p, ..)| *p == holder);
+                        requests.extend(waiting.map(|&(_, key, lock)| (key, lock)));
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+/// The key of the file in the lock table.
+///
+/// The devices and pseudo files, which do not know their file systems,
+/// are identified by the inode objects.
+fn lock_key(inode: &Arc<dyn INode>) -> INodeKey {
+    inode_key(inode).unwrap_or((0, Arc::as_ptr(inode) as *const u8 as usize))
+}
+
+/// Wait until `f` completes on the locks of the file `key`, which is tried again
+/// each time the locks of the file are changed. `f` returns `None` to wait.
+///
+/// The `waiter` is removed from the blocked ones when done or canceled.
+struct LockFuture<F> {
+    key: INodeKey,
+    waiter: usize,
+    f: F,
+}
+
+impl<F> Future for LockFuture<F>
+where
+    F: FnMut(&mut LockTable) -> Option<LxResult> + Unpin,
+{
+    type Output = LxResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let key = self.key;
+        let mut table = LOCKS.lock();
+        match (self.f)(&mut table) {
+            Some(ret) => Poll::Ready(ret),
+            None => {
+                let locks = table.files.entry(key).or_default();
+                locks.waiters.push(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<F> Drop for LockFuture<F> {
+    fn drop(&mut self) {
+        let mut table = LOCKS.lock();
+        table.blocked.remove(&self.waiter);
+        table.tidy(self.key);
+    }
+}
+
+/// The owner of the flock and open file description locks of a file, shared by the
+/// files duplicated from the same open file description.
+///
+/// The locks are released when the last file of the description is closed, so the
+/// address identifying the owner is never reused while a lock refers to it.
+pub(super) struct LockOwner {
+    /// the file locked
+    inode: Arc<dyn INode>,
+}
+
+impl LockOwner {
+    /// Create the owner of the locks for a newly opened file on `inode`.
+    pub(super) fn new(inode: Arc<dyn INode>) -> Arc<Self> {
+        Arc::new(LockOwner { inode })
+    }
+
+    fn id(&self) -> usize {
+        self as *const LockOwner as usize
+    }
+}
+
+impl Drop for LockOwner {
+    fn drop(&mut self) {
+        let mut table = LOCKS.lock();
+        if table.files.is_empty() {
+            return;
+        }
+        let key = lock_key(&self.inode);
+        let owner = self.id();
+        if let Some(locks) = table.files.get_mut(&key) {
+            locks.remove_flock(owner);
+            locks.release(Owner::File(owner));
+        }
+        table.tidy(key);
+    }
+}
+
+impl File {
+    /// Apply the flock lock `kind` on the file, or remove it if `kind` is `None`.
+    ///
+    /// An existing lock of the open file description is converted. If the lock is held by
+    /// another open file description, it waits for the release, or fails with `EAGAIN`
+    /// if `nonblock`.
+    pub async fn flock(&self, kind: Option<LockKind>, nonblock: bool) -> LxResult {
+        let key = lock_key(&self.inode());
+        let file = self.lock_owner.id();
+        // the conversion is not atomic, the existing lock is removed first
+        {
+            let mut table = LOCKS.lock();
+            if let Some(locks) = table.files.get_mut(&key) {
+                locks.remove_flock(file);
+            }
+            table.tidy(key);
+        }
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return Ok(()),
+        };
+        LockFuture {
+            key,
+            pid: None,
+            f: move |table: &mut LockTable| {
+                let locks = table.files.entry(key).or_default();
+                if !locks.flock_conflicts(file, kind) {
+                    locks.flocks.push((file, kind));
+                    Some(Ok(()))
+                } else if nonblock {
+                    Some(Err(LxError::EAGAIN))
+                } else {
+                    None
+                }
+            },
+        }
+        .await
+    }
+
+    /// The bytes `[start, end]` described by `flock` from the offset of the file.
+    fn lock_range(&self, flock: &Flock) -> LxResult<(u64, u64)> {
+        let base = match flock.l_whence {
+            0 => 0,
+            1 => self.seek(SeekFrom::Current(0))? as i64,
+            2 => self.metadata()?.size as i64,
+            _ => return Err(LxError::EINVAL),
+        };
+        let start = base.checked_add(flock.l_start).ok_or(LxError::EINVAL)?;
+        let (start, end) = match flock.l_len {
+            0 => (start, None),
+            // a negative length covers the bytes before the start
+            len if len < 0 => (
+                start.checked_add(len).ok_or(LxError::EINVAL)?,
+                Some(start - 1),
+            ),
+            len => {
+                let end = start.checked_add(len - 1).ok_or(LxError::EINVAL)?;
+                (start, Some(end))
+            }
+        };
+        if start < 0 {
+            return Err(LxError::EINVAL);
+        }
+        Ok((start as u64, end.map_or(u64::MAX, |end| end as u64)))
+    }
+
+    /// The owner of the record locks of `pid`, or of the open file description if `None`.
+    fn record_owner(&self, pid: Option<KoID>) -> Owner {
+        match pid {
+            Some(pid) => Owner::Process(pid),
+            None => Owner::File(self.lock_owner.id()),
+        }
+    }
+
+    /// Get the first record lock conflicting with the lock described by `flock`,
+    /// as `F_GETLK` does for the process `pid`, or `F_OFD_GETLK` if `pid` is `None`.
+    ///
+    /// The type of `flock` is set to `F_UNLCK` if there is no such lock.
+    pub fn get_lock(&self, pid: Option<KoID>, flock: &mut Flock) -> LxResult {
+        let kind = match flock.l_type {
+            F_RDLCK => LockKind::Shared,
+            F_WRLCK => LockKind::Exclusive,
+            _ => return Err(LxError::EINVAL),
+        };
+        let (start, end) = self.lock_range(flock)?;
+        let lock = RecordLock {
+            owner: self.record_owner(pid),
+            kind,
+            start,
+            end,
+        };
+        let key = lock_key(&self.inode());
+        let table = LOCKS.lock();
+        let conflict = table
+            .files
+            .get(&key)
+            .and_then(|locks| locks.record_conflict(&lock).copied());
+        *flock = match conflict {
+            Some(r) => Flock {
+                l_type: if r.kind == LockKind::Shared {
+                    F_RDLCK
+                } else {
+                    F_WRLCK
+                },
+                l_whence: 0,
+                l_start: r.start as i64,
+                l_len: if r.end == u64::MAX {
+                    0
+                } else {
+                    (r.end - r.start + 1) as i64
+                },
+                l_pid: match r.owner {
+                    Owner::Process(pid) => pid as i32,
+                    Owner::File(_) => -1,
+                },
+            },
+            None => Flock {
+                l_type: F_UNLCK,
+                ..*flock
+            },
+        };
+        Ok(())
+    }
+
+    /// Acquire or release the record lock described by `flock`, as `F_SETLK` and
+    /// `F_SETLKW` do for the process `pid`, or `F_OFD_SETLK` and `F_OFD_SETLKW`
+    /// if `pid` is `None`.
+    ///
+    /// If the lock conflicts with the locks of other owners, it waits for the release
+    /// if `wait`, or fails with `EAGAIN`. A process which would wait for itself fails
+    /// with `EDEADLK`.
+    pub async fn set_lock(&self, pid: Option<KoID>, flock: &Flock, wait: bool) -> LxResult {
+        let kind = match flock.l_type {
+            F_RDLCK if self.flags().readable() => LockKind::Shared,
+            F_WRLCK if self.flags().writable() => LockKind::Exclusive,
+            F_RDLCK | F_WRLCK => return Err(LxError::EBADF),
+            F_UNLCK => {
+                let (start, end) = self.lock_range(flock)?;
+                let key = lock_key(&self.inode());
+                let mut table = LOCKS.lock();
+                if let Some(locks) = table.files.get_mut(&key) {
+                    locks.unlock(self.record_owner(pid), start, end);
+                }
+                table.tidy(key);
+                return Ok(());
+            }
+            _ => return Err(LxError::EINVAL),
+        };
+        let (start, end) = self.lock_range(flock)?;
+        let lock = RecordLock {
+            owner: self.record_owner(pid),
+            kind,
+            start,
+            end,
+        };
+        let key = lock_key(&self.inode());
+        static NEXT_WAITER: AtomicUsize = AtomicUsize::new(0);
+        let waiter = NEXT_WAITER.fetch_add(1, Ordering::Relaxed);
+        LockFuture {
+            key,
+            waiter,
+            f: move |table: &mut LockTable| {
+                let locks = table.files.entry(key).or_default();
+                if locks.record_conflict(&lock).is_none() {
+                    locks.lock(lock);
+                    table.blocked.remove(&waiter);
+                    return Some(Ok(()));
+                }
+                if !wait {
+                    return Some(Err(LxError::EAGAIN));
+                }
+                // only the processes are checked, as Linux does
+                if let Some(pid) = pid {
+                    if table.deadlock(pid, key, &lock) {
+                        table.blocked.remove(&waiter);
+                        return Some(Err(LxError::EDEADLK));
+                    }
+                    table.blocked.insert(waiter, (pid, key, lock));
+                }
+                None
+            },
+        }
+        .await
+    }
+}
+
+/// Release the record locks of the process `pid` on `file`, when the process closes
+/// any file descriptor of it.
+pub fn release_posix_locks(file: &File, pid: KoID) {
+    let mut table = LOCKS.lock();
+    if table.files.is_empty() {
+        return;
+    }
+    let key = lock_key(&file.inode());
+    if let Some(locks) = table.files.get_mut(&key) {
+        locks.release(Owner::Process(pid));
+    }
+    table.tidy(key);
+}
+
+/// Release all record locks of the process `pid` when it exits.
+pub fn release_process_locks(pid: KoID) {
+    let mut table = LOCKS.lock();
+    table.blocked.retain(|_, &mut (p, ..)| p != pid);
+    for locks in table.files.values_mut() {
+        locks.release(Owner::Process(pid));
+    }
+    table.files.retain(|_, locks| !locks.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{FileLike, OpenFlags};
+    use alloc::string::String;
+    use core::time::Duration;
+    use rcore_fs::vfs::{FileSystem, FileType};
+    use rcore_fs_ramfs::RamFS;
+
+    fn open(inode: &Arc<dyn INode>) -> Arc<File> {
+        File::new(inode.clone(), OpenFlags::RDWR, String::from("/test"))
+    }
+
+    fn create() -> (Arc<RamFS>, Arc<dyn INode>) {
+        let fs = RamFS::new();
+        let inode = fs
+            .root_inode()
+            .create("test", FileType::File, 0o644)
+            .unwrap();
+        inode.resize(0x100).unwrap();
+        (fs, inode)
+    }
+
+    fn flock(l_type: i16, start: i64, len: i64) -> Flock {
+        Flock {
+            l_type,
+            l_whence: 0,
+            l_start: start,
+            l_len: len,
+            l_pid: 0,
+        }
+    }
+
+    #[async_std::test]
+    async fn flock_dup() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        let dup = file.dup();
+        let dup = dup.downcast_ref::<File>().unwrap();
+        let other = open(&inode);
+
+        file.flock(Some(LockKind::Exclusive), true).await.unwrap();
+        // the duplicated file shares the lock, which is converted
+        dup.flock(Some(LockKind::Shared), true).await.unwrap();
+        dup.flock(Some(LockKind::Exclusive), true).await.unwrap();
+        assert!(matches!(
+            other.flock(Some(LockKind::Shared), true).await,
+            Err(LxError::EAGAIN)
+        ));
+
+        // the lock is kept until the last file of the description is closed
+        drop(file);
+        assert!(matches!(
+            other.flock(Some(LockKind::Shared), true).await,
+            Err(LxError::EAGAIN)
+        ));
+        dup.flock(None, true).await.unwrap();
+        other.flock(Some(LockKind::Shared), true).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn flock_release_on_close() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        let dup = file.dup();
+        let other = open(&inode);
+
+        file.flock(Some(LockKind::Exclusive), true).await.unwrap();
+        drop(file);
+        assert!(matches!(
+            other.flock(Some(LockKind::Exclusive), true).await,
+            Err(LxError::EAGAIN)
+        ));
+        drop(dup);
+        other.flock(Some(LockKind::Exclusive), true).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn record_split() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        file.set_lock(Some(1), &flock(F_WRLCK, 0, 100), false)
+            .await
+            .unwrap();
+        file.set_lock(Some(1), &flock(F_UNLCK, 10, 10), false)
+            .await
+            .unwrap();
+
+        // the hole is free for others, the rest is still locked
+        file.set_lock(Some(2), &flock(F_WRLCK, 10, 10), false)
+            .await
+            .unwrap();
+        for &start in [9, 20].iter() {
+            assert!(matches!(
+                file.set_lock(Some(3), &flock(F_RDLCK, start, 1), false)
+                    .await,
+                Err(LxError::EAGAIN)
+            ));
+        }
+        let mut lock = flock(F_RDLCK, 50, 0);
+        file.get_lock(Some(2), &mut lock).unwrap();
+        assert_eq!((lock.l_type, lock.l_start, lock.l_len), (F_WRLCK, 20, 80));
+        assert_eq!(lock.l_pid, 1);
+    }
+
+    #[async_std::test]
+    async fn record_merge() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        file.set_lock(Some(1), &flock(F_RDLCK, 0, 10), false)
+            .await
+            .unwrap();
+        file.set_lock(Some(1), &flock(F_RDLCK, 10, 10), false)
+            .await
+            .unwrap();
+        let mut lock = flock(F_WRLCK, 0, 0);
+        file.get_lock(Some(2), &mut lock).unwrap();
+        assert_eq!((lock.l_type, lock.l_start, lock.l_len), (F_RDLCK, 0, 20));
+
+        // a lock of another type replaces the range
+        file.set_lock(Some(1), &flock(F_WRLCK, 5, 10), false)
+            .await
+            .unwrap();
+        let mut lock = flock(F_RDLCK, 0, 0);
+        file.get_lock(Some(2), &mut lock).unwrap();
+        assert_eq!((lock.l_type, lock.l_start, lock.l_len), (F_WRLCK, 5, 10));
+    }
+
+    #[async_std::test]
+    async fn record_conflict() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        let dup = file.dup();
+        let dup = dup.downcast_ref::<File>().unwrap();
+        let other = open(&inode);
+
+        // read locks are shared, and a process does not conflict with itself
+        file.set_lock(Some(1), &flock(F_RDLCK, 0, 0), false)
+            .await
+            .unwrap();
+        other
+            .set_lock(Some(2), &flock(F_RDLCK, 0, 0), false)
+            .await
+            .unwrap();
+        other
+            .set_lock(Some(2), &flock(F_WRLCK, 0, 0), false)
+            .await
+            .unwrap_err();
+        file.set_lock(Some(1), &flock(F_WRLCK, 0, 10), false)
+            .await
+            .unwrap_err();
+        release_posix_locks(&other, 2);
+        file.set_lock(Some(1), &flock(F_WRLCK, 0, 10), false)
+            .await
+            .unwrap();
+        release_process_locks(1);
+
+        // OFD locks are owned by the open file descriptions
+        dup.set_lock(None, &flock(F_WRLCK, 100, 10), false)
+            .await
+            .unwrap();
+        file.set_lock(None, &flock(F_WRLCK, 105, 10), false)
+            .await
+            .unwrap();
+        let mut lock = flock(F_RDLCK, 100, 1);
+        other.get_lock(None, &mut lock).unwrap();
+        assert_eq!((lock.l_type, lock.l_start, lock.l_pid), (F_WRLCK, 100, -1));
+        assert!(matches!(
+            other.set_lock(None, &flock(F_RDLCK, 110, 1), false).await,
+            Err(LxError::EAGAIN)
+        ));
+    }
+
+    #[async_std::test]
+    async fn record_deadlock_threads() {
+        let (_fs, inode) = create();
+        let file = open(&inode);
+        file.set_lock(Some(1), &flock(F_WRLCK, 0, 10), false)
+            .await
+            .unwrap();
+        file.set_lock(Some(2), &flock(F_WRLCK, 10, 10), false)
+            .await
+            .unwrap();
+
+        // two threads of the process 2 wait for the process 1
+        let waiter = {
+            let file = file.clone();
+            async_std::task::spawn(async move {
+                file.set_lock(Some(2), &flock(F_WRLCK, 0, 1), true).await
+            })
+        };
+        let canceled = file.set_lock(Some(2), &flock(F_WRLCK, 5, 1), true);
+        async_std::future::timeout(Duration::from_millis(10), canceled)
+            .await
+            .unwrap_err();
+
+        // the canceled one does not hide the other
+        assert!(matches!(
+            file.set_lock(Some(1), &flock(F_WRLCK, 10, 1), true).await,
+            Err(LxError::EDEADLK)
+        ));
+        release_process_locks(1);
+        waiter.await.unwrap();
+    }
+}
@@ -0,0 +1,918 @@
+//! Implement inotify, the notification of file system events
+//!
+//! The watches of all inotify instances are kept in a global table indexed by the
+//! watched files. The VFS operations of the syscalls report the events on the files
+//! by the `fsnotify_*` functions, which queue them on the instances watching the files.
+//!
+//! The event of a file is also reported to the watches on its directories with the
+//! name of the file. Since an inode does not know its directories, the names of the
+//! files in the watched directories are recorded when the directories are watched,
+//! and kept up to date by the events of creation, deletion and rename.
+#![deny(missing_docs)]
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use kernel_hal::user::UserOutPtr;
+use lazy_static::lazy_static;
+use lock::Mutex;
+use rcore_fs::vfs::{FileType, INode};
+use zircon_object::object::*;
+
+use super::ioctl::FIONREAD;
+use super::mount::{inode_key, INodeKey};
+use super::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::error::{LxError, LxResult};
+use crate::sync::{wait_for_event, Event, EventBus};
+
+bitflags::bitflags! {
+    /// Flags of `inotify_init1`
+    pub struct InotifyFlags: usize {
+        /// Set the O_NONBLOCK file status flag
+        const NON_BLOCK = 1 << 11;
+        /// Set the close-on-exec flag
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+bitflags::bitflags! {
+    /// The events of inotify, and the flags of `inotify_add_watch`
+    pub struct InotifyMask: u32 {
+        /// File was accessed
+        const ACCESS = 0x1;
+        /// File was modified
+        const MODIFY = 0x2;
+        /// Metadata changed
+        const ATTRIB = 0x4;
+        /// File opened for writing was closed
+        const CLOSE_WRITE = 0x8;
+        /// File not opened for writing was closed
+        const CLOSE_NOWRITE = 0x10;
+        /// File was opened
+        const OPEN = 0x20;
+        /// File was moved from the watched directory
+        const MOVED_FROM = 0x40;
+        /// File was moved to the watched directory
+        const MOVED_TO = 0x80;
+        /// File was created in the watched directory
+        const CREATE = 0x100;
+        /// File was deleted from the watched directory
+        const DELETE = 0x200;
+        /// The watched file was deleted
+        const DELETE_SELF = 0x400;
+        /// The watched file was moved
+        const MOVE_SELF = 0x800;
+        /// File system containing the watched file was unmounted
+        const UNMOUNT = 0x2000;
+        /// Event queue overflowed
+        const Q_OVERFLOW = 0x4000;
+        /// The watch was removed
+        const IGNORED = 0x8000;
+        /// Only watch the path if it is a directory
+        const ONLYDIR = 0x0100_0000;
+        /// Do not follow the path if it is a symbolic link
+        const DONT_FOLLOW = 0x0200_0000;
+        /// Exclude the events on the unlinked files
+        const EXCL_UNLINK = 0x0400_0000;
+        /// Only create a new watch, fail if the path is already watched
+        const MASK_CREATE = 0x1000_0000;
+        /// Add the events to the existing watch instead of replacing them
+        const MASK_ADD = 0x2000_0000;
+        /// The subject of the event is a directory
+        const ISDIR = 0x4000_0000;
+        /// Remove the watch after the first event
+        const ONESHOT = 0x8000_0000;
+
+        /// All the events which can be watched
+        const ALL_EVENTS = 0xfff;
+    }
+}
+
+/// The maximum number of the events in the queue of an instance,
+/// the default value of `/proc/sys/fs/inotify/max_queued_events`
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// The size of `struct inotify_event` without the name, `{ wd, mask, cookie, len }`
+const EVENT_HEADER_SIZE: usize = 16;
+
+lazy_static! {
+    /// The watches of all instances
+    static ref WATCHES: Mutex<Watches> = Mutex::new(Watches::default());
+}
+
+/// The cookie of the last rename, which relates its `MOVED_FROM` and `MOVED_TO` events
+static RENAME_COOKIE: AtomicU32 = AtomicU32::new(0);
+
+/// A watch of an instance on a file
+struct Watch {
+    inotify: Weak<Inotify>,
+    wd: i32,
+    mask: InotifyMask,
+}
+
+/// The table of watches
+#[derive(Default)]
+struct Watches {
+    /// the watches on each file
+    watches: BTreeMap<INodeKey, Vec<Watch>>,
+    /// the entries of each file in the watched directories, as `(directory, name)`
+    entries: BTreeMap<INodeKey, Vec<(INodeKey, String)>>,
+}
+
+impl Watches {
+    /// Queue an event on the watches of the file `key`.
+    fn queue(&mut self, key: INodeKey, mask: InotifyMask, cookie: u32, name: Option<&str>) {
+        let watches = match self.watches.get(&key) {
+            Some(watches) => watches,
+            None => return,
+        };
+        let mut oneshot = Vec::new();
+        for watch in watches.iter() {
+            // the removal of the watch is always reported
+            let events = mask & (watch.mask | InotifyMask::IGNORED);
+            if events.is_empty() {
+                continue;
+            }
+            if let Some(inotify) = watch.inotify.upgrade() {
+                let event = InotifyEvent {
+                    wd: watch.wd,
+                    mask: events | (mask & InotifyMask::ISDIR),
+                    cookie,
+                    name: name.map(|name| name.to_string()),
+                };
+                inotify.push(event);
+                if watch.mask.contains(InotifyMask::ONESHOT) {
+                    oneshot.push(inotify);
+                }
+            }
+        }
+        for inotify in oneshot {
+            if let Some(wd) = self.wd_of(key, &inotify) {
+                self.remove(key, &inotify, wd);
+            }
+        }
+    }
+
+    /// Queue an event on the watches of the file `key`, and on the watches of
+    /// its directories with its names.
+    fn queue_with_dirs(&mut self, key: INodeKey, mask: InotifyMask) {
+        self.queue(key, mask, 0, None);
+        let entries = self.entries.get(&key).cloned().unwrap_or_default();
+        for (dir, name) in entries {
+            self.queue(dir, mask, 0, Some(&name));
+        }
+    }
+
+    /// The watch descriptor of `inotify` on the file `key`.
+    fn wd_of(&self, key: INodeKey, inotify: &Inotify) -> Option<i32> {
+        let watches = self.watches.get(&key)?;
+        let watch = watches
+            .iter()
+            .find(|watch| Weak::as_ptr(&watch.inotify) == inotify as *const _)?;
+        Some(watch.wd)
+    }
+
+    /// Remove the watch `wd` of `inotify` on the file `key`, reporting `IGNORED`.
+    fn remove(&mut self, key: INodeKey, inotify: &Inotify, wd: i32) {
+        self.detach(key, inotify);
+        inotify.inner.lock().watches.remove(&wd);
+        inotify.push(InotifyEvent {
+            wd,
+            mask: InotifyMask::IGNORED,
+            cookie: 0,
+            name: None,
+        });
+    }
+
+    /// Remove the watch of `inotify` on the file `key` from the table.
+    fn detach(&mut self, key: INodeKey, inotify: *const Inotify) {
+        if let Some(watches) = self.watches.get_mut(&key) {
+            watches.retain(|watch| Weak::as_ptr(&watch.inotify) != inotify);
+            if watches.is_empty() {
+                self.forget(key);
+            }
+        }
+    }
+
+    /// Forget the file `key` which is no longer watched,
+    /// and the entries of the files in it if it is a directory.
+    fn forget(&mut self, key: INodeKey) {
+        self.watches.remove(&key);
+        self.entries.retain(|_, dirs| {
+            dirs.retain(|(dir, _)| *dir != key);
+            !dirs.is_empty()
+        });
+    }
+
+    /// Remove all watches on the deleted file `key`.
+    fn remove_all(&mut self, key: INodeKey) {
+        let watches = self.watches.get(&key).map_or(Vec::new(), |watches| {
+            watches
+                .iter()
+                .filter_map(|watch| Some((watch.inotify.upgrade()?, watch.wd)))
+                .collect()
+        });
+        for (inotify, wd) in watches {
+            self.remove(key, &inotify, wd);
+        }
+        self.forget(key);
+        self.entries.remove(&key);
+    }
+
+    /// Record the entry `name` of the file `key` in the directory `dir`, if it is watched.
+    fn add_entry(&mut self, key: INodeKey, dir: INodeKey, name: &str) {
+        if self.watches.contains_key(&dir) {
+            let dirs = self.entries.entry(key).or_default();
+            dirs.push((dir, name.to_string()));
+        }
+    }
+
+    /// Forget the entry `name` of the file `key` in the directory `dir`.
+    fn remove_entry(&mut self, key: INodeKey, dir: INodeKey, name: &str) {
+        if let Some(dirs) = self.entries.get_mut(&key) {
+            dirs.retain(|(d, n)| !(*d == dir && n == name));
+            if dirs.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Record the entries of the files in the newly watched directory `dir`.
+    fn scan(&mut self, dir: &Arc<dyn INode>, dir_key: INodeKey) {
+        let mut id = 0;
+        while let Ok(name) = dir.get_entry(id) {
+            id += 1;
+            if name == "." || name == ".." {
+                continue;
+            }
+            if let Some(key) = dir.find(&name).ok().as_ref().and_then(inode_key) {
+                self.add_entry(key, dir_key, &name);
+            }
+        }
+    }
+}
+
+/// Whether any file is watched, to skip the work of the events quickly.
+fn watching() -> bool {
+    !WATCHES.lock().watches.is_empty()
+}
+
+/// The mask of the events on `inode`, with `ISDIR` if it is a directory.
+fn event_mask(inode: &Arc<dyn INode>, mask: InotifyMask) -> InotifyMask {
+    match inode.metadata() {
+        Ok(info) if info.type_ == FileType::Dir => mask | InotifyMask::ISDIR,
+        _ => mask,
+    }
+}
+
+/// Whether the file is deleted after a deletion or a replacing rename,
+/// that is the last link of it is removed.
+fn is_deleted(inode: &Arc<dyn INode>) -> bool {
+    inode
+        .metadata()
+        .map_or(true, |info| info.type_ == FileType::Dir || info.nlinks == 0)
+}
+
+/// Report the creation of `inode` as `name` in the directory `dir`.
+pub fn fsnotify_create(dir: &Arc<dyn INode>, name: &str, inode: &Arc<dyn INode>) {
+    if !watching() {
+        return;
+    }
+    let dir_key = match inode_key(dir) {
+        Some(dir_key) => dir_key,
+        None => return,
+    };
+    let mask = event_mask(inode, InotifyMask::CREATE);
+    let mut watches = WATCHES.lock();
+    watches.queue(dir_key, mask, 0, Some(name));
+    // only the regular files and directories can be watched
+    if let Some(key) = inode_key(inode) {
+        watches.add_entry(key, dir_key, name);
+    }
+}
+
+/// Report the deletion of `inode` named `name` from the directory `dir`.
+pub fn fsnotify_delete(dir: &Arc<dyn INode>, name: &str, inode: &Arc<dyn INode>) {
+    if !watching() {
+        return;
+    }
+    let dir_key = match inode_key(dir) {
+        Some(dir_key) => dir_key,
+        None => return,
+    };
+    let mask = event_mask(inode, InotifyMask::DELETE);
+    let mut watches = WATCHES.lock();
+    watches.queue(dir_key, mask, 0, Some(name));
+    let key = match inode_key(inode) {
+        Some(key) => key,
+        None => return,
+    };
+    watches.remove_entry(key, dir_key, name);
+    if is_deleted(inode) {
+        watches.queue(key, InotifyMask::DELETE_SELF, 0, None);
+        watches.remove_all(key);
+    } else {
+        // the number of links is changed
+        watches.queue_with_dirs(key, InotifyMask::ATTRIB);
+    }
+}
+
+/// Report the rename of `inode` from `old_name` in the directory `old_dir`
+/// to `new_name` in the directory `new_dir`, which replaces the file `replaced`.
+///
+/// The `MOVED_FROM` and `MOVED_TO` events are related by a unique cookie.
+pub fn fsnotify_move(
+    old_dir: &Arc<dyn INode>,
+    old_name: &str,
+    new_dir: &Arc<dyn INode>,
+    new_name: &str,
+    inode: &Arc<dyn INode>,
+    replaced: Option<&Arc<dyn INode>>,
+) {
+    if !watching() {
+        return;
+    }
+    let (old_key, new_key) = match (inode_key(old_dir), inode_key(new_dir)) {
+        (Some(old_key), Some(new_key)) => (old_key, new_key),
+        _ => return,
+    };
+    let cookie = RENAME_COOKIE.fetch_add(1, Ordering::Relaxed) + 1;
+    let replaced = replaced.and_then(|inode| Some((inode_key(inode)?, is_deleted(inode))));
+    let mut watches = WATCHES.lock();
+    if let Some((replaced_key, deleted)) = replaced {
+        watches.remove_entry(replaced_key, new_key, new_name);
+        if deleted {
+            watches.queue(replaced_key, InotifyMask::DELETE_SELF, 0, None);
+            watches.remove_all(replaced_key);
+        }
+    }
+    let from = event_mask(inode, InotifyMask::MOVED_FROM);
+    let to = event_mask(inode, InotifyMask::MOVED_TO);
+    watches.queue(old_key, from, cookie, Some(old_name));
+    watches.queue(new_key, to, cookie, Some(new_name));
+    if let Some(key) = inode_key(inode) {
+        watches.queue(key, InotifyMask::MOVE_SELF, 0, None);
+        watches.remove_entry(key, old_key, old_name);
+        watches.add_entry(key, new_key, new_name);
+    }
+}
+
+/// Report the modification of the content of `inode`.
+pub fn fsnotify_modify(inode: &Arc<dyn INode>) {
+    fsnotify(inode, InotifyMask::MODIFY);
+}
+
+/// Report the change of the metadata of `inode`.
+pub fn fsnotify_attrib(inode: &Arc<dyn INode>) {
+    fsnotify(inode, InotifyMask::ATTRIB);
+}
+
+/// Report the close of `inode`, which is opened for writing if `writable`.
+pub(super) fn fsnotify_close(inode: &Arc<dyn INode>, writable: bool) {
+    if writable {
+        fsnotify(inode, InotifyMask::CLOSE_WRITE);
+    } else {
+        fsnotify(inode, InotifyMask::CLOSE_NOWRITE);
+    }
+}
+
+/// Report the event `mask` of `inode` to the watches on it and on its directories.
+fn fsnotify(inode: &Arc<dyn INode>, mask: InotifyMask) {
+    if !watching() {
+        return;
+    }
+    if let Some(key) = inode_key(inode) {
+        let mask = event_mask(inode, mask);
+        WATCHES.lock().queue_with_dirs(key, mask);
+    }
+}
+
+/// An event of inotify
+#[derive(PartialEq)]
+struct InotifyEvent {
+    wd: i32,
+    mask: InotifyMask,
+    cookie: u32,
+    name: Option<String>,
+}
+
+impl InotifyEvent {
+    /// The length of the name with the terminating null byte,
+    /// padded to the alignment of the events
+    fn name_len(&self) -> usize {
+        match &self.name {
+            Some(name) => (name.len() + 1 + EVENT_HEADER_SIZE - 1) & !(EVENT_HEADER_SIZE - 1),
+            None => 0,
+        }
+    }
+
+    /// The size of `struct inotify_event` with the name
+    fn size(&self) -> usize {
+        EVENT_HEADER_SIZE + self.name_len()
+    }
+
+    /// Write the `struct inotify_event` to `buf`, which is large enough.
+    fn write_to(&self, buf: &mut [u8]) {
+        let name_len = self.name_len();
+        buf[0..4].copy_from_slice(&self.wd.to_ne_bytes());
+        buf[4..8].copy_from_slice(&self.mask.bits().to_ne_bytes());
+        buf[8..12].copy_from_slice(&self.cookie.to_ne_bytes());
+        buf[12..16].copy_from_slice(&(name_len as u32).to_ne_bytes());
+        let name_buf = &mut buf[EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + name_len];
+        name_buf.fill(0);
+        if let Some(name) = &self.name {
+            name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        }
+    }
+}
+
+/// The mutable part of an inotify instance
+struct InotifyInner {
+    /// the watched files by the watch descriptors
+    watches: BTreeMap<i32, INodeKey>,
+    /// the next watch descriptor
+    next_wd: i32,
+    /// the queue of events to read
+    events: VecDeque<InotifyEvent>,
+}
+
+/// An inotify instance, a file to read the events of the watched files
+pub struct Inotify {
+    /// Kernel object base
+    base: KObjectBase,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+    /// the watches and the events
+    inner: Mutex<InotifyInner>,
+    /// event bus to wake up readers
+    eventbus: Arc<Mutex<EventBus>>,
+    /// the instance itself, which the duplicated files refer to,
+    /// as the watches know the instance by its address
+    me: Weak<Inotify>,
+}
+
+impl_kobject!(Inotify);
+
+impl Inotify {
+    /// Create an inotify instance
+    pub fn new(flags: InotifyFlags) -> Arc<Self> {
+        let mut open_flags = OpenFlags::RDONLY;
+        open_flags.set(
+            OpenFlags::NON_BLOCK,
+            flags.contains(InotifyFlags::NON_BLOCK),
+        );
+        open_flags.set(OpenFlags::CLOEXEC, flags.contains(InotifyFlags::CLOEXEC));
+        Arc::new_cyclic(|me| Inotify {
+            base: KObjectBase::new(),
+            flags: Mutex::new(open_flags),
+            inner: Mutex::new(InotifyInner {
+                watches: BTreeMap::new(),
+                next_wd: 1,
+                events: VecDeque::new(),
+            }),
+            eventbus: EventBus::new(),
+            me: me.clone(),
+        })
+    }
+
+    /// Watch the events in `mask` on `inode`, or modify the existing watch on it.
+    ///
+    /// Returns the watch descriptor.
+    pub fn add_watch(self: &Arc<Self>, inode: &Arc<dyn INode>, mask: InotifyMask) -> LxResult<i32> {
+        if !mask.intersects(InotifyMask::ALL_EVENTS)
+            || mask.contains(InotifyMask::MASK_ADD | InotifyMask::MASK_CREATE)
+        {
+            return Err(LxError::EINVAL);
+        }
+        let info = inode.metadata()?;
+        if mask.contains(InotifyMask::ONLYDIR) && info.type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        // only the regular files and directories know their file system
+        let key = inode_key(inode).ok_or(LxError::EINVAL)?;
+        let stored =
+            mask & (InotifyMask::ALL_EVENTS | InotifyMask::EXCL_UNLINK | InotifyMask::ONESHOT);
+        let mut watches = WATCHES.lock();
+        if let Some(wd) = watches.wd_of(key, self) {
+            if mask.contains(InotifyMask::MASK_CREATE) {
+                return Err(LxError::EEXIST);
+            }
+            let watch = watches
+                .watches
+                .get_mut(&key)
+                .and_then(|watches| watches.iter_mut().find(|watch| watch.wd == wd))
+                .unwrap();
+            if mask.contains(InotifyMask::MASK_ADD) {
+                watch.mask |= stored;
+            } else {
+                watch.mask = stored;
+            }
+            return Ok(wd);
+        }
+        let wd = {
+            let mut inner = self.inner.lock();
+            let wd = inner.next_wd;
+            inner.next_wd += 1;
+            inner.watches.insert(wd, key);
+            wd
+        };
+        let first = !watches.watches.contains_key(&key);
+        watches.watches.entry(key).or_default().push(Watch {
+            inotify: Arc::downgrade(self),
+            wd,
+            mask: stored,
+        });
+        if first && info.type_ == FileType::Dir {
+            watches.scan(inode, key);
+        }
+        Ok(wd)
+    }
+
+    /// Remove the watch `wd`, an `IGNORED` event is queued.
+    pub fn rm_watch(&self, wd: i32) -> LxResult {
+        let mut watches = WATCHES.lock();
+        let key = *self.inner.lock().watches.get(&wd).ok_or(LxError::EINVAL)?;
+        watches.remove(key, self, wd);
+        Ok(())
+    }
+
+    /// Queue an event, merged with the last one if they are the same.
+    ///
+    /// An overflow event is queued instead if the queue is full.
+    fn push(&self, event: InotifyEvent) {
+        let mut inner = self.inner.lock();
+        if inner.events.back() == Some(&event) {
+            return;
+        }
+        if inner.events.len() >= MAX_QUEUED_EVENTS {
+            let overflowed =
+                matches!(inner.events.back(), Some(last) if last.mask == InotifyMask::Q_OVERFLOW);
+            if !overflowed {
+                inner.events.push_back(InotifyEvent {
+                    wd: -1,
+                    mask: InotifyMask::Q_OVERFLOW,
+                    cookie: 0,
+                    name: None,
+                });
+            }
+            return;
+        }
+        inner.events.push_back(event);
+        self.eventbus.lock().set(Event::READABLE);
+    }
+
+    /// Take the events fitting in `buf`, returns `EAGAIN` if there is no event.
+    fn try_read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        let mut inner = self.inner.lock();
+        let first = inner.events.front().ok_or(LxError::EAGAIN)?;
+        if first.size() > buf.len() {
+            return Err(LxError::EINVAL);
+        }
+        let mut len = 0;
+        while let Some(event) = inner.events.front() {
+            let size = event.size();
+            if len + size > buf.len() {
+                break;
+            }
+            event.write_to(&mut buf[len..len + size]);
+            len += size;
+            inner.events.pop_front();
+        }
+        if inner.events.is_empty() {
+            self.eventbus.lock().clear(Event::READABLE);
+        }
+        Ok(len)
+    }
+
+    fn status(&self) -> PollStatus {
+        PollStatus {
+            read: !self.inner.lock().events.is_empty(),
+            write: false,
+            error: false,
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let keys: Vec<INodeKey> = self.inner.lock().watches.values().copied().collect();
+        let mut watches = WATCHES.lock();
+        for key in keys {
+            watches.detach(key, self);
+        }
+    }
+}
+
+#[async_trait]
+impl FileLike for Inotify {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        self.me.upgrade().unwrap()
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        loop {
+            match self.try_read(buf) {
+                Err(LxError::EAGAIN) if !self.flags().contains(OpenFlags::NON_BLOCK) => {
+                    wait_for_event(self.eventbus.clone(), Event::READABLE).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(self.status())
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct InotifyFuture<'a> {
+            inotify: &'a Inotify,
+            events: PollEvents,
+        }
+
+        impl<'a> Future for InotifyFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // hold the queue to avoid missing a notification
+                let inner = self.inotify.inner.lock();
+                if !inner.events.is_empty() && self.events.contains(PollEvents::IN) {
+                    drop(inner);
+                    return Poll::Ready(Ok(self.inotify.status()));
+                }
+                let waker = cx.waker().clone();
+                self.inotify.eventbus.lock().subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        InotifyFuture {
+            inotify: self,
+            events,
+        }
+        .await
+    }
+
+    fn ioctl(&self, request: usize, arg1: usize, _arg2: usize, _arg3: usize) -> LxResult<usize> {
+        match request {
+            FIONREAD => {
+                let inner = self.inner.lock();
+                let len: usize = inner.events.iter().map(InotifyEvent::size).sum();
+                UserOutPtr::<i32>::from(arg1).write(len as i32)?;
+                Ok(0)
+            }
+            _ => Err(LxError::ENOTTY),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::File;
+    use rcore_fs::vfs::FileSystem;
+    use rcore_fs_ramfs::RamFS;
+
+    /// Parse the events read from an instance as `(wd, mask, cookie, name)`
+    fn parse(mut buf: &[u8]) -> Vec<(i32, InotifyMask, u32, String)> {
+        let field = |buf: &[u8], i: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&buf[i..i + 4]);
+            bytes
+        };
+        let mut events = Vec::new();
+        while !buf.is_empty() {
+            let wd = i32::from_ne_bytes(field(buf, 0));
+            let mask = InotifyMask::from_bits_truncate(u32::from_ne_bytes(field(buf, 4)));
+            let cookie = u32::from_ne_bytes(field(buf, 8));
+            let len = u32::from_ne_bytes(field(buf, 12)) as usize;
+            assert_eq!(len % EVENT_HEADER_SIZE, 0);
+            let name = &buf[EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + len];
+            let end = name.iter().position(|&b| b == 0).unwrap_or(len);
+            assert!(name[end..].iter().all(|&b| b == 0));
+            events.push((
+                wd,
+                mask,
+                cookie,
+                String::from_utf8(name[..end].to_vec()).unwrap(),
+            ));
+            buf = &buf[EVENT_HEADER_SIZE + len..];
+        }
+        events
+    }
+
+    async fn read_events(inotify: &Inotify) -> Vec<(i32, InotifyMask, u32, String)> {
+        let mut buf = [0; 1024];
+        match inotify.read(&mut buf).await {
+            Ok(len) => parse(&buf[..len]),
+            Err(LxError::EAGAIN) => Vec::new(),
+            Err(e) => panic!("failed to read events: {:?}", e),
+        }
+    }
+
+    fn name(name: &str) -> String {
+        String::from(name)
+    }
+
+    #[async_std::test]
+    async fn encoding() {
+        let fs = RamFS::new();
+        let root = fs.root_inode();
+        let inotify = Inotify::new(InotifyFlags::NON_BLOCK);
+        let dir_wd = inotify.add_watch(&root, InotifyMask::ALL_EVENTS).unwrap();
+
+        let file = root
+            .create("a-long-file-name", FileType::File, 0o644)
+            .unwrap();
+        fsnotify_create(&root, "a-long-file-name", &file);
+        let sub = root.create("d", FileType::Dir, 0o755).unwrap();
+        fsnotify_create(&root, "d", &sub);
+        // the same events in a row are merged
+        fsnotify_modify(&file);
+        fsnotify_modify(&file);
+        let file_wd = inotify.add_watch(&file, InotifyMask::MODIFY).unwrap();
+        fsnotify_modify(&file);
+
+        let create = InotifyMask::CREATE;
+        let modify = InotifyMask::MODIFY;
+        assert_eq!(
+            read_events(&inotify).await,
+            [
+                (dir_wd, create, 0, name("a-long-file-name")),
+                (dir_wd, create | InotifyMask::ISDIR, 0, name("d")),
+                (dir_wd, modify, 0, name("a-long-file-name")),
+                (file_wd, modify, 0, name("")),
+                (dir_wd, modify, 0, name("a-long-file-name")),
+            ]
+        );
+        assert!(read_events(&inotify).await.is_empty());
+
+        // a buffer too small for the first event
+        fsnotify_modify(&sub);
+        let mut buf = [0; EVENT_HEADER_SIZE + 1];
+        assert!(matches!(inotify.read(&mut buf).await, Err(LxError::EINVAL)));
+        assert_eq!(
+            read_events(&inotify).await,
+            [(dir_wd, modify | InotifyMask::ISDIR, 0, name("d"))]
+        );
+    }
+
+    #[async_std::test]
+    async fn rename_and_delete() {
+        let fs = RamFS::new();
+        let root = fs.root_inode();
+        let inotify = Inotify::new(InotifyFlags::NON_BLOCK);
+        let file = root.create("a", FileType::File, 0o644).unwrap();
+        let dir_wd = inotify.add_watch(&root, InotifyMask::ALL_EVENTS).unwrap();
+        let file_wd = inotify
+            .add_watch(&file, InotifyMask::MOVE_SELF | InotifyMask::DELETE_SELF)
+            .unwrap();
+
+        root.move_("a", &root, "b").unwrap();
+        fsnotify_move(&root, "a", &root, "b", &file, None);
+        let events = read_events(&inotify).await;
+        assert_eq!(events.len(), 3);
+        let cookie = events[0].2;
+        assert_ne!(cookie, 0);
+        assert_eq!(
+            events,
+            [
+                (dir_wd, InotifyMask::MOVED_FROM, cookie, name("a")),
+                (dir_wd, InotifyMask::MOVED_TO, cookie, name("b")),
+                (file_wd, InotifyMask::MOVE_SELF, 0, name("")),
+            ]
+        );
+
+        // the events of the file are reported with its new name
+        fsnotify_attrib(&file);
+        assert_eq!(
+            read_events(&inotify).await,
+            [(dir_wd, InotifyMask::ATTRIB, 0, name("b"))]
+        );
+
+        root.unlink("b").unwrap();
+        fsnotify_delete(&root, "b", &file);
+        assert_eq!(
+            read_events(&inotify).await,
+            [
+                (dir_wd, InotifyMask::DELETE, 0, name("b")),
+                (file_wd, InotifyMask::DELETE_SELF, 0, name("")),
+                (file_wd, InotifyMask::IGNORED, 0, name("")),
+            ]
+        );
+        assert!(matches!(inotify.rm_watch(file_wd), Err(LxError::EINVAL)));
+        inotify.rm_watch(dir_wd).unwrap();
+        assert_eq!(
+            read_events(&inotify).await,
+            [(dir_wd, InotifyMask::IGNORED, 0, name(""))]
+        );
+    }
+
+    #[async_std::test]
+    async fn overflow() {
+        let inotify = Inotify::new(InotifyFlags::NON_BLOCK);
+        for cookie in 0..MAX_QUEUED_EVENTS as u32 + 10 {
+            inotify.push(InotifyEvent {
+                wd: 1,
+                mask: InotifyMask::MODIFY,
+                cookie,
+                name: None,
+            });
+        }
+        {
+            let inner = inotify.inner.lock();
+            assert_eq!(inner.events.len(), MAX_QUEUED_EVENTS + 1);
+            let last = inner.events.back().unwrap();
+            assert!(last.wd == -1 && last.mask == InotifyMask::Q_OVERFLOW);
+        }
+
+        let mut buf = vec![0; MAX_QUEUED_EVENTS * EVENT_HEADER_SIZE];
+        let len = inotify.read(&mut buf).await.unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(
+            read_events(&inotify).await,
+            [(-1, InotifyMask::Q_OVERFLOW, 0, name(""))]
+        );
+        assert!(!inotify.status().read);
+    }
+
+    #[async_std::test]
+    async fn dup() {
+        let fs = RamFS::new();
+        let root = fs.root_inode();
+        let inotify = Inotify::new(InotifyFlags::NON_BLOCK);
+        let dup = inotify.dup();
+        let file = root.create("a", FileType::File, 0o644).unwrap();
+        let wd = inotify.add_watch(&file, InotifyMask::ATTRIB).unwrap();
+
+        // both files read the same queue
+        fsnotify_attrib(&file);
+        assert!(dup.poll(PollEvents::IN).unwrap().read);
+        let dup = dup.downcast_ref::<Inotify>().unwrap();
+        assert_eq!(
+            read_events(dup).await,
+            [(wd, InotifyMask::ATTRIB, 0, name(""))]
+        );
+        assert!(read_events(&inotify).await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn close_dup_file() {
+        let fs = RamFS::new();
+        let root = fs.root_inode();
+        let inotify = Inotify::new(InotifyFlags::NON_BLOCK);
+        let inode = root.create("a", FileType::File, 0o644).unwrap();
+        let mask = InotifyMask::CLOSE_WRITE | InotifyMask::CLOSE_NOWRITE;
+        let wd = inotify.add_watch(&inode, mask).unwrap();
+
+        let file = File::new(inode, OpenFlags::RDWR, String::from("/a"));
+        let dup = file.dup();
+        // the description is still open through the duplicate
+        drop(file);
+        assert!(read_events(&inotify).await.is_empty());
+        drop(dup);
+        assert_eq!(
+            read_events(&inotify).await,
+            [(wd, InotifyMask::CLOSE_WRITE, 0, name(""))]
+        );
+    }
+}
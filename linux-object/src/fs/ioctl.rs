@@ -40,3 +40,23 @@ pub const FIOCLEX: usize = 0x6601;
 // rustc using pipe and ioctl pipe file with this request id
 // for non-blocking/blocking IO control setting
 pub const FIONBIO: usize = 0x5421;
+
+// terminals, see `ioctl_tty(2)`
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSW: usize = 0x5403;
+pub const TCSETSF: usize = 0x5404;
+pub const TCSBRK: usize = 0x5409;
+pub const TCXONC: usize = 0x540A;
+pub const TCFLSH: usize = 0x540B;
+pub const TIOCSCTTY: usize = 0x540E;
+pub const TIOCOUTQ: usize = 0x5411;
+pub const TIOCSWINSZ: usize = 0x5414;
+pub const FIONREAD: usize = 0x541B;
+pub const TIOCNOTTY: usize = 0x5422;
+pub const TIOCGSID: usize = 0x5429;
+// _IOR('T', 0x30, unsigned int)
+pub const TIOCGPTN: usize = 0x8004_5430;
+// _IOW('T', 0x31, int)
+pub const TIOCSPTLCK: usize = 0x4004_5431;
+// _IOR('T', 0x39, int)
+pub const TIOCGPTLCK: usize = 0x8004_5439;
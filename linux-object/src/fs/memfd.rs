@@ -0,0 +1,378 @@
+//! Anonymous memory files created by `memfd_create`
+#![deny(missing_docs)]
+
+use alloc::{boxed::Box, string::String, sync::Arc};
+
+use async_trait::async_trait;
+use lock::Mutex;
+use rcore_fs::vfs::{FileType, Metadata, PollStatus, Timespec};
+use zircon_object::object::*;
+use zircon_object::vm::{roundup_pages, VmObject, PAGE_SIZE};
+
+use super::{FileLike, OpenFlags, PollEvents, SeekFrom};
+use crate::error::{LxError, LxResult};
+
+bitflags::bitflags! {
+    /// Flags of `memfd_create`
+    pub struct MemFdFlags: usize {
+        /// Set the close-on-exec flag
+        const CLOEXEC = 1;
+        /// Allow sealing operations on the file
+        const ALLOW_SEALING = 2;
+        /// Create the file in the hugetlbfs, not supported
+        const HUGETLB = 4;
+    }
+}
+
+bitflags::bitflags! {
+    /// Seals of a memfd, set by `F_ADD_SEALS` of `fcntl`
+    pub struct Seals: u32 {
+        /// Prevent further seals from being set
+        const SEAL = 1;
+        /// Prevent the file from shrinking
+        const SHRINK = 2;
+        /// Prevent the file from growing
+        const GROW = 4;
+        /// Prevent writes to the file
+        const WRITE = 8;
+        /// Prevent future writes, while the existing shared writable mappings are kept
+        const FUTURE_WRITE = 0x10;
+    }
+}
+
+/// The longest name of a memfd, excluding the terminating null byte
+pub const MFD_NAME_MAX: usize = 249;
+
+/// The content of a memfd, shared by the duplicated file descriptors
+struct MemFdData {
+    /// the name given to `memfd_create`, for debugging
+    name: String,
+    /// the memory, resized by page
+    vmo: Arc<VmObject>,
+    /// size and seals of the file
+    state: Mutex<MemFdState>,
+}
+
+struct MemFdState {
+    /// size of the file in bytes
+    size: usize,
+    /// seals added to the file
+    seals: Seals,
+}
+
+/// Anonymous file living in memory, backed by a resizable paged VMO
+///
+/// The VMO is mapped directly by `mmap`, so the shared mappings and `read`/`write`
+/// see the same memory.
+pub struct MemFd {
+    /// Kernel object base
+    base: KObjectBase,
+    /// the content
+    data: Arc<MemFdData>,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+    /// offset of read and write
+    offset: Mutex<u64>,
+}
+
+impl_kobject!(MemFd);
+
+impl MemFd {
+    /// Create an empty memfd with `name`
+    pub fn new(name: &str, flags: MemFdFlags) -> Arc<Self> {
+        let vmo = VmObject::new_paged_with_resizable(true, 0);
+        let data = Arc::new(MemFdData {
+            name: String::from(name),
+            vmo,
+            state: Mutex::new(MemFdState {
+                size: 0,
+                seals: if flags.contains(MemFdFlags::ALLOW_SEALING) {
+                    Seals::empty()
+                } else {
+                    Seals::SEAL
+                },
+            }),
+        });
+        let mut open_flags = OpenFlags::RDWR;
+        open_flags.set(OpenFlags::CLOEXEC, flags.contains(MemFdFlags::CLOEXEC));
+        let memfd = Arc::new(MemFd {
+            base: KObjectBase::new(),
+            data,
+            flags: Mutex::new(open_flags),
+            offset: Mutex::new(0),
+        });
+        // shown in `/proc/[pid]/maps`
+        memfd.data.vmo.set_name(&memfd.path());
+        memfd
+    }
+
+    /// The path shown in `/proc/[pid]/fd`
+    pub fn path(&self) -> String {
+        format!("/memfd:{} (deleted)", self.data.name)
+    }
+
+    /// The VMO holding the content
+    pub fn vmo(&self) -> &Arc<VmObject> {
+        &self.data.vmo
+    }
+
+    /// The seals of the file
+    pub fn seals(&self) -> Seals {
+        self.data.state.lock().seals
+    }
+
+    /// Add `seals` to the file
+    ///
+    /// `WRITE` can not be added while the file is mapped shared, since the
+    /// writable mappings can not be told from the read-only ones.
+    pub fn add_seals(&self, seals: Seals) -> LxResult {
+        if !self.flags().writable() {
+            return Err(LxError::EPERM);
+        }
+        let mut state = self.data.state.lock();
+        if state.seals.contains(Seals::SEAL) {
+            return Err(LxError::EPERM);
+        }
+        if seals.contains(Seals::WRITE) && self.data.vmo.share_count() > 0 {
+            return Err(LxError::EBUSY);
+        }
+        state.seals |= seals;
+        Ok(())
+    }
+
+    /// Whether the file can be mapped shared and writable
+    pub fn check_mmap_write(&self) -> LxResult {
+        if !self.flags().writable() {
+            return Err(LxError::EACCES);
+        }
+        if self.seals().intersects(Seals::WRITE | Seals::FUTURE_WRITE) {
+            return Err(LxError::EPERM);
+        }
+        Ok(())
+    }
+
+    /// Resize the file, the content past the new end is dropped
+    pub fn set_len(&self, len: u64) -> LxResult {
+        if !self.flags().writable() {
+            return Err(LxError::EINVAL);
+        }
+        let len = len as usize;
+        let mut state = self.data.state.lock();
+        if (len < state.size && state.seals.contains(Seals::SHRINK))
+            || (len > state.size && state.seals.contains(Seals::GROW))
+        {
+            return Err(LxError::EPERM);
+        }
+        self.resize(&mut state, len)
+    }
+
+    /// Change the size of the file and the VMO, must be called with the state locked
+    fn resize(&self, state: &mut MemFdState, len: usize) -> LxResult {
+        let vmo = &self.data.vmo;
+        let new_len = roundup_pages(len);
+        if new_len != vmo.len() {
+            vmo.set_len(new_len).map_err(|_| LxError::ENOMEM)?;
+        }
+        // the bytes after the end in the last page must read as zero when grown again
+        if len < state.size && len % PAGE_SIZE != 0 {
+            vmo.zero(len, new_len - len)?;
+        }
+        state.size = len;
+        Ok(())
+    }
+
+    /// Read the content at `offset`, stops at the end of the file
+    fn read_content(&self, offset: usize, buf: &mut [u8]) -> LxResult<usize> {
+        let state = self.data.state.lock();
+        if offset >= state.size {
+            return Ok(0);
+        }
+        let len = buf.len().min(state.size - offset);
+        self.data.vmo.read(offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    /// seek from given type and offset
+    pub fn seek(&self, pos: SeekFrom) -> LxResult<u64> {
+        let mut offset = self.offset.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => self.data.state.lock().size as i64 + offset,
+            SeekFrom::Current(delta) => *offset as i64 + delta,
+        };
+        if new_offset < 0 {
+            return Err(LxError::EINVAL);
+        }
+        *offset = new_offset as u64;
+        Ok(*offset)
+    }
+
+    /// get metadata of file
+    pub fn metadata(&self) -> Metadata {
+        let size = self.data.state.lock().size;
+        Metadata {
+            dev: 0,
+            inode: self.data.vmo.id() as usize,
+            size,
+            blk_size: PAGE_SIZE,
+            blocks: roundup_pages(size) / 512,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::File,
+            mode: 0o777,
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl FileLike for MemFd {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::APPEND, f.contains(OpenFlags::APPEND));
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(MemFd {
+            base: KObjectBase::new(),
+            data: self.data.clone(),
+            flags: Mutex::new(self.flags()),
+            offset: Mutex::new(*self.offset.lock()),
+        })
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        let mut offset = self.offset.lock();
+        let len = self.read_content(*offset as usize, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        let mut offset = self.offset.lock();
+        if self.flags().is_append() {
+            *offset = self.data.state.lock().size as u64;
+        }
+        let len = self.write_at(*offset, buf)?;
+        *offset += len as u64;
+        Ok(len)
+    }
+
+    async fn read_at(&self, offset: u64, buf: &mut [u8]) -> LxResult<usize> {
+        self.read_content(offset as usize, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> LxResult<usize> {
+        let mut state = self.data.state.lock();
+        if state.seals.intersects(Seals::WRITE | Seals::FUTURE_WRITE) {
+            return Err(LxError::EPERM);
+        }
+        let offset = offset as usize;
+        let end = offset.checked_add(buf.len()).ok_or(LxError::EFBIG)?;
+        if end > state.size {
+            if state.seals.contains(Seals::GROW) {
+                return Err(LxError::EPERM);
+            }
+            self.resize(&mut state, end)?;
+        }
+        self.data.vmo.write(offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        self.poll(events)
+    }
+
+    /// Returns the whole VMO of the file, which is mapped from `offset` directly.
+    fn get_vmo(&self, offset: usize, _len: usize) -> LxResult<Arc<VmObject>> {
+        if offset % PAGE_SIZE != 0 {
+            return Err(LxError::EINVAL);
+        }
+        Ok(self.data.vmo.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zircon_object::vm::{MMUFlags, VmAddressRegion};
+
+    fn create() -> Arc<MemFd> {
+        let memfd = MemFd::new("test", MemFdFlags::ALLOW_SEALING);
+        memfd.set_len(PAGE_SIZE as u64).unwrap();
+        memfd
+    }
+
+    #[async_std::test]
+    async fn seal_write() {
+        let memfd = create();
+        let vmar = VmAddressRegion::new_root();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let addr = vmar
+            .map_shared(None, memfd.vmo().clone(), 0, PAGE_SIZE, flags)
+            .unwrap();
+        // the shared mapping may be writable
+        assert!(matches!(memfd.add_seals(Seals::WRITE), Err(LxError::EBUSY)));
+        vmar.unmap(addr, PAGE_SIZE).unwrap();
+
+        memfd.add_seals(Seals::WRITE).unwrap();
+        assert!(matches!(memfd.write_at(0, &[1]), Err(LxError::EPERM)));
+        assert!(matches!(memfd.check_mmap_write(), Err(LxError::EPERM)));
+        let mut buf = [1; 4];
+        assert_eq!(memfd.read_at(0, &mut buf).await.unwrap(), 4);
+        assert_eq!(buf, [0; 4]);
+    }
+
+    #[test]
+    fn seal_size() {
+        let memfd = create();
+        memfd.add_seals(Seals::GROW).unwrap();
+        assert!(matches!(
+            memfd.set_len(2 * PAGE_SIZE as u64),
+            Err(LxError::EPERM)
+        ));
+        // writing past the end grows the file as well
+        assert!(matches!(
+            memfd.write_at(PAGE_SIZE as u64, &[1]),
+            Err(LxError::EPERM)
+        ));
+        memfd.set_len(1).unwrap();
+
+        memfd.add_seals(Seals::SHRINK).unwrap();
+        assert!(matches!(memfd.set_len(0), Err(LxError::EPERM)));
+        memfd.set_len(1).unwrap();
+        assert_eq!(memfd.metadata().size, 1);
+    }
+
+    #[test]
+    fn sealed() {
+        let memfd = create();
+        memfd.add_seals(Seals::SEAL).unwrap();
+        assert!(matches!(memfd.add_seals(Seals::GROW), Err(LxError::EPERM)));
+        assert_eq!(memfd.seals(), Seals::SEAL);
+
+        // sealing is not allowed without `MFD_ALLOW_SEALING`
+        let memfd = MemFd::new("test", MemFdFlags::empty());
+        assert!(matches!(memfd.add_seals(Seals::WRITE), Err(LxError::EPERM)));
+        memfd.write_at(0, &[1]).unwrap();
+    }
+}
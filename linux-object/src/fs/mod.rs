@@ -1,40 +1,73 @@
 //! Linux file objects
 
 mod devfs;
+mod epoll;
+mod eventfd;
 mod file;
+mod file_lock;
+mod inotify;
 mod ioctl;
+mod memfd;
+mod mount;
+mod page_cache;
 mod pipe;
-mod pseudo;
+mod procfs;
+mod signalfd;
 mod stdio;
+mod sysfs;
+mod timerfd;
+mod tty;
 
 pub mod rcore_fs_wrapper;
 
-use alloc::{boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 use core::convert::TryFrom;
 
 use async_trait::async_trait;
 use downcast_rs::impl_downcast;
+use lazy_static::lazy_static;
 
 use kernel_hal::drivers;
-use rcore_fs::vfs::{FileSystem, FileType, INode, Result};
+use rcore_fs::vfs::{FileSystem, FileType, FsError, INode, Metadata, Result};
 use rcore_fs_devfs::{
     special::{NullINode, ZeroINode},
     DevFS,
 };
-use rcore_fs_mountfs::MountFS;
 use rcore_fs_ramfs::RamFS;
 use zircon_object::{object::KernelObject, vm::VmObject};
 
 use crate::error::{LxError, LxResult};
 use crate::net::Socket;
-use crate::process::LinuxProcess;
+use crate::process::{Credentials, LinuxProcess};
 use devfs::RandomINode;
-use pseudo::Pseudo;
 
+pub use devfs::BlockDev;
+pub use epoll::{EpollCtlOp, EpollEvent, EpollEvents, EpollInstance};
+pub use eventfd::{EventFd, EventFdFlags};
 pub use file::{File, OpenFlags, PollEvents, SeekFrom};
-pub use pipe::Pipe;
+pub use file_lock::{
+    release_posix_locks, release_process_locks, Flock, LockKind, F_RDLCK, F_UNLCK, F_WRLCK,
+};
+pub use inotify::{
+    fsnotify_attrib, fsnotify_create, fsnotify_delete, fsnotify_modify, fsnotify_move, Inotify,
+    InotifyFlags, InotifyMask,
+};
+pub use memfd::{MemFd, MemFdFlags, Seals, MFD_NAME_MAX};
+pub use mount::{find_mount, mounts_info, Mount, MountFlags, UmountFlags};
+pub use page_cache::{FileMapping, PageCache};
+pub use pipe::{read_pages, write_pages, Pipe, PipePage};
+pub use procfs::ProcFS;
 pub use rcore_fs::vfs::{self, PollStatus};
-pub use stdio::{STDIN, STDOUT};
+pub use signalfd::{SignalFd, SignalFdFlags, SignalFdSigInfo};
+pub use stdio::CONSOLE;
+pub use sysfs::SysFS;
+pub use timerfd::{TimerFd, TimerFdFlags, TimerSetFlags};
+pub use tty::{open_tty, DevPts, Termios, Tty, WinSize, DEVPTS};
 
 #[async_trait]
 /// Generic file interface
@@ -122,12 +155,13 @@ impl From<FileDesc> for i32 {
     }
 }
 
-/// create root filesystem, mount DevFS and RamFS
-pub fn create_root_fs(rootfs: Arc<dyn FileSystem>) -> Arc<dyn INode> {
-    let rootfs = MountFS::new(rootfs);
-    let root = rootfs.mountpoint_root_inode();
+lazy_static! {
+    /// The device file system, which is shared by all its mounts
+    pub static ref DEVFS: Arc<DevFS> = create_devfs();
+}
 
-    // create DevFS
+/// create DevFS with the devices from drivers
+fn create_devfs() -> Arc<DevFS> {
     let devfs = DevFS::new();
     let devfs_root = devfs.root();
     devfs_root
@@ -174,28 +208,94 @@ pub fn create_root_fs(rootfs: Arc<dyn FileSystem>) -> Arc<dyn INode> {
         }
     }
 
-    // Add uart devices at `/dev/ttyS{i}`
+    // Add the console at `/dev/console` and the terminals of uart devices at `/dev/ttyS{i}`,
+    // the first of which is the console
+    devfs_root
+        .add("console", CONSOLE.clone())
+        .expect("failed to mknod /dev/console");
     for (i, uart) in drivers::all_uart().as_vec().iter().enumerate() {
         let fname = format!("ttyS{}", i);
-        if let Err(e) = devfs_root.add(&fname, Arc::new(devfs::UartDev::new(i, uart.clone()))) {
+        let tty = if i == 0 {
+            CONSOLE.clone()
+        } else {
+            devfs::uart_tty(i, uart.clone())
+        };
+        if let Err(e) = devfs_root.add(&fname, tty) {
             warn!("failed to mknod /dev/{}: {:?}", &fname, e);
         }
     }
 
-    // mount DevFS at /dev
-    let dev = root.find(true, "dev").unwrap_or_else(|_| {
-        root.create("dev", FileType::Dir, 0o666)
-            .expect("failed to mkdir /dev")
-    });
-    dev.mount(devfs).expect("failed to mount DevFS");
+    // Add the controlling terminal at `/dev/tty`, and pseudo-terminals at `/dev/ptmx`,
+    // whose slaves are in devpts mounted at `/dev/pts`
+    devfs_root
+        .add("tty", Arc::new(tty::CurrentTty::new()))
+        .expect("failed to mknod /dev/tty");
+    devfs_root
+        .add("ptmx", DEVPTS.ptmx())
+        .expect("failed to mknod /dev/ptmx");
+    devfs_root.add_dir("pts").expect("failed to mkdir /dev/pts");
+
+    // Add block devices at `/dev/sd{a,b,...}`
+    for (i, block) in drivers::all_block().as_vec().iter().enumerate().take(26) {
+        let fname = format!("sd{}", (b'a' + i as u8) as char);
+        if let Err(e) = devfs_root.add(&fname, Arc::new(BlockDev::new(i, block.clone()))) {
+            warn!("failed to mknod /dev/{}: {:?}", &fname, e);
+        }
+    }
 
-    // mount RamFS at /tmp
-    let ramfs = RamFS::new();
-    let tmp = root.find(true, "tmp").unwrap_or_else(|_| {
-        root.create("tmp", FileType::Dir, 0o666)
-            .expect("failed to mkdir /tmp")
-    });
-    tmp.mount(ramfs).expect("failed to mount RamFS");
+    devfs
+}
+
+/// create root filesystem, mount DevFS at /dev, DevPts at /dev/pts, RamFS at /tmp,
+/// ProcFS at /proc and SysFS at /sys
+pub fn create_root_fs(rootfs: Arc<dyn FileSystem>) -> Arc<dyn INode> {
+    let root = rootfs.root_inode();
+    let root_mount = Mount::add(
+        rootfs,
+        root.clone(),
+        None,
+        "/",
+        "rootfs",
+        "rootfs",
+        MountFlags::empty(),
+    )
+    .expect("failed to mount the root file system");
+
+    let mount_at = |name: &str, mode: u32, fs: Arc<dyn FileSystem>, fstype: &str| {
+        let dir = root
+            .find(name)
+            .or_else(|_| root.create(name, FileType::Dir, mode))
+            .unwrap_or_else(|e| panic!("failed to mkdir /{}: {:?}", name, e));
+        let path = format!("/{}", name);
+        Mount::add(
+            fs.clone(),
+            fs.root_inode(),
+            Some((root_mount.clone(), dir)),
+            &path,
+            fstype,
+            fstype,
+            MountFlags::empty(),
+        )
+        .unwrap_or_else(|e| panic!("failed to mount {} at {}: {:?}", fstype, path, e))
+    };
+    let dev = mount_at("dev", 0o755, DEVFS.clone(), "devtmpfs");
+    let pts = DEVFS
+        .root_inode()
+        .find("pts")
+        .expect("failed to find /dev/pts");
+    Mount::add(
+        DEVPTS.clone(),
+        DEVPTS.root_inode(),
+        Some((dev, pts)),
+        "/dev/pts",
+        "devpts",
+        "devpts",
+        MountFlags::NOSUID | MountFlags::NOEXEC,
+    )
+    .expect("failed to mount devpts at /dev/pts");
+    mount_at("tmp", 0o1777, RamFS::new(), "tmpfs");
+    mount_at("proc", 0o555, ProcFS::new(), "proc");
+    mount_at("sys", 0o555, SysFS::new(), "sysfs");
 
     root
 }
@@ -219,7 +319,94 @@ impl INodeExt for dyn INode {
     }
 }
 
+bitflags::bitflags! {
+    /// Access permissions of a file, the same bits as the `mode` of `access`
+    pub struct Access: u32 {
+        /// execute a file or search a directory
+        const EXEC = 1;
+        /// write
+        const WRITE = 2;
+        /// read
+        const READ = 4;
+    }
+}
+
+/// set-user-ID bit of the file mode
+pub const S_ISUID: u32 = 0o4000;
+/// set-group-ID bit of the file mode
+pub const S_ISGID: u32 = 0o2000;
+
+/// Check whether a process with `cred` can access the file with `info` for `access`.
+///
+/// The owner, group or other permission bits are used depending on the effective
+/// IDs. Root can read and write any file, but execute only files with at least
+/// one execute bit set.
+pub fn check_permission(cred: &Credentials, info: &Metadata, access: Access) -> LxResult {
+    let mode = info.mode as u32;
+    if cred.is_root() {
+        if !access.contains(Access::EXEC) || info.type_ == FileType::Dir || mode & 0o111 != 0 {
+            return Ok(());
+        }
+        return Err(LxError::EACCES);
+    }
+    let perm = if cred.euid == info.uid as u32 {
+        mode >> 6
+    } else if cred.in_group(info.gid as u32) {
+        mode >> 3
+    } else {
+        mode
+    };
+    if Access::from_bits_truncate(perm).contains(access) {
+        Ok(())
+    } else {
+        Err(LxError::EACCES)
+    }
+}
+
 impl LinuxProcess {
+    /// Check whether the process can access `inode` for `access`.
+    pub fn check_access(&self, inode: &Arc<dyn INode>, access: Access) -> LxResult {
+        check_permission(&self.credentials(), &inode.metadata()?, access)
+    }
+
+    /// Create a file named `name` in directory `dir`, reached through `mount`.
+    ///
+    /// The process needs write and search permission of the directory, and the
+    /// mount must not be read-only. The file mode creation mask is applied to
+    /// `mode`, and the file is owned by the effective IDs of the process, or by
+    /// the group of the directory if it has the set-group-ID bit.
+    pub fn create_inode(
+        &self,
+        dir: &Arc<dyn INode>,
+        mount: &Mount,
+        name: &str,
+        type_: FileType,
+        mode: u32,
+    ) -> LxResult<Arc<dyn INode>> {
+        let cred = self.credentials();
+        let dir_info = dir.metadata()?;
+        check_permission(&cred, &dir_info, Access::WRITE | Access::EXEC)?;
+        mount.check_writable()?;
+        let mut mode = mode & 0o7777 & !self.umask();
+        let gid = if dir_info.mode as u32 & S_ISGID != 0 {
+            if type_ == FileType::Dir {
+                mode |= S_ISGID;
+            }
+            dir_info.gid
+        } else {
+            cred.egid as usize
+        };
+        let inode = dir.create(name, type_, mode)?;
+        let mut info = inode.metadata()?;
+        if info.uid != cred.euid as usize || info.gid != gid {
+            info.uid = cred.euid as usize;
+            info.gid = gid;
+            inode.set_metadata(&info)?;
+        }
+        fsnotify_create(dir, name, &inode);
+        Ok(inode)
+    }
+
     /// Lookup INode from the process.
     ///
     /// - If `path` is relative, then it is interpreted relative to the directory
@@ -237,37 +424,128 @@ impl LinuxProcess {
         path: &str,
         follow: bool,
     ) -> LxResult<Arc<dyn INode>> {
+        Ok(self.lookup_mount_at(dirfd, path, follow)?.0)
+    }
+
+    /// Same as `lookup_inode_at`, also returns the mount the INode is reached
+    /// through, whose flags apply to the INode.
+    pub fn lookup_mount_at(
+        &self,
+        dirfd: FileDesc,
+        path: &str,
+        follow: bool,
+    ) -> LxResult<(Arc<dyn INode>, Arc<Mount>)> {
         debug!(
-            "lookup_inode_at: dirfd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
+            "lookup_mount_at: dirfd: {:?}, cwd: {:?}, path: {:?}, follow: {:?}",
             dirfd,
             self.current_working_directory(),
             path,
             follow
         );
-        // hard code special path
-        if path == "/proc/self/exe" {
-            return Ok(Arc::new(Pseudo::new(
-                &self.execute_path(),
-                FileType::SymLink,
-            )));
-        }
-        let (fd_dir_path, fd_name) = split_path(path);
-        if fd_dir_path == "/proc/self/fd" {
-            let fd = FileDesc::try_from(fd_name)?;
-            let file = self.get_file(fd)?;
-            return Ok(Arc::new(Pseudo::new(file.path(), FileType::SymLink)));
-        }
-
-        let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == FileDesc::CWD {
-            Ok(self
-                .root_inode()
-                .lookup(&self.current_working_directory())?
-                .lookup_follow(path, follow_max_depth)?)
+        let (dir, mount) = if dirfd == FileDesc::CWD {
+            let cwd = self.current_working_directory();
+            let root = self.root_inode().clone();
+            self.lookup_follow(root, self.root_mount()?, &cwd, true)?
         } else {
             let file = self.get_file(dirfd)?;
-            Ok(file.lookup_follow(path, follow_max_depth)?)
+            let mount = match file.mount() {
+                Some(mount) => mount.clone(),
+                None => self.root_mount()?,
+            };
+            (file.inode(), mount)
+        };
+        self.lookup_follow(dir, mount, path, follow)
+    }
+
+    /// The root mount of the file system tree of the process
+    fn root_mount(&self) -> LxResult<Arc<Mount>> {
+        Mount::of_root(self.root_inode()).ok_or(LxError::ENOENT)
+    }
+
+    /// Walk `path` from directory `dir` of `mount`.
+    ///
+    /// Same as `INode::lookup_follow`, but the process needs search permission
+    /// of every directory in the path, and the walk crosses the mount points.
+    /// The symbolic links in the middle of the path are always followed, and the
+    /// last one only if `follow` is true.
+    fn lookup_follow(
+        &self,
+        dir: Arc<dyn INode>,
+        mount: Arc<Mount>,
+        path: &str,
+        follow: bool,
+    ) -> LxResult<(Arc<dyn INode>, Arc<Mount>)> {
+        if dir.metadata()?.type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        let cred = self.credentials();
+        let mut follow_times = 0;
+        let mut result = dir.find(".")?;
+        let mut mount = mount;
+        let mut rest_path = String::from(path);
+        while !rest_path.is_empty() {
+            let info = result.metadata()?;
+            if info.type_ != FileType::Dir {
+                return Err(LxError::ENOTDIR);
+            }
+            // handle absolute path
+            if let Some(rest) = rest_path.strip_prefix('/') {
+                result = self.root_inode().clone();
+                mount = self.root_mount()?;
+                rest_path = String::from(rest);
+                continue;
+            }
+            let name = match rest_path.find('/') {
+                None => core::mem::take(&mut rest_path),
+                Some(pos) => {
+                    let name = String::from(&rest_path[..pos]);
+                    rest_path = String::from(&rest_path[pos + 1..]);
+                    name
+                }
+            };
+            if name.is_empty() {
+                continue;
+            }
+            check_permission(&cred, &info, Access::EXEC)?;
+            if name == ".." {
+                // go back to the mount point from the root of a mount
+                while mount.is_root(&result) {
+                    match (mount.parent().cloned(), mount.mountpoint().cloned()) {
+                        (Some(parent), Some(mountpoint)) => {
+                            result = mountpoint;
+                            mount = parent;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            let (inode, inode_mount) = mount.enter(result.find(&name)?);
+            if inode.metadata()?.type_ == FileType::SymLink && (follow || !rest_path.is_empty()) {
+                follow_times += 1;
+                if follow_times > FOLLOW_MAX_DEPTH {
+                    return Err(LxError::ELOOP);
+                }
+                // the links of processes in procfs refer to the files directly
+                if let Some(target) = procfs::link_target(&inode) {
+                    if let Some(target_mount) = Mount::of_inode(&target) {
+                        mount = target_mount;
+                    }
+                    result = target;
+                    continue;
+                }
+                // `result` is unchanged, the link is relative to it
+                let mut new_path = read_link(&inode)?;
+                if !new_path.ends_with('/') {
+                    new_path.push('/');
+                }
+                new_path += &rest_path;
+                rest_path = new_path;
+            } else {
+                result = inode;
+                mount = inode_mount;
+            }
         }
+        Ok((result, mount))
     }
 
     /// Lookup INode from the process.
@@ -276,6 +554,13 @@ impl LinuxProcess {
     pub fn lookup_inode(&self, path: &str) -> LxResult<Arc<dyn INode>> {
         self.lookup_inode_at(FileDesc::CWD, path, true)
     }
+
+    /// Lookup INode and the mount it is reached through from the process.
+    ///
+    /// see `lookup_mount_at`
+    pub fn lookup_mount(&self, path: &str) -> LxResult<(Arc<dyn INode>, Arc<Mount>)> {
+        self.lookup_mount_at(FileDesc::CWD, path, true)
+    }
 }
 
 /// Split a `path` str to `(base_path, file_name)`
@@ -289,5 +574,124 @@ pub fn split_path(path: &str) -> (&str, &str) {
     (dir_path, file_name)
 }
 
-/// the max depth for following a link
-const FOLLOW_MAX_DEPTH: usize = 1;
+/// the max number of symbolic links followed in a path lookup
+const FOLLOW_MAX_DEPTH: usize = 40;
+
+/// the max length of a path, and of the target of a symbolic link
+const PATH_MAX: usize = 4096;
+
+/// Read the whole target of the symbolic link `inode`, until the end of file
+fn read_link(inode: &Arc<dyn INode>) -> LxResult<String> {
+    let mut content = vec![0u8; inode.metadata()?.size.clamp(1, PATH_MAX)];
+    let mut len = 0;
+    loop {
+        if len == content.len() {
+            if len >= PATH_MAX {
+                return Err(LxError::ENAMETOOLONG);
+            }
+            content.resize(len * 2, 0);
+        }
+        let read = inode.read_at(len, &mut content[len..])?;
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    content.truncate(len);
+    String::from_utf8(content).map_err(|_| FsError::NotDir.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcore_fs::vfs::Timespec;
+
+    fn metadata(type_: FileType, mode: u16, uid: usize, gid: usize) -> Metadata {
+        let time = Timespec { sec: 0, nsec: 0 };
+        Metadata {
+            dev: 0,
+            inode: 0,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            type_,
+            mode,
+            nlinks: 1,
+            uid,
+            gid,
+            rdev: 0,
+        }
+    }
+
+    fn cred(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            euid: uid,
+            suid: uid,
+            gid,
+            egid: gid,
+            sgid: gid,
+            fsuid: uid,
+            fsgid: gid,
+            groups: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn permission_bits() {
+        let file = metadata(FileType::File, 0o640, 1000, 100);
+        let check = |cred: &Credentials, access| check_permission(cred, &file, access).is_ok();
+        let owner = cred(1000, 1);
+        assert!(check(&owner, Access::READ | Access::WRITE));
+        assert!(!check(&owner, Access::EXEC));
+        let group = cred(2000, 100);
+        assert!(check(&group, Access::READ));
+        assert!(!check(&group, Access::WRITE));
+        let mut supplementary = cred(2000, 1);
+        supplementary.groups.push(100);
+        assert!(check(&supplementary, Access::READ));
+        assert!(!check(&cred(2000, 1), Access::READ));
+
+        // only the bits of the first matching class are used
+        let file = metadata(FileType::File, 0o077, 1000, 100);
+        assert!(check_permission(&owner, &file, Access::READ).is_err());
+        assert!(check_permission(&cred(2000, 1), &file, Access::READ).is_ok());
+    }
+
+    #[test]
+    fn root_permission() {
+        let root = cred(0, 0);
+        let file = metadata(FileType::File, 0o000, 1000, 100);
+        assert!(check_permission(&root, &file, Access::READ | Access::WRITE).is_ok());
+        // a file is executable by root only if any execute bit is set
+        assert!(check_permission(&root, &file, Access::EXEC).is_err());
+        let file = metadata(FileType::File, 0o001, 1000, 100);
+        assert!(check_permission(&root, &file, Access::EXEC).is_ok());
+        let dir = metadata(FileType::Dir, 0o000, 1000, 100);
+        assert!(check_permission(&root, &dir, Access::EXEC).is_ok());
+    }
+
+    #[test]
+    fn real_ids() {
+        // a set-user-ID and set-group-ID program of user 1000 run by user 2000
+        let mut cred = cred(2000, 200);
+        cred.euid = 1000;
+        cred.egid = 100;
+        let owner_only = metadata(FileType::File, 0o600, 1000, 1);
+        let group_only = metadata(FileType::File, 0o060, 1, 100);
+        let real_only = metadata(FileType::File, 0o600, 2000, 1);
+
+        // the effective IDs are checked with AT_EACCESS
+        assert!(check_permission(&cred, &owner_only, Access::READ).is_ok());
+        assert!(check_permission(&cred, &group_only, Access::READ).is_ok());
+        assert!(check_permission(&cred, &real_only, Access::READ).is_err());
+        // and the real IDs without it
+        let real = cred.real();
+        assert!(check_permission(&real, &owner_only, Access::READ).is_err());
+        assert!(check_permission(&real, &group_only, Access::READ).is_err());
+        assert!(check_permission(&real, &real_only, Access::READ).is_ok());
+    }
+}
@@ -0,0 +1,502 @@
+//! Mount table of the file system tree
+//!
+//! A mount attaches the root directory of a file system, or a directory in it
+//! for bind mounts, on a directory of another mounted file system.
+//! The path lookup of processes switches to the mounted directory when it reaches a
+//! mount point, and back to the mount point when it goes to `..` of a mounted root.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+use lock::{Mutex, RwLock};
+use rcore_fs::vfs::{FileSystem, FileType, INode};
+use rcore_fs_ramfs::RamFS;
+use rcore_fs_sfs::SimpleFileSystem;
+
+use super::rcore_fs_wrapper::{Block, BlockCache};
+use super::{BlockDev, FileDesc, ProcFS, SysFS, DEVFS, DEVPTS};
+use crate::error::{LxError, LxResult};
+use crate::process::LinuxProcess;
+
+bitflags::bitflags! {
+    /// Flags of `mount`
+    pub struct MountFlags: usize {
+        /// the file system is read-only
+        const RDONLY = 1;
+        /// do not honor set-user-ID and set-group-ID bits
+        const NOSUID = 1 << 1;
+        /// do not allow access to devices
+        const NODEV = 1 << 2;
+        /// do not allow programs to be executed
+        const NOEXEC = 1 << 3;
+        /// writes are synced at once
+        const SYNCHRONOUS = 1 << 4;
+        /// change the flags of an existing mount
+        const REMOUNT = 1 << 5;
+        /// allow mandatory locks
+        const MANDLOCK = 1 << 6;
+        /// directory modifications are synchronous
+        const DIRSYNC = 1 << 7;
+        /// do not update access times
+        const NOATIME = 1 << 10;
+        /// do not update directory access times
+        const NODIRATIME = 1 << 11;
+        /// create a bind mount
+        const BIND = 1 << 12;
+        /// move a subtree
+        const MOVE = 1 << 13;
+        /// apply to all mounts in the subtree
+        const REC = 1 << 14;
+        /// suppress some warnings
+        const SILENT = 1 << 15;
+        /// make the mount unbindable
+        const UNBINDABLE = 1 << 17;
+        /// make the mount private
+        const PRIVATE = 1 << 18;
+        /// make the mount a slave
+        const SLAVE = 1 << 19;
+        /// make the mount shared
+        const SHARED = 1 << 20;
+        /// update access times relative to modification times
+        const RELATIME = 1 << 21;
+        /// always update the last access time
+        const STRICTATIME = 1 << 24;
+
+        /// the flags of a mount shown in `/proc/mounts` and `statfs`
+        const PER_MOUNT = Self::RDONLY.bits | Self::NOSUID.bits | Self::NODEV.bits
+            | Self::NOEXEC.bits | Self::SYNCHRONOUS.bits | Self::MANDLOCK.bits
+            | Self::NOATIME.bits | Self::NODIRATIME.bits | Self::RELATIME.bits;
+        /// the flags changing the propagation type
+        const PROPAGATION = Self::UNBINDABLE.bits | Self::PRIVATE.bits | Self::SLAVE.bits
+            | Self::SHARED.bits;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags of `umount2`
+    pub struct UmountFlags: usize {
+        /// force unmounting even if busy
+        const FORCE = 1;
+        /// detach the mount and the mounts under it
+        const DETACH = 2;
+        /// mark the mount as expired
+        const EXPIRE = 4;
+        /// do not dereference the target if it is a symbolic link
+        const NOFOLLOW = 8;
+    }
+}
+
+/// Identifies a directory or a regular file by its file system and inode number
+pub(super) type INodeKey = (usize, usize);
+
+/// Get the key of a directory or a regular file.
+///
+/// Only directories and regular files are asked for their file system,
+/// since device and pseudo nodes may not know it.
+pub(super) fn inode_key(inode: &Arc<dyn INode>) -> Option<INodeKey> {
+    let info = inode.metadata().ok()?;
+    match info.type_ {
+        FileType::Dir | FileType::File => Some((fs_id(&inode.fs()), info.inode)),
+        _ => None,
+    }
+}
+
+/// An identity of the file system object
+fn fs_id(fs: &Arc<dyn FileSystem>) -> usize {
+    Arc::as_ptr(fs) as *const u8 as usize
+}
+
+/// A file system attached to the tree
+pub struct Mount {
+    /// unique ID of the mount
+    id: usize,
+    /// the mounted file system
+    fs: Arc<dyn FileSystem>,
+    /// the root directory of the mount
+    root: Arc<dyn INode>,
+    /// the mount containing `mountpoint`, `None` for the root mount
+    parent: Option<Arc<Mount>>,
+    /// the directory the file system is mounted on, `None` for the root mount
+    mountpoint: Option<Arc<dyn INode>>,
+    /// the keys of `root` and `mountpoint`
+    root_key: INodeKey,
+    mountpoint_key: Option<INodeKey>,
+    /// absolute path of the mount point
+    path: String,
+    /// the device or the source directory
+    source: String,
+    /// the type of the file system
+    fstype: String,
+    flags: Mutex<MountFlags>,
+}
+
+lazy_static! {
+    /// All mounts, in the order they were mounted
+    static ref MOUNTS: RwLock<Vec<Arc<Mount>>> = RwLock::new(Vec::new());
+}
+
+impl Mount {
+    /// Attach `root` of `fs` on the directory `mountpoint` of the mount `parent`
+    /// at `path`, or as the root of the tree if `mountpoint` is `None`.
+    pub fn add(
+        fs: Arc<dyn FileSystem>,
+        root: Arc<dyn INode>,
+        mountpoint: Option<(Arc<Mount>, Arc<dyn INode>)>,
+        path: &str,
+        source: &str,
+        fstype: &str,
+        flags: MountFlags,
+    ) -> LxResult<Arc<Self>> {
+        let (parent, mountpoint) = match mountpoint {
+            Some((parent, dir)) => (Some(parent), Some(dir)),
+            None => (None, None),
+        };
+        for dir in mountpoint.iter().chain(Some(&root)) {
+            if dir.metadata()?.type_ != FileType::Dir {
+                return Err(LxError::ENOTDIR);
+            }
+        }
+        let root_key = inode_key(&root).ok_or(LxError::EINVAL)?;
+        let mountpoint_key = mountpoint.as_ref().and_then(inode_key);
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        let mount = Arc::new(Mount {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            fs,
+            root,
+            parent,
+            mountpoint,
+            root_key,
+            mountpoint_key,
+            path: path.to_string(),
+            source: source.to_string(),
+            fstype: fstype.to_string(),
+            flags: Mutex::new(flags & MountFlags::PER_MOUNT),
+        });
+        MOUNTS.write().push(mount.clone());
+        Ok(mount)
+    }
+
+    /// Find the root mount of the tree whose root directory is `inode`.
+    pub fn of_root(inode: &Arc<dyn INode>) -> Option<Arc<Self>> {
+        let key = inode_key(inode)?;
+        let mounts = MOUNTS.read();
+        mounts
+            .iter()
+            .rev()
+            .find(|m| m.parent.is_none() && m.root_key == key)
+            .cloned()
+    }
+
+    /// Find the mount containing `inode`, which is a directory or a regular file.
+    ///
+    /// A file system mounted more than once is found by its latest mount.
+    pub fn of_inode(inode: &Arc<dyn INode>) -> Option<Arc<Self>> {
+        let (fs, _) = inode_key(inode)?;
+        let mounts = MOUNTS.read();
+        mounts.iter().rev().find(|m| fs_id(&m.fs) == fs).cloned()
+    }
+
+    /// Get the latest mount on `inode` of this mount,
+    /// or `None` if it is not a mount point.
+    pub fn mounted_on(self: &Arc<Self>, inode: &Arc<dyn INode>) -> Option<Arc<Self>> {
+        let key = inode_key(inode)?;
+        let mounts = MOUNTS.read();
+        let mut mounted: Option<Arc<Mount>> = None;
+        // a mount may be stacked on the root of a previous one
+        for mount in mounts.iter() {
+            let (top, top_key) = match &mounted {
+                Some(m) => (m, m.root_key),
+                None => (self, key),
+            };
+            let on_top = match &mount.parent {
+                Some(parent) => Arc::ptr_eq(parent, top),
+                None => false,
+            };
+            if on_top && mount.mountpoint_key == Some(top_key) {
+                mounted = Some(mount.clone());
+            }
+        }
+        mounted
+    }
+
+    /// Cross `inode` of this mount into the root of the mount on it,
+    /// `inode` and this mount are returned if it is not a mount point.
+    pub fn enter(self: &Arc<Self>, inode: Arc<dyn INode>) -> (Arc<dyn INode>, Arc<Self>) {
+        match self.mounted_on(&inode) {
+            Some(mount) => (mount.root.clone(), mount),
+            None => (inode, self.clone()),
+        }
+    }
+
+    /// Whether `inode` is the root directory of the mount
+    pub fn is_root(&self, inode: &Arc<dyn INode>) -> bool {
+        inode_key(inode) == Some(self.root_key)
+    }
+
+    /// Detach the mount at once.
+    ///
+    /// Fails with `EBUSY` if there are other mounts under it, unless `lazy` is set
+    /// in which case they are detached together. The open files are not checked,
+    /// they keep the file system alive and usable after it is detached.
+    pub fn remove(self: &Arc<Self>, lazy: bool) -> LxResult {
+        if self.mountpoint.is_none() {
+            return Err(LxError::EBUSY);
+        }
+        let mut mounts = MOUNTS.write();
+        let prefix = if self.path.ends_with('/') {
+            self.path.clone()
+        } else {
+            self.path.clone() + "/"
+        };
+        let is_child = |m: &Arc<Mount>| m.id > self.id && m.path.starts_with(&prefix);
+        if !lazy && mounts.iter().any(is_child) {
+            return Err(LxError::EBUSY);
+        }
+        self.fs.sync()?;
+        mounts.retain(|m| m.id != self.id && !is_child(m));
+        Ok(())
+    }
+
+    /// Change the per-mount flags of the mount.
+    pub fn remount(&self, flags: MountFlags) -> LxResult {
+        if flags.contains(MountFlags::RDONLY) {
+            self.fs.sync()?;
+        }
+        *self.flags.lock() = flags & MountFlags::PER_MOUNT;
+        Ok(())
+    }
+
+    /// The mounted file system
+    pub fn fs(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    /// The root directory of the mount
+    pub fn root(&self) -> &Arc<dyn INode> {
+        &self.root
+    }
+
+    /// The directory the file system is mounted on, `None` for the root mount
+    pub fn mountpoint(&self) -> Option<&Arc<dyn INode>> {
+        self.mountpoint.as_ref()
+    }
+
+    /// The mount containing the mount point, `None` for the root mount
+    pub fn parent(&self) -> Option<&Arc<Mount>> {
+        self.parent.as_ref()
+    }
+
+    /// Unique ID of the mount
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The type of the mounted file system
+    pub fn fstype(&self) -> &str {
+        &self.fstype
+    }
+
+    /// The per-mount flags
+    pub fn flags(&self) -> MountFlags {
+        *self.flags.lock()
+    }
+
+    /// Whether the mount is read-only
+    pub fn is_readonly(&self) -> bool {
+        self.flags().contains(MountFlags::RDONLY)
+    }
+
+    /// Check that the files reached through the mount can be modified,
+    /// it fails with `EROFS` if the mount is read-only.
+    pub fn check_writable(&self) -> LxResult {
+        if self.is_readonly() {
+            Err(LxError::EROFS)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The magic number of the file system type, for `statfs`
+    pub fn magic(&self) -> usize {
+        match self.fstype.as_str() {
+            "tmpfs" | "devtmpfs" => 0x0102_1994,
+            "ramfs" | "rootfs" => 0x8584_58f6,
+            "proc" => 0x9fa0,
+            "sysfs" => 0x6265_6572,
+            "devpts" => 0x1cd1,
+            "devfs" => 0x1373,
+            "sfs" => 0x2f8d_be2b,
+            _ => 0,
+        }
+    }
+
+    /// A line in `/proc/mounts`
+    fn to_line(&self) -> String {
+        let flags = self.flags();
+        let mut options = String::from(if flags.contains(MountFlags::RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        });
+        let names = [
+            (MountFlags::NOSUID, ",nosuid"),
+            (MountFlags::NODEV, ",nodev"),
+            (MountFlags::NOEXEC, ",noexec"),
+            (MountFlags::SYNCHRONOUS, ",sync"),
+            (MountFlags::MANDLOCK, ",mand"),
+            (MountFlags::NOATIME, ",noatime"),
+            (MountFlags::NODIRATIME, ",nodiratime"),
+            (MountFlags::RELATIME, ",relatime"),
+        ];
+        for (flag, name) in names.iter() {
+            if flags.contains(*flag) {
+                options += name;
+            }
+        }
+        format!(
+            "{} {} {} {} 0 0\n",
+            self.source, self.path, self.fstype, options
+        )
+    }
+}
+
+/// Get the mount at the absolute `path`, which is its mount point.
+pub fn find_mount(path: &str) -> Option<Arc<Mount>> {
+    let mounts = MOUNTS.read();
+    mounts.iter().rev().find(|m| m.path == path).cloned()
+}
+
+/// The content of `/proc/mounts`
+pub fn mounts_info() -> String {
+    let mounts = MOUNTS.read();
+    mounts.iter().map(|m| m.to_line()).collect()
+}
+
+impl LinuxProcess {
+    /// Attach the file system of `fstype` from `source` on the directory `target`.
+    ///
+    /// - With `MountFlags::REMOUNT`, change the flags of the mount at `target`.
+    /// - With `MountFlags::BIND`, attach the directory `source` on `target`.
+    /// - The propagation type is ignored, since all mounts are private.
+    pub fn mount(&self, source: &str, target: &str, fstype: &str, flags: MountFlags) -> LxResult {
+        if !self.credentials().is_root() {
+            return Err(LxError::EPERM);
+        }
+        if flags.contains(MountFlags::REMOUNT) {
+            return self.lookup_mounted(target, true)?.remount(flags);
+        }
+        if flags.intersects(MountFlags::PROPAGATION) {
+            self.lookup_inode(target)?;
+            return Ok(());
+        }
+        if flags.contains(MountFlags::MOVE) {
+            return Err(LxError::EINVAL);
+        }
+        let (mountpoint, parent) = self.lookup_mount(target)?;
+        let path = self.absolute_path(target);
+        if flags.contains(MountFlags::BIND) {
+            let (root, mount) = self.lookup_mount(source)?;
+            let fstype = mount.fstype().to_string();
+            Mount::add(
+                mount.fs().clone(),
+                root,
+                Some((parent, mountpoint)),
+                &path,
+                source,
+                &fstype,
+                flags,
+            )?;
+            return Ok(());
+        }
+        let fs: Arc<dyn FileSystem> = match fstype {
+            "tmpfs" | "ramfs" => RamFS::new(),
+            "devtmpfs" | "devfs" => DEVFS.clone(),
+            "proc" => ProcFS::new(),
+            "sysfs" => SysFS::new(),
+            "devpts" => DEVPTS.clone(),
+            "sfs" | "simplefs" => {
+                let dev = self.lookup_inode(source)?;
+                let block = dev
+                    .as_any_ref()
+                    .downcast_ref::<BlockDev>()
+                    .ok_or(LxError::ENOTBLK)?
+                    .block();
+                let device = Arc::new(BlockCache::new(Block::new(block), 0x100));
+                SimpleFileSystem::open(device).map_err(|_| LxError::EINVAL)?
+            }
+            _ => return Err(LxError::ENODEV),
+        };
+        Mount::add(
+            fs.clone(),
+            fs.root_inode(),
+            Some((parent, mountpoint)),
+            &path,
+            source,
+            fstype,
+            flags,
+        )?;
+        Ok(())
+    }
+
+    /// Detach the mount on the directory `target`.
+    ///
+    /// The mount is detached at once, even if files in it are open. With
+    /// `UmountFlags::DETACH`, the mounts under it are detached together instead
+    /// of failing with `EBUSY`.
+    pub fn umount(&self, target: &str, flags: UmountFlags) -> LxResult {
+        if !self.credentials().is_root() {
+            return Err(LxError::EPERM);
+        }
+        // expiring mounts is not supported
+        if flags.contains(UmountFlags::EXPIRE) {
+            return Err(LxError::EINVAL);
+        }
+        let follow = !flags.contains(UmountFlags::NOFOLLOW);
+        let mount = self.lookup_mounted(target, follow)?;
+        mount.remove(flags.contains(UmountFlags::DETACH))
+    }
+
+    /// Get the mount whose root directory `target` resolves to,
+    /// it fails with `EINVAL` if `target` is not a mount point.
+    fn lookup_mounted(&self, target: &str, follow: bool) -> LxResult<Arc<Mount>> {
+        let (inode, mount) = self.lookup_mount_at(FileDesc::CWD, target, follow)?;
+        if !mount.is_root(&inode) {
+            return Err(LxError::EINVAL);
+        }
+        Ok(mount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ProcessExt;
+    use zircon_object::task::{Job, Process};
+
+    #[test]
+    fn umount_through_symlink() {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let proc = proc.linux();
+        let root = proc.root_inode();
+        root.create("mnt", FileType::Dir, 0o755).unwrap();
+        let link = root.create("link", FileType::SymLink, 0o777).unwrap();
+        link.write_at(0, b"mnt").unwrap();
+        proc.mount("none", "/mnt", "tmpfs", MountFlags::empty())
+            .unwrap();
+
+        // the link itself is not a mount point
+        let ret = proc.umount("link", UmountFlags::NOFOLLOW);
+        assert!(matches!(ret, Err(LxError::EINVAL)));
+        proc.umount("link", UmountFlags::empty()).unwrap();
+        let (_, mount) = proc.lookup_mount("/mnt").unwrap();
+        assert_eq!(mount.fstype(), "rootfs");
+        let ret = proc.umount("/mnt", UmountFlags::empty());
+        assert!(matches!(ret, Err(LxError::EINVAL)));
+    }
+}
@@ -0,0 +1,250 @@
+//! Page cache of regular files for shared memory mappings
+#![deny(missing_docs)]
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use kernel_hal::VirtAddr;
+use lazy_static::lazy_static;
+use lock::{Mutex, RwLock};
+use rcore_fs::vfs::INode;
+use zircon_object::vm::{roundup_pages, VmObject, PAGE_SIZE};
+
+use super::PipePage;
+use crate::error::{LxError, LxResult};
+
+lazy_static! {
+    /// Page caches of the files, indexed by the file system and the inode number
+    static ref PAGE_CACHES: RwLock<BTreeMap<(usize, usize), Weak<PageCache>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+/// The key of the file in `PAGE_CACHES`
+///
+/// The same file may be opened as different `INode` objects, so the pointer of
+/// the inode can not be used.
+fn cache_key(inode: &Arc<dyn INode>) -> LxResult<(usize, usize)> {
+    let fs = Arc::as_ptr(&inode.fs()) as *const u8 as usize;
+    Ok((fs, inode.metadata()?.inode))
+}
+
+/// Cached content of a regular file, shared by all the `MAP_SHARED` mappings of the file
+///
+/// Once a file has a page cache, `read` and `write` of the file go through it,
+/// so they are coherent with the mappings. Changes made through the mappings are
+/// written back to the file on [`sync`](Self::sync) and when the cache is dropped.
+pub struct PageCache {
+    /// the file
+    inode: Arc<dyn INode>,
+    /// the cached content, starting from offset 0 of the file
+    vmo: Arc<VmObject>,
+    /// serialize the accesses which touch both the file and the cache
+    lock: Mutex<()>,
+}
+
+impl PageCache {
+    /// Get the page cache of `inode`, if the file is mapped shared
+    pub fn get(inode: &Arc<dyn INode>) -> Option<Arc<Self>> {
+        let caches = PAGE_CACHES.read();
+        if caches.is_empty() {
+            return None;
+        }
+        let key = cache_key(inode).ok()?;
+        caches.get(&key).and_then(Weak::upgrade)
+    }
+
+    /// Get the page cache of `inode` or create one, which covers at least `len` bytes
+    pub fn get_or_create(inode: &Arc<dyn INode>, len: usize) -> LxResult<Arc<Self>> {
+        let key = cache_key(inode)?;
+        let mut caches = PAGE_CACHES.write();
+        let cache = match caches.get(&key).and_then(Weak::upgrade) {
+            Some(cache) => cache,
+            None => {
+                let cache = Arc::new(PageCache {
+                    inode: inode.clone(),
+                    vmo: VmObject::new_paged_with_resizable(true, 0),
+                    lock: Mutex::new(()),
+                });
+                caches.retain(|_, cache| cache.strong_count() > 0);
+                caches.insert(key, Arc::downgrade(&cache));
+                cache
+            }
+        };
+        drop(caches);
+        cache.reserve(len)?;
+        Ok(cache)
+    }
+
+    /// The VMO holding the cached content
+    pub fn vmo(&self) -> &Arc<VmObject> {
+        &self.vmo
+    }
+
+    /// Make the cache cover at least `len` bytes, the new pages are read from the file
+    pub fn reserve(&self, len: usize) -> LxResult {
+        let _guard = self.lock.lock();
+        let old_len = self.vmo.len();
+        if len <= old_len {
+            return Ok(());
+        }
+        let new_len = roundup_pages(len);
+        self.vmo.set_len(new_len).map_err(|_| LxError::ENOMEM)?;
+        let mut buf = vec![0u8; new_len - old_len];
+        let mut read_len = 0;
+        while read_len < buf.len() {
+            let len = self
+                .inode
+                .read_at(old_len + read_len, &mut buf[read_len..])?;
+            if len == 0 {
+                break;
+            }
+            read_len += len;
+        }
+        self.vmo.write(old_len, &buf[..read_len])?;
+        Ok(())
+    }
+
+    /// Read the file at `offset` through the cache
+    pub fn read(&self, offset: usize, buf: &mut [u8]) -> LxResult<usize> {
+        let _guard = self.lock.lock();
+        let size = self.inode.metadata()?.size;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        let cached = self.vmo.len().saturating_sub(offset).min(len);
+        if cached > 0 {
+            self.vmo.read(offset, &mut buf[..cached])?;
+        }
+        if cached < len {
+            let read_len = self.inode.read_at(offset + cached, &mut buf[cached..len])?;
+            return Ok(cached + read_len);
+        }
+        Ok(len)
+    }
+
+    /// Write `buf` to the file at `offset`, and update the cache
+    pub fn write(&self, offset: usize, buf: &[u8]) -> LxResult<usize> {
+        let _guard = self.lock.lock();
+        let len = self.inode.write_at(offset, buf)?;
+        let end = self.vmo.len().min(offset + len);
+        if offset < end {
+            self.vmo.write(offset, &buf[..end - offset])?;
+        }
+        Ok(len)
+    }
+
+    /// Refer to the cached content in `[offset, offset + len)` without copying
+    ///
+    /// The pages are taken from a copy-on-write snapshot, so later writes to the file
+    /// do not change them. Only the part cached before the end of the file is returned.
+    pub fn snapshot(&self, offset: usize, len: usize) -> LxResult<Vec<PipePage>> {
+        let _guard = self.lock.lock();
+        let size = self.inode.metadata()?.size;
+        let end = offset.saturating_add(len).min(size).min(self.vmo.len());
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let start = offset / PAGE_SIZE * PAGE_SIZE;
+        let child = self
+            .vmo
+            .create_child(false, start, roundup_pages(end) - start)?;
+        Ok(PipePage::refer(&child, offset - start, end - offset))
+    }
+
+    /// Resize the file, the cached content past the new end is dropped
+    pub fn resize(&self, len: usize) -> LxResult {
+        let _guard = self.lock.lock();
+        self.inode.resize(len)?;
+        let vmo_len = self.vmo.len();
+        if len < vmo_len {
+            self.vmo.zero(len, vmo_len - len)?;
+        }
+        Ok(())
+    }
+
+    /// Write the cached content in `[offset, offset + len)` back to the file
+    ///
+    /// The file is not extended, the content past the end of the file is ignored.
+    pub fn sync(&self, offset: usize, len: usize) -> LxResult {
+        let _guard = self.lock.lock();
+        let size = self.inode.metadata()?.size;
+        let end = offset.saturating_add(len).min(size).min(self.vmo.len());
+        if offset >= end {
+            return Ok(());
+        }
+        let mut buf = vec![0u8; end - offset];
+        self.vmo.read(offset, &mut buf)?;
+        self.inode.write_at(offset, &buf)?;
+        Ok(())
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        if let Err(err) = self.sync(0, usize::MAX) {
+            warn!("failed to write back the page cache: {:?}", err);
+        }
+    }
+}
+
+/// A shared mapping of a file in the address space of a process
+#[derive(Clone)]
+pub struct FileMapping {
+    /// start address of the mapping
+    pub addr: VirtAddr,
+    /// length of the mapping
+    pub len: usize,
+    /// offset in the file of the start address
+    pub offset: usize,
+    /// page cache of the file
+    pub cache: Arc<PageCache>,
+}
+
+impl FileMapping {
+    /// End address of the mapping
+    pub fn end_addr(&self) -> VirtAddr {
+        self.addr + self.len
+    }
+
+    /// Whether the mapping overlaps with `[begin, end)`
+    pub fn overlap(&self, begin: VirtAddr, end: VirtAddr) -> bool {
+        self.addr < end && begin < self.end_addr()
+    }
+
+    /// Write the part in `[begin, end)` back to the file
+    pub fn sync(&self, begin: VirtAddr, end: VirtAddr) -> LxResult {
+        let begin = begin.max(self.addr);
+        let end = end.min(self.end_addr());
+        if begin >= end {
+            return Ok(());
+        }
+        self.cache
+            .sync(self.offset + (begin - self.addr), end - begin)
+    }
+
+    /// The parts of the mapping outside `[begin, end)`
+    pub fn cut(&self, begin: VirtAddr, end: VirtAddr) -> Vec<FileMapping> {
+        let mut parts = Vec::new();
+        if self.addr < begin {
+            parts.push(FileMapping {
+                len: begin.min(self.end_addr()) - self.addr,
+                ..self.clone()
+            });
+        }
+        if end < self.end_addr() {
+            let addr = end.max(self.addr);
+            parts.push(FileMapping {
+                addr,
+                len: self.end_addr() - addr,
+                offset: self.offset + (addr - self.addr),
+                cache: self.cache.clone(),
+            });
+        }
+        parts
+    }
+}
@@ -1,16 +1,130 @@
 //! Implement INode for Pipe
+//!
+//! The pipe buffer holds references to pages rather than bytes, so that `splice`
+//! and `tee` can move the data between pipes and files without copying it.
 #![deny(missing_docs)]
 
+use super::{File, FileLike, PageCache, PollEvents, SeekFrom};
+use crate::error::{LxError, LxResult};
 use crate::{sync::Event, sync::EventBus};
-use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
 use core::{any::Any, cmp::min};
 use core::{
     future::Future,
     pin::Pin,
     task::{Context, Poll},
 };
-use lock::Mutex;
+use kernel_hal::mem::phys_to_virt;
+use lock::{Mutex, MutexGuard};
 use rcore_fs::vfs::*;
+use zircon_object::vm::{MMUFlags, VmObject, PAGE_SIZE};
+
+/// A reference to bytes in a page, the unit of the pipe buffer
+#[derive(Clone)]
+pub struct PipePage {
+    /// the VMO holding the page
+    vmo: Arc<VmObject>,
+    /// offset of the bytes in the VMO, the bytes do not cross a page boundary
+    offset: usize,
+    /// number of bytes
+    len: usize,
+    /// the page is allocated for the pipe and more bytes can be appended to it
+    private: bool,
+}
+
+impl PipePage {
+    /// Allocate an empty page, which is filled through [`spare_mut`](Self::spare_mut)
+    pub fn alloc() -> Self {
+        PipePage {
+            vmo: VmObject::new_paged(1),
+            offset: 0,
+            len: 0,
+            private: true,
+        }
+    }
+
+    /// Refer to `[offset, offset + len)` of `vmo` without copying, split at the page boundaries
+    ///
+    /// The content must not be changed in place while referred to, such as a
+    /// copy-on-write snapshot.
+    pub fn refer(vmo: &Arc<VmObject>, offset: usize, len: usize) -> Vec<Self> {
+        let mut pages = Vec::new();
+        let end = offset + len;
+        let mut offset = offset;
+        while offset < end {
+            let len = min(end, (offset / PAGE_SIZE + 1) * PAGE_SIZE) - offset;
+            pages.push(PipePage {
+                vmo: vmo.clone(),
+                offset,
+                len,
+                private: false,
+            });
+            offset += len;
+        }
+        pages
+    }
+
+    /// Number of bytes in the page
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the page holds no bytes
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes in the page, accessed through the kernel mapping of the page
+    pub fn bytes(&self) -> LxResult<&[u8]> {
+        let paddr = self
+            .vmo
+            .commit_page(self.offset / PAGE_SIZE, MMUFlags::READ)?;
+        let addr = phys_to_virt(paddr) + self.offset % PAGE_SIZE;
+        // the page is kept alive by `self.vmo`
+        Ok(unsafe { core::slice::from_raw_parts(addr as *const u8, self.len) })
+    }
+
+    /// The free space after the bytes of an allocated page
+    ///
+    /// The bytes written to it are added to the page by [`fill`](Self::fill).
+    pub fn spare_mut(&mut self) -> LxResult<&mut [u8]> {
+        if !self.private {
+            return Ok(&mut []);
+        }
+        let end = self.offset + self.len;
+        let paddr = self.vmo.commit_page(end / PAGE_SIZE, MMUFlags::WRITE)?;
+        let addr = phys_to_virt(paddr) + end % PAGE_SIZE;
+        let len = PAGE_SIZE - end % PAGE_SIZE;
+        // the page is only accessed by the owner of `self`
+        Ok(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })
+    }
+
+    /// Add `len` bytes written to [`spare_mut`](Self::spare_mut) to the page
+    pub fn fill(&mut self, len: usize) {
+        self.len += len;
+    }
+
+    /// Whether bytes can be appended to the page, which is not shared with other pipes
+    fn appendable(&self) -> bool {
+        self.private && self.offset + self.len < PAGE_SIZE && Arc::strong_count(&self.vmo) == 1
+    }
+
+    /// Copy `buf` into new pages
+    pub fn copy_from(buf: &[u8]) -> LxResult<Vec<Self>> {
+        let mut pages = Vec::new();
+        let mut copied = 0;
+        while copied < buf.len() {
+            let mut page = PipePage::alloc();
+            let spare = page.spare_mut()?;
+            let len = min(spare.len(), buf.len() - copied);
+            spare[..len].copy_from_slice(&buf[copied..copied + len]);
+            page.fill(len);
+            pages.push(page);
+            copied += len;
+        }
+        Ok(pages)
+    }
+}
 
 #[derive(Clone, PartialEq, Eq)]
 #[allow(dead_code)]
@@ -25,13 +139,54 @@ pub enum PipeEnd {
 /// Pipe inner data
 pub struct PipeData {
     /// pipe buffer
-    buf: VecDeque<u8>,
+    buf: VecDeque<PipePage>,
+    /// number of bytes in the buffer
+    len: usize,
     /// event bus for pipe
     eventbus: EventBus,
     /// number of pipe ends
     end_cnt: i32,
 }
 
+impl PipeData {
+    /// Get references to the pages of at most `len` bytes at the head of the buffer
+    fn head(&self, len: usize) -> Vec<PipePage> {
+        let mut pages = Vec::new();
+        let mut left = len;
+        for page in self.buf.iter() {
+            if left == 0 {
+                break;
+            }
+            let mut page = page.clone();
+            page.len = min(page.len, left);
+            // the reader does not append to the page
+            page.private = false;
+            left -= page.len;
+            pages.push(page);
+        }
+        pages
+    }
+
+    /// Remove `len` bytes from the head of the buffer
+    fn consume(&mut self, len: usize) {
+        let mut left = min(len, self.len);
+        self.len -= left;
+        while left > 0 {
+            let page = self.buf.front_mut().unwrap();
+            if page.len > left {
+                page.offset += left;
+                page.len -= left;
+                break;
+            }
+            left -= page.len;
+            self.buf.pop_front();
+        }
+        if self.buf.is_empty() {
+            self.eventbus.clear(Event::READABLE);
+        }
+    }
+}
+
 /// pipe struct
 #[derive(Clone)]
 pub struct Pipe {
@@ -54,6 +209,7 @@ impl Pipe {
     pub fn create_pair() -> (Pipe, Pipe) {
         let inner = PipeData {
             buf: VecDeque::new(),
+            len: 0,
             eventbus: EventBus::default(),
             end_cnt: 2, // one read, one write
         };
@@ -69,25 +225,241 @@ impl Pipe {
             },
         )
     }
-    /// whether the pipe struct is readable
-    fn can_read(&self) -> bool {
-        if let PipeEnd::Read = self.direction {
-            // true
-            let data = self.data.lock();
-            !data.buf.is_empty() || data.end_cnt < 2 // other end closed
-        } else {
-            false
+    /// The status of the pipe end, with the pipe locked
+    fn status(&self, data: &PipeData) -> PollStatus {
+        PollStatus {
+            // readable at the end of file, after the other end is closed
+            read: matches!(self.direction, PipeEnd::Read)
+                && (!data.buf.is_empty() || data.end_cnt < 2),
+            write: matches!(self.direction, PipeEnd::Write) && data.end_cnt == 2,
+            error: false,
         }
     }
 
-    /// whether the pipe struct is writeable
-    fn can_write(&self) -> bool {
+    /// Wait until the pipe end is ready for any of `events`, `IN` or `OUT`
+    ///
+    /// The waker is registered even when the pipe is ready for other events.
+    pub async fn async_poll_events(&self, events: PollEvents) -> LxResult<PollStatus> {
+        Ok(PipeFuture { pipe: self, events }.await?)
+    }
+
+    /// Whether `self` and `other` are the ends of the same pipe
+    pub fn same_pipe(&self, other: &Pipe) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// Lock the pipe to read from it, returns `EAGAIN` if the buffer is empty
+    /// and the write end is open.
+    fn lock_readable(&self) -> LxResult<MutexGuard<'_, PipeData>> {
         if let PipeEnd::Write = self.direction {
-            self.data.lock().end_cnt == 2
-        } else {
-            false
+            return Err(LxError::EBADF);
+        }
+        let data = self.data.lock();
+        if data.buf.is_empty() && data.end_cnt == 2 {
+            return Err(LxError::EAGAIN);
+        }
+        Ok(data)
+    }
+
+    /// Get references to the pages of at most `len` bytes at the head of the buffer
+    ///
+    /// The pages stay in the pipe, as `tee` does. Returns `EAGAIN` if the buffer is
+    /// empty and the write end is open, or no pages on end of file.
+    pub fn peek(&self, len: usize) -> LxResult<Vec<PipePage>> {
+        Ok(self.lock_readable()?.head(len))
+    }
+
+    /// Remove the pages of at most `len` bytes at the head of the buffer
+    ///
+    /// Returns `EAGAIN` if the buffer is empty and the write end is open,
+    /// or no pages on end of file.
+    pub fn take(&self, len: usize) -> LxResult<Vec<PipePage>> {
+        let mut data = self.lock_readable()?;
+        let pages = data.head(len);
+        data.consume(pages.iter().map(PipePage::len).sum());
+        Ok(pages)
+    }
+
+    /// Pass the pages of at most `len` bytes at the head of the buffer to `f`,
+    /// and remove the number of bytes used by `f`, which is returned.
+    ///
+    /// The pipe is locked during `f`, so that the other readers never see the same data.
+    /// Returns `EAGAIN` if the buffer is empty and the write end is open.
+    pub fn take_with(
+        &self,
+        len: usize,
+        f: impl FnOnce(&[PipePage]) -> LxResult<usize>,
+    ) -> LxResult<usize> {
+        let mut data = self.lock_readable()?;
+        let pages = data.head(len);
+        let used = f(&pages)?;
+        data.consume(used);
+        Ok(used)
+    }
+
+    /// Append pages to the buffer without copying
+    pub fn push(&self, pages: Vec<PipePage>) -> LxResult {
+        if let PipeEnd::Read = self.direction {
+            return Err(LxError::EBADF);
+        }
+        let mut data = self.data.lock();
+        for page in pages.into_iter().filter(|page| !page.is_empty()) {
+            data.len += page.len;
+            data.buf.push_back(page);
+        }
+        if !data.buf.is_empty() {
+            data.eventbus.set(Event::READABLE);
+        }
+        Ok(())
+    }
+}
+
+/// The future of [`Pipe::async_poll_events`]
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct PipeFuture<'a> {
+    pipe: &'a Pipe,
+    events: PollEvents,
+}
+
+impl<'a> Future for PipeFuture<'a> {
+    type Output = Result<PollStatus>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // check and subscribe with the pipe locked, so that no change is missed
+        let mut data = self.pipe.data.lock();
+        let status = self.pipe.status(&data);
+        if (status.read && self.events.contains(PollEvents::IN))
+            || (status.write && self.events.contains(PollEvents::OUT))
+        {
+            return Poll::Ready(Ok(status));
+        }
+        let waker = cx.waker().clone();
+        data.eventbus.subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        Poll::Pending
+    }
+}
+
+/// Read at most `len` bytes of `file` as pages, at `offset` or the file position if `None`
+///
+/// A regular file is read until `len` bytes or the end of the file. Other files are
+/// read while there is data available, only the first read may block.
+pub async fn read_pages(
+    file: &dyn FileLike,
+    offset: Option<u64>,
+    len: usize,
+) -> LxResult<Vec<PipePage>> {
+    if let Some(file) = file.downcast_ref::<File>() {
+        if file.metadata()?.type_ == FileType::File {
+            let start = match offset {
+                Some(offset) => offset,
+                None => file.seek(SeekFrom::Current(0))?,
+            };
+            let pages = read_file_pages(file, start, len).await?;
+            if offset.is_none() {
+                let read_len = pages.iter().map(PipePage::len).sum::<usize>();
+                file.seek(SeekFrom::Current(read_len as i64))?;
+            }
+            return Ok(pages);
+        }
+    }
+    let mut pages = Vec::new();
+    let mut read_len = 0;
+    while read_len < len {
+        let mut page = PipePage::alloc();
+        let spare = page.spare_mut()?;
+        let spare_len = min(spare.len(), len - read_len);
+        let result = match offset {
+            Some(offset) => {
+                file.read_at(offset + read_len as u64, &mut spare[..spare_len])
+                    .await
+            }
+            None => file.read(&mut spare[..spare_len]).await,
+        };
+        let page_len = match result {
+            Ok(page_len) => page_len,
+            // the data already read is returned
+            Err(_) if read_len > 0 => break,
+            Err(err) => return Err(err),
+        };
+        page.fill(page_len);
+        if page_len > 0 {
+            pages.push(page);
+        }
+        read_len += page_len;
+        let available = file
+            .poll(PollEvents::IN)
+            .map_or(false, |status| status.read);
+        if page_len < spare_len || !available {
+            break;
         }
     }
+    Ok(pages)
+}
+
+/// Read a regular file at `offset` as pages
+///
+/// The content in the page cache is referred to by copy-on-write snapshots of the
+/// cached pages, the rest is read into new pages.
+async fn read_file_pages(file: &File, offset: u64, len: usize) -> LxResult<Vec<PipePage>> {
+    if !file.flags().readable() {
+        return Err(LxError::EBADF);
+    }
+    if let Some(cache) = PageCache::get(&file.inode()) {
+        let pages = cache.snapshot(offset as usize, len)?;
+        if !pages.is_empty() {
+            return Ok(pages);
+        }
+    }
+    let mut pages = Vec::new();
+    let mut read_len = 0;
+    while read_len < len {
+        let mut page = PipePage::alloc();
+        let spare = page.spare_mut()?;
+        let spare_len = min(spare.len(), len - read_len);
+        let page_len = file
+            .read_at(offset + read_len as u64, &mut spare[..spare_len])
+            .await?;
+        page.fill(page_len);
+        if page_len > 0 {
+            pages.push(page);
+        }
+        read_len += page_len;
+        if page_len < spare_len {
+            break;
+        }
+    }
+    Ok(pages)
+}
+
+/// Write the bytes in `pages` to `file`, at `offset` or the file position if `None`
+///
+/// Returns the number of bytes written, which stops at the first short write.
+pub fn write_pages(
+    file: &dyn FileLike,
+    offset: Option<u64>,
+    pages: &[PipePage],
+) -> LxResult<usize> {
+    let mut written = 0;
+    for page in pages {
+        let bytes = page.bytes()?;
+        let result = match offset {
+            Some(offset) => file.write_at(offset + written as u64, bytes),
+            None => file.write(bytes),
+        };
+        let len = match result {
+            Ok(len) => len,
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err),
+        };
+        written += len;
+        if len < bytes.len() {
+            break;
+        }
+    }
+    Ok(written)
 }
 
 impl INode for Pipe {
@@ -99,17 +471,20 @@ impl INode for Pipe {
         if let PipeEnd::Read = self.direction {
             let mut data = self.data.lock();
             if data.buf.is_empty() && data.end_cnt == 2 {
-                Err(FsError::Again)
-            } else {
-                let len = min(buf.len(), data.buf.len());
-                for item in buf.iter_mut().take(len) {
-                    *item = data.buf.pop_front().unwrap();
-                }
-                if data.buf.is_empty() {
-                    data.eventbus.clear(Event::READABLE);
+                return Err(FsError::Again);
+            }
+            let mut len = 0;
+            for page in data.buf.iter() {
+                if len == buf.len() {
+                    break;
                 }
-                Ok(len)
+                let bytes = page.bytes().map_err(|_| FsError::NoDeviceSpace)?;
+                let copy_len = min(bytes.len(), buf.len() - len);
+                buf[len..len + copy_len].copy_from_slice(&bytes[..copy_len]);
+                len += copy_len;
             }
+            data.consume(len);
+            Ok(len)
         } else {
             Ok(0)
         }
@@ -118,11 +493,19 @@ impl INode for Pipe {
     /// write to pipe
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
         if let PipeEnd::Write = self.direction {
-            let mut data = self.data.lock();
-            for c in buf {
-                data.buf.push_back(*c);
+            let mut guard = self.data.lock();
+            let data = &mut *guard;
+            let mut written = 0;
+            if let Some(page) = data.buf.back_mut().filter(|page| page.appendable()) {
+                let spare = page.spare_mut().map_err(|_| FsError::NoDeviceSpace)?;
+                written = min(spare.len(), buf.len());
+                spare[..written].copy_from_slice(&buf[..written]);
+                page.fill(written);
+                data.len += written;
             }
-            data.eventbus.set(Event::READABLE);
+            drop(guard);
+            let pages = PipePage::copy_from(&buf[written..]).map_err(|_| FsError::NoDeviceSpace)?;
+            self.push(pages).map_err(|_| FsError::NoDeviceSpace)?;
             Ok(buf.len())
         } else {
             Ok(0)
@@ -132,45 +515,112 @@ impl INode for Pipe {
     /// monitoring events and determine whether the pipe is readable or writeable
     /// if the write end is not close and the buffer is empty, the read end will be block
     fn poll(&self) -> Result<PollStatus> {
-        Ok(PollStatus {
-            read: self.can_read(),
-            write: self.can_write(),
-            error: false,
-        })
+        Ok(self.status(&self.data.lock()))
     }
 
     fn async_poll<'a>(
         &'a self,
     ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
-        #[must_use = "future does nothing unless polled/`await`-ed"]
-        struct PipeFuture<'a> {
-            pipe: &'a Pipe,
-        }
+        Box::pin(PipeFuture {
+            pipe: self,
+            events: PollEvents::IN | PollEvents::OUT,
+        })
+    }
 
-        impl<'a> Future for PipeFuture<'a> {
-            type Output = Result<PollStatus>;
+    /// return the any ref
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
 
-            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-                if self.pipe.can_read() || self.pipe.can_write() {
-                    return Poll::Ready(self.pipe.poll());
-                }
-                let waker = cx.waker().clone();
-                let mut data = self.pipe.data.lock();
-                data.eventbus.subscribe(Box::new({
-                    move |_| {
-                        waker.wake_by_ref();
-                        true
-                    }
-                }));
-                Poll::Pending
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(pipe: &Pipe) -> Vec<u8> {
+        let mut buf = [0; 4 * PAGE_SIZE];
+        match pipe.read_at(0, &mut buf) {
+            Ok(len) => buf[..len].to_vec(),
+            Err(FsError::Again) => Vec::new(),
+            Err(e) => panic!("failed to read the pipe: {:?}", e),
         }
+    }
 
-        Box::pin(PipeFuture { pipe: self })
+    fn bytes(pages: &[PipePage]) -> Vec<u8> {
+        pages
+            .iter()
+            .flat_map(|page| page.bytes().unwrap().to_vec())
+            .collect()
     }
 
-    /// return the any ref
-    fn as_any_ref(&self) -> &dyn Any {
-        self
+    #[test]
+    fn refer_split() {
+        let vmo = VmObject::new_paged(3);
+        let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| i as u8).collect();
+        vmo.write(0, &data).unwrap();
+
+        // split at the page boundaries
+        let pages = PipePage::refer(&vmo, PAGE_SIZE - 10, PAGE_SIZE + 20);
+        let lens: Vec<usize> = pages.iter().map(PipePage::len).collect();
+        assert_eq!(lens, [10, PAGE_SIZE, 10]);
+        assert_eq!(bytes(&pages), &data[PAGE_SIZE - 10..2 * PAGE_SIZE + 10]);
+        assert!(PipePage::refer(&vmo, 0, 0).is_empty());
+
+        // the referred pages are never appended to
+        let (read, write) = Pipe::create_pair();
+        write.push(pages).unwrap();
+        write.write_at(0, &[1, 2]).unwrap();
+        let mut expected = data[PAGE_SIZE - 10..2 * PAGE_SIZE + 10].to_vec();
+        expected.extend_from_slice(&[1, 2]);
+        assert_eq!(read_all(&read), expected);
+    }
+
+    #[test]
+    fn copy_and_append() {
+        let data: Vec<u8> = (0..PAGE_SIZE + 1).map(|i| i as u8).collect();
+        let pages = PipePage::copy_from(&data).unwrap();
+        let lens: Vec<usize> = pages.iter().map(PipePage::len).collect();
+        assert_eq!(lens, [PAGE_SIZE, 1]);
+
+        // small writes are appended to the last page
+        let (read, write) = Pipe::create_pair();
+        write.write_at(0, &[1]).unwrap();
+        write.write_at(0, &[2, 3]).unwrap();
+        assert_eq!(read.peek(PAGE_SIZE).unwrap().len(), 1);
+        assert_eq!(read_all(&read), [1, 2, 3]);
+    }
+
+    #[test]
+    fn take_and_consume() {
+        let (read, write) = Pipe::create_pair();
+        assert!(matches!(read.peek(10), Err(LxError::EAGAIN)));
+        assert!(matches!(write.take(10), Err(LxError::EBADF)));
+        write
+            .push(PipePage::copy_from(&[1, 2, 3, 4]).unwrap())
+            .unwrap();
+        write.push(PipePage::copy_from(&[5, 6]).unwrap()).unwrap();
+
+        // peeking keeps the data in the pipe
+        assert_eq!(bytes(&read.peek(5).unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(bytes(&read.take(3).unwrap()), [1, 2, 3]);
+
+        // only the bytes used are removed, in the middle of a page
+        let used = read
+            .take_with(10, |pages| {
+                assert_eq!(bytes(pages), [4, 5, 6]);
+                Ok(2)
+            })
+            .unwrap();
+        assert_eq!(used, 2);
+        assert!(matches!(
+            read.take_with(10, |_| Err(LxError::EIO)),
+            Err(LxError::EIO)
+        ));
+        assert_eq!(read_all(&read), [6]);
+        assert!(matches!(read.take(10), Err(LxError::EAGAIN)));
+
+        // no pages at the end of file
+        drop(write);
+        assert!(read.take(10).unwrap().is_empty());
     }
 }
@@ -0,0 +1,93 @@
+//! The global files in the root directory of procfs
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write;
+
+use kernel_hal::timer::timer_now;
+use zircon_object::{task::ThreadState, vm::vmo_page_bytes};
+
+use crate::fs::mounts_info;
+use crate::process::{current_process, job_processes, threads};
+
+/// A global file in the root directory, and the function generating its content
+pub(super) type GlobalFile = (&'static str, fn() -> String);
+
+/// The global files in the root directory
+pub(super) const GLOBAL_FILES: &[GlobalFile] = &[
+    ("cpuinfo", cpuinfo),
+    ("loadavg", loadavg),
+    ("meminfo", meminfo),
+    ("mounts", mounts_info),
+    ("uptime", uptime),
+];
+
+/// The name of the architecture in `/proc/cpuinfo`
+const ARCH: &str = if cfg!(target_arch = "x86_64") {
+    "x86_64"
+} else if cfg!(target_arch = "riscv64") {
+    "riscv64"
+} else if cfg!(target_arch = "aarch64") {
+    "aarch64"
+} else {
+    "unknown"
+};
+
+/// `/proc/cpuinfo`
+fn cpuinfo() -> String {
+    let cpus = kernel_hal::vdso::vdso_constants().max_num_cpus.max(1);
+    let mhz = kernel_hal::cpu::cpu_frequency();
+    let mut s = String::new();
+    for i in 0..cpus {
+        writeln!(s, "processor\t: {}", i).unwrap();
+        writeln!(s, "model name\t: {}", ARCH).unwrap();
+        writeln!(s, "cpu MHz\t\t: {}.000", mhz).unwrap();
+        writeln!(s).unwrap();
+    }
+    s
+}
+
+/// `/proc/loadavg`, the load averages are not tracked
+fn loadavg() -> String {
+    let procs = current_process().map_or_else(Default::default, |p| job_processes(&p.job()));
+    let all_threads: Vec<_> = procs.iter().flat_map(|p| threads(p)).collect();
+    let running = all_threads
+        .iter()
+        .filter(|t| t.state() == ThreadState::Running)
+        .count();
+    let last_pid = procs.iter().map(|p| p.id()).max().unwrap_or(0);
+    format!(
+        "0.00 0.00 0.00 {}/{} {}\n",
+        running,
+        all_threads.len(),
+        last_pid
+    )
+}
+
+/// `/proc/meminfo`, the memory committed to VMOs is counted as used
+fn meminfo() -> String {
+    let total: usize = kernel_hal::mem::free_pmem_regions()
+        .iter()
+        .map(|r| r.end - r.start)
+        .sum();
+    let free = total.saturating_sub(vmo_page_bytes());
+    let mut s = String::new();
+    let fields = [
+        ("MemTotal", total),
+        ("MemFree", free),
+        ("MemAvailable", free),
+        ("Buffers", 0),
+        ("Cached", 0),
+        ("SwapTotal", 0),
+        ("SwapFree", 0),
+    ];
+    for (name, bytes) in fields.iter() {
+        writeln!(s, "{:<16}{:>8} kB", format!("{}:", name), bytes / 1024).unwrap();
+    }
+    s
+}
+
+/// `/proc/uptime`, the idle time is not tracked
+fn uptime() -> String {
+    let now = timer_now();
+    format!("{}.{:02} 0.00\n", now.as_secs(), now.subsec_millis() / 10)
+}
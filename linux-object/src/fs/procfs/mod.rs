@@ -0,0 +1,537 @@
+//! Process file system, whose files are generated from the kernel state when they are opened
+//!
+//! The processes listed are those in the job of the current process, and `self`
+//! refers to the current process.
+
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+use zircon_object::{
+    object::{KernelObject, KoID},
+    task::{Process, Thread},
+};
+
+use self::global::GLOBAL_FILES;
+use self::process::{Task, TASK_FILES};
+use super::{File, FileDesc, FileLike, MemFd, Pipe};
+use crate::process::{current_process, current_thread, job_processes, ProcessExt};
+
+mod global;
+mod process;
+
+/// The inode number of the root directory
+const ROOT_INODE: usize = 1;
+
+/// Process file system
+pub struct ProcFS {
+    self_ref: Weak<ProcFS>,
+}
+
+impl ProcFS {
+    /// Create a new process file system
+    pub fn new() -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| ProcFS {
+            self_ref: self_ref.clone(),
+        })
+    }
+
+    fn arc(&self) -> Arc<Self> {
+        self.self_ref.upgrade().unwrap()
+    }
+
+    fn dir(&self, kind: DirKind) -> Arc<dyn INode> {
+        Arc::new(ProcDir {
+            fs: self.arc(),
+            kind,
+        })
+    }
+}
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.dir(DirKind::Root)
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: 4096,
+            frsize: 4096,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+/// Find the process `pid` in the job of the current process
+fn find_process(pid: KoID) -> Result<Arc<Process>> {
+    current_process()
+        .and_then(|proc| proc.job().get_child(pid).ok())
+        .and_then(|obj| obj.downcast_arc::<Process>().ok())
+        .ok_or(FsError::EntryNotFound)
+}
+
+/// Find the thread `tid` of the process `pid`
+fn find_thread(pid: KoID, tid: KoID) -> Result<(Arc<Process>, Arc<Thread>)> {
+    let proc = find_process(pid)?;
+    let thread = proc
+        .get_child(tid)
+        .ok()
+        .and_then(|obj| obj.downcast_arc::<Thread>().ok())
+        .ok_or(FsError::EntryNotFound)?;
+    Ok((proc, thread))
+}
+
+/// The user and group owning the files of `proc`
+fn owner(proc: &Process) -> (u32, u32) {
+    let cred = proc.linux().credentials();
+    (cred.euid, cred.egid)
+}
+
+/// The inode number of the `index`-th file of the process or thread `id`
+fn inode_id(id: KoID, index: usize) -> usize {
+    (id as usize) << 8 | index
+}
+
+/// The inode number of `/proc/[pid]/fd/[fd]`, in a range above those of [`inode_id`]
+fn fd_inode_id(pid: KoID, fd: usize) -> usize {
+    1 << 63 | (pid as usize) << 32 | (fd & 0xffff_ffff)
+}
+
+/// The target of a symbolic link in procfs which refers to a file directly,
+/// such as `/proc/[pid]/fd/[fd]`
+pub(super) fn link_target(inode: &Arc<dyn INode>) -> Option<Arc<dyn INode>> {
+    inode.downcast_ref::<ProcLink>()?.inode.clone()
+}
+
+/// The kinds of directories
+#[derive(Debug, Clone, Copy)]
+enum DirKind {
+    /// `/proc`
+    Root,
+    /// `/proc/[pid]`
+    Process(KoID),
+    /// `/proc/[pid]/fd`
+    Fd(KoID),
+    /// `/proc/[pid]/task`
+    Task(KoID),
+    /// `/proc/[pid]/task/[tid]`
+    Thread(KoID, KoID),
+}
+
+/// A directory, whose entries are listed when it is read
+struct ProcDir {
+    fs: Arc<ProcFS>,
+    kind: DirKind,
+}
+
+impl ProcDir {
+    /// The process or thread of the directory
+    fn task(&self) -> Result<Task> {
+        match self.kind {
+            DirKind::Root => Err(FsError::NotSupported),
+            DirKind::Process(pid) | DirKind::Fd(pid) | DirKind::Task(pid) => Ok(Task {
+                proc: find_process(pid)?,
+                thread: None,
+            }),
+            DirKind::Thread(pid, tid) => {
+                let (proc, thread) = find_thread(pid, tid)?;
+                Ok(Task {
+                    proc,
+                    thread: Some(thread),
+                })
+            }
+        }
+    }
+
+    /// The inode number of the directory
+    fn inode_id(&self) -> usize {
+        let count = TASK_FILES.len();
+        match self.kind {
+            DirKind::Root => ROOT_INODE,
+            DirKind::Process(id) | DirKind::Thread(_, id) => inode_id(id, 0),
+            DirKind::Fd(id) => inode_id(id, count + 3),
+            DirKind::Task(id) => inode_id(id, count + 4),
+        }
+    }
+
+    /// The entries except `.` and `..`
+    fn names(&self) -> Result<Vec<String>> {
+        let task_names = || {
+            TASK_FILES
+                .iter()
+                .map(|(name, _, _)| *name)
+                .chain(["cwd", "exe", "fd"].iter().cloned())
+                .map(String::from)
+        };
+        let names = match self.kind {
+            DirKind::Root => {
+                let mut pids: Vec<_> = current_process()
+                    .map_or_else(Vec::new, |p| job_processes(&p.job()))
+                    .iter()
+                    .map(|p| p.id())
+                    .collect();
+                pids.sort_unstable();
+                GLOBAL_FILES
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .chain(["self", "thread-self"].iter().map(|s| s.to_string()))
+                    .chain(pids.iter().map(|pid| pid.to_string()))
+                    .collect()
+            }
+            DirKind::Process(_) => task_names().chain(Some("task".into())).collect(),
+            DirKind::Thread(..) => task_names().collect(),
+            DirKind::Fd(_) => {
+                let mut fds: Vec<i32> = self
+                    .task()?
+                    .proc
+                    .linux()
+                    .get_files()
+                    .map_err(|_| FsError::EntryNotFound)?
+                    .keys()
+                    .map(|&fd| fd.into())
+                    .collect();
+                fds.sort_unstable();
+                fds.iter().map(|fd| fd.to_string()).collect()
+            }
+            DirKind::Task(_) => {
+                let mut tids = self.task()?.proc.thread_ids();
+                tids.sort_unstable();
+                tids.iter().map(|tid| tid.to_string()).collect()
+            }
+        };
+        Ok(names)
+    }
+
+    fn find_in_root(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let fs = &self.fs;
+        if let Some(index) = GLOBAL_FILES.iter().position(|(file, _)| *file == name) {
+            let content = (GLOBAL_FILES[index].1)();
+            return Ok(ProcFile::new(
+                fs,
+                ROOT_INODE + 1 + index,
+                0o444,
+                (0, 0),
+                content,
+            ));
+        }
+        let index = GLOBAL_FILES.len() + ROOT_INODE + 1;
+        match name {
+            "self" | "thread-self" => {
+                let thread = current_thread().ok_or(FsError::EntryNotFound)?;
+                let pid = thread.proc().id();
+                let (target, index) = match name {
+                    "self" => (pid.to_string(), index),
+                    _ => (format!("{}/task/{}", pid, thread.id()), index + 1),
+                };
+                Ok(ProcLink::new(fs, index, (0, 0), target, None))
+            }
+            _ => {
+                let pid = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                find_process(pid)?;
+                Ok(fs.dir(DirKind::Process(pid)))
+            }
+        }
+    }
+
+    fn find_in_task(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let fs = &self.fs;
+        let task = self.task()?;
+        let id = task.thread.as_ref().map_or(task.proc.id(), |t| t.id());
+        let owner = owner(&task.proc);
+        if let Some(index) = TASK_FILES.iter().position(|(file, _, _)| *file == name) {
+            let (_, generate, mode) = TASK_FILES[index];
+            let content = generate(&task);
+            return Ok(ProcFile::new(
+                fs,
+                inode_id(id, index + 1),
+                mode,
+                owner,
+                content,
+            ));
+        }
+        let index = TASK_FILES.len() + 1;
+        let linux = task.proc.linux();
+        match (name, self.kind) {
+            ("cwd", _) => {
+                let target = linux.current_working_directory();
+                Ok(ProcLink::new(fs, inode_id(id, index), owner, target, None))
+            }
+            ("exe", _) => {
+                let target = linux.execute_path();
+                Ok(ProcLink::new(
+                    fs,
+                    inode_id(id, index + 1),
+                    owner,
+                    target,
+                    None,
+                ))
+            }
+            ("fd", _) => Ok(fs.dir(DirKind::Fd(task.proc.id()))),
+            ("task", DirKind::Process(pid)) => Ok(fs.dir(DirKind::Task(pid))),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn find_fd(&self, pid: KoID, name: &str) -> Result<Arc<dyn INode>> {
+        let proc = find_process(pid)?;
+        let fd: i32 = name.parse().map_err(|_| FsError::EntryNotFound)?;
+        let file = proc
+            .linux()
+            .get_file_like(FileDesc::from(fd))
+            .map_err(|_| FsError::EntryNotFound)?;
+        let (target, inode) = match file.clone().downcast_arc::<File>() {
+            Ok(file) if file.inode().downcast_ref::<Pipe>().is_some() => {
+                (format!("pipe:[{}]", file.id()), Some(file.inode()))
+            }
+            Ok(file) => (file.path().clone(), Some(file.inode())),
+            Err(_) if file.as_socket().is_ok() => (format!("socket:[{}]", file.id()), None),
+            Err(_) if file.is::<MemFd>() => (file.downcast_ref::<MemFd>().unwrap().path(), None),
+            Err(_) => (format!("anon_inode:[{}]", file.type_name()), None),
+        };
+        Ok(ProcLink::new(
+            &self.fs,
+            fd_inode_id(pid, fd as usize),
+            owner(&proc),
+            target,
+            inode,
+        ))
+    }
+}
+
+impl INode for ProcDir {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let mut info = dir_metadata(self.inode_id(), 0o555);
+        if let DirKind::Fd(_) = self.kind {
+            info.mode = 0o500;
+        }
+        if let Ok(task) = self.task() {
+            let (uid, gid) = owner(&task.proc);
+            info.uid = uid as usize;
+            info.gid = gid as usize;
+        }
+        Ok(info)
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let fs = &self.fs;
+        match (name, self.kind) {
+            (".", kind) => Ok(fs.dir(kind)),
+            ("..", DirKind::Root) | ("..", DirKind::Process(_)) => Ok(fs.root_inode()),
+            ("..", DirKind::Fd(pid)) | ("..", DirKind::Task(pid)) => {
+                Ok(fs.dir(DirKind::Process(pid)))
+            }
+            ("..", DirKind::Thread(pid, _)) => Ok(fs.dir(DirKind::Task(pid))),
+            (_, DirKind::Root) => self.find_in_root(name),
+            (_, DirKind::Process(_)) | (_, DirKind::Thread(..)) => self.find_in_task(name),
+            (_, DirKind::Fd(pid)) => self.find_fd(pid, name),
+            (_, DirKind::Task(pid)) => {
+                let tid = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                find_thread(pid, tid)?;
+                Ok(fs.dir(DirKind::Thread(pid, tid)))
+            }
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(".".to_string()),
+            1 => Ok("..".to_string()),
+            _ => self
+                .names()?
+                .into_iter()
+                .nth(id - 2)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A read-only file with the content generated when it is opened
+struct ProcFile {
+    fs: Arc<ProcFS>,
+    inode_id: usize,
+    mode: u16,
+    owner: (u32, u32),
+    content: Vec<u8>,
+}
+
+impl ProcFile {
+    fn new(
+        fs: &Arc<ProcFS>,
+        inode_id: usize,
+        mode: u16,
+        owner: (u32, u32),
+        content: String,
+    ) -> Arc<dyn INode> {
+        Arc::new(ProcFile {
+            fs: fs.clone(),
+            inode_id,
+            mode,
+            owner,
+            content: content.into_bytes(),
+        })
+    }
+}
+
+impl INode for ProcFile {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if offset >= self.content.len() {
+            return Ok(0);
+        }
+        let len = (self.content.len() - offset).min(buf.len());
+        buf[..len].copy_from_slice(&self.content[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let mut info = dir_metadata(self.inode_id, self.mode);
+        info.type_ = FileType::File;
+        // the size is unknown before reading in Linux
+        info.size = 0;
+        info.nlinks = 1;
+        info.uid = self.owner.0 as usize;
+        info.gid = self.owner.1 as usize;
+        Ok(info)
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A symbolic link, which may refer to a file without a path
+struct ProcLink {
+    fs: Arc<ProcFS>,
+    inode_id: usize,
+    owner: (u32, u32),
+    target: String,
+    /// the file followed to, instead of looking up `target`
+    inode: Option<Arc<dyn INode>>,
+}
+
+impl ProcLink {
+    fn new(
+        fs: &Arc<ProcFS>,
+        inode_id: usize,
+        owner: (u32, u32),
+        target: String,
+        inode: Option<Arc<dyn INode>>,
+    ) -> Arc<dyn INode> {
+        Arc::new(ProcLink {
+            fs: fs.clone(),
+            inode_id,
+            owner,
+            target,
+            inode,
+        })
+    }
+}
+
+impl INode for ProcLink {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let target = self.target.as_bytes();
+        if offset >= target.len() {
+            return Ok(0);
+        }
+        let len = (target.len() - offset).min(buf.len());
+        buf[..len].copy_from_slice(&target[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let mut info = dir_metadata(self.inode_id, 0o777);
+        info.type_ = FileType::SymLink;
+        info.size = self.target.len();
+        info.nlinks = 1;
+        info.uid = self.owner.0 as usize;
+        info.gid = self.owner.1 as usize;
+        Ok(info)
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn dir_metadata(inode: usize, mode: u16) -> Metadata {
+    Metadata {
+        dev: 0,
+        inode,
+        size: 0,
+        blk_size: 4096,
+        blocks: 0,
+        atime: Timespec { sec: 0, nsec: 0 },
+        mtime: Timespec { sec: 0, nsec: 0 },
+        ctime: Timespec { sec: 0, nsec: 0 },
+        type_: FileType::Dir,
+        mode,
+        nlinks: 2,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+    }
+}
@@ -0,0 +1,269 @@
+//! The files of processes and threads in procfs
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{convert::TryFrom, fmt::Write};
+
+use zircon_object::{
+    object::{KernelObject, KoID},
+    task::{Process, Status, Thread, ThreadState},
+    vm::{MMUFlags, PAGE_SIZE},
+};
+
+use crate::process::{current_process, threads, ProcessExt};
+use crate::ptrace;
+use crate::signal::{Signal, SIG_DFL, SIG_IGN};
+use crate::thread::ThreadExt;
+
+/// A process, or one of its threads
+pub(super) struct Task {
+    pub proc: Arc<Process>,
+    pub thread: Option<Arc<Thread>>,
+}
+
+/// A file in the directory of a task, the function generating its content, and its mode
+pub(super) type TaskFile = (&'static str, fn(&Task) -> String, u16);
+
+/// The files in the directory of a task
+pub(super) const TASK_FILES: &[TaskFile] = &[
+    ("cmdline", cmdline, 0o444),
+    ("comm", comm, 0o444),
+    ("environ", environ, 0o400),
+    ("maps", maps, 0o444),
+    ("stat", stat, 0o444),
+    ("status", status, 0o444),
+];
+
+impl Task {
+    /// The process ID, or the thread ID for a thread
+    fn id(&self) -> KoID {
+        self.thread.as_ref().map_or(self.proc.id(), |t| t.id())
+    }
+
+    /// The threads of the task
+    fn threads(&self) -> Vec<Arc<Thread>> {
+        match &self.thread {
+            Some(thread) => vec![thread.clone()],
+            None => threads(&self.proc),
+        }
+    }
+
+    /// The state code and name of the task
+    fn state(&self) -> (char, &'static str) {
+        if let Status::Exited(_) = self.proc.status() {
+            return ('Z', "zombie");
+        }
+        if self.proc.linux().is_stopped() {
+            return ('T', "stopped");
+        }
+        let current = current_process().map_or(false, |p| p.id() == self.proc.id());
+        let running = self
+            .threads()
+            .iter()
+            .any(|t| t.state() == ThreadState::Running);
+        if current || running {
+            ('R', "running")
+        } else {
+            ('S', "sleeping")
+        }
+    }
+
+    /// The pending, blocked, ignored and caught signals
+    fn signals(&self) -> (u64, u64, u64, u64) {
+        let (mut pending, mut blocked) = (0, 0);
+        for (i, thread) in self.threads().iter().enumerate() {
+            let linux = thread.lock_linux();
+            pending |= linux.signals.val();
+            if i == 0 {
+                blocked = linux.signal_mask.val();
+            }
+        }
+        let (mut ignored, mut caught) = (0, 0);
+        for signal in (1..=Signal::RTMAX as u8).filter_map(|i| Signal::try_from(i).ok()) {
+            match self.proc.linux().signal_action(signal).handler {
+                SIG_DFL => {}
+                SIG_IGN => ignored |= signal.as_bit(),
+                _ => caught |= signal.as_bit(),
+            }
+        }
+        (pending, blocked, ignored, caught)
+    }
+
+    /// The size of the address space and the memory in use
+    fn memory(&self) -> (usize, usize) {
+        let vmar = self.proc.vmar();
+        let size = vmar.mappings().iter().map(|m| m.size).sum();
+        let stats = vmar.get_task_stats();
+        (size, (stats.private_bytes + stats.shared_bytes) as usize)
+    }
+}
+
+/// The file name of the program, at most 15 bytes
+fn name(task: &Task) -> String {
+    let path = task.proc.linux().execute_path();
+    let name = path.rsplit('/').next().unwrap_or_default();
+    name.chars().take(15).collect()
+}
+
+/// `/proc/[pid]/cmdline`
+fn cmdline(task: &Task) -> String {
+    let (args, _) = task.proc.linux().args();
+    args.iter().map(|arg| arg.clone() + "\0").collect()
+}
+
+/// `/proc/[pid]/comm`
+fn comm(task: &Task) -> String {
+    name(task) + "\n"
+}
+
+/// `/proc/[pid]/environ`
+fn environ(task: &Task) -> String {
+    let (_, envs) = task.proc.linux().args();
+    envs.iter().map(|env| env.clone() + "\0").collect()
+}
+
+/// `/proc/[pid]/maps`
+fn maps(task: &Task) -> String {
+    let mut s = String::new();
+    for map in task.proc.vmar().mappings() {
+        let flag = |flag, c| if map.flags.contains(flag) { c } else { '-' };
+        let name = map.vmo.name();
+        let offset = if name.is_empty() || name.starts_with('[') {
+            0
+        } else {
+            map.vmo_offset
+        };
+        let line = format!(
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0",
+            map.addr,
+            map.addr + map.size,
+            flag(MMUFlags::READ, 'r'),
+            flag(MMUFlags::WRITE, 'w'),
+            flag(MMUFlags::EXECUTE, 'x'),
+            if map.shared { 's' } else { 'p' },
+            offset,
+        );
+        if name.is_empty() {
+            writeln!(s, "{}", line).unwrap();
+        } else {
+            writeln!(s, "{:<72} {}", line, name).unwrap();
+        }
+    }
+    s
+}
+
+/// `/proc/[pid]/stat`
+fn stat(task: &Task) -> String {
+    let linux = task.proc.linux();
+    let ppid = linux.parent().map_or(0, |p| p.id());
+    let (pending, blocked, ignored, caught) = task.signals();
+    let (size, rss) = task.memory();
+    let brk_start = linux.heap().0;
+    let exit_code = task.proc.exit_code().unwrap_or(0);
+    let mut s = format!(
+        "{} ({}) {} {} {} {} 0 -1",
+        task.id(),
+        name(task),
+        task.state().0,
+        ppid,
+        linux.pgid(),
+        linux.sid(),
+    );
+    // from `flags` to `exit_code`, the unknown ones are 0
+    let fields: [u64; 44] = [
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        20,
+        0,
+        task.threads().len() as u64,
+        0,
+        0,
+        size as u64,
+        (rss / PAGE_SIZE) as u64,
+        u64::MAX,
+        0,
+        0,
+        0,
+        0,
+        0,
+        pending,
+        blocked,
+        ignored,
+        caught,
+        0,
+        0,
+        0,
+        Signal::SIGCHLD as u64,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        brk_start as u64,
+        0,
+        0,
+        0,
+        0,
+        exit_code as u64,
+    ];
+    for field in fields.iter() {
+        write!(s, " {}", field).unwrap();
+    }
+    s.push('\n');
+    s
+}
+
+/// `/proc/[pid]/status`
+fn status(task: &Task) -> String {
+    let linux = task.proc.linux();
+    let cred = linux.credentials();
+    let (state, state_name) = task.state();
+    let (pending, blocked, ignored, caught) = task.signals();
+    let (size, rss) = task.memory();
+    let groups: Vec<_> = cred.groups.iter().map(|g| g.to_string()).collect();
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", name(task)).unwrap();
+    writeln!(s, "Umask:\t{:04o}", linux.umask()).unwrap();
+    writeln!(s, "State:\t{} ({})", state, state_name).unwrap();
+    writeln!(s, "Tgid:\t{}", task.proc.id()).unwrap();
+    writeln!(s, "Pid:\t{}", task.id()).unwrap();
+    writeln!(s, "PPid:\t{}", linux.parent().map_or(0, |p| p.id())).unwrap();
+    let tracer = ptrace::tracer(&task.proc).map_or(0, |t| t.id());
+    writeln!(s, "TracerPid:\t{}", tracer).unwrap();
+    writeln!(
+        s,
+        "Uid:\t{}\t{}\t{}\t{}",
+        cred.uid, cred.euid, cred.suid, cred.fsuid
+    )
+    .unwrap();
+    writeln!(
+        s,
+        "Gid:\t{}\t{}\t{}\t{}",
+        cred.gid, cred.egid, cred.sgid, cred.fsgid
+    )
+    .unwrap();
+    writeln!(s, "FDSize:\t{}", linux.file_limit(None).cur).unwrap();
+    writeln!(s, "Groups:\t{}", groups.join(" ")).unwrap();
+    writeln!(s, "VmSize:\t{:8} kB", size / 1024).unwrap();
+    writeln!(s, "VmRSS:\t{:8} kB", rss / 1024).unwrap();
+    writeln!(s, "Threads:\t{}", task.threads().len()).unwrap();
+    writeln!(s, "SigPnd:\t{:016x}", pending).unwrap();
+    writeln!(s, "ShdPnd:\t{:016x}", 0).unwrap();
+    writeln!(s, "SigBlk:\t{:016x}", blocked).unwrap();
+    writeln!(s, "SigIgn:\t{:016x}", ignored).unwrap();
+    writeln!(s, "SigCgt:\t{:016x}", caught).unwrap();
+    s
+}
@@ -0,0 +1,388 @@
+//! Implement signalfd
+#![deny(missing_docs)]
+
+use alloc::{
+    boxed::Box,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use lock::Mutex;
+use zircon_object::object::*;
+use zircon_object::task::{Process, Thread};
+
+use super::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::error::{LxError, LxResult};
+use crate::process::{current_thread, ProcessExt};
+use crate::signal::{SigInfo, Signal, Sigset};
+use crate::sync::EventBus;
+use crate::thread::{LinuxThread, ThreadExt};
+
+bitflags::bitflags! {
+    /// Flags of `signalfd4`
+    pub struct SignalFdFlags: usize {
+        /// Set the O_NONBLOCK file status flag
+        const NON_BLOCK = 1 << 11;
+        /// Set the close-on-exec flag
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+/// `struct signalfd_siginfo`, which is read from a signalfd
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SignalFdSigInfo {
+    /// Signal number
+    pub signo: u32,
+    /// Error number (unused)
+    pub errno: i32,
+    /// Signal code
+    pub code: i32,
+    /// PID of sender
+    pub pid: u32,
+    /// Real UID of sender
+    pub uid: u32,
+    /// File descriptor (SIGIO)
+    pub fd: i32,
+    /// Kernel timer ID (POSIX timers)
+    pub tid: u32,
+    /// Band event (SIGIO)
+    pub band: u32,
+    /// POSIX timer overrun count
+    pub overrun: u32,
+    /// Trap number that caused signal
+    pub trapno: u32,
+    /// Exit status or signal (SIGCHLD)
+    pub status: i32,
+    /// Integer sent by sigqueue
+    pub int: i32,
+    /// Pointer sent by sigqueue
+    pub ptr: u64,
+    /// User CPU time consumed (SIGCHLD)
+    pub utime: u64,
+    /// System CPU time consumed (SIGCHLD)
+    pub stime: u64,
+    /// Address that generated signal
+    pub addr: u64,
+    /// Least significant bit of address
+    pub addr_lsb: u16,
+    _pad2: u16,
+    /// System call number
+    pub syscall: i32,
+    /// System call address
+    pub call_addr: u64,
+    /// System call architecture
+    pub arch: u32,
+    _pad: [u8; 28],
+}
+
+/// Size of `struct signalfd_siginfo`
+const SIGINFO_SIZE: usize = core::mem::size_of::<SignalFdSigInfo>();
+
+impl From<&SigInfo> for SignalFdSigInfo {
+    fn from(info: &SigInfo) -> Self {
+        SignalFdSigInfo {
+            signo: info.signal() as u32,
+            errno: info.errno,
+            code: info.code,
+            pid: info.pid() as u32,
+            uid: info.uid(),
+            status: info.status(),
+            int: info.value() as i32,
+            ptr: info.value() as u64,
+            addr: info.addr() as u64,
+            ..Default::default()
+        }
+    }
+}
+
+impl SignalFdSigInfo {
+    #[allow(unsafe_code)]
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, SIGINFO_SIZE) }
+    }
+}
+
+/// File descriptor for accepting signals
+///
+/// Signals in the mask are taken from the signals sent to the process, which are
+/// pending in any of its threads, and the signals sent to the calling thread.
+/// They should be blocked to avoid being handled as usual.
+pub struct SignalFd {
+    /// Kernel object base
+    base: KObjectBase,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+    /// signals to accept, shared by the duplicated files
+    mask: Arc<Mutex<Sigset>>,
+    /// the process which created the signalfd
+    process: Weak<Process>,
+    /// event bus of the process, pulsed when a signal is queued
+    signal_event: Arc<Mutex<EventBus>>,
+}
+
+impl_kobject!(SignalFd);
+
+impl SignalFd {
+    /// Create a signalfd accepting signals in `mask` which are sent to `process`
+    pub fn new(process: &Arc<Process>, mask: Sigset, flags: SignalFdFlags) -> Arc<Self> {
+        let mut open_flags = OpenFlags::RDONLY;
+        open_flags.set(
+            OpenFlags::NON_BLOCK,
+            flags.contains(SignalFdFlags::NON_BLOCK),
+        );
+        open_flags.set(OpenFlags::CLOEXEC, flags.contains(SignalFdFlags::CLOEXEC));
+        Arc::new(SignalFd {
+            base: KObjectBase::new(),
+            flags: Mutex::new(open_flags),
+            mask: Arc::new(Mutex::new(Self::sanitize(mask))),
+            process: Arc::downgrade(process),
+            signal_event: process.linux().signal_event(),
+        })
+    }
+
+    /// Replace the mask of signals to accept
+    pub fn set_mask(&self, mask: Sigset) {
+        *self.mask.lock() = Self::sanitize(mask);
+    }
+
+    /// SIGKILL and SIGSTOP can not be accepted by signalfd
+    fn sanitize(mut mask: Sigset) -> Sigset {
+        mask.remove(Signal::SIGKILL);
+        mask.remove(Signal::SIGSTOP);
+        mask
+    }
+
+    /// Linux threads of the process
+    fn threads(&self) -> Vec<Arc<Thread>> {
+        let process = match self.process.upgrade() {
+            Some(process) => process,
+            None => return Vec::new(),
+        };
+        process
+            .thread_ids()
+            .into_iter()
+            .filter_map(|tid| process.get_child(tid).ok())
+            .filter_map(|obj| obj.downcast_arc::<Thread>().ok())
+            .collect()
+    }
+
+    /// The pending signals of `thread` in the mask which the calling thread can take:
+    /// all of them if it is the calling thread, or those sent to the process
+    fn acceptable(&self, thread: &Thread, linux: &LinuxThread, current: Option<KoID>) -> Sigset {
+        let pending = if current == Some(thread.id()) {
+            linux.signals
+        } else {
+            linux.process_signals()
+        };
+        Sigset::new(pending.val() & self.mask.lock().val())
+    }
+
+    /// Whether there is any pending signal in the mask
+    fn has_pending(&self) -> bool {
+        let current = current_thread().map(|thread| thread.id());
+        self.threads().iter().any(|thread| {
+            let linux = thread.lock_linux();
+            self.acceptable(thread, &linux, current).is_not_empty()
+        })
+    }
+
+    /// Wait until a signal in the mask is pending, if `events` asks for reading
+    fn wait_pending(&self, events: PollEvents) -> impl Future<Output = ()> + '_ {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct SignalFdFuture<'a> {
+            signalfd: &'a SignalFd,
+            events: PollEvents,
+        }
+
+        impl<'a> Future for SignalFdFuture<'a> {
+            type Output = ();
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let ready = || self.events.contains(PollEvents::IN) && self.signalfd.has_pending();
+                if ready() {
+                    return Poll::Ready(());
+                }
+                // subscribe before checking again, the threads are not locked with the event bus
+                let waker = cx.waker().clone();
+                self.signalfd
+                    .signal_event
+                    .lock()
+                    .subscribe(Box::new(move |_| {
+                        waker.wake_by_ref();
+                        true
+                    }));
+                if ready() {
+                    return Poll::Ready(());
+                }
+                Poll::Pending
+            }
+        }
+
+        SignalFdFuture {
+            signalfd: self,
+            events,
+        }
+    }
+
+    /// Take a pending signal in the mask
+    fn dequeue(&self) -> Option<SigInfo> {
+        let current = current_thread().map(|thread| thread.id());
+        self.threads().iter().find_map(|thread| {
+            let mut linux = thread.lock_linux();
+            let set = self.acceptable(thread, &linux, current);
+            linux.take_signal(&set)
+        })
+    }
+
+    /// Take as many pending signals as `buf` can hold
+    fn try_read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        let mut len = 0;
+        for chunk in buf.chunks_exact_mut(SIGINFO_SIZE) {
+            let info = match self.dequeue() {
+                Some(info) => SignalFdSigInfo::from(&info),
+                None => break,
+            };
+            chunk.copy_from_slice(info.as_bytes());
+            len += SIGINFO_SIZE;
+        }
+        if len == 0 {
+            return Err(LxError::EAGAIN);
+        }
+        Ok(len)
+    }
+}
+
+#[async_trait]
+impl FileLike for SignalFd {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(SignalFd {
+            base: KObjectBase::new(),
+            flags: Mutex::new(self.flags()),
+            mask: self.mask.clone(),
+            process: self.process.clone(),
+            signal_event: self.signal_event.clone(),
+        })
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        if buf.len() < SIGINFO_SIZE {
+            return Err(LxError::EINVAL);
+        }
+        loop {
+            match self.try_read(buf) {
+                Err(LxError::EAGAIN) if !self.flags().contains(OpenFlags::NON_BLOCK) => {
+                    self.wait_pending(PollEvents::IN).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(PollStatus {
+            read: self.has_pending(),
+            write: false,
+            error: false,
+        })
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        self.wait_pending(events).await;
+        self.poll(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::{send_signal, send_signal_to_thread};
+    use crate::signal::SignalCode;
+    use rcore_fs_ramfs::RamFS;
+    use zircon_object::task::Job;
+
+    fn sigset(signals: &[Signal]) -> Sigset {
+        let mut set = Sigset::empty();
+        for &signal in signals {
+            set.insert(signal);
+        }
+        set
+    }
+
+    async fn read(sfd: &dyn FileLike) -> LxResult<u32> {
+        let mut info = SignalFdSigInfo::default();
+        #[allow(unsafe_code)]
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(&mut info as *mut _ as *mut u8, SIGINFO_SIZE)
+        };
+        sfd.read(buf).await?;
+        Ok(info.signo)
+    }
+
+    #[async_std::test]
+    async fn dup() {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let _thread = Thread::create_linux(&proc).unwrap();
+        let sfd = SignalFd::new(&proc, sigset(&[Signal::SIGUSR1]), SignalFdFlags::NON_BLOCK);
+        let dup = sfd.dup();
+
+        // the duplicated file shares the mask
+        dup.downcast_ref::<SignalFd>()
+            .unwrap()
+            .set_mask(sigset(&[Signal::SIGUSR1, Signal::SIGUSR2]));
+        send_signal(
+            &proc,
+            SigInfo::new(Signal::SIGUSR2, SignalCode::USER as i32),
+        );
+        assert!(dup.poll(PollEvents::IN).unwrap().read);
+        assert_eq!(read(&*sfd).await.unwrap(), Signal::SIGUSR2 as u32);
+        assert!(matches!(read(&*dup).await, Err(LxError::EAGAIN)));
+    }
+
+    #[async_std::test]
+    async fn thread_signals() {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let thread = Thread::create_linux(&proc).unwrap();
+        let mask = sigset(&[Signal::SIGUSR1, Signal::SIGUSR2]);
+        let sfd = SignalFd::new(&proc, mask, SignalFdFlags::NON_BLOCK);
+
+        // a signal sent to another thread is not taken
+        let info = SigInfo::new(Signal::SIGUSR1, SignalCode::TKILL as i32);
+        send_signal_to_thread(&thread, info);
+        assert!(!sfd.poll(PollEvents::IN).unwrap().read);
+        assert!(matches!(read(&*sfd).await, Err(LxError::EAGAIN)));
+
+        // but a signal sent to the process is, from any of its threads
+        let info = SigInfo::new(Signal::SIGUSR2, SignalCode::USER as i32);
+        send_signal(&proc, info);
+        assert!(sfd.poll(PollEvents::IN).unwrap().read);
+        assert_eq!(read(&*sfd).await.unwrap(), Signal::SIGUSR2 as u32);
+        assert!(matches!(read(&*sfd).await, Err(LxError::EAGAIN)));
+        assert!(thread.lock_linux().signals.contains(Signal::SIGUSR1));
+    }
+}
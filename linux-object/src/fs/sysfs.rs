@@ -0,0 +1,376 @@
+//! System file system, describing the devices found by the drivers
+//!
+//! The tree is generated when the file system is created, after the drivers are initialized.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+
+use kernel_hal::drivers::{self, prelude::CapabilityType};
+use rcore_fs::vfs::*;
+
+/// The major number of SCSI disks, `/dev/sd*`
+const BLOCK_MAJOR: usize = 8;
+/// The major number of frame buffers, `/dev/fb*`
+const FB_MAJOR: usize = 0x1d;
+/// The major number of input devices, `/dev/input/*`
+const INPUT_MAJOR: usize = 0xd;
+/// The minor number of the first event device, `/dev/input/event0`
+const EVENT_MINOR_BASE: usize = 0x40;
+/// The major number of serial ports, `/dev/ttyS*`
+const TTY_MAJOR: usize = 4;
+
+/// The capabilities of input devices in `capabilities/`
+const INPUT_CAPABILITIES: &[(&str, CapabilityType)] = &[
+    ("abs", CapabilityType::AbsAxis),
+    ("ev", CapabilityType::Event),
+    ("ff", CapabilityType::FeedBack),
+    ("key", CapabilityType::Key),
+    ("led", CapabilityType::Led),
+    ("msc", CapabilityType::Misc),
+    ("rel", CapabilityType::RelAxis),
+    ("snd", CapabilityType::Sound),
+    ("sw", CapabilityType::Switch),
+];
+
+/// The content of a file in sysfs
+enum Content {
+    Dir,
+    File(String),
+    SymLink(String),
+}
+
+/// A file in sysfs
+struct Entry {
+    inode_id: usize,
+    content: Content,
+}
+
+/// System file system
+pub struct SysFS {
+    self_ref: Weak<SysFS>,
+    /// all files indexed by their paths relative to the root, which is `""`
+    entries: BTreeMap<String, Entry>,
+}
+
+impl SysFS {
+    /// Create a new system file system with the devices from drivers
+    pub fn new() -> Arc<Self> {
+        let mut tree = Tree::default();
+        tree.dir("");
+        for class in ["block", "graphics", "input", "net", "tty"].iter() {
+            tree.dir(&format!("class/{}", class));
+        }
+        add_net_devices(&mut tree);
+        add_block_devices(&mut tree);
+        add_display_devices(&mut tree);
+        add_tty_devices(&mut tree);
+        let entries = tree
+            .0
+            .into_iter()
+            .enumerate()
+            .map(|(i, (path, content))| {
+                let entry = Entry {
+                    inode_id: i + 1,
+                    content,
+                };
+                (path, entry)
+            })
+            .collect();
+        Arc::new_cyclic(|self_ref| SysFS {
+            self_ref: self_ref.clone(),
+            entries,
+        })
+    }
+
+    fn inode(&self, path: String) -> Arc<dyn INode> {
+        Arc::new(SysINode {
+            fs: self.self_ref.upgrade().unwrap(),
+            path,
+        })
+    }
+}
+
+impl FileSystem for SysFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.inode(String::new())
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: 4096,
+            frsize: 4096,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: self.entries.len(),
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+/// The files of sysfs being built
+#[derive(Default)]
+struct Tree(BTreeMap<String, Content>);
+
+impl Tree {
+    fn dir(&mut self, path: &str) {
+        self.0.insert(path.into(), Content::Dir);
+    }
+
+    fn file(&mut self, path: &str, content: String) {
+        self.0.insert(path.into(), Content::File(content + "\n"));
+    }
+
+    fn link(&mut self, path: &str, target: &str) {
+        self.0.insert(path.into(), Content::SymLink(target.into()));
+    }
+
+    /// Add a device directory with `dev` and `uevent`
+    fn device(&mut self, dir: &str, devname: &str, rdev: (usize, usize)) {
+        let (major, minor) = rdev;
+        self.dir(dir);
+        self.file(&format!("{}/dev", dir), format!("{}:{}", major, minor));
+        self.file(
+            &format!("{}/uevent", dir),
+            format!("MAJOR={}\nMINOR={}\nDEVNAME={}", major, minor, devname),
+        );
+    }
+}
+
+/// `/sys/class/net/*`
+fn add_net_devices(tree: &mut Tree) {
+    for (i, iface) in kernel_hal::net::get_net_device().iter().enumerate() {
+        let ifname = iface.get_ifname();
+        let mac = iface.get_mac();
+        let loopback = mac.as_bytes().iter().all(|&b| b == 0);
+        let address: Vec<_> = mac
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let dir = format!("class/net/{}", ifname);
+        tree.dir(&dir);
+        tree.file(&format!("{}/address", dir), address.join(":"));
+        tree.file(&format!("{}/addr_len", dir), "6".into());
+        tree.file(&format!("{}/ifindex", dir), i.to_string());
+        tree.file(&format!("{}/operstate", dir), "up".into());
+        // the MTU and the ARP hardware type of ethernet and loopback
+        let (mtu, type_) = if loopback { (65536, 772) } else { (1500, 1) };
+        tree.file(&format!("{}/mtu", dir), mtu.to_string());
+        tree.file(&format!("{}/type", dir), type_.to_string());
+        tree.file(
+            &format!("{}/uevent", dir),
+            format!("INTERFACE={}\nIFINDEX={}", ifname, i),
+        );
+    }
+}
+
+/// `/sys/class/block/*`, the same devices as `/dev/sd*`
+fn add_block_devices(tree: &mut Tree) {
+    for i in 0..drivers::all_block().as_vec().len().min(26) {
+        let name = format!("sd{}", (b'a' + i as u8) as char);
+        let dir = format!("class/block/{}", name);
+        tree.device(&dir, &name, (BLOCK_MAJOR, i * 16));
+        tree.file(&format!("{}/ro", dir), "0".into());
+        tree.file(&format!("{}/removable", dir), "0".into());
+    }
+}
+
+/// `/sys/class/graphics/fb0` and `/sys/class/input/*`, the same devices as
+/// `/dev/fb0` and `/dev/input/event*`, which exist only with a display
+fn add_display_devices(tree: &mut Tree) {
+    let display = match drivers::all_display().first() {
+        Some(display) => display,
+        None => return,
+    };
+    let info = display.info();
+    tree.device("class/graphics/fb0", "fb0", (FB_MAJOR, 0));
+    tree.file("class/graphics/fb0/name", display.name().into());
+    tree.file(
+        "class/graphics/fb0/virtual_size",
+        format!("{},{}", info.width, info.height),
+    );
+    tree.file(
+        "class/graphics/fb0/bits_per_pixel",
+        info.format.depth().to_string(),
+    );
+    tree.file("class/graphics/fb0/stride", info.pitch().to_string());
+
+    for (i, input) in drivers::all_input().as_vec().iter().enumerate() {
+        let dir = format!("class/input/input{}", i);
+        tree.dir(&dir);
+        tree.file(&format!("{}/name", dir), input.name().into());
+        tree.dir(&format!("{}/capabilities", dir));
+        for (name, cap_type) in INPUT_CAPABILITIES.iter() {
+            let cap = input.capability(*cap_type);
+            let mut words = [0u64; 16];
+            for code in 0..1024 {
+                if cap.contains(code) {
+                    words[code as usize / 64] |= 1 << (code % 64);
+                }
+            }
+            tree.file(&format!("{}/capabilities/{}", dir, name), bitmap(&words));
+        }
+
+        let event = format!("event{}", i);
+        let event_dir = format!("class/input/{}", event);
+        let devname = format!("input/{}", event);
+        tree.device(&event_dir, &devname, (INPUT_MAJOR, EVENT_MINOR_BASE + i));
+        tree.link(&format!("{}/device", event_dir), &format!("../input{}", i));
+    }
+}
+
+/// `/sys/class/tty/*`, the same devices as `/dev/ttyS*`
+fn add_tty_devices(tree: &mut Tree) {
+    for (i, uart) in drivers::all_uart().as_vec().iter().enumerate() {
+        let name = format!("ttyS{}", i);
+        let dir = format!("class/tty/{}", name);
+        tree.device(&dir, &name, (TTY_MAJOR, i));
+        tree.file(&format!("{}/name", dir), uart.name().into());
+    }
+}
+
+/// Format a bitmap like Linux, the words in hex from the highest nonzero one
+fn bitmap(words: &[u64]) -> String {
+    let len = words.iter().rposition(|&w| w != 0).map_or(1, |i| i + 1);
+    let words: Vec<_> = words[..len]
+        .iter()
+        .rev()
+        .map(|w| format!("{:x}", w))
+        .collect();
+    words.join(" ")
+}
+
+/// The directory containing `path`
+fn parent(path: &str) -> &str {
+    path.rfind('/').map_or("", |i| &path[..i])
+}
+
+/// A file in sysfs
+struct SysINode {
+    fs: Arc<SysFS>,
+    path: String,
+}
+
+impl SysINode {
+    fn entry(&self) -> &Entry {
+        &self.fs.entries[&self.path]
+    }
+
+    fn children(&self) -> impl Iterator<Item = &str> {
+        let path = self.path.as_str();
+        self.fs
+            .entries
+            .keys()
+            .filter(move |p| !p.is_empty() && parent(p) == path)
+            .map(|p| p.rsplit('/').next().unwrap())
+    }
+}
+
+impl INode for SysINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = match &self.entry().content {
+            Content::Dir => return Err(FsError::IsDir),
+            Content::File(content) | Content::SymLink(content) => content.as_bytes(),
+        };
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = (content.len() - offset).min(buf.len());
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        match self.entry().content {
+            Content::Dir => Err(FsError::IsDir),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        match self.entry().content {
+            Content::Dir => Err(FsError::IsDir),
+            _ => Ok(PollStatus {
+                read: true,
+                write: false,
+                error: false,
+            }),
+        }
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let entry = self.entry();
+        let (type_, mode, size, nlinks) = match &entry.content {
+            Content::Dir => (FileType::Dir, 0o755, 0, 2),
+            // the size of attributes is a page in Linux
+            Content::File(_) => (FileType::File, 0o444, 4096, 1),
+            Content::SymLink(target) => (FileType::SymLink, 0o777, target.len(), 1),
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: entry.inode_id,
+            size,
+            blk_size: 4096,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode,
+            nlinks,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        if !matches!(self.entry().content, Content::Dir) {
+            return Err(FsError::NotDir);
+        }
+        let path = match name {
+            "." => self.path.clone(),
+            ".." => parent(&self.path).into(),
+            _ if self.path.is_empty() => name.into(),
+            _ => format!("{}/{}", self.path, name),
+        };
+        if !self.fs.entries.contains_key(&path) {
+            return Err(FsError::EntryNotFound);
+        }
+        Ok(self.fs.inode(path))
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        if !matches!(self.entry().content, Content::Dir) {
+            return Err(FsError::NotDir);
+        }
+        match id {
+            0 => Ok(".".to_string()),
+            1 => Ok("..".to_string()),
+            _ => self
+                .children()
+                .nth(id - 2)
+                .map(String::from)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
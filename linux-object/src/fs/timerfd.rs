@@ -0,0 +1,303 @@
+//! Implement timerfd
+#![deny(missing_docs)]
+
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use kernel_hal::timer;
+use lock::Mutex;
+use zircon_object::object::*;
+
+use super::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::error::{LxError, LxResult};
+use crate::sync::{Event, EventBus};
+use crate::time::ITimerSpec;
+
+bitflags::bitflags! {
+    /// Flags of `timerfd_create`
+    pub struct TimerFdFlags: usize {
+        /// Set the O_NONBLOCK file status flag
+        const NON_BLOCK = 1 << 11;
+        /// Set the close-on-exec flag
+        const CLOEXEC = 1 << 19;
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags of `timerfd_settime`
+    pub struct TimerSetFlags: usize {
+        /// `new_value.value` is an absolute time
+        const ABSTIME = 1;
+        /// Cancel reads if the realtime clock is changed
+        const CANCEL_ON_SET = 2;
+    }
+}
+
+/// State of the timer, expirations are counted lazily from the current time
+#[derive(Default)]
+struct TimerFdInner {
+    /// the first expiration, `None` if disarmed
+    deadline: Option<Duration>,
+    /// interval for periodic timer
+    interval: Duration,
+    /// expirations which have been read
+    consumed: u64,
+}
+
+impl TimerFdInner {
+    /// Number of expirations since the timer was armed
+    fn expirations(&self, now: Duration) -> u64 {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                if self.interval == Duration::ZERO {
+                    1
+                } else {
+                    1 + ((now - deadline).as_nanos() / self.interval.as_nanos()) as u64
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// Number of expirations which have not been read
+    fn unread(&self, now: Duration) -> u64 {
+        self.expirations(now) - self.consumed
+    }
+
+    /// The time of the next expiration after `now`
+    fn next_expiration(&self, now: Duration) -> Option<Duration> {
+        let deadline = self.deadline?;
+        if now < deadline {
+            Some(deadline)
+        } else if self.interval == Duration::ZERO {
+            None
+        } else {
+            let nanos = self.interval.as_nanos() * self.expirations(now) as u128;
+            Some(deadline + Duration::from_nanos(nanos as u64))
+        }
+    }
+
+    /// Current setting of the timer
+    fn get(&self, now: Duration) -> ITimerSpec {
+        ITimerSpec {
+            interval: self.interval.into(),
+            value: self
+                .next_expiration(now)
+                .map(|t| t - now)
+                .unwrap_or_default()
+                .into(),
+        }
+    }
+}
+
+/// Timer that notifies via file descriptor
+pub struct TimerFd {
+    /// Kernel object base
+    base: KObjectBase,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+    /// timer state, shared by the duplicated files
+    inner: Arc<Mutex<TimerFdInner>>,
+    /// event bus to wake up waiters when the timer is changed
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl_kobject!(TimerFd);
+
+impl TimerFd {
+    /// Create a disarmed timer
+    pub fn new(flags: TimerFdFlags) -> Arc<Self> {
+        let mut open_flags = OpenFlags::RDONLY;
+        open_flags.set(
+            OpenFlags::NON_BLOCK,
+            flags.contains(TimerFdFlags::NON_BLOCK),
+        );
+        open_flags.set(OpenFlags::CLOEXEC, flags.contains(TimerFdFlags::CLOEXEC));
+        Arc::new(TimerFd {
+            base: KObjectBase::new(),
+            flags: Mutex::new(open_flags),
+            inner: Arc::new(Mutex::new(TimerFdInner::default())),
+            eventbus: EventBus::new(),
+        })
+    }
+
+    /// Arm or disarm the timer, returns the previous setting
+    pub fn set(&self, flags: TimerSetFlags, new_value: ITimerSpec) -> LxResult<ITimerSpec> {
+        let now = timer::timer_now();
+        let mut inner = self.inner.lock();
+        let old = inner.get(now);
+        let value: Duration = new_value.value.into();
+        inner.interval = new_value.interval.into();
+        inner.consumed = 0;
+        inner.deadline = if value == Duration::ZERO {
+            None
+        } else if flags.contains(TimerSetFlags::ABSTIME) {
+            Some(value)
+        } else {
+            Some(now + value)
+        };
+        drop(inner);
+        // wake up the waiters to recalculate the next expiration
+        self.notify();
+        Ok(old)
+    }
+
+    /// Wake up all waiters subscribed on the event bus.
+    ///
+    /// It also drops the callbacks left by waiters which were woken by the timer.
+    fn notify(&self) {
+        let mut eventbus = self.eventbus.lock();
+        eventbus.set(Event::READABLE);
+        eventbus.clear(Event::READABLE);
+    }
+
+    /// Returns the current setting of the timer
+    pub fn get(&self) -> ITimerSpec {
+        self.inner.lock().get(timer::timer_now())
+    }
+
+    /// Take the number of expirations, returns `EAGAIN` if it is zero
+    fn try_read(&self) -> LxResult<u64> {
+        let mut inner = self.inner.lock();
+        let unread = inner.unread(timer::timer_now());
+        if unread == 0 {
+            return Err(LxError::EAGAIN);
+        }
+        inner.consumed += unread;
+        drop(inner);
+        self.notify();
+        Ok(unread)
+    }
+}
+
+#[async_trait]
+impl FileLike for TimerFd {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(TimerFd {
+            base: KObjectBase::new(),
+            flags: Mutex::new(self.flags()),
+            inner: self.inner.clone(),
+            eventbus: self.eventbus.clone(),
+        })
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        if buf.len() < 8 {
+            return Err(LxError::EINVAL);
+        }
+        let value = loop {
+            match self.try_read() {
+                Err(LxError::EAGAIN) if !self.flags().contains(OpenFlags::NON_BLOCK) => {
+                    self.async_poll(PollEvents::IN).await?;
+                }
+                ret => break ret?,
+            }
+        };
+        buf[..8].copy_from_slice(&value.to_ne_bytes());
+        Ok(8)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(PollStatus {
+            read: self.inner.lock().unread(timer::timer_now()) > 0,
+            write: false,
+            error: false,
+        })
+    }
+
+    async fn async_poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct TimerFdFuture<'a> {
+            timerfd: &'a TimerFd,
+        }
+
+        impl<'a> Future for TimerFdFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let now = timer::timer_now();
+                let inner = self.timerfd.inner.lock();
+                if inner.unread(now) > 0 {
+                    return Poll::Ready(Ok(PollStatus {
+                        read: true,
+                        write: false,
+                        error: false,
+                    }));
+                }
+                if let Some(deadline) = inner.next_expiration(now) {
+                    let waker = cx.waker().clone();
+                    timer::timer_set(deadline, Box::new(move |_| waker.wake_by_ref()));
+                }
+                let waker = cx.waker().clone();
+                self.timerfd.eventbus.lock().subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        TimerFdFuture { timerfd: self }.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::TimeSpec;
+
+    #[async_std::test]
+    async fn dup() {
+        let tfd = TimerFd::new(TimerFdFlags::NON_BLOCK);
+        let dup = tfd.dup();
+        let dup = dup.downcast_ref::<TimerFd>().unwrap();
+        let spec = ITimerSpec {
+            interval: TimeSpec::from(Duration::from_secs(100)),
+            value: TimeSpec::from(Duration::from_secs(100)),
+        };
+        tfd.set(TimerSetFlags::empty(), spec).unwrap();
+        // the duplicated file shares the timer
+        let interval: Duration = dup.get().interval.into();
+        assert_eq!(interval, Duration::from_secs(100));
+        dup.set(TimerSetFlags::empty(), ITimerSpec::default())
+            .unwrap();
+        let value: Duration = tfd.get().value.into();
+        assert_eq!(value, Duration::ZERO);
+
+        // an absolute deadline in the past expires at once
+        let spec = ITimerSpec {
+            interval: TimeSpec::default(),
+            value: TimeSpec::from(Duration::from_nanos(1)),
+        };
+        tfd.set(TimerSetFlags::ABSTIME, spec).unwrap();
+        let mut buf = [0; 8];
+        dup.read(&mut buf).await.unwrap();
+        assert_eq!(u64::from_ne_bytes(buf), 1);
+        assert!(matches!(tfd.read(&mut buf).await, Err(LxError::EAGAIN)));
+    }
+}
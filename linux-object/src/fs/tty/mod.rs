@@ -0,0 +1,823 @@
+//! Terminals with a line discipline and job control
+//!
+//! A [`Tty`] processes the input from its device as `termios(3)` describes,
+//! and sends the signals of the special characters to its foreground process group.
+
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use kernel_hal::user::{UserInPtr, UserOutPtr};
+use lock::Mutex;
+use rcore_fs::vfs::*;
+use rcore_fs_devfs::DevFS;
+use zircon_object::{
+    object::{KernelObject, KoID},
+    task::{Job, Process},
+};
+
+use super::ioctl::*;
+use super::OpenFlags;
+use crate::error::{LxError, LxResult};
+use crate::process::{current_process, current_thread, job_processes, send_signal, ProcessExt};
+use crate::signal::{SigInfo, Signal, SignalCode, SIG_IGN};
+use crate::sync::{Event, EventBus};
+use crate::thread::ThreadExt;
+
+pub use self::pty::{DevPts, DEVPTS};
+pub use self::termios::{Termios, WinSize};
+
+use self::pty::{Ptmx, PtyMaster};
+use self::termios::*;
+
+mod pty;
+mod termios;
+
+/// The session whose controlling terminal is a [`Tty`]
+struct Session {
+    /// session ID
+    sid: KoID,
+    /// the job of the processes in the session
+    job: Weak<Job>,
+}
+
+/// A terminal
+pub struct Tty {
+    self_ref: Weak<Tty>,
+    inode_id: usize,
+    rdev: usize,
+    /// the user and group owning the device
+    owner: (u32, u32),
+    inner: Mutex<TtyInner>,
+    eventbus: Mutex<EventBus>,
+    /// writes the output to the device, or the master of a pseudo-terminal
+    output: Box<dyn Fn(&[u8]) + Send + Sync>,
+}
+
+#[derive(Default)]
+struct TtyInner {
+    termios: Termios,
+    winsize: WinSize,
+    /// the line being edited in canonical mode
+    line: Vec<u8>,
+    /// the input ready to read, each item is a line in canonical mode,
+    /// and an empty line is the end of file
+    input: VecDeque<Vec<u8>>,
+    session: Option<Session>,
+    /// foreground process group ID
+    foreground: KoID,
+    /// whether the device is gone, such as the master of a pseudo-terminal is closed
+    hangup: bool,
+    /// whether the slave of a pseudo-terminal can not be opened, see `unlockpt(3)`
+    locked: bool,
+}
+
+impl Tty {
+    /// Create a terminal of the device `rdev` writing its output by `output`.
+    pub fn new(
+        rdev: usize,
+        owner: (u32, u32),
+        output: impl Fn(&[u8]) + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|self_ref| Tty {
+            self_ref: self_ref.clone(),
+            inode_id: DevFS::new_inode_id(),
+            rdev,
+            owner,
+            inner: Mutex::new(TtyInner::default()),
+            eventbus: Mutex::new(EventBus::default()),
+            output: Box::new(output),
+        })
+    }
+
+    fn arc(&self) -> Arc<Self> {
+        self.self_ref.upgrade().unwrap()
+    }
+
+    /// Set the window size.
+    pub fn set_winsize(&self, winsize: WinSize) {
+        self.inner.lock().winsize = winsize;
+    }
+
+    /// Receive the input from the device.
+    pub fn receive(&self, data: &[u8]) {
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        let mut inner = self.inner.lock();
+        for &c in data {
+            inner.receive(c, &mut echo, &mut signals);
+        }
+        let readable = inner.readable();
+        let oflag = inner.termios.oflag;
+        drop(inner);
+        if readable {
+            self.eventbus.lock().set(Event::READABLE);
+        }
+        if !echo.is_empty() {
+            (self.output)(&process_output(oflag, &echo));
+        }
+        for signal in signals {
+            self.signal_foreground(signal);
+        }
+    }
+
+    /// Hang up the terminal as its device is gone.
+    ///
+    /// The foreground process group gets `SIGHUP` and `SIGCONT`,
+    /// and the reads and writes fail afterwards.
+    pub(super) fn hangup(&self) {
+        self.inner.lock().hangup = true;
+        self.eventbus.lock().set(Event::READABLE | Event::CLOSED);
+        self.disassociate();
+    }
+
+    /// Stop being the controlling terminal of the session,
+    /// sending `SIGHUP` and `SIGCONT` to the foreground process group.
+    fn disassociate(&self) {
+        self.signal_foreground(Signal::SIGHUP);
+        self.signal_foreground(Signal::SIGCONT);
+        self.detach_session();
+    }
+
+    /// Make the processes of the session lose their controlling terminal.
+    fn detach_session(&self) {
+        let session = self.inner.lock().session.take();
+        if let Some(job) = session.and_then(|s| s.job.upgrade()) {
+            for proc in job_processes(&job) {
+                if self.controls(&proc) {
+                    proc.linux().set_terminal(None);
+                }
+            }
+        }
+    }
+
+    /// Send `signal` to the foreground process group.
+    fn signal_foreground(&self, signal: Signal) {
+        let inner = self.inner.lock();
+        let job = match inner.session.as_ref().and_then(|s| s.job.upgrade()) {
+            Some(job) => job,
+            None => return,
+        };
+        let pgid = inner.foreground;
+        drop(inner);
+        for proc in job_processes(&job) {
+            if proc.linux().pgid() == pgid {
+                send_signal(&proc, SigInfo::new(signal, SignalCode::KERNEL as i32));
+            }
+        }
+    }
+
+    /// Whether this is the controlling terminal of `proc`
+    fn controls(&self, proc: &Process) -> bool {
+        proc.linux()
+            .terminal()
+            .map_or(false, |tty| core::ptr::eq(&*tty, self))
+    }
+
+    /// Make this the controlling terminal of the session led by `proc`,
+    /// taking it from another session if `steal` is true.
+    pub(crate) fn set_controlling(&self, proc: &Arc<Process>, steal: bool) -> LxResult {
+        let linux = proc.linux();
+        if linux.sid() != proc.id() || linux.terminal().is_some() {
+            return Err(LxError::EPERM);
+        }
+        if self.inner.lock().session.is_some() {
+            if !steal {
+                return Err(LxError::EPERM);
+            }
+            self.detach_session();
+        }
+        let mut inner = self.inner.lock();
+        inner.session = Some(Session {
+            sid: proc.id(),
+            job: Arc::downgrade(&proc.job()),
+        });
+        inner.foreground = linux.pgid();
+        drop(inner);
+        linux.set_terminal(Some(self.arc()));
+        Ok(())
+    }
+
+    /// Stop being the controlling terminal of the session of `proc`, as `TIOCNOTTY` does.
+    fn release(&self, proc: &Arc<Process>) {
+        let linux = proc.linux();
+        linux.set_terminal(None);
+        if linux.sid() != proc.id() {
+            return;
+        }
+        // the session leader gives up the terminal for the whole session
+        self.disassociate();
+    }
+
+    /// Check whether the current process can read or write the terminal.
+    ///
+    /// A process in a background process group of the session gets `signal`,
+    /// unless it ignores or blocks the signal.
+    fn check_background(&self, signal: Signal) -> Result<()> {
+        let thread = match current_thread() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        let proc = thread.proc();
+        if !self.controls(proc) || proc.linux().pgid() == self.inner.lock().foreground {
+            return Ok(());
+        }
+        let ignored = proc.linux().signal_action(signal).handler == SIG_IGN
+            || thread.lock_linux().signal_mask.contains(signal);
+        match (ignored, signal) {
+            (true, Signal::SIGTTIN) => Err(FsError::DeviceError),
+            (true, _) => Ok(()),
+            (false, _) => {
+                let pgid = proc.linux().pgid();
+                for p in job_processes(&proc.job()) {
+                    if p.linux().pgid() == pgid {
+                        send_signal(&p, SigInfo::new(signal, SignalCode::KERNEL as i32));
+                    }
+                }
+                Err(FsError::Interrupted)
+            }
+        }
+    }
+
+    /// Handle `ioctl` on the terminal.
+    pub fn ioctl(&self, cmd: usize, arg: usize) -> LxResult<usize> {
+        match cmd {
+            TCGETS => {
+                let termios = self.inner.lock().termios;
+                UserOutPtr::<Termios>::from(arg).write(termios)?;
+                Ok(0)
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let termios = UserInPtr::<Termios>::from(arg).read()?;
+                let mut inner = self.inner.lock();
+                if cmd == TCSETSF {
+                    inner.flush_input();
+                } else if inner.termios.canonical() && !termios.canonical() {
+                    // the line being edited is available in noncanonical mode
+                    let line = core::mem::take(&mut inner.line);
+                    inner.input.push_back(line);
+                }
+                inner.termios = termios;
+                let readable = inner.readable();
+                drop(inner);
+                if readable {
+                    self.eventbus.lock().set(Event::READABLE);
+                }
+                Ok(0)
+            }
+            TIOCGWINSZ => {
+                let winsize = self.inner.lock().winsize;
+                UserOutPtr::<WinSize>::from(arg).write(winsize)?;
+                Ok(0)
+            }
+            TIOCSWINSZ => {
+                let winsize = UserInPtr::<WinSize>::from(arg).read()?;
+                self.set_winsize(winsize);
+                self.signal_foreground(Signal::SIGWINCH);
+                Ok(0)
+            }
+            TIOCGPGRP => {
+                let proc = current_process().ok_or(LxError::ENOTTY)?;
+                if !self.controls(&proc) {
+                    return Err(LxError::ENOTTY);
+                }
+                let pgid = self.inner.lock().foreground;
+                UserOutPtr::<i32>::from(arg).write(pgid as i32)?;
+                Ok(0)
+            }
+            TIOCSPGRP => {
+                // a background process gets `SIGTTOU` like writing to the terminal
+                self.check_background(Signal::SIGTTOU)?;
+                let proc = current_process().ok_or(LxError::ENOTTY)?;
+                if !self.controls(&proc) {
+                    return Err(LxError::ENOTTY);
+                }
+                let pgid = UserInPtr::<i32>::from(arg).read()?;
+                if pgid < 0 {
+                    return Err(LxError::EINVAL);
+                }
+                let sid = proc.linux().sid();
+                // the process group must be in the same session
+                if !job_processes(&proc.job())
+                    .iter()
+                    .any(|p| p.linux().pgid() == pgid as KoID && p.linux().sid() == sid)
+                {
+                    return Err(LxError::EPERM);
+                }
+                self.inner.lock().foreground = pgid as KoID;
+                Ok(0)
+            }
+            TIOCGSID => {
+                let proc = current_process().ok_or(LxError::ENOTTY)?;
+                if !self.controls(&proc) {
+                    return Err(LxError::ENOTTY);
+                }
+                let sid = self.inner.lock().session.as_ref().map_or(0, |s| s.sid);
+                UserOutPtr::<i32>::from(arg).write(sid as i32)?;
+                Ok(0)
+            }
+            TIOCSCTTY => {
+                let proc = current_process().ok_or(LxError::EPERM)?;
+                if self.controls(&proc) {
+                    return Ok(0);
+                }
+                let steal = arg == 1 && proc.linux().credentials().is_root();
+                self.set_controlling(&proc, steal)?;
+                Ok(0)
+            }
+            TIOCNOTTY => {
+                let proc = current_process().ok_or(LxError::ENOTTY)?;
+                if !self.controls(&proc) {
+                    return Err(LxError::ENOTTY);
+                }
+                self.release(&proc);
+                Ok(0)
+            }
+            FIONREAD => {
+                let inner = self.inner.lock();
+                let len = if inner.termios.canonical() {
+                    inner.input.front().map_or(0, |line| line.len())
+                } else {
+                    inner.input.iter().map(|line| line.len()).sum()
+                };
+                UserOutPtr::<i32>::from(arg).write(len as i32)?;
+                Ok(0)
+            }
+            TCFLSH => {
+                // TCIFLUSH or TCIOFLUSH, the output is not queued
+                if arg == 0 || arg == 2 {
+                    self.inner.lock().flush_input();
+                    self.eventbus.lock().clear(Event::READABLE);
+                }
+                Ok(0)
+            }
+            TIOCOUTQ => {
+                UserOutPtr::<i32>::from(arg).write(0)?;
+                Ok(0)
+            }
+            TCSBRK | TCXONC => Ok(0),
+            _ => Err(LxError::ENOTTY),
+        }
+    }
+}
+
+impl TtyInner {
+    /// Process an input character.
+    fn receive(&mut self, mut c: u8, echo: &mut Vec<u8>, signals: &mut Vec<Signal>) {
+        let termios = self.termios;
+        let (iflag, lflag) = (termios.iflag, termios.lflag);
+        if iflag.contains(InputFlags::ISTRIP) {
+            c &= 0x7f;
+        }
+        if c == b'\r' {
+            if iflag.contains(InputFlags::IGNCR) {
+                return;
+            }
+            if iflag.contains(InputFlags::ICRNL) {
+                c = b'\n';
+            }
+        } else if c == b'\n' && iflag.contains(InputFlags::INLCR) {
+            c = b'\r';
+        }
+
+        if lflag.contains(LocalFlags::ISIG) {
+            let signal = if termios.is_cc(VINTR, c) {
+                Some(Signal::SIGINT)
+            } else if termios.is_cc(VQUIT, c) {
+                Some(Signal::SIGQUIT)
+            } else if termios.is_cc(VSUSP, c) {
+                Some(Signal::SIGTSTP)
+            } else {
+                None
+            };
+            if let Some(signal) = signal {
+                if !lflag.contains(LocalFlags::NOFLSH) {
+                    self.flush_input();
+                }
+                self.echo(c, echo);
+                signals.push(signal);
+                return;
+            }
+        }
+
+        if !termios.canonical() {
+            self.echo(c, echo);
+            match self.input.back_mut() {
+                Some(last) if !last.is_empty() => last.push(c),
+                _ => self.input.push_back(vec![c]),
+            }
+            return;
+        }
+        let erase = lflag.contains(LocalFlags::ECHO) && lflag.contains(LocalFlags::ECHOE);
+        if termios.is_cc(VERASE, c) {
+            if self.line.pop().is_some() && erase {
+                echo.extend_from_slice(b"\x08 \x08");
+            }
+        } else if termios.is_cc(VWERASE, c) && lflag.contains(LocalFlags::IEXTEN) {
+            // erase the spaces and then the word before the cursor
+            let mut in_word = false;
+            while let Some(&last) = self.line.last() {
+                if last == b' ' || last == b'\t' {
+                    if in_word {
+                        break;
+                    }
+                } else {
+                    in_word = true;
+                }
+                self.line.pop();
+                if erase {
+                    echo.extend_from_slice(b"\x08 \x08");
+                }
+            }
+        } else if termios.is_cc(VKILL, c) {
+            let len = self.line.len();
+            self.line.clear();
+            if lflag.contains(LocalFlags::ECHO) {
+                if lflag.contains(LocalFlags::ECHOKE) {
+                    for _ in 0..len {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                } else if lflag.contains(LocalFlags::ECHOK) {
+                    self.echo(c, echo);
+                    echo.push(b'\n');
+                }
+            }
+        } else if termios.is_cc(VEOF, c) {
+            // the line is available without the EOF character
+            let line = core::mem::take(&mut self.line);
+            self.input.push_back(line);
+        } else {
+            self.echo(c, echo);
+            self.line.push(c);
+            if c == b'\n' || termios.is_cc(VEOL, c) {
+                let line = core::mem::take(&mut self.line);
+                self.input.push_back(line);
+            }
+        }
+    }
+
+    /// Echo an input character.
+    fn echo(&self, c: u8, echo: &mut Vec<u8>) {
+        let lflag = self.termios.lflag;
+        if !lflag.contains(LocalFlags::ECHO) {
+            if c == b'\n' && lflag.contains(LocalFlags::ECHONL) {
+                echo.push(c);
+            }
+            return;
+        }
+        let control = (c < b' ' && c != b'\n' && c != b'\t') || c == 0x7f;
+        if control && lflag.contains(LocalFlags::ECHOCTL) {
+            echo.push(b'^');
+            echo.push(c ^ 0x40);
+        } else {
+            echo.push(c);
+        }
+    }
+
+    fn flush_input(&mut self) {
+        self.line.clear();
+        self.input.clear();
+    }
+
+    /// Whether a read would not block
+    fn readable(&self) -> bool {
+        if self.hangup {
+            return true;
+        }
+        if self.termios.canonical() {
+            !self.input.is_empty()
+        } else {
+            self.termios.cc[VMIN] == 0 || self.input.iter().any(|line| !line.is_empty())
+        }
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.termios.canonical() {
+            let line = match self.input.front_mut() {
+                Some(line) => line,
+                None if self.hangup => return Ok(0),
+                None => return Err(FsError::Again),
+            };
+            let len = line.len().min(buf.len());
+            buf[..len].copy_from_slice(&line[..len]);
+            line.drain(..len);
+            if line.is_empty() {
+                self.input.pop_front();
+            }
+            return Ok(len);
+        }
+        let mut len = 0;
+        while len < buf.len() {
+            let line = match self.input.front_mut() {
+                Some(line) => line,
+                None => break,
+            };
+            let n = line.len().min(buf.len() - len);
+            buf[len..len + n].copy_from_slice(&line[..n]);
+            line.drain(..n);
+            len += n;
+            if line.is_empty() {
+                self.input.pop_front();
+            }
+        }
+        if len == 0 && !self.hangup && self.termios.cc[VMIN] != 0 {
+            return Err(FsError::Again);
+        }
+        Ok(len)
+    }
+}
+
+/// Process the output as `c_oflag` describes.
+fn process_output(oflag: OutputFlags, buf: &[u8]) -> Vec<u8> {
+    if !oflag.contains(OutputFlags::OPOST | OutputFlags::ONLCR) {
+        return buf.to_vec();
+    }
+    let mut output = Vec::with_capacity(buf.len());
+    for &c in buf {
+        if c == b'\n' {
+            output.push(b'\r');
+        }
+        output.push(c);
+    }
+    output
+}
+
+impl INode for Tty {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.check_background(Signal::SIGTTIN)?;
+        let mut inner = self.inner.lock();
+        let len = inner.read(buf)?;
+        if !inner.readable() {
+            self.eventbus.lock().clear(Event::READABLE);
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let inner = self.inner.lock();
+        if inner.hangup {
+            return Err(FsError::DeviceError);
+        }
+        let (oflag, lflag) = (inner.termios.oflag, inner.termios.lflag);
+        drop(inner);
+        if lflag.contains(LocalFlags::TOSTOP) {
+            self.check_background(Signal::SIGTTOU)?;
+        }
+        (self.output)(&process_output(oflag, buf));
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let inner = self.inner.lock();
+        Ok(PollStatus {
+            read: inner.readable(),
+            write: !inner.hangup,
+            error: inner.hangup,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct TtyFuture<'a> {
+            tty: &'a Tty,
+        }
+
+        impl<'a> Future for TtyFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.tty.inner.lock().readable() {
+                    return Poll::Ready(self.tty.poll());
+                }
+                let waker = cx.waker().clone();
+                self.tty.eventbus.lock().subscribe(Box::new({
+                    move |_| {
+                        waker.wake_by_ref();
+                        true
+                    }
+                }));
+                Poll::Pending
+            }
+        }
+
+        Box::pin(TtyFuture { tty: self })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 1,
+            inode: self.inode_id,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::CharDevice,
+            mode: 0o620,
+            nlinks: 1,
+            uid: self.owner.0 as usize,
+            gid: self.owner.1 as usize,
+            rdev: self.rdev,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// `/dev/tty`, the controlling terminal of the process opening it
+pub(super) struct CurrentTty {
+    inode_id: usize,
+}
+
+impl CurrentTty {
+    /// Create the device
+    pub(super) fn new() -> Self {
+        CurrentTty {
+            inode_id: DevFS::new_inode_id(),
+        }
+    }
+}
+
+impl INode for CurrentTty {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::NoDevice)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 1,
+            inode: self.inode_id,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::CharDevice,
+            mode: 0o666,
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+            rdev: make_rdev(5, 0),
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Open the terminal `inode` for the process `proc`, and get the [`INode`] of the file opened.
+///
+/// `/dev/ptmx` opens the master of a new pseudo-terminal, and `/dev/tty` the controlling terminal.
+/// A session leader without a controlling terminal acquires the terminal opened without `O_NOCTTY`.
+/// Other files are opened as they are.
+pub fn open_tty(
+    proc: &Arc<Process>,
+    inode: Arc<dyn INode>,
+    flags: OpenFlags,
+) -> LxResult<Arc<dyn INode>> {
+    if inode.downcast_ref::<Ptmx>().is_some() {
+        let cred = proc.linux().credentials();
+        return Ok(DEVPTS.open_master((cred.uid, cred.gid)));
+    }
+    if inode.downcast_ref::<CurrentTty>().is_some() {
+        let tty = proc.linux().terminal().ok_or(LxError::ENXIO)?;
+        return Ok(tty);
+    }
+    if let Some(tty) = inode.downcast_ref::<Tty>() {
+        if tty.inner.lock().locked {
+            return Err(LxError::EIO);
+        }
+        let linux = proc.linux();
+        let leader = linux.sid() == proc.id() && linux.terminal().is_none();
+        if leader && !flags.contains(OpenFlags::NOCTTY) && tty.inner.lock().session.is_none() {
+            tty.set_controlling(proc, false)?;
+        }
+    }
+    Ok(inode)
+}
+
+/// Handle `ioctl` on `inode` if it is a terminal, or return `None` otherwise.
+pub(super) fn ioctl(inode: &Arc<dyn INode>, cmd: usize, arg: usize) -> Option<LxResult<usize>> {
+    if let Some(tty) = inode.downcast_ref::<Tty>() {
+        return Some(tty.ioctl(cmd, arg));
+    }
+    if let Some(master) = inode.downcast_ref::<PtyMaster>() {
+        return Some(master.ioctl(cmd, arg));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `input` to the line discipline, returns the echo and the signals
+    fn receive(inner: &mut TtyInner, input: &[u8]) -> (Vec<u8>, Vec<Signal>) {
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        for &c in input {
+            inner.receive(c, &mut echo, &mut signals);
+        }
+        (echo, signals)
+    }
+
+    fn read(inner: &mut TtyInner) -> Result<Vec<u8>> {
+        let mut buf = [0; 64];
+        let len = inner.read(&mut buf)?;
+        Ok(buf[..len].to_vec())
+    }
+
+    #[test]
+    fn canonical() {
+        let mut inner = TtyInner::default();
+        let (echo, _) = receive(&mut inner, b"ab\r");
+        assert_eq!(echo, b"ab\n");
+        assert_eq!(read(&mut inner).unwrap(), b"ab\n");
+
+        // a line is not available until it ends
+        receive(&mut inner, b"cd");
+        assert!(!inner.readable());
+        assert!(matches!(read(&mut inner), Err(FsError::Again)));
+        // EOF ends the line without itself, and an empty line is the end of file
+        receive(&mut inner, b"\x04\x04");
+        assert_eq!(read(&mut inner).unwrap(), b"cd");
+        assert_eq!(read(&mut inner).unwrap(), b"");
+        assert!(!inner.readable());
+
+        // the line being edited is available in noncanonical mode
+        inner.termios.lflag.remove(LocalFlags::ICANON);
+        let (echo, _) = receive(&mut inner, b"x\x7fy");
+        assert_eq!(echo, b"x^?y");
+        assert_eq!(read(&mut inner).unwrap(), b"x\x7fy");
+        assert!(matches!(read(&mut inner), Err(FsError::Again)));
+    }
+
+    #[test]
+    fn erase_and_kill() {
+        let mut inner = TtyInner::default();
+        let (echo, _) = receive(&mut inner, b"abc\x7f");
+        assert_eq!(echo, b"abc\x08 \x08");
+        // nothing is erased at the start of the line
+        let (echo, _) = receive(&mut inner, b"\x7f\x7f\x7f");
+        assert_eq!(echo, b"\x08 \x08\x08 \x08");
+        assert!(inner.line.is_empty());
+
+        // ECHOKE erases each character of the killed line
+        let (echo, _) = receive(&mut inner, b"xy\x15");
+        assert_eq!(echo, b"xy\x08 \x08\x08 \x08");
+        // ECHOK echoes the KILL character and a new line instead
+        inner.termios.lflag.remove(LocalFlags::ECHOKE);
+        let (echo, _) = receive(&mut inner, b"q\x15");
+        assert_eq!(echo, b"q^U\n");
+
+        receive(&mut inner, b"z\n");
+        assert_eq!(read(&mut inner).unwrap(), b"z\n");
+    }
+
+    #[test]
+    fn signals() {
+        let mut inner = TtyInner::default();
+        receive(&mut inner, b"ab\ncd");
+        let (echo, signals) = receive(&mut inner, b"\x03");
+        assert_eq!(echo, b"^C");
+        assert_eq!(signals, [Signal::SIGINT]);
+        // the input is flushed
+        assert!(!inner.readable());
+        assert!(inner.line.is_empty());
+
+        // but not with NOFLSH
+        inner.termios.lflag.insert(LocalFlags::NOFLSH);
+        receive(&mut inner, b"ef");
+        let (_, signals) = receive(&mut inner, b"\x1c\x1a");
+        assert_eq!(signals, [Signal::SIGQUIT, Signal::SIGTSTP]);
+        assert_eq!(inner.line, b"ef");
+
+        // the characters are input without ISIG
+        inner.termios.lflag.remove(LocalFlags::ISIG);
+        let (_, signals) = receive(&mut inner, b"\x03\n");
+        assert!(signals.is_empty());
+        assert_eq!(read(&mut inner).unwrap(), b"ef\x03\n");
+    }
+}
@@ -0,0 +1,354 @@
+//! Pseudo-terminals and the devpts file system, see `pty(7)`
+//!
+//! Opening `/dev/ptmx` creates a master, whose slave appears as `/dev/pts/N`.
+//! The input written to the master goes through the line discipline of the slave,
+//! and the output of the slave is read from the master.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+};
+use core::any::Any;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use kernel_hal::user::{UserInPtr, UserOutPtr};
+use lazy_static::lazy_static;
+use lock::Mutex;
+use rcore_fs::vfs::*;
+
+use super::Tty;
+use crate::error::LxResult;
+use crate::fs::ioctl::*;
+use crate::sync::{Event, EventBus};
+
+/// The major number of `/dev/ptmx`
+const PTMX_MAJOR: usize = 5;
+/// The minor number of `/dev/ptmx`
+const PTMX_MINOR: usize = 2;
+/// The major number of the slaves `/dev/pts/N`
+const PTS_MAJOR: usize = 136;
+/// The inode number of the root directory of devpts
+const ROOT_INODE_ID: usize = 1;
+/// The inode number of `ptmx` in devpts
+const PTMX_INODE_ID: usize = 2;
+
+lazy_static! {
+    /// The devpts file system, mounted at `/dev/pts`
+    pub static ref DEVPTS: Arc<DevPts> = Arc::new_cyclic(|self_ref| DevPts {
+        self_ref: self_ref.clone(),
+        ptmx: Arc::new(Ptmx),
+        slaves: Mutex::new(BTreeMap::new()),
+    });
+}
+
+/// The file system of the slaves of pseudo-terminals
+pub struct DevPts {
+    self_ref: Weak<DevPts>,
+    ptmx: Arc<Ptmx>,
+    /// the slaves indexed by their numbers, alive as long as their masters
+    slaves: Mutex<BTreeMap<usize, Weak<Tty>>>,
+}
+
+impl DevPts {
+    /// The multiplexer device `ptmx`
+    pub fn ptmx(&self) -> Arc<dyn INode> {
+        self.ptmx.clone()
+    }
+
+    /// Create a new pseudo-terminal whose slave is owned by `owner`, and get its master.
+    pub(super) fn open_master(&self, owner: (u32, u32)) -> Arc<dyn INode> {
+        let output = Arc::new(MasterBuffer::default());
+        let mut slaves = self.slaves.lock();
+        // the smallest number not in use
+        let index = (0..).find(|i| !slaves.contains_key(i)).unwrap();
+        let tty = Tty::new(make_rdev(PTS_MAJOR, index), owner, {
+            let output = output.clone();
+            move |buf: &[u8]| output.push(buf)
+        });
+        // the slave can not be opened until `unlockpt`
+        tty.inner.lock().locked = true;
+        slaves.insert(index, Arc::downgrade(&tty));
+        Arc::new(PtyMaster { index, tty, output })
+    }
+
+    /// The slave `index`
+    fn slave(&self, index: usize) -> Option<Arc<Tty>> {
+        self.slaves.lock().get(&index)?.upgrade()
+    }
+}
+
+impl FileSystem for DevPts {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        Arc::new(DevPtsRoot {
+            fs: self.self_ref.upgrade().unwrap(),
+        })
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: 1024,
+            frsize: 1024,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: self.slaves.lock().len() + 2,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+/// The root directory of devpts, containing `ptmx` and the slaves
+struct DevPtsRoot {
+    fs: Arc<DevPts>,
+}
+
+impl INode for DevPtsRoot {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: ROOT_INODE_ID,
+            size: 0,
+            blk_size: 1024,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::Dir,
+            mode: 0o755,
+            nlinks: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." | ".." => Ok(self.fs.root_inode()),
+            "ptmx" => Ok(self.fs.ptmx()),
+            _ => {
+                let index = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                match self.fs.slave(index) {
+                    Some(tty) => Ok(tty),
+                    None => Err(FsError::EntryNotFound),
+                }
+            }
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(".".to_string()),
+            1 => Ok("..".to_string()),
+            2 => Ok("ptmx".to_string()),
+            _ => self
+                .fs
+                .slaves
+                .lock()
+                .keys()
+                .nth(id - 3)
+                .map(|index| index.to_string())
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The multiplexer `/dev/ptmx`, each open of which creates a new pseudo-terminal
+pub(super) struct Ptmx;
+
+impl INode for Ptmx {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NoDevice)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Err(FsError::NoDevice)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(ptmx_metadata())
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn ptmx_metadata() -> Metadata {
+    Metadata {
+        dev: 0,
+        inode: PTMX_INODE_ID,
+        size: 0,
+        blk_size: 0,
+        blocks: 0,
+        atime: Timespec { sec: 0, nsec: 0 },
+        mtime: Timespec { sec: 0, nsec: 0 },
+        ctime: Timespec { sec: 0, nsec: 0 },
+        type_: FileType::CharDevice,
+        mode: 0o666,
+        nlinks: 1,
+        uid: 0,
+        gid: 0,
+        rdev: make_rdev(PTMX_MAJOR, PTMX_MINOR),
+    }
+}
+
+/// The output of a slave, read from its master
+#[derive(Default)]
+struct MasterBuffer {
+    data: Mutex<VecDeque<u8>>,
+    eventbus: Mutex<EventBus>,
+}
+
+impl MasterBuffer {
+    fn push(&self, buf: &[u8]) {
+        self.data.lock().extend(buf.iter());
+        self.eventbus.lock().set(Event::READABLE);
+    }
+
+    fn can_read(&self) -> bool {
+        !self.data.lock().is_empty()
+    }
+}
+
+/// The master of a pseudo-terminal
+pub(super) struct PtyMaster {
+    index: usize,
+    tty: Arc<Tty>,
+    output: Arc<MasterBuffer>,
+}
+
+impl PtyMaster {
+    /// Handle `ioctl` on the master, the terminal requests go to the slave.
+    pub(super) fn ioctl(&self, cmd: usize, arg: usize) -> LxResult<usize> {
+        match cmd {
+            TIOCGPTN => {
+                UserOutPtr::<u32>::from(arg).write(self.index as u32)?;
+                Ok(0)
+            }
+            TIOCSPTLCK => {
+                let lock = UserInPtr::<i32>::from(arg).read()?;
+                self.tty.inner.lock().locked = lock != 0;
+                Ok(0)
+            }
+            TIOCGPTLCK => {
+                let locked = self.tty.inner.lock().locked;
+                UserOutPtr::<i32>::from(arg).write(locked as i32)?;
+                Ok(0)
+            }
+            FIONREAD => {
+                let len = self.output.data.lock().len();
+                UserOutPtr::<i32>::from(arg).write(len as i32)?;
+                Ok(0)
+            }
+            _ => self.tty.ioctl(cmd, arg),
+        }
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        self.tty.hangup();
+        DEVPTS.slaves.lock().remove(&self.index);
+    }
+}
+
+impl INode for PtyMaster {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut data = self.output.data.lock();
+        if data.is_empty() {
+            return Err(FsError::Again);
+        }
+        let len = data.len().min(buf.len());
+        for (b, c) in buf.iter_mut().zip(data.drain(..len)) {
+            *b = c;
+        }
+        if data.is_empty() {
+            self.output.eventbus.lock().clear(Event::READABLE);
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        self.tty.receive(buf);
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: self.output.can_read(),
+            write: true,
+            error: false,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct MasterFuture<'a> {
+            master: &'a PtyMaster,
+        }
+
+        impl<'a> Future for MasterFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.master.output.can_read() {
+                    return Poll::Ready(self.master.poll());
+                }
+                let waker = cx.waker().clone();
+                self.master.output.eventbus.lock().subscribe(Box::new({
+                    move |_| {
+                        waker.wake_by_ref();
+                        true
+                    }
+                }));
+                Poll::Pending
+            }
+        }
+
+        Box::pin(MasterFuture { master: self })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(ptmx_metadata())
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,151 @@
+//! Terminal attributes, see `termios(3)`
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Input modes
+    #[derive(Default)]
+    pub struct InputFlags: u32 {
+        /// strip off the eighth bit
+        const ISTRIP = 0o40;
+        /// translate NL to CR on input
+        const INLCR = 0o100;
+        /// ignore CR on input
+        const IGNCR = 0o200;
+        /// translate CR to NL on input
+        const ICRNL = 0o400;
+        /// enable XON/XOFF flow control on output
+        const IXON = 0o2000;
+        /// input is UTF-8
+        const IUTF8 = 0o40000;
+    }
+}
+
+bitflags! {
+    /// Output modes
+    #[derive(Default)]
+    pub struct OutputFlags: u32 {
+        /// enable implementation-defined output processing
+        const OPOST = 0o1;
+        /// map NL to CR-NL on output
+        const ONLCR = 0o4;
+    }
+}
+
+bitflags! {
+    /// Local modes
+    #[derive(Default)]
+    pub struct LocalFlags: u32 {
+        /// generate signals for the INTR, QUIT and SUSP characters
+        const ISIG = 0o1;
+        /// canonical mode, the input is available line by line
+        const ICANON = 0o2;
+        /// echo input characters
+        const ECHO = 0o10;
+        /// the ERASE character erases the preceding character
+        const ECHOE = 0o20;
+        /// the KILL character erases the current line
+        const ECHOK = 0o40;
+        /// echo NL even if ECHO is not set
+        const ECHONL = 0o100;
+        /// disable flushing the input and output queues for signal characters
+        const NOFLSH = 0o200;
+        /// send `SIGTTOU` to background processes writing to the terminal
+        const TOSTOP = 0o400;
+        /// echo control characters as `^X`
+        const ECHOCTL = 0o1000;
+        /// the KILL character erases each character on the line
+        const ECHOKE = 0o4000;
+        /// enable implementation-defined input processing, such as WERASE
+        const IEXTEN = 0o100000;
+    }
+}
+
+/// The number of control characters
+const NCCS: usize = 19;
+
+/// The indexes of control characters
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+
+/// The kernel `termios` structure, used by `TCGETS` and `TCSETS`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    /// input modes
+    pub iflag: InputFlags,
+    /// output modes
+    pub oflag: OutputFlags,
+    /// control modes, not used
+    pub cflag: u32,
+    /// local modes
+    pub lflag: LocalFlags,
+    /// line discipline
+    pub line: u8,
+    /// control characters
+    pub cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    /// The attributes of a newly opened terminal in Linux
+    fn default() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03; // ^C
+        cc[VQUIT] = 0x1c; // ^\
+        cc[VERASE] = 0x7f; // DEL
+        cc[VKILL] = 0x15; // ^U
+        cc[VEOF] = 0x04; // ^D
+        cc[VMIN] = 1;
+        cc[VSUSP] = 0x1a; // ^Z
+        cc[VWERASE] = 0x17; // ^W
+        Termios {
+            iflag: InputFlags::ICRNL | InputFlags::IXON | InputFlags::IUTF8,
+            oflag: OutputFlags::OPOST | OutputFlags::ONLCR,
+            // B38400 | CS8 | CREAD | HUPCL
+            cflag: 0o2277,
+            lflag: LocalFlags::ISIG
+                | LocalFlags::ICANON
+                | LocalFlags::ECHO
+                | LocalFlags::ECHOE
+                | LocalFlags::ECHOK
+                | LocalFlags::ECHOCTL
+                | LocalFlags::ECHOKE
+                | LocalFlags::IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+}
+
+impl Termios {
+    /// Whether `c` is the control character `index`, which may be disabled by 0
+    pub fn is_cc(&self, index: usize, c: u8) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+
+    /// Whether the terminal is in canonical mode
+    pub fn canonical(&self) -> bool {
+        self.lflag.contains(LocalFlags::ICANON)
+    }
+}
+
+/// The window size, used by `TIOCGWINSZ` and `TIOCSWINSZ`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WinSize {
+    /// rows, in characters
+    pub row: u16,
+    /// columns, in characters
+    pub col: u16,
+    /// horizontal size, in pixels
+    pub xpixel: u16,
+    /// vertical size, in pixels
+    pub ypixel: u16,
+}
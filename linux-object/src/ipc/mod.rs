@@ -1,8 +1,12 @@
 //! Linux Inter-Process Communication
 #![deny(missing_docs)]
+mod mqueue;
+mod msg_queue;
 mod semary;
 mod shared_mem;
 
+pub use self::mqueue::*;
+pub use self::msg_queue::*;
 pub use self::semary::*;
 pub use self::shared_mem::*;
 use alloc::collections::BTreeMap;
@@ -11,12 +15,13 @@ use bitflags::*;
 use lock::Mutex;
 
 /// Semaphore table in a process
-#[derive(Default)]
+///
+/// The undo operations are recorded in the semaphore sets by process ID,
+/// so a forked table has none of them.
+#[derive(Default, Clone)]
 pub struct SemProc {
     /// Semaphore arrays
     arrays: BTreeMap<SemId, Arc<SemArray>>,
-    /// Undo operations when process terminates
-    undos: BTreeMap<(SemId, SemNum), SemOp>,
 }
 
 /// Shared_memory table in a process
@@ -66,12 +71,6 @@ type SemId = usize;
 /// Shared_memory identifier (in a process)
 type ShmId = usize;
 
-/// Semaphore number (in an array)
-type SemNum = u16;
-
-/// Semaphore operation value
-type SemOp = i16;
-
 impl SemProc {
     /// Insert the `array` and return its ID
     pub fn add(&mut self, array: Arc<SemArray>) -> SemId {
@@ -95,36 +94,10 @@ impl SemProc {
         self.arrays.get(&id).cloned()
     }
 
-    /// Add an undo operation
-    pub fn add_undo(&mut self, id: SemId, num: SemNum, op: SemOp) {
-        let old_val = *self.undos.get(&(id, num)).unwrap_or(&0);
-        let new_val = old_val - op;
-        self.undos.insert((id, num), new_val);
-    }
-}
-
-/// Fork the semaphore table. Clear undo info.
-impl Clone for SemProc {
-    fn clone(&self) -> Self {
-        SemProc {
-            arrays: self.arrays.clone(),
-            undos: BTreeMap::default(),
-        }
-    }
-}
-
-/// Auto perform semaphores undo on drop
-impl Drop for SemProc {
-    fn drop(&mut self) {
-        for (&(id, num), &op) in self.undos.iter() {
-            debug!("semundo: id: {}, num: {}, op: {}", id, num, op);
-            let sem_array = self.arrays[&id].clone();
-            let sem = &sem_array[num as usize];
-            match op {
-                1 => sem.release(),
-                0 => {}
-                _ => unimplemented!("Semaphore: semundo.(Not 1)"),
-            }
+    /// Perform all undo operations of the process `pid`, when it terminates
+    pub fn undo_all(&self, pid: usize) {
+        for array in self.arrays.values() {
+            array.undo(pid);
         }
     }
 }
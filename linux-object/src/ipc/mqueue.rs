@@ -0,0 +1,443 @@
+//! Linux POSIX message queue ipc
+use crate::error::{LxError, LxResult};
+use crate::fs::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::process::send_signal;
+use crate::signal::{SigEvent, SigInfo, Signal, SignalCode, SIGEV_NONE, SIGEV_SIGNAL};
+use crate::sync::{wait_for_event, Event, EventBus};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use async_trait::async_trait;
+use core::convert::TryFrom;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use lazy_static::*;
+use lock::Mutex;
+use zircon_object::object::*;
+use zircon_object::task::Process;
+
+/// The default maximum number of messages in a queue
+const DFLT_MSGMAX: usize = 10;
+/// The default maximum size of a message
+const DFLT_MSGSIZEMAX: usize = 8192;
+/// The upper limit of the maximum number of messages in a queue
+const HARD_MSGMAX: usize = 65536;
+/// The upper limit of the maximum size of a message
+const HARD_MSGSIZEMAX: usize = 16 * 1024 * 1024;
+/// Message priorities are less than this value
+pub const MQ_PRIO_MAX: u32 = 32768;
+
+/// Attributes of a message queue
+///
+/// struct mq_attr
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MqAttr {
+    /// Flags: 0 or `O_NONBLOCK`
+    pub flags: isize,
+    /// Maximum number of messages in the queue
+    pub maxmsg: isize,
+    /// Maximum message size in bytes
+    pub msgsize: isize,
+    /// Number of messages currently in the queue
+    pub curmsgs: isize,
+    __reserved: [isize; 4],
+}
+
+/// The process registered by `mq_notify` for the arrival of messages
+struct MqNotify {
+    /// ID of the registered process
+    pid: KoID,
+    proc: Weak<Process>,
+    event: SigEvent,
+}
+
+/// A POSIX message queue
+pub struct Mqueue {
+    /// Maximum number of messages in the queue
+    maxmsg: usize,
+    /// Maximum message size in bytes
+    msgsize: usize,
+    inner: Mutex<MqueueInner>,
+    /// event bus to wake up senders and receivers
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+#[derive(Default)]
+struct MqueueInner {
+    /// Messages by priority, the oldest message of the highest priority is received first
+    messages: BTreeMap<u32, VecDeque<Vec<u8>>>,
+    /// Number of messages in the queue
+    count: usize,
+    /// Number of receivers blocked on the empty queue
+    receivers: usize,
+    notify: Option<MqNotify>,
+}
+
+lazy_static! {
+    /// Message queues by name, which are removed by `mq_unlink`
+    static ref MQUEUES: Mutex<BTreeMap<String, Arc<Mqueue>>> = Mutex::new(BTreeMap::new());
+}
+
+impl Mqueue {
+    /// Open the message queue `name`, which is created with `attr` if not exists
+    /// and `OpenFlags::CREATE` is in `flags`.
+    pub fn open(name: &str, flags: OpenFlags, attr: Option<MqAttr>) -> LxResult<Arc<Self>> {
+        Self::check_name(name)?;
+        let mut queues = MQUEUES.lock();
+        if let Some(queue) = queues.get(name) {
+            if flags.contains(OpenFlags::CREATE | OpenFlags::EXCLUSIVE) {
+                return Err(LxError::EEXIST);
+            }
+            return Ok(queue.clone());
+        }
+        if !flags.contains(OpenFlags::CREATE) {
+            return Err(LxError::ENOENT);
+        }
+        let (maxmsg, msgsize) = match attr {
+            Some(attr) => (
+                usize::try_from(attr.maxmsg).map_err(|_| LxError::EINVAL)?,
+                usize::try_from(attr.msgsize).map_err(|_| LxError::EINVAL)?,
+            ),
+            None => (DFLT_MSGMAX, DFLT_MSGSIZEMAX),
+        };
+        if maxmsg == 0 || maxmsg > HARD_MSGMAX || msgsize == 0 || msgsize > HARD_MSGSIZEMAX {
+            return Err(LxError::EINVAL);
+        }
+        let queue = Arc::new(Mqueue {
+            maxmsg,
+            msgsize,
+            inner: Mutex::new(MqueueInner::default()),
+            eventbus: EventBus::new(),
+        });
+        queue.notify_status(0);
+        queues.insert(String::from(name), queue.clone());
+        Ok(queue)
+    }
+
+    /// Remove the message queue `name`, which is destroyed after all descriptors are closed
+    pub fn unlink(name: &str) -> LxResult {
+        Self::check_name(name)?;
+        MQUEUES.lock().remove(name).ok_or(LxError::ENOENT)?;
+        Ok(())
+    }
+
+    /// The name is given without the leading slash
+    fn check_name(name: &str) -> LxResult {
+        if name.is_empty() {
+            Err(LxError::ENOENT)
+        } else if name.len() > 255 {
+            Err(LxError::ENAMETOOLONG)
+        } else if name.contains('/') {
+            Err(LxError::EACCES)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The attributes of the queue, without the flags of a descriptor
+    pub fn attr(&self) -> MqAttr {
+        MqAttr {
+            maxmsg: self.maxmsg as isize,
+            msgsize: self.msgsize as isize,
+            curmsgs: self.inner.lock().count as isize,
+            ..Default::default()
+        }
+    }
+
+    /// Update the event bus with the number of messages,
+    /// must be called with the queue locked
+    fn notify_status(&self, count: usize) {
+        let mut set = Event::empty();
+        if count > 0 {
+            set |= Event::READABLE;
+        }
+        if count < self.maxmsg {
+            set |= Event::WRITABLE;
+        }
+        self.eventbus
+            .lock()
+            .change(Event::READABLE | Event::WRITABLE, set);
+    }
+
+    fn status(&self, count: usize) -> PollStatus {
+        PollStatus {
+            read: count > 0,
+            write: count < self.maxmsg,
+            error: false,
+        }
+    }
+
+    /// Put the message into the queue, returns `EAGAIN` if it is full
+    fn try_send(&self, msg: &[u8], prio: u32, sender: (usize, u32)) -> LxResult {
+        let mut inner = self.inner.lock();
+        if inner.count >= self.maxmsg {
+            return Err(LxError::EAGAIN);
+        }
+        inner
+            .messages
+            .entry(prio)
+            .or_default()
+            .push_back(msg.to_vec());
+        inner.count += 1;
+        self.notify_status(inner.count);
+        // notify the registered process only if no one is waiting for the message
+        if inner.count == 1 && inner.receivers == 0 {
+            if let Some(notify) = inner.notify.take() {
+                if let (SIGEV_SIGNAL, Some(proc)) = (notify.event.notify, notify.proc.upgrade()) {
+                    if let Ok(signal) = Signal::try_from(notify.event.signo as u8) {
+                        let (pid, uid) = sender;
+                        let code = SignalCode::MESGQ as i32;
+                        let info = SigInfo::queue(signal, code, pid, uid, notify.event.value);
+                        send_signal(&proc, info);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the oldest message of the highest priority, returns `EAGAIN` if it is empty
+    fn try_receive(&self) -> LxResult<(Vec<u8>, u32)> {
+        let mut inner = self.inner.lock();
+        let (&prio, messages) = inner
+            .messages
+            .iter_mut()
+            .next_back()
+            .ok_or(LxError::EAGAIN)?;
+        let msg = messages.pop_front().unwrap();
+        if messages.is_empty() {
+            inner.messages.remove(&prio);
+        }
+        inner.count -= 1;
+        self.notify_status(inner.count);
+        Ok((msg, prio))
+    }
+
+    /// Send the message `msg` with priority `prio` from the process `pid` of user `uid`.
+    ///
+    /// Block until the queue is not full, unless `nonblock` is set.
+    pub async fn send(
+        &self,
+        msg: &[u8],
+        prio: u32,
+        nonblock: bool,
+        pid: usize,
+        uid: u32,
+    ) -> LxResult {
+        if msg.len() > self.msgsize {
+            return Err(LxError::EMSGSIZE);
+        }
+        if prio >= MQ_PRIO_MAX {
+            return Err(LxError::EINVAL);
+        }
+        loop {
+            match self.try_send(msg, prio, (pid, uid)) {
+                Err(LxError::EAGAIN) if !nonblock => {
+                    wait_for_event(self.eventbus.clone(), Event::WRITABLE).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Receive the oldest message of the highest priority into a buffer of `len` bytes,
+    /// returns the message and its priority.
+    ///
+    /// Block until the queue is not empty, unless `nonblock` is set.
+    pub async fn receive(&self, len: usize, nonblock: bool) -> LxResult<(Vec<u8>, u32)> {
+        if len < self.msgsize {
+            return Err(LxError::EMSGSIZE);
+        }
+        let _receiver = if nonblock {
+            None
+        } else {
+            Some(BlockedReceiver::new(self))
+        };
+        loop {
+            match self.try_receive() {
+                Err(LxError::EAGAIN) if !nonblock => {
+                    wait_for_event(self.eventbus.clone(), Event::READABLE).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Register the process `proc` to be notified with `event` when a message arrives
+    /// at the empty queue, or unregister it if `event` is `None`.
+    pub fn set_notify(&self, proc: &Arc<Process>, event: Option<SigEvent>) -> LxResult {
+        let mut inner = self.inner.lock();
+        let registered = inner
+            .notify
+            .as_ref()
+            .filter(|notify| notify.proc.strong_count() > 0)
+            .map(|notify| notify.pid);
+        match event {
+            None => {
+                if registered == Some(proc.id()) {
+                    inner.notify = None;
+                }
+                Ok(())
+            }
+            Some(_) if registered.is_some() => Err(LxError::EBUSY),
+            Some(event) => {
+                match event.notify {
+                    SIGEV_NONE => {}
+                    SIGEV_SIGNAL => {
+                        Signal::try_from(event.signo as u8).map_err(|_| LxError::EINVAL)?;
+                    }
+                    // `SIGEV_THREAD` is implemented with netlink sockets by the C library
+                    _ => return Err(LxError::EINVAL),
+                }
+                inner.notify = Some(MqNotify {
+                    pid: proc.id(),
+                    proc: Arc::downgrade(proc),
+                    event,
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Counts a receiver blocked on the queue while it is alive
+struct BlockedReceiver<'a>(&'a Mqueue);
+
+impl<'a> BlockedReceiver<'a> {
+    fn new(queue: &'a Mqueue) -> Self {
+        queue.inner.lock().receivers += 1;
+        BlockedReceiver(queue)
+    }
+}
+
+impl Drop for BlockedReceiver<'_> {
+    fn drop(&mut self) {
+        self.0.inner.lock().receivers -= 1;
+    }
+}
+
+/// A descriptor of a POSIX message queue
+pub struct MqueueFile {
+    /// Kernel object base
+    base: KObjectBase,
+    /// The message queue
+    queue: Arc<Mqueue>,
+    /// open flags
+    flags: Mutex<OpenFlags>,
+}
+
+impl_kobject!(MqueueFile);
+
+impl MqueueFile {
+    /// Create a descriptor of `queue` with open `flags`
+    pub fn new(queue: Arc<Mqueue>, flags: OpenFlags) -> Arc<Self> {
+        Arc::new(MqueueFile {
+            base: KObjectBase::new(),
+            queue,
+            flags: Mutex::new(flags),
+        })
+    }
+
+    /// The message queue
+    pub fn queue(&self) -> &Arc<Mqueue> {
+        &self.queue
+    }
+}
+
+#[async_trait]
+impl FileLike for MqueueFile {
+    fn flags(&self) -> OpenFlags {
+        *self.flags.lock()
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut *self.flags.lock();
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        Arc::new(MqueueFile {
+            base: KObjectBase::new(),
+            queue: self.queue.clone(),
+            flags: Mutex::new(self.flags()),
+        })
+    }
+
+    /// Read the status of the queue in text
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        let inner = self.queue.inner.lock();
+        let size: usize = inner
+            .messages
+            .values()
+            .flat_map(|messages| messages.iter())
+            .map(|msg| msg.len())
+            .sum();
+        let (notify, signo, pid) = match &inner.notify {
+            Some(notify) => (notify.event.notify, notify.event.signo, notify.pid),
+            None => (0, 0, 0),
+        };
+        let status = format!(
+            "QSIZE:{:<10} NOTIFY:{:<5} SIGNO:{:<5} NOTIFY_PID:{:<6}\n",
+            size, notify, signo, pid
+        );
+        let len = buf.len().min(status.len());
+        buf[..len].copy_from_slice(&status.as_bytes()[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EINVAL)
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(self.queue.status(self.queue.inner.lock().count))
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct MqueueFuture<'a> {
+            queue: &'a Mqueue,
+            events: PollEvents,
+        }
+
+        impl<'a> Future for MqueueFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // hold the queue to avoid missing a notification
+                let inner = self.queue.inner.lock();
+                let status = self.queue.status(inner.count);
+                if (status.read && self.events.contains(PollEvents::IN))
+                    || (status.write && self.events.contains(PollEvents::OUT))
+                {
+                    return Poll::Ready(Ok(status));
+                }
+                let waker = cx.waker().clone();
+                self.queue.eventbus.lock().subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        MqueueFuture {
+            queue: &self.queue,
+            events,
+        }
+        .await
+    }
+}
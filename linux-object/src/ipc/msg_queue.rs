@@ -0,0 +1,420 @@
+//! Linux System V message queue ipc
+use super::*;
+use crate::error::{LxError, LxResult};
+use crate::time::*;
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    vec::Vec,
+};
+use bitflags::bitflags;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use lazy_static::*;
+use lock::{Mutex, RwLock};
+
+/// The maximum size of a message
+pub const MSGMAX: usize = 8192;
+/// The default maximum number of bytes in a queue
+pub const MSGMNB: usize = 16384;
+
+bitflags! {
+    /// flags of msgsnd() and msgrcv()
+    pub struct MsgFlags: usize {
+        /// Return immediately instead of blocking
+        const IPC_NOWAIT = 1 << 11;
+        /// Truncate the message text if it is longer than the buffer
+        const MSG_NOERROR = 1 << 12;
+        /// Receive the first message of a type other than `msgtyp`
+        const MSG_EXCEPT = 1 << 13;
+        /// Copy the message at the position `msgtyp` without removing it
+        const MSG_COPY = 1 << 14;
+    }
+}
+
+/// msqid data structure
+///
+/// struct msqid_ds
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MsqidDs {
+    /// Ownership and permissions
+    pub perm: IpcPerm,
+    /// Time of last msgsnd
+    pub stime: usize,
+    /// Time of last msgrcv
+    pub rtime: usize,
+    /// Time of last change
+    pub ctime: usize,
+    /// Current number of bytes in queue
+    pub cbytes: usize,
+    /// Current number of messages in queue
+    pub qnum: usize,
+    /// Maximum number of bytes allowed in queue
+    pub qbytes: usize,
+    /// PID of last msgsnd
+    pub lspid: i32,
+    /// PID of last msgrcv
+    pub lrpid: i32,
+    __unused: [usize; 2],
+}
+
+/// A message in the queue
+struct Message {
+    /// message type, a positive number
+    mtype: usize,
+    /// message text
+    text: Vec<u8>,
+}
+
+/// A System V message queue
+///
+/// The queue exists until it is removed by `IPC_RMID`.
+pub struct MsgQueue {
+    inner: Mutex<MsgQueueInner>,
+}
+
+struct MsgQueueInner {
+    /// msqid data structure
+    msqid_ds: MsqidDs,
+    messages: VecDeque<Message>,
+    /// is removed
+    removed: bool,
+    /// senders and receivers waiting for a change of the queue
+    waiters: Vec<Waker>,
+}
+
+lazy_static! {
+    /// All message queues by ID
+    static ref MSG_QUEUES: RwLock<BTreeMap<usize, Arc<MsgQueue>>> = RwLock::new(BTreeMap::new());
+}
+
+impl MsgQueue {
+    /// Get the ID of the message queue with `key`.
+    /// If not exist, create a new one owned by `uid` and `gid`.
+    pub fn get_or_create(key: u32, flags: usize, uid: u32, gid: u32) -> LxResult<usize> {
+        let mut queues = MSG_QUEUES.write();
+        let flag = IpcGetFlag::from_bits_truncate(flags);
+
+        // IPC_PRIVATE always creates a new queue
+        if key != 0 {
+            let found = queues
+                .iter()
+                .find(|(_, queue)| queue.inner.lock().msqid_ds.perm.key == key);
+            if let Some((&id, _)) = found {
+                if flag.contains(IpcGetFlag::CREAT) && flag.contains(IpcGetFlag::EXCLUSIVE) {
+                    return Err(LxError::EEXIST);
+                }
+                return Ok(id);
+            }
+            if !flag.contains(IpcGetFlag::CREAT) {
+                return Err(LxError::ENOENT);
+            }
+        }
+
+        let queue = Arc::new(MsgQueue {
+            inner: Mutex::new(MsgQueueInner {
+                msqid_ds: MsqidDs {
+                    perm: IpcPerm {
+                        key,
+                        uid,
+                        gid,
+                        cuid: uid,
+                        cgid: gid,
+                        // least significant 9 bits
+                        mode: (flags as u32) & 0x1ff,
+                        __seq: 0,
+                        __pad1: 0,
+                        __pad2: 0,
+                    },
+                    stime: 0,
+                    rtime: 0,
+                    ctime: TimeSpec::now().sec,
+                    cbytes: 0,
+                    qnum: 0,
+                    qbytes: MSGMNB,
+                    lspid: 0,
+                    lrpid: 0,
+                    __unused: [0; 2],
+                },
+                messages: VecDeque::new(),
+                removed: false,
+                waiters: Vec::new(),
+            }),
+        });
+        let id = (0..).find(|i| !queues.contains_key(i)).unwrap();
+        queues.insert(id, queue);
+        Ok(id)
+    }
+
+    /// Get the message queue by `id`
+    pub fn get(id: usize) -> LxResult<Arc<Self>> {
+        MSG_QUEUES.read().get(&id).cloned().ok_or(LxError::EINVAL)
+    }
+
+    /// Remove the message queue `id`, the waiting senders and receivers fail with `EIDRM`
+    pub fn remove(id: usize) -> LxResult {
+        let queue = MSG_QUEUES.write().remove(&id).ok_or(LxError::EINVAL)?;
+        let mut inner = queue.inner.lock();
+        inner.removed = true;
+        inner.wake_all();
+        Ok(())
+    }
+
+    /// for IPC_STAT
+    pub fn stat(&self) -> MsqidDs {
+        self.inner.lock().msqid_ds
+    }
+
+    /// for IPC_SET
+    /// see man msgctl(2)
+    pub fn set(&self, new: &MsqidDs) -> LxResult {
+        if new.qbytes == 0 {
+            return Err(LxError::EINVAL);
+        }
+        let mut inner = self.inner.lock();
+        let ds = &mut inner.msqid_ds;
+        ds.perm.uid = new.perm.uid;
+        ds.perm.gid = new.perm.gid;
+        ds.perm.mode = new.perm.mode & 0x1ff;
+        ds.qbytes = new.qbytes;
+        ds.ctime = TimeSpec::now().sec;
+        // a larger limit may unblock senders
+        inner.wake_all();
+        Ok(())
+    }
+
+    /// Send a message with `mtype` and `text` from the process `pid`.
+    ///
+    /// Block until there is enough space in the queue, unless `IPC_NOWAIT` is in `flags`.
+    pub async fn send(&self, mtype: usize, text: Vec<u8>, flags: MsgFlags, pid: usize) -> LxResult {
+        let mut message = Some(Message { mtype, text });
+        self.wait(|inner: &mut MsgQueueInner| {
+            let len = message.as_ref().unwrap().text.len();
+            let ds = &inner.msqid_ds;
+            // the number of messages is also limited, to count empty messages
+            if ds.cbytes + len > ds.qbytes || ds.qnum + 1 > ds.qbytes {
+                if flags.contains(MsgFlags::IPC_NOWAIT) {
+                    return Poll::Ready(Err(LxError::EAGAIN));
+                }
+                return Poll::Pending;
+            }
+            inner.messages.push_back(message.take().unwrap());
+            let ds = &mut inner.msqid_ds;
+            ds.cbytes += len;
+            ds.qnum += 1;
+            ds.lspid = pid as i32;
+            ds.stime = TimeSpec::now().sec;
+            inner.wake_all();
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Receive a message selected by `msgtyp` with at most `size` bytes of text
+    /// to the process `pid`, returns the type and the text of the message.
+    ///
+    /// - If `msgtyp` is 0, the first message is received.
+    /// - If `msgtyp` is positive, the first message of type `msgtyp` is received,
+    ///   or the first message of other types with `MSG_EXCEPT`.
+    /// - If `msgtyp` is negative, the first message of the lowest type
+    ///   not greater than the absolute value of `msgtyp` is received.
+    ///
+    /// Block until there is such a message, unless `IPC_NOWAIT` is in `flags`.
+    pub async fn receive(
+        &self,
+        msgtyp: isize,
+        size: usize,
+        flags: MsgFlags,
+        pid: usize,
+    ) -> LxResult<(usize, Vec<u8>)> {
+        self.wait(|inner: &mut MsgQueueInner| {
+            let index = match inner.find(msgtyp, flags) {
+                Some(index) => index,
+                None if flags.contains(MsgFlags::IPC_NOWAIT) => {
+                    return Poll::Ready(Err(LxError::ENOMSG))
+                }
+                None => return Poll::Pending,
+            };
+            let len = inner.messages[index].text.len();
+            if len > size && !flags.contains(MsgFlags::MSG_NOERROR) {
+                return Poll::Ready(Err(LxError::E2BIG));
+            }
+            let mut message = inner.messages.remove(index).unwrap();
+            message.text.truncate(size);
+            let ds = &mut inner.msqid_ds;
+            ds.cbytes -= len;
+            ds.qnum -= 1;
+            ds.lrpid = pid as i32;
+            ds.rtime = TimeSpec::now().sec;
+            inner.wake_all();
+            Poll::Ready(Ok((message.mtype, message.text)))
+        })
+        .await
+    }
+
+    /// Run `op` on the queue until it is ready
+    fn wait<T, F>(&self, op: F) -> MsgQueueFuture<'_, F>
+    where
+        F: FnMut(&mut MsgQueueInner) -> Poll<LxResult<T>> + Unpin,
+    {
+        MsgQueueFuture { queue: self, op }
+    }
+}
+
+impl MsgQueueInner {
+    /// Find the index of the message to receive
+    fn find(&self, msgtyp: isize, flags: MsgFlags) -> Option<usize> {
+        let mut messages = self.messages.iter().enumerate();
+        if msgtyp == 0 {
+            messages.next().map(|(i, _)| i)
+        } else if msgtyp > 0 {
+            let except = flags.contains(MsgFlags::MSG_EXCEPT);
+            messages
+                .find(|(_, m)| (m.mtype == msgtyp as usize) != except)
+                .map(|(i, _)| i)
+        } else {
+            messages
+                .filter(|(_, m)| m.mtype <= msgtyp.unsigned_abs())
+                .min_by_key(|(i, m)| (m.mtype, *i))
+                .map(|(i, _)| i)
+        }
+    }
+
+    /// Wake up all senders and receivers to check the queue again
+    fn wake_all(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Future of an operation on the message queue
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct MsgQueueFuture<'a, F> {
+    queue: &'a MsgQueue,
+    op: F,
+}
+
+impl<'a, T, F> Future for MsgQueueFuture<'a, F>
+where
+    F: FnMut(&mut MsgQueueInner) -> Poll<LxResult<T>> + Unpin,
+{
+    type Output = LxResult<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let queue = self.queue;
+        let mut inner = queue.inner.lock();
+        if inner.removed {
+            return Poll::Ready(Err(LxError::EIDRM));
+        }
+        let ret = (self.op)(&mut inner);
+        if ret.is_pending() {
+            inner.waiters.push(cx.waker().clone());
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create() -> (usize, Arc<MsgQueue>) {
+        let id = MsgQueue::get_or_create(0, 0o600, 0, 0).unwrap();
+        (id, MsgQueue::get(id).unwrap())
+    }
+
+    async fn send(queue: &MsgQueue, mtype: usize, text: &[u8]) {
+        let flags = MsgFlags::IPC_NOWAIT;
+        queue.send(mtype, text.to_vec(), flags, 1).await.unwrap();
+    }
+
+    async fn receive(queue: &MsgQueue, msgtyp: isize, flags: MsgFlags) -> LxResult<usize> {
+        let flags = flags | MsgFlags::IPC_NOWAIT;
+        let (mtype, _) = queue.receive(msgtyp, MSGMAX, flags, 1).await?;
+        Ok(mtype)
+    }
+
+    #[async_std::test]
+    async fn type_selection() {
+        let (id, queue) = create();
+        for &mtype in [3, 1, 2, 1, 5].iter() {
+            send(&queue, mtype, b"").await;
+        }
+        assert_eq!(queue.stat().qnum, 5);
+        // the first message of the lowest type not greater than 4
+        assert_eq!(receive(&queue, -4, MsgFlags::empty()).await.unwrap(), 1);
+        // the first message of type 1
+        assert_eq!(receive(&queue, 1, MsgFlags::empty()).await.unwrap(), 1);
+        // the first message of other types than 3
+        assert_eq!(receive(&queue, 3, MsgFlags::MSG_EXCEPT).await.unwrap(), 2);
+        assert!(matches!(
+            receive(&queue, 4, MsgFlags::empty()).await,
+            Err(LxError::ENOMSG)
+        ));
+        assert!(matches!(
+            receive(&queue, -2, MsgFlags::empty()).await,
+            Err(LxError::ENOMSG)
+        ));
+        // the first message
+        assert_eq!(receive(&queue, 0, MsgFlags::empty()).await.unwrap(), 3);
+        assert_eq!(receive(&queue, 0, MsgFlags::empty()).await.unwrap(), 5);
+        assert_eq!(queue.stat().qnum, 0);
+        MsgQueue::remove(id).unwrap();
+    }
+
+    #[async_std::test]
+    async fn size_limits() {
+        let (id, queue) = create();
+        send(&queue, 1, b"hello").await;
+        let flags = MsgFlags::IPC_NOWAIT;
+        assert!(matches!(
+            queue.receive(0, 4, flags, 1).await,
+            Err(LxError::E2BIG)
+        ));
+        let flags = flags | MsgFlags::MSG_NOERROR;
+        assert_eq!(
+            queue.receive(0, 4, flags, 1).await.unwrap(),
+            (1, b"hell".to_vec())
+        );
+        assert_eq!(queue.stat().cbytes, 0);
+
+        // the queue is full by the number of bytes
+        let mut ds = queue.stat();
+        ds.qbytes = 8;
+        queue.set(&ds).unwrap();
+        send(&queue, 1, b"12345678").await;
+        assert!(matches!(
+            queue.send(1, b"9".to_vec(), MsgFlags::IPC_NOWAIT, 1).await,
+            Err(LxError::EAGAIN)
+        ));
+        MsgQueue::remove(id).unwrap();
+    }
+
+    #[async_std::test]
+    async fn blocking() {
+        let (id, queue) = create();
+        let receiver = {
+            let queue = queue.clone();
+            async_std::task::spawn(
+                async move { queue.receive(2, MSGMAX, MsgFlags::empty(), 1).await },
+            )
+        };
+        async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+        send(&queue, 1, b"a").await;
+        send(&queue, 2, b"b").await;
+        assert_eq!(receiver.await.unwrap(), (2, b"b".to_vec()));
+
+        let receiver = {
+            let queue = queue.clone();
+            async_std::task::spawn(
+                async move { queue.receive(2, MSGMAX, MsgFlags::empty(), 1).await },
+            )
+        };
+        async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+        MsgQueue::remove(id).unwrap();
+        assert!(matches!(receiver.await, Err(LxError::EIDRM)));
+    }
+}
@@ -1,13 +1,22 @@
 //! Linux semaphore ipc
 use super::*;
-use crate::error::LxError;
-use crate::sync::Semaphore;
+use crate::error::{LxError, LxResult};
 use crate::time::*;
 use alloc::{collections::BTreeMap, sync::Arc, sync::Weak, vec::Vec};
-use core::ops::Index;
+use bitflags::bitflags;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 use lazy_static::*;
 use lock::{Mutex, RwLock};
 
+/// The maximum number of semaphores in a set
+pub const SEMMSL: usize = 256;
+/// The maximum value of a semaphore
+pub const SEMVMX: i32 = 32767;
+/// The maximum number of operations in a `semop` call
+pub const SEMOPM: usize = 500;
+
 /// semid data structure
 ///
 /// struct semid_ds
@@ -26,18 +35,59 @@ pub struct SemidDs {
     pub nsems: usize,
 }
 
+/// An operation to be performed on a single semaphore
+///
+/// Ref: <http://man7.org/linux/man-pages/man2/semop.2.html>
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SemBuf {
+    /// Semaphore number
+    pub num: u16,
+    /// Semaphore operation
+    pub op: i16,
+    /// Operation flags
+    pub flags: i16,
+}
+
+bitflags! {
+    /// flags of a semaphore operation
+    pub struct SemFlags: i16 {
+        /// For SemOP
+        const IPC_NOWAIT = 0x800;
+        /// it will be automatically undone when the process terminates.
+        const SEM_UNDO = 0x1000;
+    }
+}
+
+/// A semaphore in a set
+#[derive(Default, Clone, Copy)]
+struct Sem {
+    /// semval, the value of the semaphore
+    value: i32,
+    /// sempid, the process which performed the last operation
+    pid: usize,
+    /// semncnt, the number of processes waiting for the value to increase
+    ncnt: usize,
+    /// semzcnt, the number of processes waiting for the value to become zero
+    zcnt: usize,
+}
+
 /// A System V semaphore set
 pub struct SemArray {
     /// semid data structure
     pub semid_ds: Mutex<SemidDs>,
-    sems: Vec<Semaphore>,
+    inner: Mutex<SemArrayInner>,
 }
 
-impl Index<usize> for SemArray {
-    type Output = Semaphore;
-    fn index(&self, idx: usize) -> &Semaphore {
-        &self.sems[idx]
-    }
+struct SemArrayInner {
+    sems: Vec<Sem>,
+    /// is removed
+    removed: bool,
+    /// processes waiting for a change of the semaphores
+    waiters: Vec<Waker>,
+    /// `semadj` of the `SEM_UNDO` operations by process ID and semaphore number,
+    /// added to the semaphores when the process terminates
+    undos: BTreeMap<(usize, u16), i32>,
 }
 
 lazy_static! {
@@ -45,14 +95,14 @@ lazy_static! {
 }
 
 impl SemArray {
-    /// remove semaphores
+    /// remove semaphores, the waiting processes fail with `EIDRM`
     pub fn remove(&self) {
         let mut key2sem = KEY2SEM.write();
         let key = self.semid_ds.lock().perm.key;
         key2sem.remove(&key);
-        for sem in self.sems.iter() {
-            sem.remove();
-        }
+        let mut inner = self.inner.lock();
+        inner.removed = true;
+        inner.wake_all();
     }
 
     /// set last semop time
@@ -92,17 +142,21 @@ impl SemArray {
                         // exclusive
                         return Err(LxError::EEXIST);
                     }
+                    if nsems > array.nsems() {
+                        return Err(LxError::EINVAL);
+                    }
                     return Ok(array);
                 }
             }
+            if !flag.contains(IpcGetFlag::CREAT) {
+                return Err(LxError::ENOENT);
+            }
         }
-
-        // not found, create one
-        let mut semaphores = Vec::new();
-        for _ in 0..nsems {
-            semaphores.push(Semaphore::new(0));
+        if nsems == 0 {
+            return Err(LxError::EINVAL);
         }
 
+        // not found, create one
         // insert to global map
         let array = Arc::new(SemArray {
             semid_ds: Mutex::new(SemidDs {
@@ -124,9 +178,365 @@ impl SemArray {
                 __pad1: 0,
                 __pad2: 0,
             }),
-            sems: semaphores,
+            inner: Mutex::new(SemArrayInner {
+                sems: vec![Sem::default(); nsems],
+                removed: false,
+                waiters: Vec::new(),
+                undos: BTreeMap::new(),
+            }),
         });
         key2sem.insert(key, Arc::downgrade(&array));
         Ok(array)
     }
+
+    /// The number of semaphores in the set
+    pub fn nsems(&self) -> usize {
+        self.inner.lock().sems.len()
+    }
+
+    /// Get the `num`-th semaphore, or `EINVAL` if it does not exist
+    fn sem(&self, num: usize) -> LxResult<Sem> {
+        self.inner
+            .lock()
+            .sems
+            .get(num)
+            .copied()
+            .ok_or(LxError::EINVAL)
+    }
+
+    /// for GETVAL
+    pub fn get_val(&self, num: usize) -> LxResult<usize> {
+        Ok(self.sem(num)?.value as usize)
+    }
+
+    /// for GETPID
+    pub fn get_pid(&self, num: usize) -> LxResult<usize> {
+        Ok(self.sem(num)?.pid)
+    }
+
+    /// for GETNCNT
+    pub fn get_ncnt(&self, num: usize) -> LxResult<usize> {
+        Ok(self.sem(num)?.ncnt)
+    }
+
+    /// for GETZCNT
+    pub fn get_zcnt(&self, num: usize) -> LxResult<usize> {
+        Ok(self.sem(num)?.zcnt)
+    }
+
+    /// for GETALL
+    pub fn get_all(&self) -> Vec<u16> {
+        let inner = self.inner.lock();
+        inner.sems.iter().map(|sem| sem.value as u16).collect()
+    }
+
+    /// for SETVAL, set the value of the `num`-th semaphore by the process `pid`
+    pub fn set_val(&self, num: usize, value: i32, pid: usize) -> LxResult {
+        if !(0..=SEMVMX).contains(&value) {
+            return Err(LxError::ERANGE);
+        }
+        let mut inner = self.inner.lock();
+        let sem = inner.sems.get_mut(num).ok_or(LxError::EINVAL)?;
+        sem.value = value;
+        sem.pid = pid;
+        inner.undos.retain(|&(_, n), _| n as usize != num);
+        inner.wake_all();
+        drop(inner);
+        self.ctime();
+        Ok(())
+    }
+
+    /// for SETALL, set the values of all semaphores by the process `pid`
+    pub fn set_all(&self, values: &[u16], pid: usize) -> LxResult {
+        if values.iter().any(|&value| value as i32 > SEMVMX) {
+            return Err(LxError::ERANGE);
+        }
+        let mut inner = self.inner.lock();
+        for (sem, &value) in inner.sems.iter_mut().zip(values) {
+            sem.value = value as i32;
+            sem.pid = pid;
+        }
+        inner.undos.clear();
+        inner.wake_all();
+        drop(inner);
+        self.ctime();
+        Ok(())
+    }
+
+    /// Perform the operations `ops` atomically by the process `pid`.
+    ///
+    /// Either all operations are performed, or the call blocks until they can be,
+    /// unless the operation to block has `SemFlags::IPC_NOWAIT`.
+    ///
+    /// - If `op` is positive, it is added to the semaphore.
+    /// - If `op` is negative, the call waits until the semaphore is not less than `-op`,
+    ///   and then `-op` is subtracted from it.
+    /// - If `op` is zero, the call waits until the semaphore becomes zero.
+    pub async fn operate(&self, ops: &[SemBuf], pid: usize) -> LxResult {
+        let nsems = self.nsems();
+        if ops.iter().any(|op| op.num as usize >= nsems) {
+            return Err(LxError::EFBIG);
+        }
+        SemopFuture {
+            array: self,
+            ops,
+            pid,
+            blocked: None,
+        }
+        .await?;
+        self.otime();
+        Ok(())
+    }
+
+    /// Perform the undo operations of the process `pid`.
+    ///
+    /// The values are clamped to the valid range instead of blocking the process.
+    pub fn undo(&self, pid: usize) {
+        let mut inner = self.inner.lock();
+        if inner.removed {
+            return;
+        }
+        let nums: Vec<u16> = inner
+            .undos
+            .range((pid, 0)..=(pid, u16::MAX))
+            .map(|(&(_, num), _)| num)
+            .collect();
+        for num in nums {
+            let adj = inner.undos.remove(&(pid, num)).unwrap();
+            debug!("semundo: pid: {}, num: {}, adj: {}", pid, num, adj);
+            let sem = &mut inner.sems[num as usize];
+            sem.value = (sem.value + adj).max(0).min(SEMVMX);
+            sem.pid = pid;
+        }
+        inner.wake_all();
+    }
+
+    /// The number of semaphore sets and semaphores in the system
+    pub fn used() -> (usize, usize) {
+        let key2sem = KEY2SEM.read();
+        let arrays = key2sem.values().filter_map(Weak::upgrade);
+        arrays.fold((0, 0), |(sets, sems), array| {
+            (sets + 1, sems + array.nsems())
+        })
+    }
+}
+
+impl SemArrayInner {
+    /// Try to perform the operations, returns the index of the operation to block
+    fn try_operate(&mut self, ops: &[SemBuf], pid: usize) -> LxResult<Option<usize>> {
+        let mut values: Vec<i32> = self.sems.iter().map(|sem| sem.value).collect();
+        let mut undos = BTreeMap::new();
+        for (i, op) in ops.iter().enumerate() {
+            let value = &mut values[op.num as usize];
+            let new = *value + op.op as i32;
+            if op.op > 0 && new > SEMVMX {
+                return Err(LxError::ERANGE);
+            }
+            if new < 0 || (op.op == 0 && *value != 0) {
+                return Ok(Some(i));
+            }
+            *value = new;
+            if SemFlags::from_bits_truncate(op.flags).contains(SemFlags::SEM_UNDO) {
+                let key = (pid, op.num);
+                let adj = undos
+                    .entry(key)
+                    .or_insert_with(|| self.undos.get(&key).copied().unwrap_or(0));
+                *adj -= op.op as i32;
+                if adj.abs() > SEMVMX {
+                    return Err(LxError::ERANGE);
+                }
+            }
+        }
+        for (sem, value) in self.sems.iter_mut().zip(values) {
+            sem.value = value;
+        }
+        for op in ops {
+            self.sems[op.num as usize].pid = pid;
+        }
+        for (key, adj) in undos {
+            if adj == 0 {
+                self.undos.remove(&key);
+            } else {
+                self.undos.insert(key, adj);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Count a process waiting on the `num`-th semaphore, for a zero value if `zero`
+    fn count_waiter(&mut self, (num, zero): (usize, bool), waiting: bool) {
+        let sem = &mut self.sems[num];
+        let count = if zero { &mut sem.zcnt } else { &mut sem.ncnt };
+        if waiting {
+            *count += 1;
+        } else {
+            *count -= 1;
+        }
+    }
+
+    /// Wake up all waiting processes to check the semaphores again
+    fn wake_all(&mut self) {
+        for waker in self.waiters.drain(..) {
+            waker.wake();
+        }
+    }
+}
+
+/// Future of the operations on a semaphore set
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct SemopFuture<'a> {
+    array: &'a SemArray,
+    ops: &'a [SemBuf],
+    pid: usize,
+    /// The semaphore waited on, and whether for a zero value
+    blocked: Option<(usize, bool)>,
+}
+
+impl Future for SemopFuture<'_> {
+    type Output = LxResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let array = self.array;
+        let mut inner = array.inner.lock();
+        if let Some(blocked) = self.blocked.take() {
+            inner.count_waiter(blocked, false);
+        }
+        if inner.removed {
+            return Poll::Ready(Err(LxError::EIDRM));
+        }
+        match inner.try_operate(self.ops, self.pid) {
+            Ok(None) => {
+                inner.wake_all();
+                Poll::Ready(Ok(()))
+            }
+            Ok(Some(i)) => {
+                let op = self.ops[i];
+                if SemFlags::from_bits_truncate(op.flags).contains(SemFlags::IPC_NOWAIT) {
+                    return Poll::Ready(Err(LxError::EAGAIN));
+                }
+                let blocked = (op.num as usize, op.op == 0);
+                inner.count_waiter(blocked, true);
+                inner.waiters.push(cx.waker().clone());
+                self.blocked = Some(blocked);
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+impl Drop for SemopFuture<'_> {
+    fn drop(&mut self) {
+        // stop waiting on timeout
+        if let Some(blocked) = self.blocked.take() {
+            self.array.inner.lock().count_waiter(blocked, false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn op(num: u16, op: i16, flags: SemFlags) -> SemBuf {
+        SemBuf {
+            num,
+            op,
+            flags: flags.bits(),
+        }
+    }
+
+    fn create(values: &[u16]) -> Arc<SemArray> {
+        let array = SemArray::get_or_create(0, values.len(), 0o600).unwrap();
+        array.set_all(values, 1).unwrap();
+        array
+    }
+
+    #[async_std::test]
+    async fn atomic() {
+        let array = create(&[1, 0]);
+        let nowait = SemFlags::IPC_NOWAIT;
+        // the second operation can not be performed, so neither is the first
+        let ops = [op(0, -1, nowait), op(1, -1, nowait)];
+        assert!(matches!(array.operate(&ops, 2).await, Err(LxError::EAGAIN)));
+        assert_eq!(array.get_all(), [1, 0]);
+
+        // the operations see the results of the previous ones on the same semaphore
+        let ops = [op(0, 2, nowait), op(0, -3, nowait), op(1, 1, nowait)];
+        array.operate(&ops, 2).await.unwrap();
+        assert_eq!(array.get_all(), [0, 1]);
+        assert_eq!(array.get_pid(0).unwrap(), 2);
+
+        let ops = [
+            op(1, 1, nowait),
+            op(0, SEMVMX as i16, nowait),
+            op(0, 1, nowait),
+        ];
+        assert!(matches!(array.operate(&ops, 2).await, Err(LxError::ERANGE)));
+        assert_eq!(array.get_all(), [0, 1]);
+        assert!(matches!(
+            array.operate(&[op(2, 1, nowait)], 2).await,
+            Err(LxError::EFBIG)
+        ));
+        array.remove();
+    }
+
+    #[async_std::test]
+    async fn blocking() {
+        let array = create(&[1, 0]);
+        let waiter = {
+            let array = array.clone();
+            async_std::task::spawn(async move {
+                let ops = [op(0, -2, SemFlags::empty()), op(1, 1, SemFlags::empty())];
+                array.operate(&ops, 2).await
+            })
+        };
+        async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+        assert_eq!(array.get_ncnt(0).unwrap(), 1);
+        // nothing is done while waiting
+        assert_eq!(array.get_all(), [1, 0]);
+
+        array
+            .operate(&[op(0, 1, SemFlags::empty())], 3)
+            .await
+            .unwrap();
+        waiter.await.unwrap();
+        assert_eq!(array.get_all(), [0, 1]);
+        assert_eq!(array.get_ncnt(0).unwrap(), 0);
+
+        // wait for zero
+        let waiter = {
+            let array = array.clone();
+            async_std::task::spawn(
+                async move { array.operate(&[op(1, 0, SemFlags::empty())], 2).await },
+            )
+        };
+        async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+        assert_eq!(array.get_zcnt(1).unwrap(), 1);
+        array.remove();
+        assert!(matches!(waiter.await, Err(LxError::EIDRM)));
+    }
+
+    #[async_std::test]
+    async fn undo() {
+        let array = create(&[2]);
+        let undo = SemFlags::SEM_UNDO | SemFlags::IPC_NOWAIT;
+        array.operate(&[op(0, -2, undo)], 5).await.unwrap();
+        array.operate(&[op(0, 3, undo)], 6).await.unwrap();
+        assert_eq!(array.get_all(), [3]);
+        array.undo(5);
+        assert_eq!(array.get_all(), [5]);
+        // setting the value drops the adjustments
+        array.set_val(0, 1, 1).unwrap();
+        array.undo(6);
+        assert_eq!(array.get_all(), [1]);
+
+        // the value is clamped instead of blocking
+        array.operate(&[op(0, 2, undo)], 6).await.unwrap();
+        let nowait = SemFlags::IPC_NOWAIT;
+        array.operate(&[op(0, -3, nowait)], 7).await.unwrap();
+        array.undo(6);
+        assert_eq!(array.get_all(), [0]);
+        array.remove();
+    }
 }
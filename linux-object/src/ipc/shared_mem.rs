@@ -121,7 +121,7 @@ impl ShmGuard {
     pub fn detach(&self, pid: u32) {
         let mut ds = self.shmid_ds.lock();
         ds.dtime = TimeSpec::now().sec;
-        ds.nattch -= 1;
+        ds.nattch = ds.nattch.saturating_sub(1);
         ds.lpid = pid;
     }
 
@@ -136,7 +136,7 @@ impl ShmGuard {
         let mut lock = self.shmid_ds.lock();
         lock.perm.uid = new.perm.uid;
         lock.perm.gid = new.perm.gid;
-        lock.perm.mode = new.perm.mode & 0x1ff;
+        lock.perm.mode = (lock.perm.mode & !0x1ff) | (new.perm.mode & 0x1ff);
     }
 
     /// remove Shared memory
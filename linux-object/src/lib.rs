@@ -22,6 +22,7 @@ pub mod ipc;
 pub mod loader;
 pub mod net;
 pub mod process;
+pub mod ptrace;
 pub mod signal;
 pub mod sync;
 pub mod thread;
@@ -4,26 +4,35 @@
 use {
     crate::error::LxResult,
     crate::fs::INodeExt,
+    crate::process::ProcessExt,
     alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec},
-    rcore_fs::vfs::INode,
     xmas_elf::{program::ProgramHeader, ElfFile},
-    zircon_object::{util::elf_loader::*, vm::*, ZxError},
+    zircon_object::{object::KernelObject, task::Process, util::elf_loader::*, vm::*, ZxError},
 };
 
 mod abi;
 
+/// The maximum number of pages of the program break (heap), 512 MiB.
+///
+/// The whole range is reserved when the program is loaded, but no page is
+/// committed until the program break is moved over it and the page is touched.
+pub const USER_HEAP_PAGES: usize = 0x2_0000;
+
 /// Linux ELF Program Loader.
 pub struct LinuxElfLoader {
     /// syscall entry
     pub syscall_entry: usize,
     /// stack page number
     pub stack_pages: usize,
-    /// root inode of LinuxElfLoader
-    pub root_inode: Arc<dyn INode>,
+    /// the process whose file system tree the interpreter is looked up in
+    pub proc: Arc<Process>,
 }
 
 impl LinuxElfLoader {
-    /// load a Linux ElfFile and return a tuple of (entry,sp)
+    /// load a Linux ElfFile and return a tuple of (entry, sp, heap_start)
+    ///
+    /// The address space of `USER_HEAP_PAGES` after the loaded image is reserved for
+    /// the program break, which starts at `heap_start`.
     pub fn load(
         &self,
         vmar: &Arc<VmAddressRegion>,
@@ -31,7 +40,7 @@ impl LinuxElfLoader {
         args: Vec<String>,
         envs: Vec<String>,
         path: String,
-    ) -> LxResult<(VirtAddr, VirtAddr)> {
+    ) -> LxResult<(VirtAddr, VirtAddr, VirtAddr)> {
         debug!(
             "load: vmar.addr & size: {:#x?}, data {:#x?}, args: {:?}, envs: {:?}",
             vmar.get_info(),
@@ -46,17 +55,18 @@ impl LinuxElfLoader {
 
         if let Ok(interp) = elf.get_interpreter() {
             info!("interp: {:?}, path: {:?}", interp, path);
-            let inode = self.root_inode.lookup(interp)?;
+            let inode = self.proc.linux().lookup_inode(interp)?;
             let data = inode.read_as_vec()?;
             let mut new_args = vec![interp.into(), path.clone()];
             new_args.extend_from_slice(&args[1..]);
-            return self.load(vmar, &data, new_args, envs, path);
+            return self.load(vmar, &data, new_args, envs, interp.into());
         }
 
         let size = elf.load_segment_size();
         let image_vmar = vmar.allocate(None, size, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)?;
         let mut base = image_vmar.addr();
         let vmo = image_vmar.load_from_elf(&elf)?;
+        vmo.set_name(&path);
         let entry = base + elf.header.pt2.entry_point() as usize;
 
         // for static exec program
@@ -82,7 +92,21 @@ impl LinuxElfLoader {
             }
         }
 
+        // reserve the heap right after the image, no page is accessible until `brk`
+        let heap_start = image_vmar.addr() + size;
+        let heap_vmo = VmObject::new_paged(USER_HEAP_PAGES);
+        heap_vmo.set_name("[heap]");
+        vmar.map_at(
+            heap_start - vmar.addr(),
+            heap_vmo.clone(),
+            0,
+            heap_vmo.len(),
+            MMUFlags::USER,
+        )?;
+        debug!("load heap start: {:#x}", heap_start);
+
         let stack_vmo = VmObject::new_paged(self.stack_pages);
+        stack_vmo.set_name("[stack]");
         let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
         let stack_bottom = vmar.map(None, stack_vmo.clone(), 0, stack_vmo.len(), flags)?;
         let mut sp = stack_bottom + stack_vmo.len();
@@ -126,6 +150,6 @@ impl LinuxElfLoader {
             info.auxv, entry, sp
         );
 
-        Ok((entry, sp))
+        Ok((entry, sp, heap_start))
     }
 }
@@ -4,7 +4,7 @@
 /// missing documentation
 #[macro_use]
 pub mod socket_address;
-use crate::fs::{FileLike, PollEvents};
+use crate::fs::{FileLike, PollEvents, PollStatus};
 use smoltcp::wire::IpEndpoint;
 pub use socket_address::*;
 
@@ -24,6 +24,10 @@ pub use raw::*;
 pub mod netlink;
 pub use netlink::*;
 
+/// Unix domain socket
+pub mod unix;
+pub use unix::*;
+
 /// missing documentation
 // pub mod icmp;
 // pub use icmp::*;
@@ -189,6 +193,8 @@ numeric_enum! {
         RCVBUF = 8,  // 获取接收缓冲区长度
         /// linger
         LINGER = 13,
+        /// enable receiving of SCM_CREDENTIALS
+        PASSCRED = 16,
     }
 }
 
@@ -212,6 +218,26 @@ numeric_enum! {
     }
 }
 
+bitflags::bitflags! {
+    /// Flags of `sendmsg`, `recvmsg` and `struct msghdr`
+    pub struct MsgFlags: usize {
+        /// Return data without removing it from the queue
+        const PEEK = 0x2;
+        /// Control data was discarded due to lack of space
+        const CTRUNC = 0x8;
+        /// Return the real length of a truncated datagram
+        const TRUNC = 0x20;
+        /// Enable nonblocking operation
+        const DONTWAIT = 0x40;
+        /// Block until the full request is satisfied
+        const WAITALL = 0x100;
+        /// Don't generate SIGPIPE if the peer has closed the connection
+        const NOSIGNAL = 0x4000;
+        /// Set close-on-exec flag for file descriptors received by SCM_RIGHTS
+        const CMSG_CLOEXEC = 0x4000_0000;
+    }
+}
+
 // ============= Define =============
 
 // ============= SocketHandle =============
@@ -257,6 +283,47 @@ fn poll_ifaces() {
     }
 }
 
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use kernel_hal::timer;
+
+/// Interval to check a TCP or UDP socket again while it is waited for,
+/// since the network stack does not wake up the waiters of its sockets.
+const SOCKET_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Wait until the TCP or UDP `socket` is ready for `events`, or has an error.
+async fn poll_socket(socket: &dyn Socket, events: PollEvents) -> LxResult<PollStatus> {
+    #[must_use = "future does nothing unless polled/`await`-ed"]
+    struct SocketFuture<'a> {
+        socket: &'a dyn Socket,
+        events: PollEvents,
+    }
+
+    impl<'a> Future for SocketFuture<'a> {
+        type Output = LxResult<PollStatus>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+            let (read, write, error) = self.socket.poll(self.events);
+            if error
+                || (read && self.events.contains(PollEvents::IN))
+                || (write && self.events.contains(PollEvents::OUT))
+            {
+                return Poll::Ready(Ok(PollStatus { read, write, error }));
+            }
+            let waker = cx.waker().clone();
+            let deadline = timer::timer_now() + SOCKET_POLL_INTERVAL;
+            timer::timer_set(deadline, Box::new(move |_| waker.wake_by_ref()));
+            Poll::Pending
+        }
+    }
+
+    SocketFuture { socket, events }.await
+}
+
 // ============= SocketHandle =============
 
 // ============= Rand Port =============
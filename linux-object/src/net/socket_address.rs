@@ -2,8 +2,11 @@
 
 use core::{cmp::min, mem::size_of};
 
+// alloc
+use alloc::{string::String, vec::Vec};
+
 // crate
-use crate::error::LxError;
+use crate::error::{LxError, LxResult};
 // use crate::net::Endpoint;
 
 // smoltcp
@@ -55,6 +58,27 @@ pub struct SockAddrUn {
     pub sun_path: [u8; 108],
 }
 
+impl SockAddrUn {
+    /// Length of the address, including the terminating null byte of a path
+    ///
+    /// Trailing null bytes of an abstract name are not counted.
+    fn len(&self) -> usize {
+        let path_len = match self.sun_path[0] {
+            0 => self
+                .sun_path
+                .iter()
+                .rposition(|&c| c != 0)
+                .map_or(0, |i| i + 1),
+            _ => self
+                .sun_path
+                .iter()
+                .position(|&c| c == 0)
+                .map_or(self.sun_path.len(), |i| i + 1),
+        };
+        size_of::<u16>() + path_len
+    }
+}
+
 /// missing documentation
 #[derive(Clone, Copy)]
 #[repr(C)]
@@ -108,6 +132,8 @@ pub enum Endpoint {
     LinkLevel(LinkLevelEndpoint),
     /// missing documentation
     Netlink(NetlinkEndpoint),
+    /// Unix domain socket address
+    Unix(UnixEndpoint),
 }
 
 /// missing documentation
@@ -145,6 +171,36 @@ impl NetlinkEndpoint {
     }
 }
 
+/// Address of a unix domain socket
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum UnixEndpoint {
+    /// The socket is not bound
+    Unnamed,
+    /// Bound to a path in the file system
+    Path(String),
+    /// Bound to a name in the abstract namespace
+    Abstract(Vec<u8>),
+}
+
+impl UnixEndpoint {
+    /// Parse the address from `sockaddr_un` of `len` bytes
+    fn from_sockaddr(addr: &SockAddrUn, len: usize) -> LxResult<Self> {
+        if len > size_of::<SockAddrUn>() {
+            return Err(LxError::EINVAL);
+        }
+        let path = &addr.sun_path[..len - size_of::<u16>()];
+        match path.first() {
+            None => Ok(UnixEndpoint::Unnamed),
+            Some(0) => Ok(UnixEndpoint::Abstract(path[1..].to_vec())),
+            Some(_) => {
+                let end = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                let path = core::str::from_utf8(&path[..end]).map_err(|_| LxError::EINVAL)?;
+                Ok(UnixEndpoint::Path(path.into()))
+            }
+        }
+    }
+}
+
 // ============= Endpoint =============
 
 impl From<Endpoint> for SockAddr {
@@ -189,6 +245,25 @@ impl From<Endpoint> for SockAddr {
                     nl_groups: netlink.multicast_groups_mask,
                 },
             }
+        } else if let Endpoint::Unix(unix) = endpoint {
+            let mut addr_un = SockAddrUn {
+                sun_family: AddressFamily::Unix.into(),
+                sun_path: [0; 108],
+            };
+            // keep the last byte for the terminating null byte of a path
+            let max_len = addr_un.sun_path.len() - 1;
+            match unix {
+                UnixEndpoint::Unnamed => {}
+                UnixEndpoint::Path(path) => {
+                    let len = min(path.len(), max_len);
+                    addr_un.sun_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+                }
+                UnixEndpoint::Abstract(name) => {
+                    let len = min(name.len(), max_len);
+                    addr_un.sun_path[1..len + 1].copy_from_slice(&name[..len]);
+                }
+            }
+            SockAddr { addr_un }
         } else {
             unimplemented!("not match");
         }
@@ -201,12 +276,15 @@ pub fn sockaddr_to_endpoint(addr: SockAddr, len: usize) -> Result<Endpoint, LxEr
         return Err(LxError::EINVAL);
     }
     // let addr = unsafe { vm.check_read_ptr(addr)? };
-    if len < addr.len()? {
+    #[allow(unsafe_code)]
+    let family = AddressFamily::from(unsafe { addr.family });
+    // the length of a unix domain socket address is variable
+    if family != AddressFamily::Unix && len < addr.len()? {
         return Err(LxError::EINVAL);
     }
     #[allow(unsafe_code)]
     unsafe {
-        match AddressFamily::from(addr.family) {
+        match family {
             AddressFamily::Internet => {
                 let port = u16::from_be(addr.addr_in.sin_port);
                 let addr = IpAddress::from(Ipv4Address::from_bytes(
@@ -214,7 +292,10 @@ pub fn sockaddr_to_endpoint(addr: SockAddr, len: usize) -> Result<Endpoint, LxEr
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
-            AddressFamily::Unix => Err(LxError::EINVAL),
+            AddressFamily::Unix => Ok(Endpoint::Unix(UnixEndpoint::from_sockaddr(
+                &addr.addr_un,
+                len,
+            )?)),
             // AddressFamily::Packet => Ok(Endpoint::LinkLevel(LinkLevelEndpoint::new(
             //     addr.addr_ll.sll_ifindex as usize,
             // ))),
@@ -234,7 +315,7 @@ impl SockAddr {
             AddressFamily::Internet => Ok(size_of::<SockAddrIn>()),
             AddressFamily::Packet => Ok(size_of::<SockAddrLl>()),
             AddressFamily::Netlink => Ok(size_of::<SockAddrNl>()),
-            AddressFamily::Unix => Err(LxError::EINVAL),
+            AddressFamily::Unix => Ok(unsafe { self.addr_un.len() }),
             _ => Err(LxError::EINVAL),
         }
     }
@@ -303,7 +384,7 @@ impl SockAddr {
     /// Write to msg
     /// Check mutability for user
     #[allow(dead_code)]
-    pub fn write_to_msg(self, msg: UserInOutPtr<MsgHdr>) -> SysResult {
+    pub fn write_to_msg(self, mut msg: UserInOutPtr<MsgHdr>) -> SysResult {
         if msg.is_null() {
             return Ok(0);
         }
@@ -313,13 +394,15 @@ impl SockAddr {
         let full_len = self.len()?;
         let written_len = min(max_addr_len, full_len);
         hdr.set_msg_name_len(full_len as u32);
+        let msg_name = hdr.msg_name;
+        msg.write(hdr)?;
 
         use core::slice;
 
         #[allow(unsafe_code)]
         unsafe {
             let source = slice::from_raw_parts(&self as *const SockAddr as *const u8, written_len);
-            let mut addr: UserOutPtr<u8> = core::mem::transmute(hdr.msg_name);
+            let mut addr: UserOutPtr<u8> = core::mem::transmute(msg_name);
             addr.write_array(source)?;
         }
         Ok(0)
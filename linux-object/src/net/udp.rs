@@ -324,8 +324,7 @@ impl FileLike for UdpSocketState {
     }
 
     async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
-        let (read, write, error) = Socket::poll(self, events);
-        Ok(PollStatus { read, write, error })
+        poll_socket(self, events).await
     }
 
     fn ioctl(&self, request: usize, arg1: usize, arg2: usize, arg3: usize) -> LxResult<usize> {
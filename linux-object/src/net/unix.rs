@@ -0,0 +1,1016 @@
+//! Unix domain socket
+//!
+//! The receive queue holds up to [`UNIX_BUFFER_SIZE`] bytes unless `SO_RCVBUF` changes
+//! it, and a datagram must fit in the send buffer set by `SO_SNDBUF`. `sendmsg` waits for
+//! room in it unless the socket is nonblocking. `write` can not wait, since it is not
+//! async, so it returns `EAGAIN` if the queue is full.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    format,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    cmp::min,
+    convert::TryFrom,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use lock::{Mutex, RwLock};
+use zircon_object::object::*;
+
+use crate::error::{LxError, LxResult, SysResult};
+use crate::fs::{FileLike, OpenFlags, PollEvents, PollStatus};
+use crate::net::{Endpoint, Level, MsgFlags, Socket, SocketType, SolOptname, UnixEndpoint};
+use crate::process::{current_thread, send_signal_to_thread};
+use crate::signal::{SigInfo, Signal, SignalCode};
+use crate::sync::{wait_for_event, Event, EventBus};
+
+/// Size of the receive queue in bytes, the default socket buffer size of Linux
+pub const UNIX_BUFFER_SIZE: usize = 212_992;
+
+/// Minimum size of the socket buffers, `SOCK_MIN_SNDBUF` of Linux
+const UNIX_MIN_BUFFER_SIZE: usize = 4608;
+
+lazy_static! {
+    /// Bound sockets, in both the file system and the abstract namespace
+    ///
+    /// A path is kept as an absolute path, its uniqueness is guaranteed by
+    /// the socket file created in the file system.
+    static ref UNIX_NAMESPACE: RwLock<BTreeMap<UnixEndpoint, Weak<UnixSocketState>>> =
+        RwLock::new(BTreeMap::new());
+}
+
+/// Credentials of a process, `struct ucred`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UCred {
+    /// process ID
+    pub pid: i32,
+    /// user ID
+    pub uid: u32,
+    /// group ID
+    pub gid: u32,
+}
+
+/// Ancillary data of a message
+#[derive(Default)]
+pub struct UnixAncillary {
+    /// files passed by SCM_RIGHTS
+    pub files: Vec<Arc<dyn FileLike>>,
+    /// credentials passed by SCM_CREDENTIALS
+    pub cred: Option<UCred>,
+}
+
+/// Result of `UnixSocketState::recvmsg`
+pub struct UnixRecvInfo {
+    /// number of bytes copied to the buffer
+    pub len: usize,
+    /// real length of the datagram, longer than `len` if it is truncated
+    pub msg_len: usize,
+    /// address of the sender
+    pub from: UnixEndpoint,
+    /// ancillary data, credentials are only reported if SO_PASSCRED is set
+    pub ancillary: UnixAncillary,
+}
+
+/// A message in the receive queue
+struct UnixMessage {
+    /// payload
+    data: Vec<u8>,
+    /// bytes which have been read, only used by stream sockets
+    offset: usize,
+    /// address of the sender
+    from: UnixEndpoint,
+    /// credentials of the sender
+    cred: UCred,
+    /// files passed by SCM_RIGHTS, taken by the first read of the message
+    files: Vec<Arc<dyn FileLike>>,
+}
+
+/// Room in the receive queue of a socket, shared with the sockets sending to it
+struct RecvSpace {
+    /// bytes in the receive queue
+    used: AtomicUsize,
+    /// capacity of the receive queue in bytes
+    size: AtomicUsize,
+    /// `Event::WRITABLE` is set if the queue is not full, or the socket is gone
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl RecvSpace {
+    fn is_full(&self) -> bool {
+        self.used.load(Ordering::Relaxed) >= self.size.load(Ordering::Relaxed)
+    }
+}
+
+/// Connection state of a socket
+enum UnixState {
+    /// not connected
+    Unconnected,
+    /// listening, with the queue of connections to be accepted
+    Listening(VecDeque<Arc<UnixSocketState>>),
+    /// connected to a peer, or the default destination of a datagram socket
+    Connected {
+        /// the peer
+        peer: Weak<UnixSocketState>,
+        /// address of the peer
+        peer_addr: UnixEndpoint,
+        /// room in the receive queue of the peer
+        peer_space: Arc<RecvSpace>,
+    },
+}
+
+/// Unix socket inner
+struct UnixInner {
+    /// flags on the socket
+    flags: OpenFlags,
+    /// bound address
+    local: Option<UnixEndpoint>,
+    /// connection state
+    state: UnixState,
+    /// received messages
+    queue: VecDeque<UnixMessage>,
+    /// report credentials of the sender on receiving
+    passcred: bool,
+    /// the socket is shut down
+    shutdown: bool,
+    /// the peer is closed or shut down
+    peer_closed: bool,
+}
+
+/// Unix domain socket structure
+pub struct UnixSocketState {
+    /// Kernel object base
+    base: KObjectBase,
+    /// SOCK_STREAM, SOCK_DGRAM or SOCK_SEQPACKET
+    socket_type: SocketType,
+    /// credentials of the creator, used if a message carries no SCM_CREDENTIALS
+    cred: UCred,
+    /// weak reference to itself, to be found by others
+    me: Weak<UnixSocketState>,
+    /// UnixSocket Inner
+    inner: Mutex<UnixInner>,
+    /// event bus to wake up waiters
+    eventbus: Arc<Mutex<EventBus>>,
+    /// room in the receive queue
+    space: Arc<RecvSpace>,
+    /// the largest datagram to send in bytes
+    sndbuf: AtomicUsize,
+}
+
+impl_kobject!(UnixSocketState);
+
+impl UnixSocketState {
+    /// Create an unbound unix socket
+    pub fn new(socket_type: SocketType, cred: UCred) -> Arc<Self> {
+        let socket = Arc::new_cyclic(|me| UnixSocketState {
+            base: KObjectBase::new(),
+            socket_type,
+            cred,
+            me: me.clone(),
+            inner: Mutex::new(UnixInner {
+                flags: OpenFlags::RDWR,
+                local: None,
+                state: UnixState::Unconnected,
+                queue: VecDeque::new(),
+                passcred: false,
+                shutdown: false,
+                peer_closed: false,
+            }),
+            eventbus: EventBus::new(),
+            space: Arc::new(RecvSpace {
+                used: AtomicUsize::new(0),
+                size: AtomicUsize::new(UNIX_BUFFER_SIZE),
+                eventbus: EventBus::new(),
+            }),
+            sndbuf: AtomicUsize::new(UNIX_BUFFER_SIZE),
+        });
+        socket.update_events(&socket.inner.lock());
+        socket
+    }
+
+    /// Create a pair of connected unix sockets
+    pub fn pair(socket_type: SocketType, cred: UCred) -> (Arc<Self>, Arc<Self>) {
+        let socket0 = Self::new(socket_type, cred);
+        let socket1 = Self::new(socket_type, cred);
+        socket0.set_connected(&socket1, UnixEndpoint::Unnamed);
+        socket1.set_connected(&socket0, UnixEndpoint::Unnamed);
+        (socket0, socket1)
+    }
+
+    /// Whether it is a connection-based socket
+    fn is_connection_mode(&self) -> bool {
+        self.socket_type != SocketType::SOCK_DGRAM
+    }
+
+    fn status(&self, inner: &UnixInner) -> PollStatus {
+        let hangup = self.is_connection_mode() && (inner.peer_closed || inner.shutdown);
+        let read = match &inner.state {
+            UnixState::Listening(backlog) => !backlog.is_empty(),
+            _ => !inner.queue.is_empty() || hangup,
+        };
+        let write = match &inner.state {
+            _ if inner.shutdown => false,
+            UnixState::Connected { peer_space, .. } => !inner.peer_closed && !peer_space.is_full(),
+            _ => !self.is_connection_mode(),
+        };
+        PollStatus {
+            read,
+            write,
+            error: false,
+        }
+    }
+
+    /// Notify the event bus with the new state, must be called with the inner locked
+    fn update_events(&self, inner: &UnixInner) {
+        let status = self.status(inner);
+        let mut set = Event::empty();
+        if status.read {
+            set |= Event::READABLE;
+        }
+        if status.write {
+            set |= Event::WRITABLE;
+        }
+        if self.is_connection_mode() && inner.peer_closed {
+            set |= Event::CLOSED;
+        }
+        self.eventbus
+            .lock()
+            .change(Event::READABLE | Event::WRITABLE | Event::CLOSED, set);
+        // senders waiting for room fail after a shutdown
+        let room = if inner.shutdown || !self.space.is_full() {
+            Event::WRITABLE
+        } else {
+            Event::empty()
+        };
+        self.space.eventbus.lock().change(Event::WRITABLE, room);
+    }
+
+    fn set_connected(&self, peer: &Arc<Self>, peer_addr: UnixEndpoint) {
+        let mut inner = self.inner.lock();
+        inner.state = UnixState::Connected {
+            peer: Arc::downgrade(peer),
+            peer_addr,
+            peer_space: peer.space.clone(),
+        };
+        self.update_events(&inner);
+    }
+
+    fn set_peer_closed(&self) {
+        let mut inner = self.inner.lock();
+        inner.peer_closed = true;
+        self.update_events(&inner);
+    }
+
+    /// Find the socket bound to `addr`
+    fn lookup(addr: &UnixEndpoint) -> LxResult<Arc<Self>> {
+        UNIX_NAMESPACE
+            .read()
+            .get(addr)
+            .and_then(Weak::upgrade)
+            .ok_or(LxError::ECONNREFUSED)
+    }
+
+    /// Bind to `addr`, or a unique name in the abstract namespace if it is unnamed
+    fn bind_locked(&self, inner: &mut UnixInner, addr: UnixEndpoint) -> LxResult {
+        if inner.local.is_some() {
+            return Err(LxError::EINVAL);
+        }
+        let mut namespace = UNIX_NAMESPACE.write();
+        let in_use = |addr: &UnixEndpoint| {
+            namespace
+                .get(addr)
+                .map_or(false, |socket| socket.strong_count() > 0)
+        };
+        let addr = match addr {
+            UnixEndpoint::Unnamed => {
+                // autobind, the name is 5 hex digits like Linux
+                static NEXT_NAME: AtomicUsize = AtomicUsize::new(0);
+                loop {
+                    let name = NEXT_NAME.fetch_add(1, Ordering::Relaxed) & 0xfffff;
+                    let addr = UnixEndpoint::Abstract(format!("{:05x}", name).into_bytes());
+                    if !in_use(&addr) {
+                        break addr;
+                    }
+                }
+            }
+            UnixEndpoint::Abstract(_) if in_use(&addr) => return Err(LxError::EADDRINUSE),
+            // a socket bound to a path which has been removed is replaced
+            addr => addr,
+        };
+        namespace.insert(addr.clone(), self.me.clone());
+        inner.local = Some(addr);
+        Ok(())
+    }
+
+    /// Connect to the socket bound to `addr`
+    fn connect_to(&self, addr: UnixEndpoint) -> SysResult {
+        let target = Self::lookup(&addr)?;
+        if target.socket_type != self.socket_type {
+            return Err(LxError::EPROTOTYPE);
+        }
+        if core::ptr::eq(&*target, self) && self.is_connection_mode() {
+            // a socket can not be listening and connecting
+            return Err(LxError::ECONNREFUSED);
+        }
+        if !self.is_connection_mode() {
+            // only set the default destination for a datagram socket
+            self.set_connected(&target, addr);
+            return Ok(0);
+        }
+        let mut inner = self.inner.lock();
+        match inner.state {
+            UnixState::Unconnected => {}
+            UnixState::Connected { .. } => return Err(LxError::EISCONN),
+            UnixState::Listening(_) => return Err(LxError::EINVAL),
+        }
+        let mut target_inner = target.inner.lock();
+        let target_addr = target_inner.local.clone();
+        let backlog = match &mut target_inner.state {
+            UnixState::Listening(backlog) => backlog,
+            _ => return Err(LxError::ECONNREFUSED),
+        };
+        // the socket to be accepted
+        let server = Self::new(self.socket_type, target.cred);
+        server.inner.lock().local = target_addr;
+        let client_addr = inner.local.clone().unwrap_or(UnixEndpoint::Unnamed);
+        server.set_connected(&self.me.upgrade().unwrap(), client_addr);
+        inner.state = UnixState::Connected {
+            peer: Arc::downgrade(&server),
+            peer_addr: addr,
+            peer_space: server.space.clone(),
+        };
+        backlog.push_back(server);
+        target.update_events(&target_inner);
+        self.update_events(&inner);
+        Ok(0)
+    }
+
+    /// Take a connection from the queue, returns `EAGAIN` if it is empty
+    fn try_accept(&self) -> LxResult<Arc<Self>> {
+        let mut inner = self.inner.lock();
+        let server = match &mut inner.state {
+            UnixState::Listening(backlog) => backlog.pop_front().ok_or(LxError::EAGAIN)?,
+            _ => return Err(LxError::EINVAL),
+        };
+        self.update_events(&inner);
+        Ok(server)
+    }
+
+    /// Send a message to the peer, or `to` for a datagram socket
+    ///
+    /// Waits if the receive queue is full unless it is nonblocking,
+    /// a stream socket only sends the part of `data` which fits in the queue.
+    /// A broken stream raises `SIGPIPE` unless `MSG_NOSIGNAL` is given.
+    /// The credentials of the creator are attached if `ancillary` has none.
+    pub async fn sendmsg(
+        &self,
+        data: &[u8],
+        to: Option<UnixEndpoint>,
+        mut ancillary: UnixAncillary,
+        flags: MsgFlags,
+    ) -> SysResult {
+        let nonblock =
+            flags.contains(MsgFlags::DONTWAIT) || self.flags().contains(OpenFlags::NON_BLOCK);
+        loop {
+            let space = match self.receiver(to.clone()) {
+                Ok((peer, from)) => match self.push_to(&peer, data, from, &mut ancillary) {
+                    Err(LxError::EAGAIN) if !nonblock => peer.space.clone(),
+                    ret => return self.sigpipe_on_error(ret, flags),
+                },
+                Err(err) => return self.sigpipe_on_error(Err(err), flags),
+            };
+            wait_for_event(space.eventbus.clone(), Event::WRITABLE).await;
+        }
+    }
+
+    /// Send a message without waiting, returns `EAGAIN` if the receive queue is full
+    pub fn try_sendmsg(
+        &self,
+        data: &[u8],
+        to: Option<UnixEndpoint>,
+        mut ancillary: UnixAncillary,
+        flags: MsgFlags,
+    ) -> SysResult {
+        let ret = self
+            .receiver(to)
+            .and_then(|(peer, from)| self.push_to(&peer, data, from, &mut ancillary));
+        self.sigpipe_on_error(ret, flags)
+    }
+
+    /// Find the receiver of a message, returns it with the address of the sender
+    fn receiver(&self, to: Option<UnixEndpoint>) -> LxResult<(Arc<Self>, UnixEndpoint)> {
+        let inner = self.inner.lock();
+        if inner.shutdown {
+            return Err(LxError::EPIPE);
+        }
+        let (peer, to) = match (&inner.state, to) {
+            (UnixState::Connected { .. }, Some(_)) if self.is_connection_mode() => {
+                return Err(LxError::EISCONN);
+            }
+            (_, Some(to)) if !self.is_connection_mode() => (Weak::new(), Some(to)),
+            (UnixState::Connected { .. }, None) if inner.peer_closed => {
+                return Err(LxError::EPIPE);
+            }
+            (UnixState::Connected { peer, .. }, None) => (peer.clone(), None),
+            _ => return Err(LxError::ENOTCONN),
+        };
+        let from = inner.local.clone().unwrap_or(UnixEndpoint::Unnamed);
+        // the peer must not be dropped with the inner locked
+        drop(inner);
+        let peer = match to {
+            Some(to) => {
+                let target = Self::lookup(&to)?;
+                if target.socket_type != self.socket_type {
+                    return Err(LxError::EPROTOTYPE);
+                }
+                target
+            }
+            None if self.is_connection_mode() => peer.upgrade().ok_or(LxError::EPIPE)?,
+            None => peer.upgrade().ok_or(LxError::ECONNREFUSED)?,
+        };
+        Ok((peer, from))
+    }
+
+    /// Put a message in the receive queue of `peer`, returns `EAGAIN` if it is full
+    ///
+    /// The files of `ancillary` are taken only if the message is sent.
+    fn push_to(
+        &self,
+        peer: &Self,
+        data: &[u8],
+        from: UnixEndpoint,
+        ancillary: &mut UnixAncillary,
+    ) -> SysResult {
+        let stream = self.socket_type == SocketType::SOCK_STREAM;
+        // there is no zero-length message in a byte stream
+        if stream && data.is_empty() {
+            return Ok(0);
+        }
+        if !stream && data.len() > self.sndbuf.load(Ordering::Relaxed) {
+            return Err(LxError::EMSGSIZE);
+        }
+        let mut peer_inner = peer.inner.lock();
+        if peer_inner.shutdown {
+            return Err(LxError::EPIPE);
+        }
+        let used = peer.space.used.load(Ordering::Relaxed);
+        let size = peer.space.size.load(Ordering::Relaxed);
+        if used >= size {
+            return Err(LxError::EAGAIN);
+        }
+        // a datagram may exceed the room left, a byte stream is cut
+        let len = if stream {
+            min(data.len(), size - used)
+        } else {
+            data.len()
+        };
+        peer_inner.queue.push_back(UnixMessage {
+            data: data[..len].to_vec(),
+            offset: 0,
+            from,
+            cred: ancillary.cred.unwrap_or(self.cred),
+            files: core::mem::take(&mut ancillary.files),
+        });
+        peer.space.used.fetch_add(len, Ordering::Relaxed);
+        peer.update_events(&peer_inner);
+        Ok(len)
+    }
+
+    /// Raise `SIGPIPE` in the calling thread if a byte stream is broken,
+    /// unless `MSG_NOSIGNAL` is given
+    fn sigpipe_on_error(&self, ret: SysResult, flags: MsgFlags) -> SysResult {
+        if matches!(ret, Err(LxError::EPIPE))
+            && self.socket_type == SocketType::SOCK_STREAM
+            && !flags.contains(MsgFlags::NOSIGNAL)
+        {
+            if let Some(thread) = current_thread() {
+                let info = SigInfo::new(Signal::SIGPIPE, SignalCode::KERNEL as i32);
+                send_signal_to_thread(&thread, info);
+            }
+        }
+        ret
+    }
+
+    /// Receive a message, waits if the queue is empty unless it is nonblocking
+    pub async fn recvmsg(&self, buf: &mut [u8], flags: MsgFlags) -> LxResult<UnixRecvInfo> {
+        loop {
+            let ret = self.try_recv(&mut self.inner.lock(), buf, flags);
+            match ret {
+                Err(LxError::EAGAIN)
+                    if !flags.contains(MsgFlags::DONTWAIT)
+                        && !self.flags().contains(OpenFlags::NON_BLOCK) =>
+                {
+                    wait_for_event(self.eventbus.clone(), Event::READABLE).await;
+                }
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Take data from the receive queue, returns `EAGAIN` if there is nothing
+    ///
+    /// Files in the result must be dropped after the inner is unlocked,
+    /// since dropping a socket may lock its peer.
+    fn try_recv(
+        &self,
+        inner: &mut UnixInner,
+        buf: &mut [u8],
+        flags: MsgFlags,
+    ) -> LxResult<UnixRecvInfo> {
+        let front = match inner.queue.front() {
+            Some(msg) => msg,
+            None => {
+                return match inner.state {
+                    UnixState::Connected { .. } if inner.peer_closed || inner.shutdown => {
+                        // end of file
+                        Ok(UnixRecvInfo {
+                            len: 0,
+                            msg_len: 0,
+                            from: UnixEndpoint::Unnamed,
+                            ancillary: UnixAncillary::default(),
+                        })
+                    }
+                    UnixState::Connected { .. } => Err(LxError::EAGAIN),
+                    _ if self.is_connection_mode() => Err(LxError::ENOTCONN),
+                    _ if inner.shutdown => Err(LxError::EPIPE),
+                    _ => Err(LxError::EAGAIN),
+                };
+            }
+        };
+        let peek = flags.contains(MsgFlags::PEEK);
+        let from = front.from.clone();
+        let cred = front.cred;
+        let mut files = Vec::new();
+        let mut len = 0;
+        let msg_len;
+        if self.socket_type == SocketType::SOCK_STREAM {
+            // read across messages, but not into the next one carrying files
+            let mut consumed = 0;
+            for msg in inner.queue.iter_mut() {
+                if len == buf.len() || (len > 0 && !msg.files.is_empty()) {
+                    break;
+                }
+                let n = min(buf.len() - len, msg.data.len() - msg.offset);
+                buf[len..len + n].copy_from_slice(&msg.data[msg.offset..msg.offset + n]);
+                len += n;
+                if peek {
+                    files.extend(msg.files.iter().cloned());
+                } else {
+                    files.append(&mut msg.files);
+                    msg.offset += n;
+                    if msg.offset == msg.data.len() {
+                        consumed += 1;
+                    }
+                }
+            }
+            inner.queue.drain(..consumed);
+            if !peek {
+                self.space.used.fetch_sub(len, Ordering::Relaxed);
+            }
+            msg_len = len;
+        } else {
+            // one message at a time, the rest of a message is discarded
+            let msg = inner.queue.front_mut().unwrap();
+            len = min(buf.len(), msg.data.len());
+            msg_len = msg.data.len();
+            buf[..len].copy_from_slice(&msg.data[..len]);
+            if peek {
+                files.extend(msg.files.iter().cloned());
+            } else {
+                files.append(&mut msg.files);
+                inner.queue.pop_front();
+                self.space.used.fetch_sub(msg_len, Ordering::Relaxed);
+            }
+        }
+        self.update_events(inner);
+        Ok(UnixRecvInfo {
+            len,
+            msg_len,
+            from,
+            ancillary: UnixAncillary {
+                files,
+                cred: if inner.passcred { Some(cred) } else { None },
+            },
+        })
+    }
+}
+
+impl Drop for UnixSocketState {
+    fn drop(&mut self) {
+        let inner = self.inner.lock();
+        if let Some(addr) = &inner.local {
+            let mut namespace = UNIX_NAMESPACE.write();
+            // the address may have been taken by another socket
+            if namespace
+                .get(addr)
+                .map_or(false, |socket| socket.as_ptr() == self as *const Self)
+            {
+                namespace.remove(addr);
+            }
+        }
+        let peer = match &inner.state {
+            UnixState::Connected { peer, .. } if self.is_connection_mode() => peer.upgrade(),
+            _ => None,
+        };
+        drop(inner);
+        if let Some(peer) = peer {
+            peer.set_peer_closed();
+        }
+        // wake up the senders waiting for room
+        self.space.eventbus.lock().set(Event::WRITABLE);
+    }
+}
+
+#[async_trait]
+impl Socket for UnixSocketState {
+    /// read to buffer
+    async fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        match self.recvmsg(data, MsgFlags::empty()).await {
+            Ok(info) => (Ok(info.len), Endpoint::Unix(info.from)),
+            Err(err) => (Err(err), Endpoint::Unix(UnixEndpoint::Unnamed)),
+        }
+    }
+
+    /// write from buffer
+    fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
+        let to = match sendto_endpoint {
+            Some(Endpoint::Unix(addr)) => Some(addr),
+            Some(_) => return Err(LxError::EINVAL),
+            None => None,
+        };
+        self.try_sendmsg(data, to, UnixAncillary::default(), MsgFlags::empty())
+    }
+
+    /// wait for some event on a file descriptor
+    fn poll(&self, _events: PollEvents) -> (bool, bool, bool) {
+        let status = self.status(&self.inner.lock());
+        (status.read, status.write, status.error)
+    }
+
+    /// connect
+    async fn connect(&self, endpoint: Endpoint) -> SysResult {
+        match endpoint {
+            Endpoint::Unix(addr) => self.connect_to(addr),
+            _ => Err(LxError::EINVAL),
+        }
+    }
+
+    fn bind(&self, endpoint: Endpoint) -> SysResult {
+        match endpoint {
+            Endpoint::Unix(addr) => {
+                self.bind_locked(&mut self.inner.lock(), addr)?;
+                Ok(0)
+            }
+            _ => Err(LxError::EINVAL),
+        }
+    }
+
+    fn listen(&self) -> SysResult {
+        if !self.is_connection_mode() {
+            return Err(LxError::EOPNOTSUPP);
+        }
+        let mut inner = self.inner.lock();
+        match inner.state {
+            UnixState::Unconnected => {}
+            // it is ok to listen twice
+            UnixState::Listening(_) => return Ok(0),
+            UnixState::Connected { .. } => return Err(LxError::EINVAL),
+        }
+        if inner.local.is_none() {
+            self.bind_locked(&mut inner, UnixEndpoint::Unnamed)?;
+        }
+        inner.state = UnixState::Listening(VecDeque::new());
+        self.update_events(&inner);
+        Ok(0)
+    }
+
+    fn shutdown(&self) -> SysResult {
+        let mut inner = self.inner.lock();
+        inner.shutdown = true;
+        self.update_events(&inner);
+        let peer = match &inner.state {
+            UnixState::Connected { peer, .. } if self.is_connection_mode() => peer.upgrade(),
+            _ => None,
+        };
+        drop(inner);
+        if let Some(peer) = peer {
+            peer.set_peer_closed();
+        }
+        Ok(0)
+    }
+
+    async fn accept(&self) -> LxResult<(Arc<dyn FileLike>, Endpoint)> {
+        loop {
+            match self.try_accept() {
+                Err(LxError::EAGAIN) if !self.flags().contains(OpenFlags::NON_BLOCK) => {
+                    wait_for_event(self.eventbus.clone(), Event::READABLE).await;
+                }
+                ret => {
+                    let server = ret?;
+                    let remote_endpoint = Socket::remote_endpoint(&*server)
+                        .unwrap_or(Endpoint::Unix(UnixEndpoint::Unnamed));
+                    return Ok((server as Arc<dyn FileLike>, remote_endpoint));
+                }
+            }
+        }
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        let local = self.inner.lock().local.clone();
+        Some(Endpoint::Unix(local.unwrap_or(UnixEndpoint::Unnamed)))
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        match &self.inner.lock().state {
+            UnixState::Connected { peer_addr, .. } => Some(Endpoint::Unix(peer_addr.clone())),
+            _ => None,
+        }
+    }
+
+    fn setsockopt(&self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        if !matches!(Level::try_from(level), Ok(Level::SOL_SOCKET)) {
+            return Err(LxError::ENOPROTOOPT);
+        }
+        let opt = SolOptname::try_from(opt).map_err(|_| LxError::ENOPROTOOPT)?;
+        let value = data.get(..4).ok_or(LxError::EINVAL)?;
+        let value = i32::from_ne_bytes([value[0], value[1], value[2], value[3]]);
+        // the buffer size is doubled for the bookkeeping overhead, as Linux does
+        let size = (value.max(0) as usize).min(UNIX_BUFFER_SIZE) * 2;
+        let size = size.max(UNIX_MIN_BUFFER_SIZE);
+        match opt {
+            SolOptname::PASSCRED => self.inner.lock().passcred = value != 0,
+            SolOptname::SNDBUF => self.sndbuf.store(size, Ordering::Relaxed),
+            SolOptname::RCVBUF => {
+                let inner = self.inner.lock();
+                self.space.size.store(size, Ordering::Relaxed);
+                self.update_events(&inner);
+            }
+            _ => return Err(LxError::ENOPROTOOPT),
+        }
+        Ok(0)
+    }
+
+    fn get_buffer_capacity(&self) -> Option<(usize, usize)> {
+        Some((
+            self.space.size.load(Ordering::Relaxed),
+            self.sndbuf.load(Ordering::Relaxed),
+        ))
+    }
+
+    fn socket_type(&self) -> Option<SocketType> {
+        Some(self.socket_type)
+    }
+}
+
+#[async_trait]
+impl FileLike for UnixSocketState {
+    fn flags(&self) -> OpenFlags {
+        self.inner.lock().flags
+    }
+
+    fn set_flags(&self, f: OpenFlags) -> LxResult {
+        let flags = &mut self.inner.lock().flags;
+        flags.set(OpenFlags::NON_BLOCK, f.contains(OpenFlags::NON_BLOCK));
+        flags.set(OpenFlags::CLOEXEC, f.contains(OpenFlags::CLOEXEC));
+        Ok(())
+    }
+
+    fn dup(&self) -> Arc<dyn FileLike> {
+        self.me.upgrade().unwrap()
+    }
+
+    async fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        Socket::read(self, buf).await.0
+    }
+
+    async fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        Socket::write(self, buf, None)
+    }
+
+    fn poll(&self, _events: PollEvents) -> LxResult<PollStatus> {
+        Ok(self.status(&self.inner.lock()))
+    }
+
+    async fn async_poll(&self, events: PollEvents) -> LxResult<PollStatus> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct UnixSocketFuture<'a> {
+            socket: &'a UnixSocketState,
+            events: PollEvents,
+        }
+
+        impl<'a> Future for UnixSocketFuture<'a> {
+            type Output = LxResult<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // hold the inner to avoid missing a notification
+                let inner = self.socket.inner.lock();
+                let status = self.socket.status(&inner);
+                if (status.read && self.events.contains(PollEvents::IN))
+                    || (status.write && self.events.contains(PollEvents::OUT))
+                {
+                    return Poll::Ready(Ok(status));
+                }
+                let waker = cx.waker().clone();
+                self.socket.eventbus.lock().subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                // the peer makes room for writing
+                if let UnixState::Connected { peer_space, .. } = &inner.state {
+                    if self.events.contains(PollEvents::OUT) {
+                        let waker = cx.waker().clone();
+                        peer_space.eventbus.lock().subscribe(Box::new(move |_| {
+                            waker.wake_by_ref();
+                            true
+                        }));
+                    }
+                }
+                Poll::Pending
+            }
+        }
+
+        UnixSocketFuture {
+            socket: self,
+            events,
+        }
+        .await
+    }
+
+    fn as_socket(&self) -> LxResult<&dyn Socket> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(socket_type: SocketType) -> (Arc<UnixSocketState>, Arc<UnixSocketState>) {
+        UnixSocketState::pair(socket_type, UCred::default())
+    }
+
+    async fn send(socket: &UnixSocketState, data: &[u8], flags: MsgFlags) -> SysResult {
+        socket
+            .sendmsg(data, None, UnixAncillary::default(), flags)
+            .await
+    }
+
+    #[async_std::test]
+    async fn pair_round_trip() {
+        for socket_type in [SocketType::SOCK_STREAM, SocketType::SOCK_DGRAM] {
+            let (a, b) = pair(socket_type);
+            assert_eq!(send(&a, b"hello", MsgFlags::empty()).await.unwrap(), 5);
+            let mut buf = [0; 8];
+            let info = b.recvmsg(&mut buf, MsgFlags::empty()).await.unwrap();
+            assert_eq!(&buf[..info.len], b"hello");
+            assert_eq!(FileLike::write(&*b, b"world").unwrap(), 5);
+            assert_eq!(FileLike::read(&*a, &mut buf).await.unwrap(), 5);
+            assert_eq!(&buf[..5], b"world");
+            let status = b.status(&b.inner.lock());
+            assert!(!status.read && status.write);
+        }
+    }
+
+    #[async_std::test]
+    async fn closed_peer() {
+        let (a, b) = pair(SocketType::SOCK_STREAM);
+        send(&a, b"bye", MsgFlags::empty()).await.unwrap();
+        drop(a);
+        // the data is read before the end of file
+        let mut buf = [0; 8];
+        assert_eq!(FileLike::read(&*b, &mut buf).await.unwrap(), 3);
+        assert_eq!(FileLike::read(&*b, &mut buf).await.unwrap(), 0);
+        let ret = send(&b, b"x", MsgFlags::NOSIGNAL).await;
+        assert!(matches!(ret, Err(LxError::EPIPE)));
+    }
+
+    #[async_std::test]
+    async fn full_queue() {
+        let (a, b) = pair(SocketType::SOCK_STREAM);
+        // a byte stream is cut to the room left
+        let data = vec![0; UNIX_BUFFER_SIZE + 1];
+        assert_eq!(FileLike::write(&*a, &data).unwrap(), UNIX_BUFFER_SIZE);
+        assert!(matches!(FileLike::write(&*a, &data), Err(LxError::EAGAIN)));
+        let ret = send(&a, &data, MsgFlags::DONTWAIT).await;
+        assert!(matches!(ret, Err(LxError::EAGAIN)));
+        assert!(!a.status(&a.inner.lock()).write);
+
+        let sender = {
+            let a = a.clone();
+            async_std::task::spawn(async move { send(&a, &[1; 10], MsgFlags::empty()).await })
+        };
+        let mut buf = [0; 4];
+        assert_eq!(FileLike::read(&*b, &mut buf).await.unwrap(), 4);
+        assert_eq!(sender.await.unwrap(), 4);
+        assert!(!a.status(&a.inner.lock()).write);
+
+        // a datagram is never cut
+        let (a, _b) = pair(SocketType::SOCK_DGRAM);
+        assert!(matches!(
+            FileLike::write(&*a, &data),
+            Err(LxError::EMSGSIZE)
+        ));
+        assert_eq!(FileLike::write(&*a, &data[1..]).unwrap(), UNIX_BUFFER_SIZE);
+        assert!(matches!(FileLike::write(&*a, &[0]), Err(LxError::EAGAIN)));
+    }
+
+    #[async_std::test]
+    async fn buffer_size() {
+        let (a, b) = pair(SocketType::SOCK_STREAM);
+        let sol = Level::SOL_SOCKET as usize;
+        let rcvbuf = SolOptname::RCVBUF as usize;
+        b.setsockopt(sol, rcvbuf, &4096i32.to_ne_bytes()).unwrap();
+        assert_eq!(b.get_buffer_capacity().unwrap().0, 8192);
+        let data = vec![0; 10000];
+        assert_eq!(FileLike::write(&*a, &data).unwrap(), 8192);
+        assert!(!a.status(&a.inner.lock()).write);
+
+        // a larger queue makes room for the sender
+        b.setsockopt(sol, rcvbuf, &8192i32.to_ne_bytes()).unwrap();
+        assert!(a.status(&a.inner.lock()).write);
+
+        let (a, _b) = pair(SocketType::SOCK_DGRAM);
+        let sndbuf = SolOptname::SNDBUF as usize;
+        a.setsockopt(sol, sndbuf, &0i32.to_ne_bytes()).unwrap();
+        assert_eq!(a.get_buffer_capacity().unwrap().1, UNIX_MIN_BUFFER_SIZE);
+        assert!(matches!(
+            FileLike::write(&*a, &data),
+            Err(LxError::EMSGSIZE)
+        ));
+
+        let linger = SolOptname::LINGER as usize;
+        let ret = a.setsockopt(sol, linger, &[0; 8]);
+        assert!(matches!(ret, Err(LxError::ENOPROTOOPT)));
+        let ret = a.setsockopt(Level::IPPROTO_TCP as usize, 1, &[0; 4]);
+        assert!(matches!(ret, Err(LxError::ENOPROTOOPT)));
+    }
+
+    #[async_std::test]
+    async fn pass_files() {
+        let (a, b) = pair(SocketType::SOCK_STREAM);
+        let (file, _) = pair(SocketType::SOCK_DGRAM);
+        let ancillary = UnixAncillary {
+            files: vec![file.clone() as Arc<dyn FileLike>],
+            cred: None,
+        };
+        send(&a, b"x", MsgFlags::empty()).await.unwrap();
+        a.sendmsg(b"y", None, ancillary, MsgFlags::empty())
+            .await
+            .unwrap();
+
+        // a read does not go into a message carrying files
+        let mut buf = [0; 8];
+        let info = b.recvmsg(&mut buf, MsgFlags::empty()).await.unwrap();
+        assert_eq!(info.len, 1);
+        assert!(info.ancillary.files.is_empty());
+        // peeking leaves the files in the queue
+        let info = b.recvmsg(&mut buf, MsgFlags::PEEK).await.unwrap();
+        assert_eq!(info.ancillary.files.len(), 1);
+        let info = b.recvmsg(&mut buf, MsgFlags::empty()).await.unwrap();
+        assert_eq!(&buf[..info.len], b"y");
+        let received = info.ancillary.files[0].downcast_ref::<UnixSocketState>();
+        assert!(core::ptr::eq(received.unwrap(), &*file));
+    }
+
+    #[async_std::test]
+    async fn abstract_namespace() {
+        let new = || UnixSocketState::new(SocketType::SOCK_STREAM, UCred::default());
+        let addr = Endpoint::Unix(UnixEndpoint::Abstract(b"unix-test".to_vec()));
+        let server = new();
+        Socket::bind(&*server, addr.clone()).unwrap();
+        Socket::listen(&*server).unwrap();
+        let other = new();
+        let ret = Socket::bind(&*other, addr.clone());
+        assert!(matches!(ret, Err(LxError::EADDRINUSE)));
+
+        let client = new();
+        Socket::connect(&*client, addr.clone()).await.unwrap();
+        assert!(matches!(
+            Socket::remote_endpoint(&*client),
+            Some(Endpoint::Unix(UnixEndpoint::Abstract(name))) if name == b"unix-test"
+        ));
+        let (conn, _) = Socket::accept(&*server).await.unwrap();
+        FileLike::write(&*client, b"hi").unwrap();
+        let mut buf = [0; 4];
+        assert_eq!(conn.read(&mut buf).await.unwrap(), 2);
+
+        // the name is free again once the socket is gone
+        drop(server);
+        let ret = Socket::connect(&*new(), addr.clone()).await;
+        assert!(matches!(ret, Err(LxError::ECONNREFUSED)));
+        Socket::bind(&*other, addr).unwrap();
+    }
+}
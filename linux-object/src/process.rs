@@ -2,28 +2,38 @@
 
 use crate::{
     error::{LxError, LxResult},
-    fs::{File, FileDesc, FileLike, OpenFlags, STDIN, STDOUT},
+    fs::{
+        release_posix_locks, release_process_locks, File, FileDesc, FileLike, FileMapping,
+        OpenFlags, Tty, CONSOLE,
+    },
     ipc::*,
     net::SOCKET_FD,
-    signal::{Signal as LinuxSignal, SignalAction},
+    ptrace::{self, Ptrace},
+    signal::{
+        ITimerWhich, SigInfo, Signal as LinuxSignal, SignalAction, SignalTimer, CLD_EXITED,
+        CLD_KILLED, SIG_DFL, SIG_IGN,
+    },
+    sync::EventBus,
+    thread::ThreadExt,
 };
 use alloc::{
     boxed::Box,
+    collections::BTreeMap,
     string::String,
     sync::{Arc, Weak},
     vec::Vec,
 };
-use core::sync::atomic::AtomicI32;
+use core::{convert::TryFrom, sync::atomic::AtomicI32};
 use hashbrown::HashMap;
 use kernel_hal::VirtAddr;
-use lock::{Mutex, MutexGuard};
+use lock::Mutex;
 use rcore_fs::vfs::{FileSystem, INode};
 
 use zircon_object::{
     object::{KernelObject, KoID, Signal},
     signal::Futex,
-    task::{Job, Process, Status},
-    ZxResult,
+    task::{Job, Process, Status, Task, Thread},
+    ZxError, ZxResult,
 };
 
 pub use rcore_fs::vfs::FsInfo;
@@ -34,14 +44,27 @@ pub trait ProcessExt {
     fn create_linux(job: &Arc<Job>, rootfs: Arc<dyn FileSystem>) -> ZxResult<Arc<Self>>;
     /// get linux process
     fn linux(&self) -> &LinuxProcess;
-    /// fork from current linux process
-    fn fork_from(parent: &Arc<Self>, vfork: bool) -> ZxResult<Arc<Self>>;
+    /// fork from current linux process, sharing the resources selected by `flags` with it
+    fn fork_from(parent: &Arc<Self>, flags: CloneFlags) -> ZxResult<Arc<Self>>;
 }
 
 impl ProcessExt for Process {
     fn create_linux(job: &Arc<Job>, rootfs: Arc<dyn FileSystem>) -> ZxResult<Arc<Self>> {
         let linux_proc = LinuxProcess::new(rootfs);
-        Process::create_with_ext(job, "root", linux_proc)
+        let proc = Process::create_with_ext(job, "root", linux_proc)?;
+        // the first process leads a new session and process group
+        let mut inner = proc.linux().inner.lock();
+        inner.pid = proc.id();
+        inner.pgid = proc.id();
+        inner.sid = proc.id();
+        drop(inner);
+        // and the console is its controlling terminal
+        if let Err(e) = CONSOLE.set_controlling(&proc, true) {
+            warn!("failed to set the controlling terminal: {:?}", e);
+        }
+        undo_semaphores_on_exit(&proc);
+        release_locks_on_exit(&proc);
+        Ok(proc)
     }
 
     fn linux(&self) -> &LinuxProcess {
@@ -50,89 +73,468 @@ impl ProcessExt for Process {
 
     /// [Fork] the process.
     ///
+    /// The address space, file descriptor table, filesystem information and signal handlers
+    /// are shared with the parent if `CLONE_VM`, `CLONE_FILES`, `CLONE_FS` and `CLONE_SIGHAND`
+    /// are set in `flags`, otherwise the child gets copies of them.
+    /// With `CLONE_PARENT` the child becomes a sibling of the calling process.
+    ///
     /// [Fork]: http://man7.org/linux/man-pages/man2/fork.2.html
-    fn fork_from(parent: &Arc<Self>, vfork: bool) -> ZxResult<Arc<Self>> {
+    fn fork_from(parent: &Arc<Self>, flags: CloneFlags) -> ZxResult<Arc<Self>> {
         let linux_parent = parent.linux();
-        let mut linux_parent_inner = linux_parent.inner.lock();
+        let new_parent = if flags.contains(CloneFlags::PARENT) {
+            linux_parent.parent().ok_or(ZxError::INVALID_ARGS)?
+        } else {
+            parent.clone()
+        };
+        let linux_parent_inner = linux_parent.inner.lock();
         let new_linux_proc = LinuxProcess {
             root_inode: linux_parent.root_inode.clone(),
-            parent: Arc::downgrade(parent),
+            parent: Arc::downgrade(&new_parent),
             inner: Mutex::new(LinuxProcessInner {
                 execute_path: linux_parent_inner.execute_path.clone(),
-                current_working_directory: linux_parent_inner.current_working_directory.clone(),
-                files: linux_parent_inner.files.clone(),
-                signal_actions: linux_parent_inner.signal_actions.clone(),
+                args: linux_parent_inner.args.clone(),
+                envs: linux_parent_inner.envs.clone(),
+                fs: share_or_copy(&linux_parent_inner.fs, flags.contains(CloneFlags::FS)),
+                file_limit: linux_parent_inner.file_limit,
+                files: share_or_copy(&linux_parent_inner.files, flags.contains(CloneFlags::FILES)),
+                signal_actions: share_or_copy(
+                    &linux_parent_inner.signal_actions,
+                    flags.contains(CloneFlags::SIGHAND),
+                ),
+                exit_signal: LinuxSignal::try_from((flags & CloneFlags::CSIGNAL).bits() as u8).ok(),
+                heap_start: linux_parent_inner.heap_start,
+                brk: linux_parent_inner.brk,
+                file_mappings: linux_parent_inner.file_mappings.clone(),
+                cred: linux_parent_inner.cred.clone(),
+                pgid: linux_parent_inner.pgid,
+                sid: linux_parent_inner.sid,
+                terminal: linux_parent_inner.terminal.clone(),
+                semaphores: linux_parent_inner.semaphores.clone(),
+                shm_identifiers: linux_parent_inner.shm_identifiers.clone(),
                 ..Default::default()
             }),
+            ptrace: Mutex::new(Ptrace::default()),
+            signal_event: EventBus::new(),
         };
-        let new_proc = Process::create_with_ext(&parent.job(), "", new_linux_proc)?;
-        linux_parent_inner
+        drop(linux_parent_inner);
+        let new_proc = if flags.contains(CloneFlags::VM) {
+            Process::create_with_vmar(&parent.job(), "", new_linux_proc, parent.vmar())?
+        } else {
+            let new_proc = Process::create_with_ext(&parent.job(), "", new_linux_proc)?;
+            new_proc.vmar().fork_from(&parent.vmar())?;
+            new_proc
+        };
+        new_proc.linux().inner.lock().pid = new_proc.id();
+        new_parent
+            .linux()
+            .inner
+            .lock()
             .children
             .insert(new_proc.id(), new_proc.clone());
-        if !vfork {
-            new_proc.vmar().fork_from(&parent.vmar())?;
-        }
 
         // notify parent on terminated
-        let parent = parent.clone();
+        let exit_signal = new_proc.linux().inner.lock().exit_signal;
+        let uid = new_proc.linux().credentials().uid;
+        let child = Arc::downgrade(&new_proc);
         new_proc.add_signal_callback(Box::new(move |signal| {
             if signal.contains(Signal::PROCESS_TERMINATED) {
                 info!("Received signal: {:?}", signal);
-                parent.signal_set(Signal::SIGCHLD);
+                new_parent.signal_set(Signal::SIGCHLD);
+                if let (Some(exit_signal), Some(child)) = (exit_signal, child.upgrade()) {
+                    let handler = new_parent.linux().signal_action(exit_signal).handler;
+                    if handler != SIG_IGN {
+                        let (code, status) = match child.linux().term_signal() {
+                            Some(signal) => (CLD_KILLED, signal as i32),
+                            None => (CLD_EXITED, child.exit_code().unwrap_or(0) as i32),
+                        };
+                        let mut info = SigInfo::child(code, child.id() as _, uid, status);
+                        info.set_signal(exit_signal);
+                        send_signal(&new_parent, info);
+                    }
+                }
+                return true;
             }
             false
         }));
+        undo_semaphores_on_exit(&new_proc);
+        release_locks_on_exit(&new_proc);
         Ok(new_proc)
     }
 }
 
+/// Perform the `SEM_UNDO` adjustments of `proc` as soon as it terminates.
+fn undo_semaphores_on_exit(proc: &Arc<Process>) {
+    let proc_weak = Arc::downgrade(proc);
+    proc.add_signal_callback(Box::new(move |signal| {
+        if signal.contains(Signal::PROCESS_TERMINATED) {
+            if let Some(proc) = proc_weak.upgrade() {
+                let semaphores = proc.linux().inner.lock().semaphores.clone();
+                semaphores.undo_all(proc.id() as usize);
+            }
+            return true;
+        }
+        false
+    }));
+}
+
+/// Release the record locks of `proc` as soon as it terminates.
+fn release_locks_on_exit(proc: &Arc<Process>) {
+    let pid = proc.id();
+    proc.add_signal_callback(Box::new(move |signal| {
+        if signal.contains(Signal::PROCESS_TERMINATED) {
+            release_process_locks(pid);
+            return true;
+        }
+        false
+    }));
+}
+
+/// Share `table` with the child if `shared`, otherwise give the child a copy of it.
+fn share_or_copy<T: Clone>(table: &Arc<Mutex<T>>, shared: bool) -> Arc<Mutex<T>> {
+    if shared {
+        table.clone()
+    } else {
+        Arc::new(Mutex::new(table.lock().clone()))
+    }
+}
+
+bitflags::bitflags! {
+    /// Flags of `clone`, selecting the resources shared between the caller and the child
+    pub struct CloneFlags: usize {
+        /// signal sent to the parent when the child terminates
+        const CSIGNAL =         0xff;
+        /// the calling process and the child process run in the same memory space
+        const VM =              1 << 8;
+        /// the caller and the child process share the same filesystem information
+        const FS =              1 << 9;
+        /// the calling process and the child process share the same file descriptor table
+        const FILES =           1 << 10;
+        /// the calling process and the child process share the same table of signal handlers.
+        const SIGHAND =         1 << 11;
+        /// store a file descriptor referring to the child at the location in the parent's memory
+        const PIDFD =           1 << 12;
+        /// the calling process is being traced
+        const PTRACE =          1 << 13;
+        /// the execution of the calling process is suspended until the child releases its virtual memory resources
+        const VFORK =           1 << 14;
+        /// the parent of the new child will be the same as that of the call‐ing process.
+        const PARENT =          1 << 15;
+        /// the child is placed in the same thread group as the calling process.
+        const THREAD =          1 << 16;
+        /// cloned child is started in a new mount namespace
+        const NEWNS	=           1 << 17;
+        /// the child and the calling process share a single list of System V semaphore adjustment values.
+        const SYSVSEM =         1 << 18;
+        /// architecture dependent, The TLS (Thread Local Storage) descriptor is set to tls.
+        const SETTLS =          1 << 19;
+        /// Store the child thread ID at the location in the parent's memory.
+        const PARENT_SETTID =   1 << 20;
+        /// Clear (zero) the child thread ID
+        const CHILD_CLEARTID =  1 << 21;
+        /// the parent not to receive a signal when the child terminated
+        const DETACHED =        1 << 22;
+        /// a tracing process cannot force CLONE_PTRACE on this child process.
+        const UNTRACED =        1 << 23;
+        /// Store the child thread ID
+        const CHILD_SETTID =    1 << 24;
+        /// Create the process in a new cgroup namespace.
+        const NEWCGROUP =       1 << 25;
+        /// create the process in a new UTS namespace
+        const NEWUTS =          1 << 26;
+        /// create the process in a new IPC namespace.
+        const NEWIPC =          1 << 27;
+        /// create the process in a new user namespace
+        const NEWUSER =         1 << 28;
+        /// create the process in a new PID namespace
+        const NEWPID =          1 << 29;
+        /// create the process in a new net‐work namespace.
+        const NEWNET =          1 << 30;
+        /// the new process shares an I/O context with the calling process.
+        const IO =              1 << 31;
+    }
+}
+
+/// Which children to wait for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitTarget {
+    /// any child process
+    AnyChild,
+    /// the child with the process ID
+    Pid(KoID),
+    /// any child in the process group
+    Group(KoID),
+}
+
+bitflags::bitflags! {
+    /// Options of `wait4`
+    pub struct WaitOptions: u32 {
+        /// return immediately if no child has changed state
+        const NOHANG    = 1;
+        /// also report children stopped by a signal
+        const UNTRACED  = 2;
+        /// report terminated children
+        const EXITED    = 4;
+        /// also report stopped children resumed by `SIGCONT`
+        const CONTINUED = 8;
+        /// leave the child in a waitable state
+        const NOWAIT    = 0x100_0000;
+    }
+}
+
+/// State change of a child reported by [`wait_child`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// the child terminated
+    Exited(ExitCode),
+    /// the child was terminated by the signal
+    Signaled(LinuxSignal),
+    /// the child was stopped by the signal
+    Stopped(LinuxSignal),
+    /// the child was resumed by `SIGCONT`
+    Continued,
+    /// the traced child entered a ptrace-stop, with the signal and the event in the status
+    Traced(i32),
+}
+
+impl WaitStatus {
+    /// Encode the status as the `wstatus` of `wait4`.
+    pub fn to_wstatus(self) -> i32 {
+        match self {
+            WaitStatus::Exited(code) => (code & 0xff) << 8,
+            WaitStatus::Signaled(signal) => signal as i32,
+            WaitStatus::Stopped(signal) => ((signal as i32) << 8) | 0x7f,
+            WaitStatus::Continued => 0xffff,
+            WaitStatus::Traced(status) => (status << 8) | 0x7f,
+        }
+    }
+}
+
 /// Wait for state changes in a child of the calling process, and obtain information about
 /// the child whose state has changed.
 ///
 /// A state change is considered to be:
 /// - the child terminated.
-/// - the child was stopped by a signal. TODO
-/// - the child was resumed by a signal. TODO
-pub async fn wait_child(proc: &Arc<Process>, pid: KoID, nonblock: bool) -> LxResult<ExitCode> {
+/// - the child was stopped by a signal, if `UNTRACED` is set.
+/// - the child was resumed by `SIGCONT`, if `CONTINUED` is set.
+/// - the child traced by the calling process entered a ptrace-stop.
+///
+/// The processes traced by the calling process are waited for as its children.
+///
+/// Returns `None` if `NOHANG` is set and no child has changed state.
+pub async fn wait_child(
+    proc: &Arc<Process>,
+    target: WaitTarget,
+    options: WaitOptions,
+) -> LxResult<Option<(KoID, WaitStatus)>> {
     loop {
+        // clear before checking, so that a change after the check is not missed
+        proc.signal_clear(Signal::SIGCHLD);
+        let tracees = ptrace::tracees(proc);
         let mut inner = proc.linux().inner.lock();
-        let child = inner.children.get(&pid).ok_or(LxError::ECHILD)?;
-        if let Status::Exited(code) = child.status() {
-            inner.children.remove(&pid);
-            return Ok(code as ExitCode);
+        let mut candidates: Vec<_> = inner
+            .children
+            .iter()
+            .map(|(&pid, child)| (pid, child.clone()))
+            .collect();
+        for (pid, tracee) in tracees {
+            if !inner.children.contains_key(&pid) {
+                candidates.push((pid, tracee));
+            }
         }
-        if nonblock {
-            return Err(LxError::EAGAIN);
+        let mut found = false;
+        let mut changed = None;
+        for (pid, child) in candidates {
+            let matched = match target {
+                WaitTarget::AnyChild => true,
+                WaitTarget::Pid(target_pid) => pid == target_pid,
+                WaitTarget::Group(pgid) => child.linux().pgid() == pgid,
+            };
+            if !matched {
+                continue;
+            }
+            found = true;
+            // the ptrace-stops are reported to the tracer regardless of the options
+            let nowait = options.contains(WaitOptions::NOWAIT);
+            if let Some(status) = ptrace::take_stop_status(proc, &child, nowait) {
+                changed = Some((pid, WaitStatus::Traced(status)));
+                break;
+            }
+            if let Status::Exited(code) = child.status() {
+                let status = match child.linux().term_signal() {
+                    Some(signal) => WaitStatus::Signaled(signal),
+                    None => WaitStatus::Exited(code as ExitCode),
+                };
+                changed = Some((pid, status));
+                break;
+            }
+            let mut child_inner = child.linux().inner.lock();
+            let status = match child_inner.child_event {
+                Some(WaitStatus::Stopped(signal)) if options.contains(WaitOptions::UNTRACED) => {
+                    WaitStatus::Stopped(signal)
+                }
+                Some(WaitStatus::Continued) if options.contains(WaitOptions::CONTINUED) => {
+                    WaitStatus::Continued
+                }
+                _ => continue,
+            };
+            if !options.contains(WaitOptions::NOWAIT) {
+                child_inner.child_event = None;
+            }
+            changed = Some((pid, status));
+            break;
+        }
+        if !found {
+            return Err(LxError::ECHILD);
+        }
+        if let Some((pid, status)) = changed {
+            if let WaitStatus::Exited(_) | WaitStatus::Signaled(_) = status {
+                if !options.contains(WaitOptions::NOWAIT) {
+                    inner.children.remove(&pid);
+                    ptrace::release(proc, pid);
+                }
+            }
+            return Ok(Some((pid, status)));
         }
-        let child: Arc<dyn KernelObject> = child.clone();
         drop(inner);
-        child.signal_clear(Signal::PROCESS_TERMINATED);
-        child.wait_signal(Signal::PROCESS_TERMINATED).await;
+        if options.contains(WaitOptions::NOHANG) {
+            return Ok(None);
+        }
+        //等待子进程状态改变
+        proc.wait_signal(Signal::SIGCHLD).await;
     }
 }
 
-/// Wait for state changes in a child of the calling process.
-pub async fn wait_child_any(proc: &Arc<Process>, nonblock: bool) -> LxResult<(KoID, ExitCode)> {
-    loop {
-        let mut inner = proc.linux().inner.lock();
-        if inner.children.is_empty() {
-            return Err(LxError::ECHILD);
+/// Get the thread running on the current CPU, which is set when a thread is scheduled.
+pub(crate) fn current_thread() -> Option<Arc<Thread>> {
+    kernel_hal::thread::get_current_thread()?
+        .downcast::<Thread>()
+        .ok()
+}
+
+/// Get the process of the thread running on the current CPU.
+pub(crate) fn current_process() -> Option<Arc<Process>> {
+    current_thread().map(|thread| thread.proc().clone())
+}
+
+/// Get the Linux processes in `job`.
+pub fn job_processes(job: &Job) -> Vec<Arc<Process>> {
+    job.process_ids()
+        .into_iter()
+        .filter_map(|pid| job.get_child(pid).ok())
+        .filter_map(|obj| obj.downcast_arc::<Process>().ok())
+        .collect()
+}
+
+/// Get the threads of `proc`.
+pub(crate) fn threads(proc: &Process) -> Vec<Arc<Thread>> {
+    proc.thread_ids()
+        .into_iter()
+        .filter_map(|tid| proc.get_child(tid).ok())
+        .filter_map(|obj| obj.downcast_arc::<Thread>().ok())
+        .collect()
+}
+
+/// Send the signal described by `info` to process `proc`.
+///
+/// The signal is delivered to an arbitrary thread which does not block it.
+/// If every thread blocks the signal, it is kept pending on the first thread,
+/// and may be accepted by a signalfd.
+pub fn send_signal(proc: &Arc<Process>, info: SigInfo) {
+    let signal = info.signal();
+    if !job_control(proc, signal) {
+        return;
+    }
+    let threads = threads(proc);
+    let target = threads
+        .iter()
+        .find(|thread| !thread.lock_linux().signal_mask.contains(signal))
+        .or_else(|| threads.first());
+    if let Some(thread) = target {
+        thread.lock_linux().queue_process_signal(info);
+    }
+}
+
+/// Send the signal described by `info` to `thread`.
+pub fn send_signal_to_thread(thread: &Arc<Thread>, info: SigInfo) {
+    if job_control(thread.proc(), info.signal()) {
+        thread.lock_linux().queue_signal(info);
+    }
+}
+
+/// Terminate the process `proc` by `signal`, as the default action of the signal does.
+///
+/// The parent sees the process terminated by the signal in `wait4`.
+pub fn exit_by_signal(proc: &Arc<Process>, signal: LinuxSignal) {
+    let linux = proc.linux();
+    linux.inner.lock().term_signal.get_or_insert(signal);
+    linux.continue_threads();
+    proc.exit((128 + signal as i32) as i64);
+}
+
+/// Stop the process `proc` by `signal`, as the default action of the stop signals does.
+///
+/// The parent sees the process stopped by the signal in `wait4`.
+pub fn stop_by_signal(proc: &Arc<Process>, signal: LinuxSignal) {
+    let linux = proc.linux();
+    if linux.stop_threads(threads(proc), signal) {
+        linux.notify_parent();
+    }
+}
+
+/// Get the interval timer `which` of the process `proc`, which is created on first use.
+pub fn itimer(proc: &Arc<Process>, which: ITimerWhich) -> Arc<SignalTimer> {
+    let linux = proc.linux();
+    let mut inner = linux.inner.lock();
+    inner.itimers[which as usize]
+        .get_or_insert_with(|| SignalTimer::new_itimer(proc, which))
+        .clone()
+}
+
+/// Take the actions of `SIGKILL`, `SIGCONT` and the stop signals on the whole process.
+///
+/// Returns whether the signal should be queued on a thread afterwards.
+fn job_control(proc: &Arc<Process>, signal: LinuxSignal) -> bool {
+    const STOP_SIGNALS: [LinuxSignal; 4] = [
+        LinuxSignal::SIGSTOP,
+        LinuxSignal::SIGTSTP,
+        LinuxSignal::SIGTTIN,
+        LinuxSignal::SIGTTOU,
+    ];
+    let linux = proc.linux();
+    match signal {
+        LinuxSignal::SIGKILL => {
+            exit_by_signal(proc, signal);
+            false
         }
-        for (&pid, child) in inner.children.iter() {
-            if let Status::Exited(code) = child.status() {
-                inner.children.remove(&pid);
-                return Ok((pid, code as ExitCode));
+        LinuxSignal::SIGCONT => {
+            // a continue discards the pending stop signals
+            for thread in threads(proc) {
+                let mut linux_thread = thread.lock_linux();
+                for &stop in STOP_SIGNALS.iter() {
+                    linux_thread.signals.remove(stop);
+                }
+            }
+            if linux.continue_threads() {
+                linux.notify_parent();
             }
+            let handler = linux.signal_action(signal).handler;
+            handler != SIG_DFL && handler != SIG_IGN
         }
-        drop(inner);
-        if nonblock {
-            warn!("Nonblock flag isn't supported");
-            // return Err(LxError::EAGAIN);
+        _ if STOP_SIGNALS.contains(&signal) => {
+            // a stop discards the pending continue
+            for thread in threads(proc) {
+                thread.lock_linux().signals.remove(LinuxSignal::SIGCONT);
+            }
+            let handler = linux.signal_action(signal).handler;
+            // a traced process reports the signal to the tracer before it is stopped
+            if (signal == LinuxSignal::SIGSTOP || handler == SIG_DFL) && !ptrace::is_traced(proc) {
+                stop_by_signal(proc, signal);
+                false
+            } else {
+                handler != SIG_IGN
+            }
         }
-        let proc: Arc<dyn KernelObject> = proc.clone();
-        proc.signal_clear(Signal::SIGCHLD);
-        //等待进程结束信号
-        proc.wait_signal(Signal::SIGCHLD).await;
+        _ => true,
     }
 }
 
@@ -144,6 +546,10 @@ pub struct LinuxProcess {
     parent: Weak<Process>,
     /// Inner
     inner: Mutex<LinuxProcessInner>,
+    /// The tracing state, as a tracee and as a tracer
+    pub(crate) ptrace: Mutex<Ptrace>,
+    /// Event bus pulsed when a signal is queued to a thread of the process
+    signal_event: Arc<Mutex<EventBus>>,
 }
 
 /// Linux process mut inner data
@@ -151,14 +557,17 @@ pub struct LinuxProcess {
 struct LinuxProcessInner {
     /// Execute path
     execute_path: String,
-    /// Current Working Directory
-    ///
-    /// Omit leading '/'.
-    current_working_directory: String,
+    /// Arguments of the program, shown in `/proc/[pid]/cmdline`
+    args: Vec<String>,
+    /// Environment of the program, shown in `/proc/[pid]/environ`
+    envs: Vec<String>,
+    /// Current working directory and file mode creation mask,
+    /// shared with the processes created by `clone(CLONE_FS)`
+    fs: Arc<Mutex<FsContext>>,
     /// file open number limit
     file_limit: RLimit,
-    /// Opened files
-    files: HashMap<FileDesc, Arc<dyn FileLike>>,
+    /// Opened files, shared with the processes created by `clone(CLONE_FILES)`
+    files: Arc<Mutex<FileTable>>,
     /// Semaphore
     semaphores: SemProc,
     /// Share Memory
@@ -167,8 +576,52 @@ struct LinuxProcessInner {
     futexes: HashMap<VirtAddr, Arc<Futex>>,
     /// Child processes
     children: HashMap<KoID, Arc<Process>>,
-    /// Signal actions
-    signal_actions: SignalActions,
+    /// Signal actions, shared with the processes created by `clone(CLONE_SIGHAND)`
+    signal_actions: Arc<Mutex<SignalActions>>,
+    /// Signal sent to the parent when the process terminates
+    exit_signal: Option<LinuxSignal>,
+    /// Start of the program break (heap)
+    heap_start: VirtAddr,
+    /// Current program break
+    brk: VirtAddr,
+    /// Shared mappings of files, which are written back on `msync` and `munmap`
+    file_mappings: Vec<FileMapping>,
+    /// User and group identity
+    cred: Credentials,
+    /// Process ID, the owner of the record locks
+    pid: KoID,
+    /// Process group ID
+    pgid: KoID,
+    /// Session ID
+    sid: KoID,
+    /// The controlling terminal of the session
+    terminal: Option<Arc<Tty>>,
+    /// Whether the process is stopped by a signal
+    stopped: bool,
+    /// Threads suspended when the process is stopped, resumed by `SIGCONT`
+    stopped_threads: Vec<Arc<Thread>>,
+    /// The latest stop or continue not reported to the parent yet
+    child_event: Option<WaitStatus>,
+    /// The signal which terminated the process
+    term_signal: Option<LinuxSignal>,
+    /// Interval timers, indexed by `ITimerWhich`
+    itimers: [Option<Arc<SignalTimer>>; 3],
+    /// POSIX timers created by `timer_create`
+    timers: BTreeMap<usize, Arc<SignalTimer>>,
+}
+
+/// File descriptor table
+type FileTable = HashMap<FileDesc, Arc<dyn FileLike>>;
+
+/// Filesystem information of a process
+#[derive(Default, Clone)]
+struct FsContext {
+    /// Current Working Directory
+    ///
+    /// Omit leading '/'.
+    cwd: String,
+    /// File mode creation mask
+    umask: u32,
 }
 
 #[derive(Clone)]
@@ -203,6 +656,51 @@ impl Default for RLimit {
     }
 }
 
+/// User and group identity of a process
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    /// real user ID
+    pub uid: u32,
+    /// effective user ID, used for permission checks
+    pub euid: u32,
+    /// saved set-user-ID
+    pub suid: u32,
+    /// real group ID
+    pub gid: u32,
+    /// effective group ID, used for permission checks
+    pub egid: u32,
+    /// saved set-group-ID
+    pub sgid: u32,
+    /// file system user ID, which follows the effective user ID
+    pub fsuid: u32,
+    /// file system group ID, which follows the effective group ID
+    pub fsgid: u32,
+    /// supplementary group IDs
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// Whether the process is privileged (effective user ID is 0).
+    pub fn is_root(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether `gid` is the effective group ID or one of the supplementary groups.
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// The credentials with the effective IDs replaced by the real IDs,
+    /// which are used by `access`.
+    pub fn real(&self) -> Self {
+        Credentials {
+            euid: self.uid,
+            egid: self.gid,
+            ..self.clone()
+        }
+    }
+}
+
 /// The type of process exit code.
 pub type ExitCode = i32;
 
@@ -210,19 +708,19 @@ impl LinuxProcess {
     /// Create a new process.
     pub fn new(rootfs: Arc<dyn FileSystem>) -> Self {
         let stdin = File::new(
-            STDIN.clone(), // FIXME: stdin
+            CONSOLE.clone(),
             OpenFlags::RDONLY,
-            String::from("/dev/stdin"),
+            String::from("/dev/console"),
         ) as Arc<dyn FileLike>;
         let stdout = File::new(
-            STDOUT.clone(), // TODO: open from '/dev/stdout'
+            CONSOLE.clone(),
             OpenFlags::WRONLY,
-            String::from("/dev/stdout"),
+            String::from("/dev/console"),
         ) as Arc<dyn FileLike>;
         let stderr = File::new(
-            STDOUT.clone(), // TODO: open from '/dev/stderr'
+            CONSOLE.clone(),
             OpenFlags::WRONLY,
-            String::from("/dev/stderr"),
+            String::from("/dev/console"),
         ) as Arc<dyn FileLike>;
         let mut files = HashMap::new();
         files.insert(0.into(), stdin);
@@ -233,12 +731,25 @@ impl LinuxProcess {
             root_inode: crate::fs::create_root_fs(rootfs), //Arc::clone(&ROOT_INODE),访问磁盘可能更快？
             parent: Weak::default(),
             inner: Mutex::new(LinuxProcessInner {
-                files,
+                fs: Arc::new(Mutex::new(FsContext {
+                    cwd: String::new(),
+                    umask: 0o022,
+                })),
+                files: Arc::new(Mutex::new(files)),
+                exit_signal: Some(LinuxSignal::SIGCHLD),
                 ..Default::default()
             }),
+            ptrace: Mutex::new(Ptrace::default()),
+            signal_event: EventBus::new(),
         }
     }
 
+    /// The event bus pulsed with `RECEIVE_SIGNAL` when a signal is queued to
+    /// any thread of the process
+    pub fn signal_event(&self) -> Arc<Mutex<EventBus>> {
+        self.signal_event.clone()
+    }
+
     /// Get futex object.
     #[allow(unsafe_code)]
     pub fn get_futex(&self, uaddr: VirtAddr) -> Arc<Futex> {
@@ -255,49 +766,84 @@ impl LinuxProcess {
 
     /// Get lowest free fd
     pub fn get_free_fd(&self) -> FileDesc {
-        self.inner.lock().get_free_fd()
+        self.get_free_fd_from(0)
     }
 
     /// get the lowest available fd great than or equal to `start`.
     pub fn get_free_fd_from(&self, start: usize) -> FileDesc {
-        self.inner.lock().get_free_fd_from(start)
+        free_fd_from(&self.files().lock(), start)
     }
 
     /// Add a file to the file descriptor table.
     pub fn add_file(&self, file: Arc<dyn FileLike>) -> LxResult<FileDesc> {
-        let inner = self.inner.lock();
-        let fd = inner.get_free_fd();
-        self.insert_file(inner, fd, file)
+        self.insert_file(None, 0, file)
     }
 
     /// Add a socket to the fd table.
     pub fn add_socket(&self, file: Arc<dyn FileLike>) -> LxResult<FileDesc> {
-        let inner = self.inner.lock();
-        let fd = inner.get_free_fd_from(SOCKET_FD);
-        self.insert_file(inner, fd, file)
+        self.insert_file(None, SOCKET_FD, file)
     }
 
     /// Add a file to the file descriptor table at given `fd`.
     pub fn add_file_at(&self, fd: FileDesc, file: Arc<dyn FileLike>) -> LxResult<FileDesc> {
-        let inner = self.inner.lock();
-        self.insert_file(inner, fd, file)
+        self.insert_file(Some(fd), 0, file)
     }
 
-    /// insert a file and fd into the file descriptor table
+    /// insert a file into the file descriptor table at `fd`,
+    /// or at the lowest free fd from `start` if `fd` is `None`
     fn insert_file(
         &self,
-        mut inner: MutexGuard<LinuxProcessInner>,
-        fd: FileDesc,
+        fd: Option<FileDesc>,
+        start: usize,
         file: Arc<dyn FileLike>,
     ) -> LxResult<FileDesc> {
-        if inner.files.len() < inner.file_limit.cur as usize {
-            inner.files.insert(fd, file);
+        let limit = self.inner.lock().file_limit.cur as usize;
+        let files = self.files();
+        let mut files = files.lock();
+        let fd = fd.unwrap_or_else(|| free_fd_from(&files, start));
+        if files.len() < limit {
+            files.insert(fd, file);
             Ok(fd)
         } else {
             Err(LxError::EMFILE)
         }
     }
 
+    /// The file descriptor table, which may be shared with other processes.
+    fn files(&self) -> Arc<Mutex<FileTable>> {
+        self.inner.lock().files.clone()
+    }
+
+    /// The filesystem information, which may be shared with other processes.
+    fn fs(&self) -> Arc<Mutex<FsContext>> {
+        self.inner.lock().fs.clone()
+    }
+
+    /// The signal actions, which may be shared with other processes.
+    fn signal_actions(&self) -> Arc<Mutex<SignalActions>> {
+        self.inner.lock().signal_actions.clone()
+    }
+
+    /// Stop sharing the file descriptor table, filesystem information and signal actions
+    /// with other processes, as `execve` does.
+    ///
+    /// The caught signals are reset to the default action, and the POSIX timers are deleted.
+    pub fn unshare_for_exec(&self) {
+        let mut inner = self.inner.lock();
+        inner.timers.clear();
+        let files = inner.files.lock().clone();
+        let fs = inner.fs.lock().clone();
+        let mut actions = inner.signal_actions.lock().clone();
+        for action in actions.table.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
+        }
+        inner.files = Arc::new(Mutex::new(files));
+        inner.fs = Arc::new(Mutex::new(fs));
+        inner.signal_actions = Arc::new(Mutex::new(actions));
+    }
+
     /// get and set file limit number
     pub fn file_limit(&self, new_limit: Option<RLimit>) -> RLimit {
         let mut inner = self.inner.lock();
@@ -330,21 +876,27 @@ impl LinuxProcess {
 
     /// Get the `FileLike` with given `fd`.
     pub fn get_file_like(&self, fd: FileDesc) -> LxResult<Arc<dyn FileLike>> {
-        let inner = self.inner.lock();
-        trace!("get_file_like: {:x?}", inner.files);
-        inner.files.get(&fd).cloned().ok_or(LxError::EBADF)
+        let files = self.files();
+        let files = files.lock();
+        trace!("get_file_like: {:x?}", *files);
+        files.get(&fd).cloned().ok_or(LxError::EBADF)
     }
 
     /// get all files
     pub fn get_files(&self) -> LxResult<HashMap<FileDesc, Arc<dyn FileLike>>> {
-        let inner = self.inner.lock();
-        Ok(inner.files.clone())
+        Ok(self.files().lock().clone())
     }
 
     /// Close file descriptor `fd`.
+    ///
+    /// The record locks of the process on the file are released.
     pub fn close_file(&self, fd: FileDesc) -> LxResult {
-        let mut inner = self.inner.lock();
-        inner.files.remove(&fd).map(|_| ()).ok_or(LxError::EBADF)
+        let files = self.files();
+        let file = files.lock().remove(&fd).ok_or(LxError::EBADF)?;
+        if let Ok(file) = file.downcast_arc::<File>() {
+            release_posix_locks(&file, self.inner.lock().pid);
+        }
+        Ok(())
     }
 
     /// Get root INode of the process.
@@ -359,7 +911,7 @@ impl LinuxProcess {
 
     /// Get current working directory.
     pub fn current_working_directory(&self) -> String {
-        String::from("/") + &self.inner.lock().current_working_directory
+        String::from("/") + &self.fs().lock().cwd
     }
 
     /// Change working directory.
@@ -367,10 +919,16 @@ impl LinuxProcess {
         if path.is_empty() {
             return;
         }
-        let mut inner = self.inner.lock();
-        let cwd = match path.as_bytes()[0] {
-            b'/' => String::new(),
-            _ => inner.current_working_directory.clone(),
+        let cwd = self.absolute_path(path);
+        self.fs().lock().cwd = String::from(&cwd[1..]);
+    }
+
+    /// Get the normalized absolute path of `path`, which is relative to
+    /// the current working directory.
+    pub fn absolute_path(&self, path: &str) -> String {
+        let cwd = match path.as_bytes().first() {
+            Some(b'/') => String::new(),
+            _ => self.fs().lock().cwd.clone(),
         };
         let mut cwd_vec: Vec<_> = cwd.split('/').filter(|x| !x.is_empty()).collect();
         for seg in path.split('/') {
@@ -382,7 +940,7 @@ impl LinuxProcess {
                 _ => cwd_vec.push(seg),
             }
         }
-        inner.current_working_directory = cwd_vec.join("/");
+        String::from("/") + &cwd_vec.join("/")
     }
 
     /// Get execute path.
@@ -395,21 +953,217 @@ impl LinuxProcess {
         self.inner.lock().execute_path = String::from(path);
     }
 
+    /// Get the arguments and the environment of the program.
+    pub fn args(&self) -> (Vec<String>, Vec<String>) {
+        let inner = self.inner.lock();
+        (inner.args.clone(), inner.envs.clone())
+    }
+
+    /// Set the arguments and the environment of the program, when it is executed.
+    pub fn set_args(&self, args: &[String], envs: &[String]) {
+        let mut inner = self.inner.lock();
+        inner.args = args.to_vec();
+        inner.envs = envs.to_vec();
+    }
+
+    /// Get the start of the heap and the current program break.
+    pub fn heap(&self) -> (VirtAddr, VirtAddr) {
+        let inner = self.inner.lock();
+        (inner.heap_start, inner.brk)
+    }
+
+    /// Set the program break.
+    pub fn set_brk(&self, brk: VirtAddr) {
+        self.inner.lock().brk = brk;
+    }
+
+    /// Reset the heap to be empty at `heap_start` after a new program is loaded.
+    pub fn reset_heap(&self, heap_start: VirtAddr) {
+        let mut inner = self.inner.lock();
+        inner.heap_start = heap_start;
+        inner.brk = heap_start;
+    }
+
+    /// Get the user and group identity.
+    pub fn credentials(&self) -> Credentials {
+        self.inner.lock().cred.clone()
+    }
+
+    /// Set the user and group identity.
+    ///
+    /// The file system IDs are set to the effective IDs, as every change of
+    /// the effective IDs does in Linux.
+    pub fn set_credentials(&self, mut cred: Credentials) {
+        cred.fsuid = cred.euid;
+        cred.fsgid = cred.egid;
+        self.inner.lock().cred = cred;
+    }
+
+    /// Get the file mode creation mask.
+    pub fn umask(&self) -> u32 {
+        self.fs().lock().umask
+    }
+
+    /// Set the file mode creation mask, return the previous one.
+    pub fn set_umask(&self, umask: u32) -> u32 {
+        core::mem::replace(&mut self.fs().lock().umask, umask & 0o777)
+    }
+
+    /// Get the process group ID.
+    pub fn pgid(&self) -> KoID {
+        self.inner.lock().pgid
+    }
+
+    /// Move the process to process group `pgid`.
+    pub fn set_pgid(&self, pgid: KoID) {
+        self.inner.lock().pgid = pgid;
+    }
+
+    /// Get the session ID.
+    pub fn sid(&self) -> KoID {
+        self.inner.lock().sid
+    }
+
+    /// Make the process the leader of a new session and a new process group,
+    /// both have the ID `pid`, without a controlling terminal.
+    pub fn set_session(&self, pid: KoID) {
+        let mut inner = self.inner.lock();
+        inner.sid = pid;
+        inner.pgid = pid;
+        inner.terminal = None;
+    }
+
+    /// Get the controlling terminal.
+    pub fn terminal(&self) -> Option<Arc<Tty>> {
+        self.inner.lock().terminal.clone()
+    }
+
+    /// Set the controlling terminal.
+    pub fn set_terminal(&self, terminal: Option<Arc<Tty>>) {
+        self.inner.lock().terminal = terminal;
+    }
+
+    /// Get the child process with `pid`.
+    pub fn child(&self, pid: KoID) -> Option<Arc<Process>> {
+        self.inner.lock().children.get(&pid).cloned()
+    }
+
+    /// Whether the process is stopped by a signal.
+    pub fn is_stopped(&self) -> bool {
+        self.inner.lock().stopped
+    }
+
+    /// Suspend `threads` of the process stopped by `signal`.
+    ///
+    /// Returns `false` if the process is already stopped.
+    fn stop_threads(&self, threads: Vec<Arc<Thread>>, signal: LinuxSignal) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.stopped {
+            return false;
+        }
+        for thread in threads.iter() {
+            thread.suspend();
+        }
+        inner.stopped = true;
+        inner.stopped_threads = threads;
+        inner.child_event = Some(WaitStatus::Stopped(signal));
+        true
+    }
+
+    /// Resume the threads suspended by a stop signal.
+    ///
+    /// Returns `false` if the process is not stopped.
+    fn continue_threads(&self) -> bool {
+        let mut inner = self.inner.lock();
+        if !inner.stopped {
+            return false;
+        }
+        inner.stopped = false;
+        inner.child_event = Some(WaitStatus::Continued);
+        let threads = core::mem::take(&mut inner.stopped_threads);
+        drop(inner);
+        for thread in threads {
+            thread.resume();
+        }
+        true
+    }
+
+    /// The signal which terminated the process.
+    pub fn term_signal(&self) -> Option<LinuxSignal> {
+        self.inner.lock().term_signal
+    }
+
+    /// Wake up the parent waiting for state changes of children.
+    fn notify_parent(&self) {
+        if let Some(parent) = self.parent() {
+            parent.signal_set(Signal::SIGCHLD);
+        }
+    }
+
+    /// Record a shared mapping of a file.
+    pub fn add_file_mapping(&self, mapping: FileMapping) {
+        self.inner.lock().file_mappings.push(mapping);
+    }
+
+    /// Get the shared mapping of a file which contains `addr`.
+    pub fn file_mapping(&self, addr: VirtAddr) -> Option<FileMapping> {
+        let inner = self.inner.lock();
+        inner
+            .file_mappings
+            .iter()
+            .find(|map| map.overlap(addr, addr + 1))
+            .cloned()
+    }
+
+    /// Write the shared mappings of files in `[addr, addr + len)` back to the files.
+    pub fn sync_file_mappings(&self, addr: VirtAddr, len: usize) -> LxResult {
+        let end = addr.saturating_add(len);
+        let mappings: Vec<_> = self
+            .inner
+            .lock()
+            .file_mappings
+            .iter()
+            .filter(|map| map.overlap(addr, end))
+            .cloned()
+            .collect();
+        for map in mappings {
+            map.sync(addr, end)?;
+        }
+        Ok(())
+    }
+
+    /// Write back and forget the shared mappings of files in `[addr, addr + len)`.
+    pub fn remove_file_mappings(&self, addr: VirtAddr, len: usize) -> LxResult {
+        self.sync_file_mappings(addr, len)?;
+        let end = addr.saturating_add(len);
+        let mut inner = self.inner.lock();
+        let (removed, mut kept): (Vec<_>, Vec<_>) = core::mem::take(&mut inner.file_mappings)
+            .into_iter()
+            .partition(|map| map.overlap(addr, end));
+        kept.extend(removed.iter().flat_map(|map| map.cut(addr, end)));
+        inner.file_mappings = kept;
+        drop(inner);
+        // the page caches may be written back when dropped
+        drop(removed);
+        Ok(())
+    }
+
     /// Get signal action.
     pub fn signal_action(&self, signal: LinuxSignal) -> SignalAction {
-        self.inner.lock().signal_actions.table[signal as u8 as usize]
+        self.signal_actions().lock().table[signal as u8 as usize]
     }
 
     /// Set signal action.
     pub fn set_signal_action(&self, signal: LinuxSignal, action: SignalAction) {
-        self.inner.lock().signal_actions.table[signal as u8 as usize] = action;
+        self.signal_actions().lock().table[signal as u8 as usize] = action;
     }
 
     /// Close file that FD_CLOEXEC is set
     pub fn remove_cloexec_files(&self) {
-        let mut inner = self.inner.lock();
-        let close_fds = inner
-            .files
+        let pid = self.inner.lock().pid;
+        let files = self.files();
+        let mut files = files.lock();
+        let close_fds = files
             .iter()
             .filter_map(|(fd, file_like)| {
                 if let Ok(file) = file_like.clone().downcast_arc::<File>() {
@@ -424,7 +1178,9 @@ impl LinuxProcess {
             })
             .collect::<Vec<_>>();
         for fd in close_fds {
-            inner.files.remove(&fd).map(|_| ()).unwrap();
+            if let Some(Ok(file)) = files.remove(&fd).map(|file| file.downcast_arc::<File>()) {
+                release_posix_locks(&file, pid);
+            }
         }
     }
 
@@ -438,11 +1194,6 @@ impl LinuxProcess {
         self.inner.lock().semaphores.get(id)
     }
 
-    /// Add an undo operation
-    pub fn semaphores_add_undo(&self, id: usize, num: u16, op: i16) {
-        self.inner.lock().semaphores.add_undo(id, num, op)
-    }
-
     /// Remove an `SemArray` by ID
     pub fn semaphores_remove(&self, id: usize) {
         self.inner.lock().semaphores.remove(id)
@@ -468,21 +1219,44 @@ impl LinuxProcess {
         self.inner.lock().shm_identifiers.add(shared_guard)
     }
 
+    /// Add a POSIX timer created by `new` with the lowest free timer ID.
+    pub fn add_timer(&self, new: impl FnOnce(usize) -> Arc<SignalTimer>) -> usize {
+        let mut inner = self.inner.lock();
+        let id = (0..).find(|id| !inner.timers.contains_key(id)).unwrap();
+        inner.timers.insert(id, new(id));
+        id
+    }
+
+    /// Get the POSIX timer `id`.
+    pub fn get_timer(&self, id: usize) -> LxResult<Arc<SignalTimer>> {
+        self.inner
+            .lock()
+            .timers
+            .get(&id)
+            .cloned()
+            .ok_or(LxError::EINVAL)
+    }
+
+    /// Delete the POSIX timer `id`, whose pending expirations are ignored.
+    pub fn remove_timer(&self, id: usize) -> LxResult {
+        self.inner
+            .lock()
+            .timers
+            .remove(&id)
+            .ok_or(LxError::EINVAL)?;
+        Ok(())
+    }
+
     /// Set Virtual Addr for shared memory
     pub fn shm_set(&self, id: usize, shm_id: ShmIdentifier) {
         self.inner.lock().shm_identifiers.set(id, shm_id)
     }
 }
 
-impl LinuxProcessInner {
-    fn get_free_fd(&self) -> FileDesc {
-        self.get_free_fd_from(0)
-    }
-
-    fn get_free_fd_from(&self, start: usize) -> FileDesc {
-        (start..)
-            .map(|i| i.into())
-            .find(|fd| !self.files.contains_key(fd))
-            .unwrap()
-    }
+/// Get the lowest fd not in `files` which is great than or equal to `start`.
+fn free_fd_from(files: &FileTable, start: usize) -> FileDesc {
+    (start..)
+        .map(|i| i.into())
+        .find(|fd| !files.contains_key(fd))
+        .unwrap()
 }
@@ -0,0 +1,758 @@
+//! Process tracing for debuggers and `strace`, see `ptrace(2)`
+//!
+//! A process is traced as a whole. Its threads enter ptrace-stops at the signal deliveries,
+//! the syscalls and the events asked by the tracer, which are reported one at a time
+//! by `wait4` in the order they happen. The requests act on the thread in the earliest stop,
+//! and the next stop is reported after it is resumed.
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::mem::size_of;
+
+use kernel_hal::context::UserContext;
+use lock::MutexGuard;
+use zircon_object::{
+    object::{KernelObject, KoID, Signal},
+    task::{CurrentThread, Process, Task, Thread},
+};
+
+use crate::error::{LxError, LxResult};
+use crate::process::{exit_by_signal, send_signal, CloneFlags, ProcessExt};
+use crate::signal::{MachineContext, SigInfo, Signal as LinuxSignal, SignalCode};
+use crate::thread::ThreadExt;
+
+bitflags::bitflags! {
+    /// Options of a tracee, set by `PTRACE_SETOPTIONS` or `PTRACE_SEIZE`
+    #[derive(Default)]
+    pub struct PtraceOptions: usize {
+        /// report the syscall stops with `SIGTRAP | 0x80`
+        const TRACESYSGOOD  = 1;
+        /// stop at `fork` and trace the new child
+        const TRACEFORK     = 1 << 1;
+        /// stop at `vfork` and trace the new child
+        const TRACEVFORK    = 1 << 2;
+        /// stop at `clone` and trace the new child
+        const TRACECLONE    = 1 << 3;
+        /// stop at `execve`
+        const TRACEEXEC     = 1 << 4;
+        /// kill the tracee when the tracer exits
+        const EXITKILL      = 1 << 20;
+    }
+}
+
+/// The event of an event stop, `PTRACE_EVENT_*`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtraceEvent {
+    /// `fork`, with the new process ID as the message
+    Fork = 1,
+    /// `vfork`, with the new process ID as the message
+    Vfork = 2,
+    /// `clone`, with the new process or thread ID as the message
+    Clone = 3,
+    /// `execve`, with the former thread ID as the message
+    Exec = 4,
+}
+
+/// How the tracer resumes a stopped tracee
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// `PTRACE_CONT`
+    Continue,
+    /// `PTRACE_SYSCALL`, stop at the next entry or exit of a syscall as well
+    Syscall,
+    /// `PTRACE_SINGLESTEP`, stop after executing one instruction
+    SingleStep,
+}
+
+/// The tracing state of a process, as a tracee and as a tracer
+#[derive(Default)]
+pub struct Ptrace {
+    /// The tracer, if the process is traced
+    tracer: Option<Weak<Process>>,
+    options: PtraceOptions,
+    /// Whether attached by `PTRACE_SEIZE`
+    seized: bool,
+    /// Whether the threads stop at the entries and exits of syscalls
+    trace_syscalls: bool,
+    /// Threads in ptrace-stops, in the order they stopped
+    stops: VecDeque<PtraceStop>,
+    /// The stops just resumed by the tracer, taken by the threads when they run again
+    resumed: BTreeMap<KoID, PtraceStop>,
+    /// Events reported when the syscalls of the threads return, and their messages
+    events: BTreeMap<KoID, (PtraceEvent, usize)>,
+    /// The processes traced by this process
+    tracees: BTreeMap<KoID, Arc<Process>>,
+}
+
+/// A thread in a ptrace-stop
+struct PtraceStop {
+    thread: Arc<Thread>,
+    /// The status reported by `wait4`, without the low byte
+    status: i32,
+    /// The signal delivered, or the `SIGTRAP` of a syscall or event stop
+    info: SigInfo,
+    /// The syscall number in a syscall stop, which may be changed by the tracer
+    syscall: Option<usize>,
+    /// The message of an event stop
+    event_msg: usize,
+    /// Whether the stop is reported by `wait4`
+    reported: bool,
+    /// The signal to deliver on resumption, given by the tracer
+    injected: Option<SigInfo>,
+}
+
+impl PtraceStop {
+    /// Whether the thread stops to deliver a signal
+    fn is_signal_stop(&self) -> bool {
+        self.syscall.is_none() && self.status == self.info.signal() as i32
+    }
+}
+
+impl Ptrace {
+    /// The tracer if it is alive
+    fn tracer(&self) -> Option<Arc<Process>> {
+        self.tracer.as_ref()?.upgrade()
+    }
+
+    /// Stop tracing the process and resume its stopped threads.
+    fn detach(&mut self) {
+        self.tracer = None;
+        self.options = PtraceOptions::empty();
+        self.seized = false;
+        self.trace_syscalls = false;
+        self.events.clear();
+        while let Some(mut stop) = self.stops.pop_front() {
+            // the signals reported but not delivered yet are delivered now
+            if stop.is_signal_stop() {
+                stop.injected = Some(stop.info);
+            }
+            let thread = stop.thread.clone();
+            self.resumed.insert(thread.id(), stop);
+            thread.resume();
+        }
+    }
+}
+
+/// Lock the tracing state of `proc`.
+fn lock(proc: &Process) -> MutexGuard<'_, Ptrace> {
+    proc.linux().ptrace.lock()
+}
+
+/// The tracer of `proc`.
+pub fn tracer(proc: &Process) -> Option<Arc<Process>> {
+    lock(proc).tracer()
+}
+
+/// Whether `proc` is traced.
+pub fn is_traced(proc: &Process) -> bool {
+    lock(proc).tracer.is_some()
+}
+
+/// Whether the threads of `proc` stop at the entries and exits of syscalls.
+pub fn traces_syscalls(proc: &Process) -> bool {
+    let ptrace = lock(proc);
+    ptrace.tracer.is_some() && ptrace.trace_syscalls
+}
+
+/// Whether an `execve` of `proc` may change its effective IDs by the set-user-ID
+/// and set-group-ID bits, which is denied while it is traced by an unprivileged tracer.
+pub fn may_gain_privileges(proc: &Process) -> bool {
+    let tracer = {
+        let ptrace = lock(proc);
+        if ptrace.tracer.is_none() {
+            return true;
+        }
+        ptrace.tracer()
+    };
+    tracer.map_or(false, |tracer| tracer.linux().credentials().is_root())
+}
+
+/// The processes traced by `proc`.
+pub(crate) fn tracees(proc: &Process) -> Vec<(KoID, Arc<Process>)> {
+    let ptrace = lock(proc);
+    ptrace
+        .tracees
+        .iter()
+        .map(|(&pid, tracee)| (pid, tracee.clone()))
+        .collect()
+}
+
+/// Get the status of the ptrace-stop of `tracee` not reported to its tracer `proc` yet.
+///
+/// The stop is marked reported unless `nowait`.
+pub(crate) fn take_stop_status(proc: &Process, tracee: &Process, nowait: bool) -> Option<i32> {
+    let mut ptrace = lock(tracee);
+    if ptrace.tracer()?.id() != proc.id() {
+        return None;
+    }
+    let stop = ptrace.stops.front_mut().filter(|stop| !stop.reported)?;
+    stop.reported = !nowait;
+    Some(stop.status)
+}
+
+/// Forget the terminated `tracee` of `proc`, which is reported by `wait4`.
+pub(crate) fn release(proc: &Process, tracee: KoID) {
+    lock(proc).tracees.remove(&tracee);
+}
+
+/// Get the process `pid` traced by `tracer`.
+pub fn tracee(tracer: &Process, pid: KoID) -> LxResult<Arc<Process>> {
+    lock(tracer)
+        .tracees
+        .get(&pid)
+        .cloned()
+        .ok_or(LxError::ESRCH)
+}
+
+/// Make `proc` traced by its parent, as `PTRACE_TRACEME` does.
+pub fn trace_me(proc: &Arc<Process>) -> LxResult {
+    let parent = proc.linux().parent().ok_or(LxError::EPERM)?;
+    trace(&parent, proc, PtraceOptions::empty(), false)
+}
+
+/// Make `tracer` trace `tracee`, as `PTRACE_ATTACH` does,
+/// or `PTRACE_SEIZE` with `options` if `seize`.
+///
+/// The tracee is sent a `SIGSTOP` unless `seize`.
+pub fn attach(
+    tracer: &Arc<Process>,
+    tracee: &Arc<Process>,
+    options: PtraceOptions,
+    seize: bool,
+) -> LxResult {
+    // only the processes of the same user can be traced by an unprivileged user
+    let (cred, target) = (tracer.linux().credentials(), tracee.linux().credentials());
+    if !cred.is_root() && (cred.euid != target.uid || cred.euid != target.suid) {
+        return Err(LxError::EPERM);
+    }
+    trace(tracer, tracee, options, seize)?;
+    if !seize {
+        let info = SigInfo::kill(
+            LinuxSignal::SIGSTOP,
+            SignalCode::USER as i32,
+            tracer.id() as usize,
+            cred.uid,
+        );
+        send_signal(tracee, info);
+    }
+    Ok(())
+}
+
+/// Make `tracer` trace `tracee`.
+fn trace(
+    tracer: &Arc<Process>,
+    tracee: &Arc<Process>,
+    options: PtraceOptions,
+    seized: bool,
+) -> LxResult {
+    // a process can not trace itself or its tracers
+    let mut ancestor = Some(tracer.clone());
+    while let Some(proc) = ancestor {
+        if proc.id() == tracee.id() {
+            return Err(LxError::EPERM);
+        }
+        ancestor = self::tracer(&proc);
+    }
+    {
+        let mut ptrace = lock(tracee);
+        if ptrace.tracer.is_some() {
+            return Err(LxError::EPERM);
+        }
+        ptrace.tracer = Some(Arc::downgrade(tracer));
+        ptrace.options = options;
+        ptrace.seized = seized;
+    }
+    lock(tracer).tracees.insert(tracee.id(), tracee.clone());
+    watch(tracer, tracee);
+    Ok(())
+}
+
+/// Wake up the tracer waiting for `tracee` when the tracee terminates,
+/// and detach or kill the tracee when the tracer terminates.
+fn watch(tracer: &Arc<Process>, tracee: &Arc<Process>) {
+    let tracer_weak = Arc::downgrade(tracer);
+    tracee.add_signal_callback(Box::new(move |signal| {
+        if signal.contains(Signal::PROCESS_TERMINATED) {
+            if let Some(tracer) = tracer_weak.upgrade() {
+                tracer.signal_set(Signal::SIGCHLD);
+            }
+            return true;
+        }
+        false
+    }));
+    let (tracer_id, tracee_weak) = (tracer.id(), Arc::downgrade(tracee));
+    tracer.add_signal_callback(Box::new(move |signal| {
+        if signal.contains(Signal::PROCESS_TERMINATED) {
+            if let Some(tracee) = tracee_weak.upgrade() {
+                let mut ptrace = lock(&tracee);
+                // the tracee may be detached and traced by another tracer since
+                let traced = match ptrace.tracer() {
+                    Some(tracer) => tracer.id() == tracer_id,
+                    None => ptrace.tracer.is_some(),
+                };
+                if traced && ptrace.options.contains(PtraceOptions::EXITKILL) {
+                    drop(ptrace);
+                    exit_by_signal(&tracee, LinuxSignal::SIGKILL);
+                } else if traced {
+                    ptrace.detach();
+                }
+            }
+            return true;
+        }
+        false
+    }));
+}
+
+/// Trace the child `new_thread` created by `thread` with `flags` as well,
+/// if the tracer asks for it, and report the event when the syscall returns.
+///
+/// The new thread starts with a `SIGSTOP` then.
+pub fn trace_clone(thread: &Thread, new_thread: &Arc<Thread>, flags: CloneFlags) {
+    let mut ptrace = lock(thread.proc());
+    let tracer = match ptrace.tracer() {
+        Some(tracer) => tracer,
+        None => return,
+    };
+    let exit_signal = (flags & CloneFlags::CSIGNAL).bits();
+    let (event, option) = if flags.contains(CloneFlags::VFORK) {
+        (PtraceEvent::Vfork, PtraceOptions::TRACEVFORK)
+    } else if !flags.contains(CloneFlags::THREAD) && exit_signal == LinuxSignal::SIGCHLD as usize {
+        (PtraceEvent::Fork, PtraceOptions::TRACEFORK)
+    } else {
+        (PtraceEvent::Clone, PtraceOptions::TRACECLONE)
+    };
+    if !ptrace.options.contains(option) {
+        return;
+    }
+    let id = if flags.contains(CloneFlags::THREAD) {
+        new_thread.id()
+    } else {
+        new_thread.proc().id()
+    };
+    ptrace.events.insert(thread.id(), (event, id as usize));
+    let (options, seized) = (ptrace.options, ptrace.seized);
+    drop(ptrace);
+    if !flags.contains(CloneFlags::THREAD) {
+        let child = new_thread.proc();
+        let mut child_ptrace = lock(child);
+        child_ptrace.tracer = Some(Arc::downgrade(&tracer));
+        child_ptrace.options = options;
+        child_ptrace.seized = seized;
+        drop(child_ptrace);
+        lock(&tracer).tracees.insert(child.id(), child.clone());
+        watch(&tracer, child);
+    }
+    let info = SigInfo::new(LinuxSignal::SIGSTOP, SignalCode::KERNEL as i32);
+    new_thread.lock_linux().queue_signal(info);
+}
+
+/// Report the `execve` of `thread` when the syscall returns,
+/// by an event stop with `PTRACE_O_TRACEEXEC`, or by a `SIGTRAP` unless seized.
+pub fn trace_exec(thread: &Thread) {
+    let mut ptrace = lock(thread.proc());
+    if ptrace.tracer.is_none() {
+        return;
+    }
+    if ptrace.options.contains(PtraceOptions::TRACEEXEC) {
+        let event = (PtraceEvent::Exec, thread.id() as usize);
+        ptrace.events.insert(thread.id(), event);
+    } else if !ptrace.seized {
+        drop(ptrace);
+        let info = SigInfo::new(LinuxSignal::SIGTRAP, SignalCode::USER as i32);
+        thread.lock_linux().queue_signal(info);
+    }
+}
+
+/// Enter a ptrace-stop reported as `status`, and wait for the tracer to resume the thread.
+///
+/// The context of the thread must be put back to be accessed by the tracer.
+/// Returns the stop resumed by the tracer, or `None` if not traced or killed while stopped.
+async fn stop(thread: &CurrentThread, stop: PtraceStop) -> Option<PtraceStop> {
+    let tracer = {
+        let mut ptrace = lock(thread.proc());
+        let tracer = ptrace.tracer()?;
+        // a single step ends with the stop
+        thread.with_context(|ctx| set_single_step(ctx, false)).ok();
+        ptrace.stops.push_back(stop);
+        thread.suspend();
+        tracer
+    };
+    tracer.signal_set(Signal::SIGCHLD);
+    let ctx = thread.wait_for_run().await;
+    thread.put_context(ctx);
+    let mut ptrace = lock(thread.proc());
+    let id = thread.id();
+    ptrace.stops.retain(|stop| stop.thread.id() != id);
+    ptrace.resumed.remove(&id)
+}
+
+/// A stop of the current thread reported as `status`, with the signal `info`
+fn new_stop(thread: &CurrentThread, status: i32, info: SigInfo) -> PtraceStop {
+    PtraceStop {
+        thread: thread.inner(),
+        status,
+        info,
+        syscall: None,
+        event_msg: 0,
+        reported: false,
+        injected: None,
+    }
+}
+
+/// Report the signal `info` to the tracer by a signal-delivery-stop before delivering it.
+///
+/// Returns the signal to deliver given by the tracer, which is `info` itself if not traced,
+/// or `None` if the tracer suppresses it.
+pub async fn signal_stop(thread: &CurrentThread, info: SigInfo) -> Option<SigInfo> {
+    let signal = info.signal();
+    if signal == LinuxSignal::SIGKILL || !is_traced(thread.proc()) {
+        return Some(info);
+    }
+    stop(thread, new_stop(thread, signal as i32, info))
+        .await?
+        .injected
+}
+
+/// Report the entry or the exit of the syscall `num` to the tracer by a syscall stop.
+///
+/// Returns the syscall number, which may be changed by the tracer at the entry.
+pub async fn syscall_stop(thread: &CurrentThread, num: usize) -> usize {
+    let sysgood = lock(thread.proc())
+        .options
+        .contains(PtraceOptions::TRACESYSGOOD);
+    let status = LinuxSignal::SIGTRAP as i32 | if sysgood { 0x80 } else { 0 };
+    let mut syscall_stop = new_stop(thread, status, SigInfo::new(LinuxSignal::SIGTRAP, status));
+    syscall_stop.syscall = Some(num);
+    match stop(thread, syscall_stop).await {
+        Some(stop) => {
+            inject(thread, &stop);
+            stop.syscall.unwrap_or(num)
+        }
+        None => num,
+    }
+}
+
+/// Report the event of the syscall just returned to the tracer by an event stop.
+pub async fn event_stop(thread: &CurrentThread) {
+    let event = lock(thread.proc()).events.remove(&thread.id());
+    if let Some((event, msg)) = event {
+        let status = LinuxSignal::SIGTRAP as i32 | (event as i32) << 8;
+        let info = SigInfo::new(LinuxSignal::SIGTRAP, status);
+        let mut event_stop = new_stop(thread, status, info);
+        event_stop.event_msg = msg;
+        if let Some(stop) = stop(thread, event_stop).await {
+            inject(thread, &stop);
+        }
+    }
+}
+
+/// Send the signal given by the tracer when resuming from a syscall or event stop.
+fn inject(thread: &CurrentThread, stop: &PtraceStop) {
+    if let Some(info) = stop.injected {
+        thread.lock_linux().queue_signal(info);
+    }
+}
+
+/// Act on the thread of `tracee` in the earliest ptrace-stop.
+///
+/// Fails with `ESRCH` if no thread of the tracee is stopped.
+fn with_stop<T>(tracee: &Process, f: impl FnOnce(&mut PtraceStop) -> LxResult<T>) -> LxResult<T> {
+    let mut ptrace = lock(tracee);
+    let stop = ptrace.stops.front_mut().ok_or(LxError::ESRCH)?;
+    f(stop)
+}
+
+/// Read a word at `addr` in the memory of the stopped `tracee`, as `PTRACE_PEEKDATA` does.
+pub fn peek(tracee: &Process, addr: usize) -> LxResult<usize> {
+    with_stop(tracee, |_| Ok(()))?;
+    let mut buf = [0; size_of::<usize>()];
+    match tracee.vmar().read_memory(addr, &mut buf) {
+        Ok(len) if len == buf.len() => Ok(usize::from_ne_bytes(buf)),
+        _ => Err(LxError::EIO),
+    }
+}
+
+/// Write the word `data` at `addr` in the memory of the stopped `tracee`,
+/// as `PTRACE_POKEDATA` does. The code can also be written even if it is read-only.
+pub fn poke(tracee: &Process, addr: usize, data: usize) -> LxResult {
+    with_stop(tracee, |_| Ok(()))?;
+    let buf = data.to_ne_bytes();
+    match tracee.vmar().write_memory(addr, &buf) {
+        Ok(len) if len == buf.len() => Ok(()),
+        _ => Err(LxError::EIO),
+    }
+}
+
+/// Get the registers of the stopped thread of `tracee`.
+pub fn get_regs(tracee: &Process) -> LxResult<PtraceRegs> {
+    with_stop(tracee, |stop| {
+        let syscall = stop.syscall;
+        Ok(stop
+            .thread
+            .with_context(|ctx| PtraceRegs::new(ctx, syscall))?)
+    })
+}
+
+/// Set the registers of the stopped thread of `tracee`.
+///
+/// The syscall can be changed at a syscall-entry-stop.
+pub fn set_regs(tracee: &Process, regs: &PtraceRegs) -> LxResult {
+    with_stop(tracee, |stop| {
+        stop.thread.with_context(|ctx| regs.restore(ctx))?;
+        if stop.syscall.is_some() {
+            stop.syscall = Some(regs.syscall());
+        }
+        Ok(())
+    })
+}
+
+/// Get the signal of the stopped thread of `tracee`, as `PTRACE_GETSIGINFO` does.
+pub fn siginfo(tracee: &Process) -> LxResult<SigInfo> {
+    with_stop(tracee, |stop| Ok(stop.info))
+}
+
+/// Get the message of the event stop of `tracee`, as `PTRACE_GETEVENTMSG` does.
+pub fn event_msg(tracee: &Process) -> LxResult<usize> {
+    with_stop(tracee, |stop| Ok(stop.event_msg))
+}
+
+/// Set the options of the stopped `tracee`, as `PTRACE_SETOPTIONS` does.
+pub fn set_options(tracee: &Process, options: PtraceOptions) -> LxResult {
+    with_stop(tracee, |_| Ok(()))?;
+    lock(tracee).options = options;
+    Ok(())
+}
+
+/// Resume the stopped thread of `tracee` as `how`, delivering `signal` if any.
+pub fn resume(tracee: &Process, how: Resume, signal: Option<LinuxSignal>) -> LxResult {
+    if how == Resume::SingleStep && !SINGLE_STEP {
+        return Err(LxError::EIO);
+    }
+    let mut ptrace = lock(tracee);
+    let mut stop = ptrace.stops.pop_front().ok_or(LxError::ESRCH)?;
+    if how == Resume::SingleStep {
+        stop.thread.with_context(|ctx| set_single_step(ctx, true))?;
+    }
+    ptrace.trace_syscalls = how == Resume::Syscall;
+    // the information of the reported signal is kept if it is delivered
+    stop.injected = signal.map(|signal| {
+        if stop.is_signal_stop() && stop.info.signal() == signal {
+            stop.info
+        } else {
+            SigInfo::new(signal, SignalCode::USER as i32)
+        }
+    });
+    let thread = stop.thread.clone();
+    ptrace.resumed.insert(thread.id(), stop);
+    drop(ptrace);
+    thread.resume();
+    Ok(())
+}
+
+/// Stop tracing the stopped `tracee` by `tracer`, as `PTRACE_DETACH` does,
+/// and resume it delivering `signal` if any.
+pub fn detach(tracer: &Process, tracee: &Process, signal: Option<LinuxSignal>) -> LxResult {
+    resume(tracee, Resume::Continue, signal)?;
+    lock(tracee).detach();
+    release(tracer, tracee.id());
+    Ok(())
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_arch = "x86_64")] {
+        /// Whether single-stepping is supported
+        const SINGLE_STEP: bool = true;
+
+        /// The registers of a tracee, `struct user_regs_struct`
+        #[repr(C)]
+        #[derive(Debug, Default, Clone, Copy)]
+        #[allow(missing_docs)]
+        pub struct PtraceRegs {
+            pub r15: usize,
+            pub r14: usize,
+            pub r13: usize,
+            pub r12: usize,
+            pub rbp: usize,
+            pub rbx: usize,
+            pub r11: usize,
+            pub r10: usize,
+            pub r9: usize,
+            pub r8: usize,
+            pub rax: usize,
+            pub rcx: usize,
+            pub rdx: usize,
+            pub rsi: usize,
+            pub rdi: usize,
+            /// the syscall number in a syscall stop, otherwise -1
+            pub orig_rax: usize,
+            pub rip: usize,
+            pub cs: usize,
+            pub eflags: usize,
+            pub rsp: usize,
+            pub ss: usize,
+            pub fs_base: usize,
+            pub gs_base: usize,
+            pub ds: usize,
+            pub es: usize,
+            pub fs: usize,
+            pub gs: usize,
+        }
+
+        impl PtraceRegs {
+            /// Save the registers of `ctx` in the syscall `syscall` if any
+            fn new(ctx: &UserContext, syscall: Option<usize>) -> Self {
+                // the segment selectors of the user code and data
+                const USER_CS: usize = 0x33;
+                const USER_SS: usize = 0x2b;
+                let regs = ctx.general();
+                Self {
+                    r15: regs.r15,
+                    r14: regs.r14,
+                    r13: regs.r13,
+                    r12: regs.r12,
+                    rbp: regs.rbp,
+                    rbx: regs.rbx,
+                    r11: regs.r11,
+                    r10: regs.r10,
+                    r9: regs.r9,
+                    r8: regs.r8,
+                    rax: regs.rax,
+                    rcx: regs.rcx,
+                    rdx: regs.rdx,
+                    rsi: regs.rsi,
+                    rdi: regs.rdi,
+                    orig_rax: syscall.unwrap_or(usize::MAX),
+                    rip: regs.rip,
+                    cs: USER_CS,
+                    eflags: regs.rflags,
+                    rsp: regs.rsp,
+                    ss: USER_SS,
+                    fs_base: regs.fsbase,
+                    gs_base: regs.gsbase,
+                    ..Default::default()
+                }
+            }
+
+            /// Restore the registers to `ctx`, the segment selectors are not changed
+            fn restore(&self, ctx: &mut UserContext) {
+                let mut context = MachineContext::from_context(ctx);
+                context.r15 = self.r15;
+                context.r14 = self.r14;
+                context.r13 = self.r13;
+                context.r12 = self.r12;
+                context.rbp = self.rbp;
+                context.rbx = self.rbx;
+                context.r11 = self.r11;
+                context.r10 = self.r10;
+                context.r9 = self.r9;
+                context.r8 = self.r8;
+                context.rax = self.rax;
+                context.rcx = self.rcx;
+                context.rdx = self.rdx;
+                context.rsi = self.rsi;
+                context.rdi = self.rdi;
+                context.rip = self.rip;
+                context.eflags = self.eflags;
+                context.rsp = self.rsp;
+                context.restore(ctx);
+                let regs = ctx.general_mut();
+                regs.fsbase = self.fs_base;
+                regs.gsbase = self.gs_base;
+            }
+
+            /// The syscall number
+            fn syscall(&self) -> usize {
+                self.orig_rax
+            }
+        }
+
+        /// Set the trap flag of `ctx` to stop after executing an instruction.
+        fn set_single_step(ctx: &mut UserContext, enable: bool) {
+            const TRAP_FLAG: usize = 1 << 8;
+            let regs = ctx.general_mut();
+            if enable {
+                regs.rflags |= TRAP_FLAG;
+            } else {
+                regs.rflags &= !TRAP_FLAG;
+            }
+        }
+    } else if #[cfg(target_arch = "riscv64")] {
+        /// Whether single-stepping is supported
+        const SINGLE_STEP: bool = false;
+
+        /// The registers of a tracee, `struct user_regs_struct`,
+        /// which are `pc` and the general registers `x1` to `x31`
+        #[repr(C)]
+        #[derive(Debug, Default, Clone, Copy)]
+        pub struct PtraceRegs(pub [usize; 32]);
+
+        impl PtraceRegs {
+            /// Save the registers of `ctx`
+            fn new(ctx: &UserContext, _syscall: Option<usize>) -> Self {
+                Self(MachineContext::from_context(ctx).general_regs)
+            }
+
+            /// Restore the registers to `ctx`
+            fn restore(&self, ctx: &mut UserContext) {
+                let mut context = MachineContext::default();
+                context.general_regs = self.0;
+                context.restore(ctx);
+            }
+
+            /// The syscall number in `a7`
+            fn syscall(&self) -> usize {
+                self.0[17]
+            }
+        }
+
+        /// Single-stepping is not supported.
+        fn set_single_step(_ctx: &mut UserContext, _enable: bool) {}
+    } else {
+        /// Whether single-stepping is supported
+        const SINGLE_STEP: bool = false;
+
+        /// The registers of a tracee, `struct user_pt_regs`
+        #[repr(C)]
+        #[derive(Debug, Default, Clone, Copy)]
+        #[allow(missing_docs)]
+        pub struct PtraceRegs {
+            pub regs: [usize; 31],
+            pub sp: usize,
+            pub pc: usize,
+            pub pstate: usize,
+        }
+
+        impl PtraceRegs {
+            /// Save the registers of `ctx`
+            fn new(ctx: &UserContext, _syscall: Option<usize>) -> Self {
+                let context = MachineContext::from_context(ctx);
+                Self {
+                    regs: context.regs,
+                    sp: context.sp,
+                    pc: context.pc,
+                    pstate: 0,
+                }
+            }
+
+            /// Restore the registers to `ctx`, `pstate` is not changed
+            fn restore(&self, ctx: &mut UserContext) {
+                let mut context = MachineContext::new(self.pc);
+                context.regs = self.regs;
+                context.sp = self.sp;
+                context.restore(ctx);
+            }
+
+            /// The syscall number in `x8`
+            fn syscall(&self) -> usize {
+                self.regs[8]
+            }
+        }
+
+        /// Single-stepping is not supported.
+        fn set_single_step(_ctx: &mut UserContext, _enable: bool) {}
+    }
+}
@@ -68,45 +68,248 @@ pub struct SignalAction {
     pub mask: Sigset,
 }
 
+/// The fields of `siginfo_t` for signals sent by a process, including `sigqueue(3)`
 #[repr(C)]
-#[derive(Copy, Clone, Eq, PartialEq)]
-pub struct SiginfoFields {
-    pad: [u8; Self::PAD_SIZE],
-    // TODO: fill this union
+#[derive(Debug, Copy, Clone)]
+pub struct SiginfoKill {
+    /// `si_pid`: the process ID of the sender
+    pub pid: i32,
+    /// `si_uid`: the real user ID of the sender
+    pub uid: u32,
+    /// `si_value`: the value sent by `sigqueue(3)`
+    pub value: usize,
+}
+
+/// The fields of `siginfo_t` for `SIGCHLD`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SiginfoChild {
+    /// `si_pid`: the process ID of the child
+    pub pid: i32,
+    /// `si_uid`: the real user ID of the child
+    pub uid: u32,
+    /// `si_status`: the exit status or the signal of the child
+    pub status: i32,
+    /// `si_utime`: the user CPU time consumed by the child
+    pub utime: isize,
+    /// `si_stime`: the system CPU time consumed by the child
+    pub stime: isize,
+}
+
+/// The fields of `siginfo_t` for signals caused by a fault
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SiginfoFault {
+    /// `si_addr`: the address of the fault
+    pub addr: usize,
+}
+
+/// The fields of `siginfo_t` for signals sent by a POSIX timer
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SiginfoTimer {
+    /// `si_timerid`: the ID of the timer
+    pub tid: i32,
+    /// `si_overrun`: the number of expirations not notified
+    pub overrun: i32,
+    /// `si_value`: the value given in `struct sigevent`
+    pub value: usize,
+}
+
+/// The signal specific fields of `siginfo_t`
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub union SiginfoFields {
+    /// sent by a process
+    pub kill: SiginfoKill,
+    /// `SIGCHLD`
+    pub child: SiginfoChild,
+    /// caused by a fault
+    pub fault: SiginfoFault,
+    /// sent by a POSIX timer
+    pub timer: SiginfoTimer,
+    pad: [u64; Self::PAD_SIZE / 8],
 }
 
 impl SiginfoFields {
-    const PAD_SIZE: usize = 128 - 2 * core::mem::size_of::<i32>() - core::mem::size_of::<usize>();
+    const PAD_SIZE: usize = 128 - 4 * core::mem::size_of::<i32>();
 }
 
 impl Default for SiginfoFields {
     fn default() -> Self {
         SiginfoFields {
-            pad: [0; Self::PAD_SIZE],
+            pad: [0; Self::PAD_SIZE / 8],
         }
     }
 }
 
+/// Linux struct siginfo_t
 #[repr(C)]
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Copy, Clone)]
 pub struct SigInfo {
-    pub signo: i32,
+    /// always a valid signal, it is only set by the constructors and `set_signal`
+    signo: i32,
     pub errno: i32,
-    pub code: SignalCode,
+    pub code: i32,
     pub field: SiginfoFields,
 }
 
-impl Default for SigInfo {
-    fn default() -> Self {
-        Self {
-            signo: 0,
+#[allow(unsafe_code)]
+impl SigInfo {
+    /// Information of `signal` without the signal specific fields
+    pub fn new(signal: Signal, code: i32) -> Self {
+        SigInfo {
+            signo: signal as i32,
             errno: 0,
-            code: SignalCode::USER,
+            code,
             field: Default::default(),
         }
     }
+
+    /// Information of `signal` sent by the process `pid` of user `uid`
+    pub fn kill(signal: Signal, code: i32, pid: usize, uid: u32) -> Self {
+        Self::queue(signal, code, pid, uid, 0)
+    }
+
+    /// Information of `signal` with `value` sent by the process `pid` of user `uid`,
+    /// as `sigqueue(3)` does
+    pub fn queue(signal: Signal, code: i32, pid: usize, uid: u32, value: usize) -> Self {
+        let mut info = Self::new(signal, code);
+        info.field.kill = SiginfoKill {
+            pid: pid as i32,
+            uid,
+            value,
+        };
+        info
+    }
+
+    /// Information of `SIGCHLD` for the status change of the child `pid` of user `uid`
+    pub fn child(code: i32, pid: usize, uid: u32, status: i32) -> Self {
+        let mut info = Self::new(Signal::SIGCHLD, code);
+        info.field.child = SiginfoChild {
+            pid: pid as i32,
+            uid,
+            status,
+            utime: 0,
+            stime: 0,
+        };
+        info
+    }
+
+    /// Information of `signal` caused by a fault at `addr`
+    pub fn fault(signal: Signal, code: i32, addr: usize) -> Self {
+        let mut info = Self::new(signal, code);
+        info.field.fault = SiginfoFault { addr };
+        info
+    }
+
+    /// Information of `signal` sent by the POSIX timer `id`
+    pub fn timer(signal: Signal, id: usize, overrun: usize, value: usize) -> Self {
+        let mut info = Self::new(signal, SignalCode::TIMER as i32);
+        info.field.timer = SiginfoTimer {
+            tid: id as i32,
+            overrun: overrun as i32,
+            value,
+        };
+        info
+    }
+
+    /// The signal, which is checked when it is set
+    pub fn signal(&self) -> Signal {
+        Signal::try_from(self.signo as u8).unwrap()
+    }
+
+    /// Replace the signal, such as the one in the information given by the user
+    pub fn set_signal(&mut self, signal: Signal) {
+        self.signo = signal as i32;
+    }
+
+    /// `si_pid`
+    pub fn pid(&self) -> i32 {
+        unsafe { self.field.kill.pid }
+    }
+
+    /// `si_uid`
+    pub fn uid(&self) -> u32 {
+        unsafe { self.field.kill.uid }
+    }
+
+    /// `si_value`
+    pub fn value(&self) -> usize {
+        unsafe { self.field.kill.value }
+    }
+
+    /// `si_status`
+    pub fn status(&self) -> i32 {
+        unsafe { self.field.child.status }
+    }
+
+    /// `si_addr`
+    pub fn addr(&self) -> usize {
+        unsafe { self.field.fault.addr }
+    }
+
+    /// `si_timerid`
+    pub fn timer_id(&self) -> i32 {
+        unsafe { self.field.timer.tid }
+    }
+
+    /// `si_overrun`
+    pub fn overrun(&self) -> i32 {
+        unsafe { self.field.timer.overrun }
+    }
+
+    /// Count more expirations of the timer in `si_overrun`
+    pub fn add_overrun(&mut self, count: usize) {
+        unsafe {
+            let overrun = &mut self.field.timer.overrun;
+            *overrun = overrun.saturating_add(count as i32);
+        }
+    }
+}
+
+impl core::fmt::Debug for SigInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("SigInfo")
+            .field("signo", &self.signo)
+            .field("errno", &self.errno)
+            .field("code", &self.code)
+            .field("pid", &self.pid())
+            .field("uid", &self.uid())
+            .field("value", &self.value())
+            .finish()
+    }
 }
 
+/// `si_code` of `SIGCHLD`: the child has exited
+pub const CLD_EXITED: i32 = 1;
+/// `si_code` of `SIGCHLD`: the child was killed
+pub const CLD_KILLED: i32 = 2;
+/// `si_code` of `SIGCHLD`: the child terminated abnormally
+pub const CLD_DUMPED: i32 = 3;
+/// `si_code` of `SIGCHLD`: the traced child has trapped
+pub const CLD_TRAPPED: i32 = 4;
+/// `si_code` of `SIGCHLD`: the child has stopped
+pub const CLD_STOPPED: i32 = 5;
+/// `si_code` of `SIGCHLD`: the stopped child has continued
+pub const CLD_CONTINUED: i32 = 6;
+/// `si_code` of `SIGSEGV`: address not mapped to object
+pub const SEGV_MAPERR: i32 = 1;
+/// `si_code` of `SIGSEGV`: invalid permissions for mapped object
+pub const SEGV_ACCERR: i32 = 2;
+/// `si_code` of `SIGBUS`: invalid address alignment
+pub const BUS_ADRALN: i32 = 1;
+/// `si_code` of `SIGILL`: illegal opcode
+pub const ILL_ILLOPC: i32 = 1;
+/// `si_code` of `SIGFPE`: integer divide by zero
+pub const FPE_INTDIV: i32 = 1;
+/// `si_code` of `SIGFPE`: invalid floating-point operation
+pub const FPE_FLTINV: i32 = 7;
+/// `si_code` of `SIGTRAP`: process breakpoint
+pub const TRAP_BRKPT: i32 = 1;
+/// `si_code` of `SIGTRAP`: hardware breakpoint or watchpoint
+pub const TRAP_HWBKPT: i32 = 4;
+
 /// A code identifying the cause of the signal.
 #[repr(i32)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -1,10 +1,13 @@
 //! Linux signals
 use bitflags::*;
+use kernel_hal::context::UserContext;
 use numeric_enum_macro::numeric_enum;
 
 mod action;
+mod timer;
 
 pub use action::*;
+pub use timer::*;
 
 cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
@@ -115,8 +118,61 @@ cfg_if::cfg_if! {
             pub fn set_pc(&mut self, pc: usize) {
                 self.rip = pc;
             }
+
+            /// Save the registers of the user context `ctx`
+            pub fn from_context(ctx: &UserContext) -> Self {
+                let regs = ctx.general();
+                Self {
+                    r8: regs.r8,
+                    r9: regs.r9,
+                    r10: regs.r10,
+                    r11: regs.r11,
+                    r12: regs.r12,
+                    r13: regs.r13,
+                    r14: regs.r14,
+                    r15: regs.r15,
+                    rdi: regs.rdi,
+                    rsi: regs.rsi,
+                    rbp: regs.rbp,
+                    rbx: regs.rbx,
+                    rdx: regs.rdx,
+                    rax: regs.rax,
+                    rcx: regs.rcx,
+                    rsp: regs.rsp,
+                    rip: regs.rip,
+                    eflags: regs.rflags,
+                    ..Default::default()
+                }
+            }
+
+            /// Restore the registers to the user context `ctx`
+            pub fn restore(&self, ctx: &mut UserContext) {
+                // the flags which can be changed by the user: AC, RF, OF, DF, TF, SF, ZF, AF, PF, CF
+                const FIX_EFLAGS: usize = 0x50dd5;
+                let regs = ctx.general_mut();
+                regs.r8 = self.r8;
+                regs.r9 = self.r9;
+                regs.r10 = self.r10;
+                regs.r11 = self.r11;
+                regs.r12 = self.r12;
+                regs.r13 = self.r13;
+                regs.r14 = self.r14;
+                regs.r15 = self.r15;
+                regs.rdi = self.rdi;
+                regs.rsi = self.rsi;
+                regs.rbp = self.rbp;
+                regs.rbx = self.rbx;
+                regs.rdx = self.rdx;
+                regs.rax = self.rax;
+                regs.rcx = self.rcx;
+                regs.rsp = self.rsp;
+                regs.rip = self.rip;
+                regs.rflags = (regs.rflags & !FIX_EFLAGS) | (self.eflags & FIX_EFLAGS);
+            }
         }
     } else if #[cfg(target_arch = "riscv64")] {
+        use kernel_hal::context::UserContextField;
+
         /// struct mcontext
         #[repr(C, align(16))]
         #[derive(Clone, Debug, Eq, PartialEq)]
@@ -150,37 +206,98 @@ cfg_if::cfg_if! {
             pub fn set_pc(&mut self, pc: usize) {
                 self.general_regs[0] = pc;
             }
+
+            /// Save the registers of the user context `ctx`
+            pub fn from_context(ctx: &UserContext) -> Self {
+                let mut ctx = *ctx;
+                let mut n = Self::new(ctx.get_field(UserContextField::InstrPointer));
+                n.general_regs[1..].copy_from_slice(&general_regs(&mut ctx)[1..]);
+                n
+            }
+
+            /// Restore the registers to the user context `ctx`
+            pub fn restore(&self, ctx: &mut UserContext) {
+                general_regs(ctx)[1..].copy_from_slice(&self.general_regs[1..]);
+                ctx.set_field(UserContextField::InstrPointer, self.get_pc());
+            }
+        }
+
+        /// The general registers `x0` to `x31` of `ctx` in order
+        #[allow(unsafe_code)]
+        fn general_regs(ctx: &mut UserContext) -> &mut [usize; 32] {
+            unsafe { &mut *(ctx.general_mut() as *mut _ as *mut [usize; 32]) }
         }
     } else {
+        use kernel_hal::context::UserContextField;
+
         /// TODO: other archs, this sample is for aarch64
         /// struct mcontext
-        #[repr(C)]
+        #[repr(C, align(16))]
         #[derive(Clone, Debug, Eq, PartialEq)]
         pub struct MachineContext {
-            pub reserved_: [usize; 18 + 256],
+            pub fault_address: usize,
+            pub regs: [usize; 31],
+            pub sp: usize,
+            pub pc: usize,
+            pub pstate: usize,
+            _pad: usize,
+            pub reserved_: [usize; 512],
         }
 
         impl Default for MachineContext {
             fn default() -> Self {
                 Self {
-                    reserved_: [0; 18 + 256],
+                    fault_address: 0,
+                    regs: [0; 31],
+                    sp: 0,
+                    pc: 0,
+                    pstate: 0,
+                    _pad: 0,
+                    reserved_: [0; 512],
                 }
             }
         }
 
         impl MachineContext {
-            pub fn new(_pc : usize) -> Self {
-                unimplemented!();
+            pub fn new(pc : usize) -> Self {
+                Self {
+                    pc,
+                    ..Default::default()
+                }
             }
 
             pub fn get_pc(&self) -> usize {
-                unimplemented!();
+                self.pc
+            }
+
+            pub fn set_pc(&mut self, pc: usize) {
+                self.pc = pc;
+            }
+
+            /// Save the registers of the user context `ctx`
+            pub fn from_context(ctx: &UserContext) -> Self {
+                let mut ctx = *ctx;
+                let mut n = Self::new(ctx.get_field(UserContextField::InstrPointer));
+                n.sp = ctx.get_field(UserContextField::StackPointer);
+                n.regs.copy_from_slice(general_regs(&mut ctx));
+                n
             }
 
-            pub fn set_pc(&mut self, _pc: usize) -> usize {
-                unimplemented!();
+            /// Restore the registers to the user context `ctx`
+            ///
+            /// `pstate` is not restored.
+            pub fn restore(&self, ctx: &mut UserContext) {
+                general_regs(ctx).copy_from_slice(&self.regs);
+                ctx.set_field(UserContextField::StackPointer, self.sp);
+                ctx.set_field(UserContextField::InstrPointer, self.pc);
             }
         }
+
+        /// The general registers `x0` to `x30` of `ctx` in order
+        #[allow(unsafe_code)]
+        fn general_regs(ctx: &mut UserContext) -> &mut [usize; 31] {
+            unsafe { &mut *(ctx.general_mut() as *mut _ as *mut [usize; 31]) }
+        }
     }
 }
 
@@ -214,6 +331,13 @@ pub struct SignalStack {
     pub size: usize,
 }
 
+impl SignalStack {
+    /// Whether the stack pointer `sp` is on the enabled stack
+    pub fn contains(&self, sp: usize) -> bool {
+        !self.flags.contains(SignalStackFlags::DISABLE) && sp > self.sp && sp - self.sp <= self.size
+    }
+}
+
 impl Default for SignalStack {
     fn default() -> Self {
         // default to disabled
@@ -308,4 +432,21 @@ impl Signal {
     pub fn as_bit(&self) -> u64 {
         1 << (*self as u64 - 1)
     }
+
+    /// Whether the default action of the signal is to ignore it,
+    /// otherwise the process is terminated or stopped.
+    pub fn is_ignored_by_default(self) -> bool {
+        matches!(
+            self,
+            Signal::SIGCHLD | Signal::SIGCONT | Signal::SIGURG | Signal::SIGWINCH
+        )
+    }
+
+    /// Whether the default action of the signal is to stop the process.
+    pub fn is_stopping_by_default(self) -> bool {
+        matches!(
+            self,
+            Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU
+        )
+    }
 }
@@ -0,0 +1,333 @@
+//! Interval timers and POSIX timers, which notify their expirations by signals
+
+use super::{SigInfo, Signal, SignalCode};
+use crate::process::{send_signal, send_signal_to_thread, threads};
+use crate::thread::ThreadExt;
+use crate::time::ITimerSpec;
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use core::time::Duration;
+use kernel_hal::timer::{timer_now, timer_set};
+use lock::Mutex;
+use numeric_enum_macro::numeric_enum;
+use zircon_object::task::{Process, Thread};
+
+/// Notify the process by sending the signal
+pub const SIGEV_SIGNAL: i32 = 0;
+/// Do not notify when the timer expires
+pub const SIGEV_NONE: i32 = 1;
+/// Notify by a new thread, which is emulated by the C library
+pub const SIGEV_THREAD: i32 = 2;
+/// Notify the thread given in `sigev_notify_thread_id` by sending the signal
+pub const SIGEV_THREAD_ID: i32 = 4;
+
+/// Linux struct sigevent
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct SigEvent {
+    /// `sigev_value`: the value passed in `si_value`
+    pub value: usize,
+    /// `sigev_signo`: the signal to send
+    pub signo: i32,
+    /// `sigev_notify`: the notification method
+    pub notify: i32,
+    /// `sigev_notify_thread_id`: the thread to notify with `SIGEV_THREAD_ID`
+    pub tid: i32,
+    _pad: [i32; 11],
+}
+
+numeric_enum! {
+    #[repr(usize)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    /// The interval timers of a process, see `getitimer(2)`
+    pub enum ITimerWhich {
+        /// counts down in real time, sends `SIGALRM`
+        Real = 0,
+        /// counts down in user CPU time, sends `SIGVTALRM`
+        Virtual = 1,
+        /// counts down in user and system CPU time, sends `SIGPROF`
+        Prof = 2,
+    }
+}
+
+impl ITimerWhich {
+    /// The signal sent when the timer expires
+    pub fn signal(self) -> Signal {
+        match self {
+            ITimerWhich::Real => Signal::SIGALRM,
+            ITimerWhich::Virtual => Signal::SIGVTALRM,
+            ITimerWhich::Prof => Signal::SIGPROF,
+        }
+    }
+}
+
+/// The receiver of the signals of a timer
+#[derive(Debug)]
+pub enum TimerTarget {
+    /// The expirations are not notified
+    None,
+    /// Send the signal to the process
+    Process(Weak<Process>),
+    /// Send the signal to the thread
+    Thread(Weak<Thread>),
+}
+
+/// A timer sending a signal to the target on each expiration
+///
+/// The CPU time of processes is not accounted, so all timers count down in real time,
+/// and only the `ITIMER_REAL` interval timer is available.
+pub struct SignalTimer {
+    /// `si_timerid` of the POSIX timer
+    id: usize,
+    /// The signal to send
+    signal: Signal,
+    /// `si_code`, `SI_TIMER` for POSIX timers and `SI_KERNEL` for interval timers
+    code: i32,
+    /// `si_value`
+    value: usize,
+    target: TimerTarget,
+    inner: Mutex<SignalTimerInner>,
+}
+
+#[derive(Default)]
+struct SignalTimerInner {
+    /// The time of the next expiration, or `None` if the timer is disarmed
+    deadline: Option<Duration>,
+    /// The period of the timer, zero for a one-shot timer
+    interval: Duration,
+    /// Increased each time the timer is set, to ignore the callbacks set before
+    generation: usize,
+    /// The expirations not notified since the last signal was queued
+    overrun: usize,
+}
+
+impl SignalTimer {
+    /// Create a POSIX timer `id` notifying `target` with `signal` and `value`.
+    pub fn new(id: usize, signal: Signal, value: usize, target: TimerTarget) -> Arc<Self> {
+        Arc::new(SignalTimer {
+            id,
+            signal,
+            code: SignalCode::TIMER as i32,
+            value,
+            target,
+            inner: Mutex::new(SignalTimerInner::default()),
+        })
+    }
+
+    /// Create the interval timer `which` of the process `proc`.
+    pub fn new_itimer(proc: &Arc<Process>, which: ITimerWhich) -> Arc<Self> {
+        Arc::new(SignalTimer {
+            id: which as usize,
+            signal: which.signal(),
+            code: SignalCode::KERNEL as i32,
+            value: 0,
+            target: TimerTarget::Process(Arc::downgrade(proc)),
+            inner: Mutex::new(SignalTimerInner::default()),
+        })
+    }
+
+    /// Get the time until the next expiration and the interval.
+    pub fn get(&self) -> ITimerSpec {
+        self.inner.lock().get(timer_now())
+    }
+
+    /// Arm the timer with `value`, which is an absolute time if `abs` is set,
+    /// or disarm it if the value is zero. Returns the old setting.
+    pub fn set(self: &Arc<Self>, value: ITimerSpec, abs: bool) -> ITimerSpec {
+        let now = timer_now();
+        let mut inner = self.inner.lock();
+        let old = inner.get(now);
+        let expire: Duration = value.value.into();
+        inner.generation += 1;
+        inner.overrun = 0;
+        inner.interval = value.interval.into();
+        inner.deadline = match expire {
+            Duration::ZERO => None,
+            _ if abs => Some(expire),
+            _ => Some(now + expire),
+        };
+        let (deadline, generation) = (inner.deadline, inner.generation);
+        drop(inner);
+        if let Some(deadline) = deadline {
+            self.arm(deadline, generation);
+        }
+        old
+    }
+
+    /// The number of expirations not notified since the last signal was queued
+    pub fn overrun(&self) -> usize {
+        self.inner.lock().overrun
+    }
+
+    fn arm(self: &Arc<Self>, deadline: Duration, generation: usize) {
+        let timer = Arc::downgrade(self);
+        timer_set(
+            deadline,
+            Box::new(move |now| {
+                if let Some(timer) = timer.upgrade() {
+                    timer.expire(now, generation);
+                }
+            }),
+        );
+    }
+
+    fn expire(self: &Arc<Self>, now: Duration, generation: usize) {
+        let mut inner = self.inner.lock();
+        let deadline = match inner.deadline {
+            Some(deadline) if inner.generation == generation => deadline,
+            _ => return,
+        };
+        // the periods passed before the callback is called are overruns
+        let mut missed = 0;
+        if inner.interval == Duration::ZERO {
+            inner.deadline = None;
+        } else {
+            let interval = inner.interval.as_nanos();
+            missed = (now.saturating_sub(deadline).as_nanos() / interval) as usize;
+            let next = deadline + Duration::from_nanos(((missed + 1) as u128 * interval) as u64);
+            inner.deadline = Some(next);
+        }
+        let next = inner.deadline;
+        drop(inner);
+        if let Some(next) = next {
+            self.arm(next, generation);
+        }
+        self.notify(missed);
+    }
+
+    /// Queue the signal to the target, or count the expirations as overruns
+    /// if the signal of the last expiration is still pending.
+    fn notify(&self, missed: usize) {
+        let (proc, thread) = match &self.target {
+            TimerTarget::None => return,
+            TimerTarget::Process(proc) => (proc.upgrade(), None),
+            TimerTarget::Thread(thread) => (None, thread.upgrade()),
+        };
+        let targets = match (&proc, &thread) {
+            (Some(proc), _) => threads(proc),
+            (_, Some(thread)) => vec![thread.clone()],
+            _ => return,
+        };
+        for target in targets.iter() {
+            let mut linux = target.lock_linux();
+            if let Some(info) = linux.pending_signal_mut(self.signal) {
+                if info.code == self.code && info.timer_id() == self.id as i32 {
+                    info.add_overrun(missed + 1);
+                    self.inner.lock().overrun += missed + 1;
+                    return;
+                }
+            }
+        }
+        self.inner.lock().overrun = missed;
+        let info = if self.code == SignalCode::TIMER as i32 {
+            SigInfo::timer(self.signal, self.id, missed, self.value)
+        } else {
+            SigInfo::new(self.signal, self.code)
+        };
+        match (proc, thread) {
+            (Some(proc), _) => send_signal(&proc, info),
+            (_, Some(thread)) => send_signal_to_thread(&thread, info),
+            _ => {}
+        }
+    }
+}
+
+impl SignalTimerInner {
+    fn get(&self, now: Duration) -> ITimerSpec {
+        ITimerSpec {
+            interval: self.interval.into(),
+            value: self
+                .deadline
+                .map(|deadline| deadline.saturating_sub(now))
+                .unwrap_or_default()
+                .into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process::ProcessExt;
+    use rcore_fs_ramfs::RamFS;
+    use zircon_object::task::Job;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// A periodic POSIX timer of `thread`, which does not expire by itself in a test
+    fn periodic_timer(thread: &Arc<Thread>) -> (Arc<SignalTimer>, Duration) {
+        let target = TimerTarget::Thread(Arc::downgrade(thread));
+        let timer = SignalTimer::new(7, Signal::SIGUSR1, 42, target);
+        let value = ITimerSpec {
+            interval: SECOND.into(),
+            value: (1000 * SECOND).into(),
+        };
+        timer.set(value, false);
+        let deadline = timer.inner.lock().deadline.unwrap();
+        (timer, deadline)
+    }
+
+    fn pending(thread: &Thread) -> Option<SigInfo> {
+        thread
+            .lock_linux()
+            .pending_signal_mut(Signal::SIGUSR1)
+            .map(|info| *info)
+    }
+
+    #[test]
+    fn overrun() {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let thread = Thread::create_linux(&proc).unwrap();
+        let (timer, deadline) = periodic_timer(&thread);
+
+        // the periods passed before the expiration are counted in the signal
+        timer.expire(deadline + SECOND * 5 / 2, 1);
+        let info = pending(&thread).unwrap();
+        assert_eq!(info.timer_id(), 7);
+        assert_eq!(info.value(), 42);
+        assert_eq!(info.overrun(), 2);
+        assert_eq!(timer.overrun(), 2);
+        assert_eq!(timer.inner.lock().deadline, Some(deadline + 3 * SECOND));
+
+        // the expirations while the signal is pending are added to it
+        timer.expire(deadline + 3 * SECOND, 1);
+        assert_eq!(pending(&thread).unwrap().overrun(), 3);
+        assert_eq!(timer.overrun(), 3);
+
+        // a new signal starts counting again
+        thread.lock_linux().dequeue_signal();
+        timer.expire(deadline + 4 * SECOND, 1);
+        assert_eq!(pending(&thread).unwrap().overrun(), 0);
+        assert_eq!(timer.overrun(), 0);
+    }
+
+    #[test]
+    fn cancel() {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let thread = Thread::create_linux(&proc).unwrap();
+        let (timer, deadline) = periodic_timer(&thread);
+
+        // the callback of the last setting is ignored after the timer is set again
+        let value = ITimerSpec {
+            interval: Duration::ZERO.into(),
+            value: (2000 * SECOND).into(),
+        };
+        timer.set(value, false);
+        let new_deadline = timer.inner.lock().deadline.unwrap();
+        timer.expire(deadline, 1);
+        assert!(pending(&thread).is_none());
+        assert_eq!(timer.inner.lock().deadline, Some(new_deadline));
+
+        // a one-shot timer is disarmed once it expires
+        timer.expire(new_deadline, 2);
+        assert!(pending(&thread).is_some());
+        assert_eq!(timer.inner.lock().deadline, None);
+
+        // nothing expires after the timer is disarmed
+        thread.lock_linux().dequeue_signal();
+        timer.set(ITimerSpec::default(), false);
+        timer.expire(new_deadline, 3);
+        assert!(pending(&thread).is_none());
+    }
+}
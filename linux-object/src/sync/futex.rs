@@ -0,0 +1,190 @@
+//! Linux futexes shared between processes, the futex word operations and robust futexes.
+
+use crate::error::{LxError, LxResult};
+use crate::process::ProcessExt;
+use crate::thread::RobustList;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicI32, Ordering};
+use kernel_hal::mem::phys_to_virt;
+use kernel_hal::user::UserInPtr;
+use kernel_hal::VirtAddr;
+use lazy_static::lazy_static;
+use lock::Mutex;
+use zircon_object::object::{KernelObject, KoID};
+use zircon_object::signal::Futex;
+use zircon_object::task::Process;
+use zircon_object::vm::{MMUFlags, VmObject, PAGE_SIZE};
+
+/// There are threads blocked in the kernel on the futex.
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+/// The owner of the futex died without unlocking it.
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+/// The bits of the owner thread ID in the futex word.
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+lazy_static! {
+    /// Futexes in shared mappings, keyed by the VMO and the offset in it.
+    ///
+    /// A futex is only kept alive by the threads waiting on it, and its entry keeps
+    /// the VMO holding the futex word alive until the futex is dropped, even if the memory
+    /// is unmapped by all processes meanwhile.
+    static ref SHARED_FUTEXES: Mutex<BTreeMap<(KoID, usize), (Weak<Futex>, Arc<VmObject>)>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Get the futex at `uaddr` in the address space of `proc`.
+///
+/// A process-shared (`shared`) futex in a shared mapping is identified by the memory
+/// behind `uaddr`, so that all processes mapping the memory get the same futex.
+/// Otherwise the futex is private to the process.
+pub fn get_futex(proc: &Arc<Process>, uaddr: VirtAddr, shared: bool) -> LxResult<Arc<Futex>> {
+    if uaddr % core::mem::size_of::<u32>() != 0 {
+        return Err(LxError::EINVAL);
+    }
+    if shared {
+        if let Some((vmo, offset)) = proc.vmar().find_shared_vmo(uaddr) {
+            let paddr = vmo.commit_page(offset / PAGE_SIZE, MMUFlags::WRITE)?;
+            let key = (vmo.id(), offset);
+            let mut futexes = SHARED_FUTEXES.lock();
+            if let Some(futex) = futexes.get(&key).and_then(|(futex, _)| futex.upgrade()) {
+                return Ok(futex);
+            }
+            futexes.retain(|_, (futex, _)| futex.strong_count() > 0);
+            // the value is accessed by the kernel mapping of the page,
+            // which is the same in the address spaces of all processes
+            let value =
+                unsafe { &*((phys_to_virt(paddr) + offset % PAGE_SIZE) as *const AtomicI32) };
+            let futex = Futex::new(value);
+            futexes.insert(key, (Arc::downgrade(&futex), vmo));
+            return Ok(futex);
+        }
+    }
+    Ok(proc.linux().get_futex(uaddr))
+}
+
+/// Get the futex word at `uaddr` of the current process for atomic operations.
+///
+/// The page is committed and made writable in advance, or `EFAULT` is returned.
+pub fn futex_word(proc: &Arc<Process>, uaddr: VirtAddr) -> LxResult<&'static AtomicI32> {
+    if uaddr % core::mem::size_of::<u32>() != 0 {
+        return Err(LxError::EINVAL);
+    }
+    proc.vmar()
+        .handle_page_fault(uaddr, MMUFlags::READ | MMUFlags::WRITE)
+        .map_err(|_| LxError::EFAULT)?;
+    Ok(unsafe { &*(uaddr as *const AtomicI32) })
+}
+
+/// The operation of `FUTEX_WAKE_OP` encoded in `val3`.
+#[derive(Debug)]
+pub struct FutexWakeOp {
+    op: u32,
+    oparg: i32,
+    cmp: u32,
+    cmparg: i32,
+}
+
+impl FutexWakeOp {
+    /// Use `1 << oparg` as the operand.
+    const OPARG_SHIFT: u32 = 8;
+
+    /// Decode the operation from `val3`.
+    pub fn new(val3: u32) -> LxResult<Self> {
+        // sign extend the 12-bit arguments
+        let sign_extend = |x: u32| ((x << 20) as i32) >> 20;
+        let op = (val3 >> 28) & 0xf;
+        let cmp = (val3 >> 24) & 0xf;
+        let mut oparg = sign_extend((val3 >> 12) & 0xfff);
+        if op & Self::OPARG_SHIFT != 0 {
+            oparg = 1 << (oparg & 31);
+        }
+        if op & !Self::OPARG_SHIFT > 4 || cmp > 5 {
+            return Err(LxError::ENOSYS);
+        }
+        Ok(FutexWakeOp {
+            op: op & !Self::OPARG_SHIFT,
+            oparg,
+            cmp,
+            cmparg: sign_extend(val3 & 0xfff),
+        })
+    }
+
+    /// Atomically apply the operation to the futex `word`,
+    /// return whether the old value satisfies the comparison.
+    pub fn apply(&self, word: &AtomicI32) -> bool {
+        let oparg = self.oparg;
+        let old = match self.op {
+            0 => word.swap(oparg, Ordering::SeqCst),
+            1 => word.fetch_add(oparg, Ordering::SeqCst),
+            2 => word.fetch_or(oparg, Ordering::SeqCst),
+            3 => word.fetch_and(!oparg, Ordering::SeqCst),
+            _ => word.fetch_xor(oparg, Ordering::SeqCst),
+        };
+        match self.cmp {
+            0 => old == self.cmparg,
+            1 => old != self.cmparg,
+            2 => old < self.cmparg,
+            3 => old <= self.cmparg,
+            4 => old > self.cmparg,
+            _ => old >= self.cmparg,
+        }
+    }
+}
+
+/// Release the robust futexes still held by the exiting thread `tid`,
+/// which are linked in the list at `head` (see `set_robust_list(2)`).
+///
+/// The futex words are marked with `FUTEX_OWNER_DIED`, and a waiter of each one is woken up.
+pub fn exit_robust_list(proc: &Arc<Process>, head: UserInPtr<RobustList>, tid: u32) {
+    /// At most this number of entries are handled, in case the list is circular.
+    const ROBUST_LIST_LIMIT: usize = 2048;
+    let head_addr = head.as_addr();
+    let list = match head.read() {
+        Ok(list) => list,
+        Err(_) => return,
+    };
+    // the lowest bit of an entry marks a PI futex
+    let futex_addr = |entry: usize| (entry & !1).wrapping_add(list.off as usize);
+    let mut entry = list.head;
+    for _ in 0..ROBUST_LIST_LIMIT {
+        if entry == head_addr {
+            break;
+        }
+        let next = match UserInPtr::<usize>::from(entry & !1).read() {
+            Ok(next) => next,
+            Err(_) => return,
+        };
+        // the pending one is handled at last
+        if entry != list.pending {
+            handle_futex_death(proc, futex_addr(entry), tid);
+        }
+        entry = next;
+    }
+    if list.pending != 0 {
+        handle_futex_death(proc, futex_addr(list.pending), tid);
+    }
+}
+
+/// Mark the futex at `uaddr` owned by the dead thread `tid`, and wake up a waiter.
+fn handle_futex_death(proc: &Arc<Process>, uaddr: VirtAddr, tid: u32) {
+    let word = match futex_word(proc, uaddr) {
+        Ok(word) => word,
+        Err(_) => return,
+    };
+    let old = word.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |value| {
+        let value = value as u32;
+        if value & FUTEX_TID_MASK == tid {
+            Some(((value & FUTEX_WAITERS) | FUTEX_OWNER_DIED) as i32)
+        } else {
+            None
+        }
+    });
+    if let Ok(old) = old {
+        if old as u32 & FUTEX_WAITERS != 0 {
+            if let Ok(futex) = get_futex(proc, uaddr, true) {
+                futex.wake(1);
+            }
+        }
+    }
+}
@@ -2,7 +2,9 @@
 #![deny(missing_docs)]
 
 pub use self::event_bus::*;
+pub use self::futex::*;
 pub use self::semaphore::*;
 
 mod event_bus;
+mod futex;
 mod semaphore;
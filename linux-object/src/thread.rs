@@ -1,12 +1,23 @@
 //! Linux Thread
 
-use crate::error::SysResult;
+use crate::error::{LxError, LxResult, SysResult};
 use crate::process::ProcessExt;
-use crate::signal::{SigInfo, Signal, SignalStack, SignalUserContext, Sigset};
+use crate::signal::{
+    SigInfo, Signal, SignalActionFlags, SignalCode, SignalStack, SignalStackFlags,
+    SignalUserContext, Sigset, SIG_DFL, SIG_IGN,
+};
+use crate::sync::{exit_robust_list, get_futex, Event, EventBus};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
-use kernel_hal::context::{UserContext, UserContextField};
+use core::convert::TryFrom;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use kernel_hal::context::UserContext;
 use kernel_hal::user::{Out, UserInPtr, UserOutPtr, UserPtr};
 use lock::{Mutex, MutexGuard};
+use zircon_object::object::KernelObject;
 use zircon_object::task::{CurrentThread, Process, Thread};
 use zircon_object::ZxResult;
 
@@ -21,8 +32,8 @@ pub trait ThreadExt {
     /// Get robust list.
     fn get_robust_list(
         &self,
-        _head_ptr: UserOutPtr<UserOutPtr<RobustList>>,
-        _len_ptr: UserOutPtr<usize>,
+        head_ptr: UserOutPtr<UserOutPtr<RobustList>>,
+        len_ptr: UserOutPtr<usize>,
     ) -> SysResult;
     /// Set robust list.
     fn set_robust_list(&self, head: UserInPtr<RobustList>, len: usize);
@@ -39,11 +50,15 @@ impl ThreadExt for Thread {
         let linux_thread = Mutex::new(LinuxThread {
             clear_child_tid: 0.into(),
             signals: Sigset::default(),
+            process_signals: Sigset::default(),
+            signal_infos: BTreeMap::new(),
             signal_mask: Sigset::default(),
+            saved_signal_mask: None,
             signal_alternate_stack: SignalStack::default(),
+            signal_waker: None,
+            signal_event: proc.linux().signal_event(),
             robust_list: 0.into(),
             robust_list_len: 0,
-            handling_signal: None,
         });
         Thread::create_with_ext(proc, "", linux_thread)
     }
@@ -62,11 +77,17 @@ impl ThreadExt for Thread {
 
     fn get_robust_list(
         &self,
-        mut _head_ptr: UserOutPtr<UserOutPtr<RobustList>>,
-        mut _len_ptr: UserOutPtr<usize>,
+        mut head_ptr: UserOutPtr<UserOutPtr<RobustList>>,
+        mut len_ptr: UserOutPtr<usize>,
     ) -> SysResult {
-        _head_ptr = (self.lock_linux().robust_list.as_addr() as *mut RobustList as usize).into();
-        _len_ptr = (&self.lock_linux().robust_list_len as *const usize as usize).into();
+        let linux_thread = self.lock_linux();
+        let (head, len) = (
+            linux_thread.robust_list.as_addr(),
+            linux_thread.robust_list_len,
+        );
+        drop(linux_thread);
+        head_ptr.write(head.into())?;
+        len_ptr.write(len)?;
         Ok(0)
     }
 
@@ -80,22 +101,30 @@ impl CurrentThreadExt for CurrentThread {
     /// Exit current thread for Linux.
     fn exit_linux(&self, _exit_code: i32) {
         let mut linux_thread = self.lock_linux();
-        let clear_child_tid = &mut linux_thread.clear_child_tid;
+        let robust_list = core::mem::replace(&mut linux_thread.robust_list, 0.into());
+        let mut clear_child_tid = core::mem::replace(&mut linux_thread.clear_child_tid, 0.into());
+        drop(linux_thread);
+        // release the robust futexes still held
+        // ref: https://man7.org/linux/man-pages/man2/set_robust_list.2.html
+        if !robust_list.is_null() {
+            exit_robust_list(self.proc(), robust_list, self.id() as u32);
+        }
         // perform futex wake 1
         // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
         if !clear_child_tid.is_null() {
             info!("exit: do futex {:?} wake 1", clear_child_tid);
             let uaddr = clear_child_tid.as_addr();
             clear_child_tid.write(0).unwrap();
-            let futex = self.proc().linux().get_futex(uaddr);
-            futex.wake(1);
+            if let Ok(futex) = get_futex(self.proc(), uaddr, true) {
+                futex.wake(1);
+            }
         }
         self.exit();
     }
 }
 
 /// robust_list
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct RobustList {
     /// head
     pub head: usize,
@@ -112,77 +141,283 @@ pub struct LinuxThread {
     clear_child_tid: UserOutPtr<i32>,
     /// Linux signals
     pub signals: Sigset,
+    /// The pending signals which were sent to the process rather than to the thread
+    process_signals: Sigset,
+    /// Information of the pending signals, indexed by the signal number
+    signal_infos: BTreeMap<u8, SigInfo>,
     /// Signal mask
     pub signal_mask: Sigset,
+    /// The signal mask to restore once the thread returns to user space,
+    /// saved by the syscalls waiting with a temporary mask such as `epoll_pwait`
+    saved_signal_mask: Option<Sigset>,
     /// signal alternate stack
     pub signal_alternate_stack: SignalStack,
+    /// Wakes the blocking syscall of the thread when a signal is queued
+    signal_waker: Option<Waker>,
+    /// Wakes the signalfd readers of the process when a signal is queued
+    signal_event: Arc<Mutex<EventBus>>,
     /// robust_list
     robust_list: UserInPtr<RobustList>,
     robust_list_len: usize,
-    /// handling signals
-    pub handling_signal: Option<u32>,
 }
 
-fn unmodified_check(siginfo: &SigInfo, user_ctx: &SignalUserContext) -> usize {
-    let mut check = 0usize;
-    let default_info = SigInfo::default();
-    let mut default_ctx = SignalUserContext::default();
-    default_ctx.context.set_pc(user_ctx.context.get_pc());
-    check |= (*siginfo != default_info) as usize;
-    check |= ((user_ctx.flags != default_ctx.flags) as usize) << 1;
-    check |= ((user_ctx.link != default_ctx.link) as usize) << 2;
-    check |= ((user_ctx.stack != default_ctx.stack) as usize) << 3;
-    check |= ((user_ctx._pad != default_ctx._pad) as usize) << 4;
-    check |= ((user_ctx.context != default_ctx.context) as usize) << 5;
-    #[cfg(target_arch = "x86_64")]
-    {
-        check |= ((user_ctx.fpregs_mem != default_ctx.fpregs_mem) as usize) << 6;
-    }
-    check
+impl LinuxThread {
+    /// Restore the registers, the signal mask and the alternate signal stack
+    /// from `uctx` after the signal handler returns
+    pub fn restore_after_handle_signal(&mut self, ctx: &mut UserContext, uctx: &SignalUserContext) {
+        uctx.context.restore(ctx);
+        let mut mask = uctx.sig_mask;
+        mask.remove(Signal::SIGKILL);
+        mask.remove(Signal::SIGSTOP);
+        self.signal_mask = mask;
+        // the stack is disarmed during the handler with `SS_AUTODISARM`
+        let mut stack = uctx.stack;
+        stack.flags.remove(SignalStackFlags::ONSTACK);
+        if (SignalStackFlags::AUTODISARM | SignalStackFlags::DISABLE).contains(stack.flags) {
+            self.signal_alternate_stack = stack;
+        }
+    }
+
+    /// Replace the signal mask with `mask` until the thread returns to user space,
+    /// so that the signals unblocked by `mask` are delivered before the old mask is restored.
+    pub fn set_temporary_mask(&mut self, mut mask: Sigset) {
+        mask.remove(Signal::SIGKILL);
+        mask.remove(Signal::SIGSTOP);
+        let old = core::mem::replace(&mut self.signal_mask, mask);
+        self.saved_signal_mask.get_or_insert(old);
+    }
+
+    /// Take the signal mask saved by [`Self::set_temporary_mask`],
+    /// which is restored by the signal handler instead.
+    pub fn take_saved_mask(&mut self) -> Option<Sigset> {
+        self.saved_signal_mask.take()
+    }
+
+    /// Restore the signal mask saved by [`Self::set_temporary_mask`], if any.
+    pub fn restore_saved_mask(&mut self) {
+        if let Some(mask) = self.saved_signal_mask.take() {
+            self.signal_mask = mask;
+        }
+    }
+
+    /// Queue a signal sent to the process, which may be taken by any thread
+    pub fn queue_process_signal(&mut self, info: SigInfo) {
+        let signal = info.signal();
+        self.queue_signal(info);
+        self.process_signals.insert(signal);
+    }
+
+    /// The pending signals which were sent to the process rather than to the thread
+    pub fn process_signals(&self) -> Sigset {
+        self.process_signals
+    }
+
+    /// Queue a signal with its information
+    pub fn queue_signal(&mut self, info: SigInfo) {
+        let signal = info.signal();
+        self.signals.insert(signal);
+        self.process_signals.remove(signal);
+        self.signal_infos.insert(signal as u8, info);
+        if let Some(waker) = self.signal_waker.take() {
+            waker.wake();
+        }
+        let mut event = self.signal_event.lock();
+        event.set(Event::RECEIVE_SIGNAL);
+        event.clear(Event::RECEIVE_SIGNAL);
+    }
+
+    /// Get the information of `signal` if it is pending
+    pub fn pending_signal_mut(&mut self, signal: Signal) -> Option<&mut SigInfo> {
+        if !self.signals.contains(signal) {
+            return None;
+        }
+        self.signal_infos.get_mut(&(signal as u8))
+    }
+
+    /// Get the first pending signal which is not blocked
+    pub fn next_signal(&self) -> Option<Signal> {
+        self.signals
+            .mask_with(&self.signal_mask)
+            .find_first_signal()
+    }
+
+    /// Take the first pending signal which is not blocked
+    pub fn dequeue_signal(&mut self) -> Option<SigInfo> {
+        let mask = self.signal_mask;
+        self.take_signal(&Sigset::new(!mask.val()))
+    }
+
+    /// Take the first pending signal in `set`
+    pub fn take_signal(&mut self, set: &Sigset) -> Option<SigInfo> {
+        let signal = Sigset::new(self.signals.val() & set.val()).find_first_signal()?;
+        self.signals.remove(signal);
+        self.process_signals.remove(signal);
+        let info = self.signal_infos.remove(&(signal as u8));
+        Some(info.unwrap_or_else(|| SigInfo::new(signal, SignalCode::KERNEL as i32)))
+    }
 }
 
-#[allow(unsafe_code)]
-impl LinuxThread {
-    /// Restore the information after the signal handler returns
-    pub fn restore_after_handle_signal(
-        &mut self,
-        ctx: &mut UserContext,
-        old_ctx: &UserContext,
-        siginfo_ptr: usize,
-        uctx_ptr: usize,
-    ) {
-        let siginfo = unsafe { &*(siginfo_ptr as *const SigInfo) };
-        let user_ctx = unsafe { &*(uctx_ptr as *const SignalUserContext) };
-        let check = unmodified_check(siginfo, user_ctx);
-        if check != 0 {
-            error!("unsupported signal fields : {:b}", check);
-            trace!("uctx = {:x?}", *user_ctx);
-            panic!("unsupported signal fields");
+/// Run `future` in a blocking syscall of `thread` until it completes,
+/// or fails with [`ERESTARTSYS`](LxError::ERESTARTSYS) once a signal to be handled is pending.
+///
+/// The signals blocked by the thread or ignored by the process do not interrupt the syscall.
+/// Syscalls never restarted after a signal handler, such as `nanosleep` and `poll`,
+/// fail with `EINTR` instead.
+pub fn interruptible<F: Future>(thread: &Arc<Thread>, future: F) -> Interruptible<F> {
+    Interruptible {
+        thread: thread.clone(),
+        future: Box::pin(future),
+    }
+}
+
+/// Whether the syscall of `thread` interrupted by the pending signal should be restarted,
+/// i.e. no handler is run for the signal, as a stop signal or a signal discarded
+/// by the tracer, or the handler is installed with `SA_RESTART`.
+pub fn restart_on_signal(thread: &Thread) -> bool {
+    let signal = thread.lock_linux().next_signal();
+    signal.map_or(false, |signal| {
+        let action = thread.proc().linux().signal_action(signal);
+        action.handler == SIG_DFL || action.flags.contains(SignalActionFlags::RESTART)
+    })
+}
+
+/// The future returned by [`interruptible`]
+#[must_use = "future does nothing unless polled/`await`-ed"]
+pub struct Interruptible<F: Future> {
+    thread: Arc<Thread>,
+    future: Pin<Box<F>>,
+}
+
+impl<F: Future> Interruptible<F> {
+    /// Whether a signal not blocked or ignored is pending
+    fn interrupted(&self, waker: &Waker) -> bool {
+        let pending = {
+            let mut linux_thread = self.thread.lock_linux();
+            linux_thread.signal_waker = Some(waker.clone());
+            linux_thread.signals.mask_with(&linux_thread.signal_mask)
+        };
+        let linux = self.thread.proc().linux();
+        (1..=Signal::RTMAX as u8)
+            .filter_map(|i| Signal::try_from(i).ok())
+            .filter(|&signal| pending.contains(signal))
+            .any(|signal| match linux.signal_action(signal).handler {
+                SIG_IGN => false,
+                SIG_DFL => !signal.is_ignored_by_default(),
+                _ => true,
+            })
+    }
+}
+
+impl<F: Future> Future for Interruptible<F> {
+    type Output = LxResult<F::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Poll::Ready(ret) = self.future.as_mut().poll(cx) {
+            self.thread.lock_linux().signal_waker = None;
+            return Poll::Ready(Ok(ret));
         }
-        *ctx = *old_ctx;
-        ctx.set_field(UserContextField::InstrPointer, user_ctx.context.get_pc());
-        self.signal_mask = Sigset::new(user_ctx.sig_mask.val());
-        self.handling_signal = None;
-    }
-
-    /// Get signal info
-    pub fn get_signal_info(&self) -> (Sigset, Sigset, Option<u32>) {
-        (self.signals, self.signal_mask, self.handling_signal)
-    }
-
-    /// Handle signal
-    pub fn handle_signal(&mut self) -> Option<(Signal, Sigset)> {
-        if self.handling_signal.is_none() {
-            let signal = self
-                .signals
-                .mask_with(&self.signal_mask)
-                .find_first_signal();
-            if let Some(signal) = signal {
-                self.handling_signal = Some(signal as u32);
-                self.signals.remove(signal);
-                return Some((signal, self.signal_mask));
-            }
+        if self.interrupted(cx.waker()) {
+            self.thread.lock_linux().signal_waker = None;
+            return Poll::Ready(Err(LxError::ERESTARTSYS));
         }
-        None
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::SignalAction;
+    use core::time::Duration;
+    use rcore_fs_ramfs::RamFS;
+    use zircon_object::task::Job;
+
+    fn create() -> (Arc<Process>, Arc<Thread>) {
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let thread = Thread::create_linux(&proc).unwrap();
+        (proc, thread)
+    }
+
+    fn signal(thread: &Thread, signal: Signal) {
+        let info = SigInfo::new(signal, SignalCode::USER as i32);
+        thread.lock_linux().queue_signal(info);
+    }
+
+    fn handle(proc: &Process, signal: Signal, flags: SignalActionFlags) {
+        let action = SignalAction {
+            handler: 0x1000,
+            flags,
+            ..Default::default()
+        };
+        proc.linux().set_signal_action(signal, action);
+    }
+
+    /// Whether the endless syscall is still waiting after a while
+    async fn waits(thread: &Arc<Thread>) -> bool {
+        let wait = interruptible(thread, core::future::pending::<()>());
+        async_std::future::timeout(Duration::from_millis(10), wait)
+            .await
+            .is_err()
+    }
+
+    #[async_std::test]
+    async fn pending_signal() {
+        let (_proc, thread) = create();
+        assert!(waits(&thread).await);
+
+        // a signal queued while waiting wakes up the syscall
+        let wait = {
+            let thread = thread.clone();
+            async_std::task::spawn(async move {
+                interruptible(&thread, core::future::pending::<()>()).await
+            })
+        };
+        async_std::task::sleep(Duration::from_millis(10)).await;
+        signal(&thread, Signal::SIGTERM);
+        assert!(matches!(wait.await, Err(LxError::ERESTARTSYS)));
+
+        // the completed syscall is not interrupted
+        let ret = interruptible(&thread, async { 1 }).await;
+        assert!(matches!(ret, Ok(1)));
+    }
+
+    #[async_std::test]
+    async fn blocked_or_ignored() {
+        let (proc, thread) = create();
+        thread.lock_linux().signal_mask.insert(Signal::SIGUSR1);
+        signal(&thread, Signal::SIGUSR1);
+        proc.linux().set_signal_action(
+            Signal::SIGUSR2,
+            SignalAction {
+                handler: SIG_IGN,
+                ..Default::default()
+            },
+        );
+        signal(&thread, Signal::SIGUSR2);
+        // ignored by the default action
+        signal(&thread, Signal::SIGCHLD);
+        assert!(waits(&thread).await);
+
+        // unblocking the signal interrupts the next wait
+        thread.lock_linux().signal_mask.remove(Signal::SIGUSR1);
+        assert!(!waits(&thread).await);
+    }
+
+    #[async_std::test]
+    async fn restart() {
+        let (proc, thread) = create();
+        handle(&proc, Signal::SIGUSR1, SignalActionFlags::RESTART);
+        signal(&thread, Signal::SIGUSR1);
+        let ret = interruptible(&thread, core::future::pending::<()>()).await;
+        assert!(matches!(ret, Err(LxError::ERESTARTSYS)));
+        assert!(restart_on_signal(&thread));
+
+        // fails with `EINTR` without `SA_RESTART`
+        thread.lock_linux().dequeue_signal();
+        handle(&proc, Signal::SIGUSR1, SignalActionFlags::empty());
+        signal(&thread, Signal::SIGUSR1);
+        let ret = interruptible(&thread, core::future::pending::<()>()).await;
+        assert!(matches!(ret, Err(LxError::ERESTARTSYS)));
+        assert!(!restart_on_signal(&thread));
     }
 }
@@ -33,6 +33,10 @@ impl TimeVal {
     pub fn to_msec(&self) -> usize {
         self.sec * 1_000 + self.usec / 1_000
     }
+    /// whether the microseconds are in range
+    pub fn is_valid(&self) -> bool {
+        self.usec < 1_000_000
+    }
 }
 
 impl TimeSpec {
@@ -62,6 +66,11 @@ impl TimeSpec {
     pub fn to_msec(&self) -> usize {
         self.sec * 1_000 + self.nsec / 1_000_000
     }
+
+    /// check whether the nanoseconds are in range
+    pub fn is_valid(&self) -> bool {
+        self.nsec < 1_000_000_000
+    }
 }
 
 impl From<Timespec> for TimeSpec {
@@ -88,6 +97,15 @@ impl From<TimeSpec> for Duration {
     }
 }
 
+impl From<Duration> for TimeSpec {
+    fn from(d: Duration) -> Self {
+        Self {
+            sec: d.as_secs() as _,
+            nsec: d.subsec_nanos() as _,
+        }
+    }
+}
+
 impl From<TimeSpec> for TimeVal {
     fn from(t: TimeSpec) -> Self {
         Self {
@@ -97,6 +115,53 @@ impl From<TimeSpec> for TimeVal {
     }
 }
 
+/// ITimerSpec struct for timerfd_settime() and timer_settime()
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ITimerSpec {
+    /// interval for periodic timer
+    pub interval: TimeSpec,
+    /// time until next expiration
+    pub value: TimeSpec,
+}
+
+impl From<TimeVal> for TimeSpec {
+    fn from(t: TimeVal) -> Self {
+        Self {
+            sec: t.sec,
+            nsec: t.usec * 1_000,
+        }
+    }
+}
+
+/// ITimerVal struct for getitimer() and setitimer()
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ITimerVal {
+    /// interval for periodic timer
+    pub interval: TimeVal,
+    /// time until next expiration
+    pub value: TimeVal,
+}
+
+impl From<ITimerSpec> for ITimerVal {
+    fn from(t: ITimerSpec) -> Self {
+        Self {
+            interval: t.interval.into(),
+            value: t.value.into(),
+        }
+    }
+}
+
+impl From<ITimerVal> for ITimerSpec {
+    fn from(t: ITimerVal) -> Self {
+        Self {
+            interval: t.interval.into(),
+            value: t.value.into(),
+        }
+    }
+}
+
 /// RUsage for sys_getrusage()
 /// ignore other fields for now
 #[repr(C)]
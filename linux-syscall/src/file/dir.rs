@@ -1,4 +1,4 @@
-﻿//! Directory operations
+//! Directory operations
 //!
 //! - getcwd
 //! - chdir
@@ -43,6 +43,7 @@ impl Syscall<'_> {
         if info.type_ != FileType::Dir {
             return Err(LxError::ENOTDIR);
         }
+        check_permission(&proc.credentials(), &info, Access::EXEC)?;
         proc.change_directory(path);
         Ok(0)
     }
@@ -65,11 +66,11 @@ impl Syscall<'_> {
 
         let (dir_path, file_name) = split_path(path);
         let proc = self.linux_process();
-        let inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        let (inode, mount) = proc.lookup_mount_at(dirfd, dir_path, true)?;
         if inode.find(file_name).is_ok() {
             return Err(LxError::EEXIST);
         }
-        inode.create(file_name, FileType::Dir, mode as u32)?;
+        proc.create_inode(&inode, &mount, file_name, FileType::Dir, mode as u32)?;
         Ok(0)
     }
     /// Remove a directory.
@@ -80,12 +81,18 @@ impl Syscall<'_> {
 
         let (dir_path, file_name) = split_path(path);
         let proc = self.linux_process();
-        let dir_inode = proc.lookup_inode(dir_path)?;
+        let (dir_inode, dir_mount) = proc.lookup_mount(dir_path)?;
         let file_inode = dir_inode.find(file_name)?;
         if file_inode.metadata()?.type_ != FileType::Dir {
             return Err(LxError::ENOTDIR);
         }
+        if dir_mount.mounted_on(&file_inode).is_some() {
+            return Err(LxError::EBUSY);
+        }
+        proc.check_access(&dir_inode, Access::WRITE | Access::EXEC)?;
+        dir_mount.check_writable()?;
         dir_inode.unlink(file_name)?;
+        fsnotify_delete(&dir_inode, file_name, &file_inode);
         Ok(0)
     }
 
@@ -152,8 +159,13 @@ impl Syscall<'_> {
         let proc = self.linux_process();
         let (new_dir_path, new_file_name) = split_path(newpath);
         let inode = proc.lookup_inode_at(olddirfd, oldpath, true)?;
-        let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
+        let (new_dir_inode, new_dir_mount) = proc.lookup_mount_at(newdirfd, new_dir_path, true)?;
+        proc.check_access(&new_dir_inode, Access::WRITE | Access::EXEC)?;
+        new_dir_mount.check_writable()?;
         new_dir_inode.link(new_file_name, &inode)?;
+        fsnotify_create(&new_dir_inode, new_file_name, &inode);
+        // the number of links is changed
+        fsnotify_attrib(&inode);
         Ok(0)
     }
 
@@ -183,12 +195,15 @@ impl Syscall<'_> {
 
         let proc = self.linux_process();
         let (dir_path, file_name) = split_path(path);
-        let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        let (dir_inode, dir_mount) = proc.lookup_mount_at(dirfd, dir_path, true)?;
         let file_inode = dir_inode.find(file_name)?;
         if file_inode.metadata()?.type_ == FileType::Dir {
             return Err(LxError::EISDIR);
         }
+        proc.check_access(&dir_inode, Access::WRITE | Access::EXEC)?;
+        dir_mount.check_writable()?;
         dir_inode.unlink(file_name)?;
+        fsnotify_delete(&dir_inode, file_name, &file_inode);
         Ok(0)
     }
 
@@ -215,9 +230,26 @@ impl Syscall<'_> {
         let proc = self.linux_process();
         let (old_dir_path, old_file_name) = split_path(oldpath);
         let (new_dir_path, new_file_name) = split_path(newpath);
-        let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
-        let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+        let (old_dir_inode, old_dir_mount) = proc.lookup_mount_at(olddirfd, old_dir_path, false)?;
+        let (new_dir_inode, new_dir_mount) = proc.lookup_mount_at(newdirfd, new_dir_path, false)?;
+        proc.check_access(&old_dir_inode, Access::WRITE | Access::EXEC)?;
+        proc.check_access(&new_dir_inode, Access::WRITE | Access::EXEC)?;
+        old_dir_mount.check_writable()?;
+        new_dir_mount.check_writable()?;
+        let inode = old_dir_inode.find(old_file_name)?;
+        if old_dir_mount.mounted_on(&inode).is_some() {
+            return Err(LxError::EBUSY);
+        }
+        let replaced = new_dir_inode.find(new_file_name).ok();
         old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
+        fsnotify_move(
+            &old_dir_inode,
+            old_file_name,
+            &new_dir_inode,
+            new_file_name,
+            &inode,
+            replaced.as_ref(),
+        );
         Ok(0)
     }
 
@@ -360,5 +392,6 @@ bitflags! {
     pub struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        const EACCESS = 0x200;
     }
 }
@@ -0,0 +1,117 @@
+//! IO event notification facility
+//!
+//! - epoll_create, epoll_create1
+//! - epoll_ctl
+//! - epoll_wait, epoll_pwait
+
+use super::*;
+use core::time::Duration;
+use kernel_hal::timer;
+use linux_object::signal::Sigset;
+use linux_object::thread::ThreadExt;
+
+impl Syscall<'_> {
+    /// Open an epoll file descriptor, `size` is ignored but must be greater than zero
+    pub fn sys_epoll_create(&self, size: isize) -> SysResult {
+        info!("epoll_create: size={}", size);
+        if size <= 0 {
+            return Err(LxError::EINVAL);
+        }
+        self.sys_epoll_create1(0)
+    }
+
+    /// Open an epoll file descriptor, `flags` can only be `EPOLL_CLOEXEC`
+    pub fn sys_epoll_create1(&self, flags: usize) -> SysResult {
+        info!("epoll_create1: flags={:#x}", flags);
+        let flags = OpenFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        if !OpenFlags::CLOEXEC.contains(flags) {
+            return Err(LxError::EINVAL);
+        }
+        let epoll = EpollInstance::new(flags);
+        let fd = self.linux_process().add_file(epoll)?;
+        Ok(fd.into())
+    }
+
+    /// Add, modify, or remove entries in the interest list of the epoll instance `epfd`
+    pub fn sys_epoll_ctl(
+        &self,
+        epfd: FileDesc,
+        op: usize,
+        fd: FileDesc,
+        event: UserInPtr<EpollEvent>,
+    ) -> SysResult {
+        let op = EpollCtlOp::try_from(op).map_err(|_| LxError::EINVAL)?;
+        info!(
+            "epoll_ctl: epfd={:?}, op={:?}, fd={:?}, event={:?}",
+            epfd, op, fd, event
+        );
+        let proc = self.linux_process();
+        let epoll = proc.get_file_like(epfd)?;
+        let file = proc.get_file_like(fd)?;
+        // the event is ignored by EPOLL_CTL_DEL
+        let event = match op {
+            EpollCtlOp::DEL => EpollEvent {
+                events: EpollEvents::empty(),
+                data: 0,
+            },
+            _ => event.read()?,
+        };
+        epoll
+            .downcast_ref::<EpollInstance>()
+            .ok_or(LxError::EINVAL)?
+            .control(op, fd, &file, event)?;
+        Ok(0)
+    }
+
+    /// Wait for events on the epoll instance `epfd`
+    ///
+    /// `timeout_msecs` of -1 blocks indefinitely, and 0 returns immediately.
+    pub async fn sys_epoll_wait(
+        &self,
+        epfd: FileDesc,
+        mut events: UserOutPtr<EpollEvent>,
+        maxevents: usize,
+        timeout_msecs: isize,
+    ) -> SysResult {
+        info!(
+            "epoll_wait: epfd={:?}, events={:?}, maxevents={}, timeout_msecs={}",
+            epfd, events, maxevents, timeout_msecs
+        );
+        if maxevents == 0 || maxevents > i32::MAX as usize {
+            return Err(LxError::EINVAL);
+        }
+        events.check()?;
+        let file = self.linux_process().get_file_like(epfd)?;
+        let epoll = file
+            .downcast_ref::<EpollInstance>()
+            .ok_or(LxError::EINVAL)?;
+        let deadline = match timeout_msecs {
+            t if t < 0 => None,
+            t => Some(timer::deadline_after(Duration::from_millis(t as u64))),
+        };
+        // never restarted after a signal handler
+        let ready = interruptible(self.thread, epoll.wait(maxevents, deadline))
+            .await
+            .map_err(|_| LxError::EINTR)?;
+        events.write_array(&ready)?;
+        Ok(ready.len())
+    }
+
+    /// Same as `epoll_wait`, but replaces the signal mask with `sigmask` while waiting
+    pub async fn sys_epoll_pwait(
+        &self,
+        epfd: FileDesc,
+        events: UserOutPtr<EpollEvent>,
+        maxevents: usize,
+        timeout_msecs: isize,
+        sigmask: UserInPtr<Sigset>,
+    ) -> SysResult {
+        if let Some(mask) = sigmask.read_if_not_null()? {
+            // the old mask is restored when returning to user space,
+            // after the signals unblocked by `sigmask` are delivered
+            self.thread.lock_linux().set_temporary_mask(mask);
+        }
+        self.sys_epoll_wait(epfd, events, maxevents, timeout_msecs)
+            .await
+    }
+}
@@ -4,9 +4,13 @@
 //! - close
 //! - dup2
 //! - pipe
+//! - eventfd
+//! - inotify
+//! - memfd_create
 
 use super::*;
 use alloc::string::String;
+use linux_object::error::LxResult;
 
 impl Syscall<'_> {
     /// Opens or creates a file, depending on the flags passed to the call. Returns an integer with the file descriptor.
@@ -36,26 +40,55 @@ impl Syscall<'_> {
             dir_fd, path, flags, mode
         );
 
-        let inode = if flags.contains(OpenFlags::CREATE) {
+        let (inode, mount, created) = if flags.contains(OpenFlags::CREATE) {
             let (dir_path, file_name) = split_path(path);
             // relative to cwd
-            let dir_inode = proc.lookup_inode_at(dir_fd, dir_path, true)?;
+            let (dir_inode, dir_mount) = proc.lookup_mount_at(dir_fd, dir_path, true)?;
             match dir_inode.find(file_name) {
                 Ok(file_inode) => {
                     if flags.contains(OpenFlags::EXCLUSIVE) {
                         return Err(LxError::EEXIST);
                     }
-                    file_inode
+                    let (inode, mount) = dir_mount.enter(file_inode);
+                    (inode, mount, false)
                 }
                 Err(FsError::EntryNotFound) => {
-                    dir_inode.create(file_name, FileType::File, mode as u32)?
+                    let inode = proc.create_inode(
+                        &dir_inode,
+                        &dir_mount,
+                        file_name,
+                        FileType::File,
+                        mode as u32,
+                    )?;
+                    (inode, dir_mount, true)
                 }
                 Err(e) => return Err(LxError::from(e)),
             }
         } else {
-            proc.lookup_inode_at(dir_fd, path, true)?
+            let (inode, mount) = proc.lookup_mount_at(dir_fd, path, true)?;
+            (inode, mount, false)
         };
-        let file = File::new(inode, flags, path.into());
+        // a new file can be opened with any access mode
+        if !created {
+            let mut access = Access::empty();
+            if flags.readable() {
+                access |= Access::READ;
+            }
+            if flags.writable() || flags.contains(OpenFlags::TRUNCATE) {
+                access |= Access::WRITE;
+            }
+            let info = inode.metadata()?;
+            if info.type_ == FileType::Dir && access.contains(Access::WRITE) {
+                return Err(LxError::EISDIR);
+            }
+            check_permission(&proc.credentials(), &info, access)?;
+            if access.contains(Access::WRITE) {
+                mount.check_writable()?;
+            }
+        }
+        // terminals may be opened as another file, or become the controlling terminal
+        let inode = open_tty(self.zircon_process(), inode, flags)?;
+        let file = File::with_mount(inode, flags, path.into(), mount);
         let fd = proc.add_file(file)?;
         Ok(fd.into())
     }
@@ -123,9 +156,78 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// Creates an eventfd object that can be used as an event wait/notify mechanism.
+    pub fn sys_eventfd(&self, initval: usize) -> SysResult {
+        self.sys_eventfd2(initval, 0)
+    }
+
+    /// Creates an eventfd object that can be used as an event wait/notify mechanism.
+    pub fn sys_eventfd2(&self, initval: usize, flags: usize) -> SysResult {
+        info!("eventfd2: initval={}, flags={:#x}", initval, flags);
+        let flags = EventFdFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        let eventfd = EventFd::new(initval as u32 as u64, flags);
+        let fd = self.linux_process().add_file(eventfd)?;
+        Ok(fd.into())
+    }
+
+    /// Creates an inotify instance, equivalent to `inotify_init1` with no flags.
+    pub fn sys_inotify_init(&self) -> SysResult {
+        self.sys_inotify_init1(0)
+    }
+
+    /// Creates an inotify instance, whose events of the watched files are read from
+    /// the returned file descriptor
+    /// (see [linux man inotify(7)](https://man7.org/linux/man-pages/man7/inotify.7.html)).
+    pub fn sys_inotify_init1(&self, flags: usize) -> SysResult {
+        info!("inotify_init1: flags={:#x}", flags);
+        let flags = InotifyFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        let fd = self.linux_process().add_file(Inotify::new(flags))?;
+        Ok(fd.into())
+    }
+
+    /// Adds a watch on the file `path` to the inotify instance `fd`, or modifies the
+    /// events of the existing watch on it. Returns the watch descriptor.
+    pub fn sys_inotify_add_watch(&self, fd: FileDesc, path: UserInPtr<u8>, mask: u32) -> SysResult {
+        let path = path.as_c_str()?;
+        let mask = InotifyMask::from_bits_truncate(mask);
+        info!(
+            "inotify_add_watch: fd={:?}, path={:?}, mask={:?}",
+            fd, path, mask
+        );
+        let proc = self.linux_process();
+        let inotify = inotify_of(proc, fd)?;
+        let follow = !mask.contains(InotifyMask::DONT_FOLLOW);
+        let inode = proc.lookup_inode_at(FileDesc::CWD, path, follow)?;
+        proc.check_access(&inode, Access::READ)?;
+        let wd = inotify.add_watch(&inode, mask)?;
+        Ok(wd as usize)
+    }
+
+    /// Removes the watch `wd` from the inotify instance `fd`.
+    pub fn sys_inotify_rm_watch(&self, fd: FileDesc, wd: i32) -> SysResult {
+        info!("inotify_rm_watch: fd={:?}, wd={}", fd, wd);
+        inotify_of(self.linux_process(), fd)?.rm_watch(wd)?;
+        Ok(0)
+    }
+
+    /// create an anonymous file living in memory, which can be sealed if `MFD_ALLOW_SEALING`.
+    pub fn sys_memfd_create(&self, name: UserInPtr<u8>, flags: usize) -> SysResult {
+        let name = name.as_c_str()?;
+        info!("memfd_create: name={:?}, flags={:#x}", name, flags);
+        let flags = MemFdFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        if name.len() > MFD_NAME_MAX || flags.contains(MemFdFlags::HUGETLB) {
+            return Err(LxError::EINVAL);
+        }
+        let fd = self.linux_process().add_file(MemFd::new(name, flags))?;
+        Ok(fd.into())
+    }
+
     /// apply or remove an advisory lock on an open file
-    /// TODO: handle operation
-    pub fn sys_flock(&mut self, fd: FileDesc, operation: usize) -> SysResult {
+    ///
+    /// The lock is owned by the open file description, which is shared by the duplicated
+    /// file descriptors and the children, and released when it is closed.
+    /// A blocking wait for the lock is interrupted by signals, and restarted by `SA_RESTART`.
+    pub async fn sys_flock(&mut self, fd: FileDesc, operation: usize) -> SysResult {
         bitflags! {
             struct Operation: u8 {
                 const LOCK_SH = 1;
@@ -134,11 +236,24 @@ impl Syscall<'_> {
                 const LOCK_UN = 8;
             }
         }
-        let operation = Operation::from_bits(operation as u8).unwrap();
+        let operation = Operation::from_bits(operation as u8).ok_or(LxError::EINVAL)?;
         info!("flock: fd: {:?}, operation: {:?}", fd, operation);
-        let proc = self.linux_process();
-
-        proc.get_file(fd)?;
+        let file = self.linux_process().get_file(fd)?;
+        let kind = match operation - Operation::LOCK_NB {
+            Operation::LOCK_SH => Some(LockKind::Shared),
+            Operation::LOCK_EX => Some(LockKind::Exclusive),
+            Operation::LOCK_UN => None,
+            _ => return Err(LxError::EINVAL),
+        };
+        let nonblock = operation.contains(Operation::LOCK_NB);
+        interruptible(self.thread, file.flock(kind, nonblock)).await??;
         Ok(0)
     }
 }
+
+/// The inotify instance of the file descriptor `fd`.
+fn inotify_of(proc: &LinuxProcess, fd: FileDesc) -> LxResult<Arc<Inotify>> {
+    proc.get_file_like(fd)?
+        .downcast_arc::<Inotify>()
+        .map_err(|_| LxError::EINVAL)
+}
@@ -5,12 +5,18 @@
 //! - lseek
 //! - truncate, ftruncate
 //! - sendfile, copy_file_range
+//! - splice, tee, vmsplice
 //! - sync, fsync, fdatasync
 //! - ioctl, fcntl
 //! - access, faccessat
+//! - chmod, fchmod, fchmodat
+//! - chown, fchown, fchownat
+//! - umask
 
 use super::*;
-use linux_object::{process::FsInfo, time::TimeSpec};
+use alloc::vec::Vec;
+use linux_object::error::LxResult;
+use linux_object::time::TimeSpec;
 
 impl Syscall<'_> {
     /// Reads from a specified file using a file descriptor. Before using this call,
@@ -23,7 +29,7 @@ impl Syscall<'_> {
         let proc = self.linux_process();
         let file_like = proc.get_file_like(fd)?;
         let mut buf = vec![0u8; len];
-        let len = file_like.read(&mut buf).await?;
+        let len = interruptible(self.thread, file_like.read(&mut buf)).await??;
         base.write_array(&buf[..len])?;
         Ok(len)
     }
@@ -57,7 +63,7 @@ impl Syscall<'_> {
         let proc = self.linux_process();
         let file_like = proc.get_file_like(fd)?;
         let mut buf = vec![0u8; len];
-        let len = file_like.read_at(offset, &mut buf).await?;
+        let len = interruptible(self.thread, file_like.read_at(offset, &mut buf)).await??;
         base.write_array(&buf[..len])?;
         Ok(len)
     }
@@ -94,7 +100,7 @@ impl Syscall<'_> {
         let proc = self.linux_process();
         let file_like = proc.get_file_like(fd)?;
         let mut buf = vec![0u8; iovs.total_len()];
-        let len = file_like.read(&mut buf).await?;
+        let len = interruptible(self.thread, file_like.read(&mut buf)).await??;
         iovs.write_from_buf(&buf)?;
         Ok(len)
     }
@@ -136,8 +142,11 @@ impl Syscall<'_> {
         info!("lseek: fd={:?}, pos={:?}", fd, pos);
 
         let proc = self.linux_process();
-        let file = proc.get_file(fd)?;
-        let offset = file.seek(pos)?;
+        let file_like = proc.get_file_like(fd)?;
+        let offset = match file_like.downcast_ref::<MemFd>() {
+            Some(memfd) => memfd.seek(pos)?,
+            None => proc.get_file(fd)?.seek(pos)?,
+        };
         Ok(offset as usize)
     }
 
@@ -145,7 +154,12 @@ impl Syscall<'_> {
     pub fn sys_truncate(&self, path: UserInPtr<u8>, len: usize) -> SysResult {
         let path = path.as_c_str()?;
         info!("truncate: path={:?}, len={}", path, len);
-        self.linux_process().lookup_inode(path)?.resize(len)?;
+        let proc = self.linux_process();
+        let (inode, mount) = proc.lookup_mount(path)?;
+        proc.check_access(&inode, Access::WRITE)?;
+        mount.check_writable()?;
+        inode.resize(len)?;
+        fsnotify_modify(&inode);
         Ok(0)
     }
 
@@ -153,20 +167,47 @@ impl Syscall<'_> {
     pub fn sys_ftruncate(&self, fd: FileDesc, len: usize) -> SysResult {
         info!("ftruncate: fd={:?}, len={}", fd, len);
         let proc = self.linux_process();
-        proc.get_file(fd)?.set_len(len as u64)?;
+        let file_like = proc.get_file_like(fd)?;
+        match file_like.downcast_ref::<MemFd>() {
+            Some(memfd) => memfd.set_len(len as u64)?,
+            None => proc.get_file(fd)?.set_len(len as u64)?,
+        }
         Ok(0)
     }
 
     /// copies data between one file descriptor and another.
+    ///
+    /// The data is moved as pages from `in_fd`, which must be a regular file, to `out_fd`.
     pub async fn sys_sendfile(
         &self,
         out_fd: FileDesc,
         in_fd: FileDesc,
-        offset_ptr: UserInOutPtr<u64>,
+        mut offset_ptr: UserInOutPtr<u64>,
         count: usize,
     ) -> SysResult {
-        self.sys_copy_file_range(in_fd, offset_ptr, out_fd, 0.into(), count, 0)
-            .await
+        info!(
+            "sendfile: out={:?}, in={:?}, offset={:?}, count={}",
+            out_fd, in_fd, offset_ptr, count
+        );
+        let proc = self.linux_process();
+        let in_file = proc.get_file(in_fd)?;
+        let out_file = proc.get_file_like(out_fd)?;
+        if in_file.metadata()?.type_ != FileType::File {
+            return Err(LxError::EINVAL);
+        }
+        // a null offset means using and updating the file offset
+        let read_offset = if !offset_ptr.is_null() {
+            offset_ptr.read()?
+        } else {
+            in_file.seek(SeekFrom::Current(0))?
+        };
+        let len = transfer(&*in_file, read_offset, &*out_file, None, count).await?;
+        if !offset_ptr.is_null() {
+            offset_ptr.write(read_offset + len as u64)?;
+        } else {
+            in_file.seek(SeekFrom::Current(len as i64))?;
+        }
+        Ok(len)
     }
 
     /// copies data between one file descriptor and anothe, read from specified offset and write new offset back
@@ -183,68 +224,234 @@ impl Syscall<'_> {
             "copy_file_range: in={:?}, out={:?}, in_offset={:?}, out_offset={:?}, count={}, flags={}",
             in_fd, out_fd, in_offset, out_offset, count, flags
         );
+        if flags != 0 {
+            return Err(LxError::EINVAL);
+        }
         let proc = self.linux_process();
         let in_file = proc.get_file(in_fd)?;
         let out_file = proc.get_file(out_fd)?;
-        let mut buffer = [0u8; 1024];
+        if in_file.metadata()?.type_ != FileType::File
+            || out_file.metadata()?.type_ != FileType::File
+        {
+            return Err(LxError::EINVAL);
+        }
 
         // for in_offset and out_offset
         // null means update file offset
         // non-null means update {in,out}_offset instead
-
-        let mut read_offset = if !in_offset.is_null() {
+        let read_offset = if !in_offset.is_null() {
             in_offset.read()?
         } else {
             in_file.seek(SeekFrom::Current(0))?
         };
-
-        let orig_out_file_offset = out_file.seek(SeekFrom::Current(0))?;
         let write_offset = if !out_offset.is_null() {
-            let offset = out_offset.read()?;
-            out_file.seek(SeekFrom::Start(offset))?
+            Some(out_offset.read()?)
         } else {
-            0
+            None
         };
 
-        // read from specified offset and write new offset back
-        let mut bytes_read = 0;
-        let mut total_written = 0;
-        while bytes_read < count {
-            let len = buffer.len().min(count - bytes_read);
-            let read_len = in_file.read_at(read_offset, &mut buffer[..len]).await?;
-            if read_len == 0 {
-                break;
+        let len = transfer(&*in_file, read_offset, &*out_file, write_offset, count).await?;
+        if !in_offset.is_null() {
+            in_offset.write(read_offset + len as u64)?;
+        } else {
+            in_file.seek(SeekFrom::Current(len as i64))?;
+        }
+        if let Some(offset) = write_offset {
+            out_offset.write(offset + len as u64)?;
+        }
+        Ok(len)
+    }
+
+    /// moves data between two file descriptors, one of which must be a pipe,
+    /// without copying between kernel address space and user address space.
+    ///
+    /// The pipe holds references to the pages of the data, so the data is only
+    /// copied when it enters or leaves the pipes.
+    pub async fn sys_splice(
+        &self,
+        fd_in: FileDesc,
+        mut off_in: UserInOutPtr<u64>,
+        fd_out: FileDesc,
+        mut off_out: UserInOutPtr<u64>,
+        len: usize,
+        flags: usize,
+    ) -> SysResult {
+        let flags = SpliceFlags::from_bits_truncate(flags);
+        info!(
+            "splice: in={:?}, off_in={:?}, out={:?}, off_out={:?}, len={}, flags={:?}",
+            fd_in, off_in, fd_out, off_out, len, flags
+        );
+        let proc = self.linux_process();
+        let file_in = proc.get_file_like(fd_in)?;
+        let file_out = proc.get_file_like(fd_out)?;
+        let inode_in = pipe_inode(&file_in);
+        let inode_out = pipe_inode(&file_out);
+        if (inode_in.is_some() && !off_in.is_null()) || (inode_out.is_some() && !off_out.is_null())
+        {
+            return Err(LxError::ESPIPE);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        match (as_pipe(&inode_in), as_pipe(&inode_out)) {
+            (Some(pipe_in), Some(pipe_out)) => {
+                if pipe_in.same_pipe(pipe_out) {
+                    return Err(LxError::EINVAL);
+                }
+                let nonblock = flags.contains(SpliceFlags::NONBLOCK) || file_in.flags().non_block();
+                let pages = self
+                    .wait_pipe(pipe_in, nonblock, |pipe| pipe.take(len))
+                    .await?;
+                let len = pages.iter().map(PipePage::len).sum::<usize>();
+                pipe_out.push(pages)?;
+                Ok(len)
+            }
+            (Some(pipe_in), None) => {
+                let nonblock = flags.contains(SpliceFlags::NONBLOCK) || file_in.flags().non_block();
+                let offset = if off_out.is_null() {
+                    None
+                } else {
+                    Some(off_out.read()?)
+                };
+                // only the bytes written are removed from the pipe
+                let len = self
+                    .wait_pipe(pipe_in, nonblock, |pipe| {
+                        pipe.take_with(len, |pages| write_pages(&*file_out, offset, pages))
+                    })
+                    .await?;
+                if let Some(offset) = offset {
+                    off_out.write(offset + len as u64)?;
+                }
+                Ok(len)
             }
-            bytes_read += read_len;
-            read_offset += read_len as u64;
-
-            let mut bytes_written = 0;
-            let mut rlen = read_len;
-            while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..(bytes_written + rlen)])?;
-                if write_len == 0 {
-                    info!(
-                        "copy_file_range:END_ERR in={:?}, out={:?}, in_offset={:?}, out_offset={:?}, count={} = bytes_read {}, bytes_written {}, write_len {}",
-                        in_fd, out_fd, in_offset, out_offset, count, bytes_read, bytes_written, write_len
-                    );
-                    return Err(LxError::EBADF);
+            (None, Some(pipe_out)) => {
+                let offset = if off_in.is_null() {
+                    None
+                } else {
+                    Some(off_in.read()?)
+                };
+                let len = len.min(SPLICE_CHUNK);
+                let pages =
+                    interruptible(self.thread, read_pages(&*file_in, offset, len)).await??;
+                let len = pages.iter().map(PipePage::len).sum::<usize>();
+                pipe_out.push(pages)?;
+                if let Some(offset) = offset {
+                    off_in.write(offset + len as u64)?;
                 }
-                bytes_written += write_len;
-                rlen -= write_len;
+                Ok(len)
             }
-            total_written += bytes_written;
+            (None, None) => Err(LxError::EINVAL),
         }
+    }
 
-        if !in_offset.is_null() {
-            in_offset.write(read_offset)?;
-        } else {
-            in_file.seek(SeekFrom::Current(bytes_read as i64))?;
+    /// duplicates up to `len` bytes of data from the pipe `fd_in` to the pipe `fd_out`,
+    /// without consuming the data in `fd_in`.
+    ///
+    /// Both pipes refer to the same pages, nothing is copied.
+    pub async fn sys_tee(
+        &self,
+        fd_in: FileDesc,
+        fd_out: FileDesc,
+        len: usize,
+        flags: usize,
+    ) -> SysResult {
+        let flags = SpliceFlags::from_bits_truncate(flags);
+        info!(
+            "tee: in={:?}, out={:?}, len={}, flags={:?}",
+            fd_in, fd_out, len, flags
+        );
+        let proc = self.linux_process();
+        let file_in = proc.get_file_like(fd_in)?;
+        let inode_in = pipe_inode(&file_in);
+        let inode_out = pipe_inode(&proc.get_file_like(fd_out)?);
+        let (pipe_in, pipe_out) = match (as_pipe(&inode_in), as_pipe(&inode_out)) {
+            (Some(pipe_in), Some(pipe_out)) if !pipe_in.same_pipe(pipe_out) => (pipe_in, pipe_out),
+            _ => return Err(LxError::EINVAL),
+        };
+        if len == 0 {
+            return Ok(0);
+        }
+        let nonblock = flags.contains(SpliceFlags::NONBLOCK) || file_in.flags().non_block();
+        let pages = self
+            .wait_pipe(pipe_in, nonblock, |pipe| pipe.peek(len))
+            .await?;
+        let len = pages.iter().map(PipePage::len).sum::<usize>();
+        pipe_out.push(pages)?;
+        Ok(len)
+    }
+
+    /// copies the user memory described by `iov` into the pipe `fd` if it is the write end,
+    /// or copies the data in the pipe out to the user memory if it is the read end.
+    ///
+    /// The user pages are not referred to by the pipe, since they may be changed
+    /// by the process while the data is still in the pipe.
+    pub async fn sys_vmsplice(
+        &self,
+        fd: FileDesc,
+        iov_ptr: UserInPtr<IoVecOut>,
+        iov_count: usize,
+        flags: usize,
+    ) -> SysResult {
+        let flags = SpliceFlags::from_bits_truncate(flags);
+        info!(
+            "vmsplice: fd={:?}, iov={:?}, count={}, flags={:?}",
+            fd, iov_ptr, iov_count, flags
+        );
+        let iovs = iov_ptr.read_iovecs(iov_count)?;
+        let file = self.linux_process().get_file_like(fd)?;
+        let inode = pipe_inode(&file);
+        let pipe = as_pipe(&inode).ok_or(LxError::EBADF)?;
+        if file.flags().writable() {
+            let mut pages = Vec::new();
+            for iov in iovs.iter().filter(|iov| !iov.is_empty()) {
+                pages.extend(PipePage::copy_from(iov.as_slice()?)?);
+            }
+            let len = pages.iter().map(PipePage::len).sum::<usize>();
+            pipe.push(pages)?;
+            return Ok(len);
         }
-        out_offset.write_if_not_null(write_offset + total_written as u64)?;
-        if !out_offset.is_null() {
-            out_file.seek(SeekFrom::Start(orig_out_file_offset))?;
+        let nonblock = flags.contains(SpliceFlags::NONBLOCK) || file.flags().non_block();
+        let copy_out = |pages: &[PipePage]| -> LxResult<usize> {
+            let mut iovs = iovs.iter().filter(|iov| !iov.is_empty());
+            let (mut buf, mut len) = (&mut [][..], 0);
+            for page in pages.iter() {
+                let mut bytes = page.bytes()?;
+                while !bytes.is_empty() {
+                    if buf.is_empty() {
+                        buf = iovs.next().unwrap().as_mut_slice()?;
+                    }
+                    let copy_len = buf.len().min(bytes.len());
+                    buf[..copy_len].copy_from_slice(&bytes[..copy_len]);
+                    buf = &mut core::mem::take(&mut buf)[copy_len..];
+                    bytes = &bytes[copy_len..];
+                    len += copy_len;
+                }
+            }
+            Ok(len)
+        };
+        self.wait_pipe(pipe, nonblock, |pipe| {
+            pipe.take_with(iovs.total_len(), copy_out)
+        })
+        .await
+    }
+
+    /// Run `op` on the read end `pipe`, which fails with `EAGAIN` if the pipe is empty,
+    /// and wait until there is data in the pipe or the write end is closed to retry it.
+    async fn wait_pipe<T>(
+        &self,
+        pipe: &Pipe,
+        nonblock: bool,
+        mut op: impl FnMut(&Pipe) -> LxResult<T>,
+    ) -> LxResult<T> {
+        loop {
+            match op(pipe) {
+                // `EAGAIN` from the other file is returned if the pipe has data
+                Err(LxError::EAGAIN) if !nonblock && !pipe.poll()?.read => {
+                    interruptible(self.thread, pipe.async_poll()).await??;
+                }
+                result => return result,
+            }
         }
-        Ok(total_written)
     }
 
     /// causes all buffered modifications to file metadata and data to be written to the underlying file systems.
@@ -293,7 +500,7 @@ impl Syscall<'_> {
     /// Manipulate a file descriptor.
     /// - cmd – cmd flag
     /// - arg – additional parameters based on cmd
-    pub fn sys_fcntl(&self, fd: FileDesc, cmd: usize, arg: usize) -> SysResult {
+    pub async fn sys_fcntl(&self, fd: FileDesc, cmd: usize, arg: usize) -> SysResult {
         info!("fcntl: fd={:?}, cmd={}, arg={}", fd, cmd, arg);
         let proc = self.linux_process();
         let file_like = proc.get_file_like(fd)?;
@@ -328,20 +535,60 @@ impl Syscall<'_> {
                     dup.set_flags(flags)?;
                     Ok(new_fd.into())
                 }
-                _ => Err(LxError::EINVAL),
+                FcntlCmd::GETLK | FcntlCmd::OFD_GETLK => {
+                    let file = proc.get_file(fd)?;
+                    let mut ptr = UserInOutPtr::<Flock>::from(arg);
+                    let mut flock = ptr.read()?;
+                    let owner = self.lock_owner(cmd == FcntlCmd::GETLK, &flock)?;
+                    file.get_lock(owner, &mut flock)?;
+                    ptr.write(flock)?;
+                    Ok(0)
+                }
+                FcntlCmd::SETLK | FcntlCmd::SETLKW | FcntlCmd::OFD_SETLK | FcntlCmd::OFD_SETLKW => {
+                    let file = proc.get_file(fd)?;
+                    let flock = UserInPtr::<Flock>::from(arg).read()?;
+                    let posix = cmd == FcntlCmd::SETLK || cmd == FcntlCmd::SETLKW;
+                    let owner = self.lock_owner(posix, &flock)?;
+                    let wait = cmd == FcntlCmd::SETLKW || cmd == FcntlCmd::OFD_SETLKW;
+                    interruptible(self.thread, file.set_lock(owner, &flock, wait)).await??;
+                    Ok(0)
+                }
+                FcntlCmd::ADD_SEALS => {
+                    let memfd = file_like.downcast_ref::<MemFd>().ok_or(LxError::EINVAL)?;
+                    let seals = Seals::from_bits(arg as u32).ok_or(LxError::EINVAL)?;
+                    memfd.add_seals(seals)?;
+                    Ok(0)
+                }
+                FcntlCmd::GET_SEALS => {
+                    let memfd = file_like.downcast_ref::<MemFd>().ok_or(LxError::EINVAL)?;
+                    Ok(memfd.seals().bits() as usize)
+                }
             }
         } else {
             Err(LxError::EINVAL)
         }
     }
 
+    /// The owner of the record locks, the process for the POSIX locks, or the open
+    /// file description for the open file description locks, whose `l_pid` must be 0.
+    fn lock_owner(&self, posix: bool, flock: &Flock) -> LxResult<Option<KoID>> {
+        if posix {
+            Ok(Some(self.zircon_process().id()))
+        } else if flock.l_pid != 0 {
+            Err(LxError::EINVAL)
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Checks whether the calling process can access the file pathname
     pub fn sys_access(&self, path: UserInPtr<u8>, mode: usize) -> SysResult {
         self.sys_faccessat(FileDesc::CWD, path, mode, 0)
     }
 
     /// Check user's permissions of a file relative to a directory file descriptor
-    /// TODO: check permissions based on uid/gid
+    ///
+    /// The check uses the real user and group IDs, unless `AT_EACCESS` is set.
     pub fn sys_faccessat(
         &self,
         dirfd: FileDesc,
@@ -349,19 +596,171 @@ impl Syscall<'_> {
         mode: usize,
         flags: usize,
     ) -> SysResult {
-        // TODO: check permissions based on uid/gid
         let path = path.as_c_str()?;
         let flags = AtFlags::from_bits_truncate(flags);
         info!(
             "faccessat: dirfd={:?}, path={:?}, mode={:#o}, flags={:?}",
             dirfd, path, mode, flags
         );
+        let access = Access::from_bits(mode as u32).ok_or(LxError::EINVAL)?;
         let proc = self.linux_process();
         let follow = !flags.contains(AtFlags::SYMLINK_NOFOLLOW);
-        let _inode = proc.lookup_inode_at(dirfd, path, follow)?;
+        let inode = proc.lookup_inode_at(dirfd, path, follow)?;
+        let cred = proc.credentials();
+        let cred = if flags.contains(AtFlags::EACCESS) {
+            cred
+        } else {
+            cred.real()
+        };
+        check_permission(&cred, &inode.metadata()?, access)?;
+        Ok(0)
+    }
+
+    /// Change permissions of a file
+    pub fn sys_chmod(&self, path: UserInPtr<u8>, mode: usize) -> SysResult {
+        self.sys_fchmodat(FileDesc::CWD, path, mode)
+    }
+
+    /// Change permissions of an opened file
+    pub fn sys_fchmod(&self, fd: FileDesc, mode: usize) -> SysResult {
+        info!("fchmod: fd={:?}, mode={:#o}", fd, mode);
+        let file = self.linux_process().get_file(fd)?;
+        file.check_writable()?;
+        self.chmod_inode(&file.inode(), mode as u32)
+    }
+
+    /// Change permissions of a file relative to a directory file descriptor
+    pub fn sys_fchmodat(&self, dirfd: FileDesc, path: UserInPtr<u8>, mode: usize) -> SysResult {
+        let path = path.as_c_str()?;
+        info!(
+            "fchmodat: dirfd={:?}, path={:?}, mode={:#o}",
+            dirfd, path, mode
+        );
+        let (inode, mount) = self.linux_process().lookup_mount_at(dirfd, path, true)?;
+        mount.check_writable()?;
+        self.chmod_inode(&inode, mode as u32)
+    }
+
+    /// Only the owner of the file or root can change its mode.
+    fn chmod_inode(&self, inode: &Arc<dyn INode>, mode: u32) -> SysResult {
+        let cred = self.linux_process().credentials();
+        let mut info = inode.metadata()?;
+        if !cred.is_root() && cred.euid != info.uid as u32 {
+            return Err(LxError::EPERM);
+        }
+        let mut mode = mode & 0o7777;
+        if !cred.is_root() && !cred.in_group(info.gid as u32) {
+            mode &= !S_ISGID;
+        }
+        info.mode = mode as u16;
+        inode.set_metadata(&info)?;
+        fsnotify_attrib(inode);
+        Ok(0)
+    }
+
+    /// Change ownership of a file
+    pub fn sys_chown(&self, path: UserInPtr<u8>, uid: usize, gid: usize) -> SysResult {
+        self.sys_fchownat(FileDesc::CWD, path, uid, gid, 0)
+    }
+
+    /// Change ownership of a file, do not dereference symbolic links
+    pub fn sys_lchown(&self, path: UserInPtr<u8>, uid: usize, gid: usize) -> SysResult {
+        self.sys_fchownat(
+            FileDesc::CWD,
+            path,
+            uid,
+            gid,
+            AtFlags::SYMLINK_NOFOLLOW.bits(),
+        )
+    }
+
+    /// Change ownership of an opened file
+    pub fn sys_fchown(&self, fd: FileDesc, uid: usize, gid: usize) -> SysResult {
+        info!(
+            "fchown: fd={:?}, uid={}, gid={}",
+            fd, uid as i32, gid as i32
+        );
+        let file = self.linux_process().get_file(fd)?;
+        file.check_writable()?;
+        self.chown_inode(&file.inode(), uid as u32, gid as u32)
+    }
+
+    /// Change ownership of a file relative to a directory file descriptor
+    ///
+    /// If `uid` or `gid` is -1, that ID is not changed.
+    pub fn sys_fchownat(
+        &self,
+        dirfd: FileDesc,
+        path: UserInPtr<u8>,
+        uid: usize,
+        gid: usize,
+        flags: usize,
+    ) -> SysResult {
+        let path = path.as_c_str()?;
+        let flags = AtFlags::from_bits_truncate(flags);
+        info!(
+            "fchownat: dirfd={:?}, path={:?}, uid={}, gid={}, flags={:?}",
+            dirfd, path, uid as i32, gid as i32, flags
+        );
+        let proc = self.linux_process();
+        let inode = if path.is_empty() && flags.contains(AtFlags::EMPTY_PATH) {
+            let file = proc.get_file(dirfd)?;
+            file.check_writable()?;
+            file.inode()
+        } else {
+            let follow = !flags.contains(AtFlags::SYMLINK_NOFOLLOW);
+            let (inode, mount) = proc.lookup_mount_at(dirfd, path, follow)?;
+            mount.check_writable()?;
+            inode
+        };
+        self.chown_inode(&inode, uid as u32, gid as u32)
+    }
+
+    /// Only root can change the owner of a file. The owner can change the group
+    /// to any group it is a member of.
+    ///
+    /// The set-user-ID and set-group-ID bits of a non-directory file are cleared.
+    fn chown_inode(&self, inode: &Arc<dyn INode>, uid: u32, gid: u32) -> SysResult {
+        let cred = self.linux_process().credentials();
+        let mut info = inode.metadata()?;
+        let uid = if uid == u32::MAX {
+            info.uid as u32
+        } else {
+            uid
+        };
+        let gid = if gid == u32::MAX {
+            info.gid as u32
+        } else {
+            gid
+        };
+        if !cred.is_root() {
+            let owner = cred.euid == info.uid as u32;
+            if uid != info.uid as u32 || !owner || (gid != info.gid as u32 && !cred.in_group(gid)) {
+                return Err(LxError::EPERM);
+            }
+        }
+        if info.type_ != FileType::Dir {
+            let mut mode = info.mode as u32 & !S_ISUID;
+            // without group execute, set-group-ID means mandatory locking
+            if mode & 0o010 != 0 {
+                mode &= !S_ISGID;
+            }
+            info.mode = mode as u16;
+        }
+        info.uid = uid as usize;
+        info.gid = gid as usize;
+        inode.set_metadata(&info)?;
+        fsnotify_attrib(inode);
         Ok(0)
     }
 
+    /// Set the file mode creation mask of the process, return the previous mask
+    pub fn sys_umask(&self, mask: usize) -> SysResult {
+        info!("umask: mask={:#o}", mask);
+        let old = self.linux_process().set_umask(mask as u32);
+        Ok(old as usize)
+    }
+
     /// change file timestamps with nanosecond precision
     pub fn sys_utimensat(
         &mut self,
@@ -387,7 +786,9 @@ impl Syscall<'_> {
         let inode = if pathname.is_null() {
             let fd = dirfd;
             info!("futimens: fd: {:?}, times: {:?}", fd, times);
-            proc.get_file(fd)?.inode()
+            let file = proc.get_file(fd)?;
+            file.check_writable()?;
+            file.inode()
         } else {
             let pathname = pathname.as_c_str()?;
             info!(
@@ -401,7 +802,9 @@ impl Syscall<'_> {
             } else {
                 return Err(LxError::EINVAL);
             };
-            proc.lookup_inode_at(dirfd, pathname, follow)?
+            let (inode, mount) = proc.lookup_mount_at(dirfd, pathname, follow)?;
+            mount.check_writable()?;
+            inode
         };
         let mut metadata = inode.metadata()?;
         if times[0].nsec != UTIME_OMIT {
@@ -423,6 +826,7 @@ impl Syscall<'_> {
             };
         }
         inode.set_metadata(&metadata)?;
+        fsnotify_attrib(&inode);
         Ok(0)
     }
 
@@ -436,13 +840,9 @@ impl Syscall<'_> {
         let path = path.as_c_str()?;
         info!("statfs: path={:?}, buf={:?}", path, buf);
 
-        // TODO
-        // 现在 `path` 没用到，因为没实现真正的挂载，不可能搞一个非主要文件系统的路径。
-        // 实现挂载之后，要用 `path` 分辨路径在哪个文件系统里，根据对应文件系统的特性返回统计信息。
-        // （以及根据挂载选项填写 `StatFs::f_flags`！）
-
-        let info = self.linux_process().root_inode().fs().info();
-        buf.write(info.into())?;
+        let proc = self.linux_process();
+        let (_, mount) = proc.lookup_mount(path)?;
+        buf.write(StatFs::new(&mount))?;
         Ok(0)
     }
 
@@ -455,8 +855,13 @@ impl Syscall<'_> {
     pub fn sys_fstatfs(&self, fd: FileDesc, mut buf: UserOutPtr<StatFs>) -> SysResult {
         info!("statfs: fd={:?}, buf={:?}", fd, buf);
 
-        let info = self.linux_process().get_file(fd)?.inode().fs().info();
-        buf.write(info.into())?;
+        let file = self.linux_process().get_file(fd)?;
+        let mount = file
+            .mount()
+            .cloned()
+            .or_else(|| find_mount("/"))
+            .ok_or(LxError::ENOSYS)?;
+        buf.write(StatFs::new(&mount))?;
         Ok(0)
     }
 }
@@ -484,29 +889,98 @@ pub struct StatFs {
 // 保证 `StatFs` 的定义和常见的 linux 一致
 static_assertions::const_assert_eq!(120, core::mem::size_of::<StatFs>());
 
-impl From<FsInfo> for StatFs {
-    fn from(info: FsInfo) -> Self {
+impl StatFs {
+    /// The statistics of the file system of `mount`, with its flags
+    fn new(mount: &Mount) -> Self {
+        /// `f_flags` is valid
+        const ST_VALID: usize = 0x20;
+        let info = mount.fs().info();
         StatFs {
-            // TODO 文件系统的魔数，需要 rcore-fs 提供一个渠道获取
-            // 但是这个似乎并没有什么用处，新的 vfs 相关函数都去掉了，也许永远填个常数就好了
-            f_type: 0,
+            f_type: mount.magic() as _,
             f_bsize: info.bsize as _,
             f_blocks: info.blocks as _,
             f_bfree: info.bfree as _,
             f_bavail: info.bavail as _,
             f_files: info.files as _,
             f_ffree: info.ffree as _,
-            // 一个由 OS 决定的号码，用于区分文件系统
-            f_fsid: (0, 0),
+            f_fsid: (mount.id() as _, 0),
             f_namelen: info.namemax as _,
             f_frsize: info.frsize as _,
-            // TODO 需要先实现挂载
-            f_flags: 0,
+            f_flags: ((mount.flags() & MountFlags::PER_MOUNT).bits() | ST_VALID) as _,
             f_spare: [0; 4],
         }
     }
 }
 
+/// Move at most `count` bytes from `in_file` at `read_offset` to `out_file` as pages.
+///
+/// The pages of `in_file` are written to `out_file` directly,
+/// without going through an intermediate buffer.
+async fn transfer(
+    in_file: &dyn FileLike,
+    read_offset: u64,
+    out_file: &dyn FileLike,
+    write_offset: Option<u64>,
+    count: usize,
+) -> LxResult<usize> {
+    let mut total = 0;
+    while total < count {
+        let len = (count - total).min(SPLICE_CHUNK);
+        let pages = match read_pages(in_file, Some(read_offset + total as u64), len).await {
+            Ok(pages) => pages,
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        };
+        let read_len = pages.iter().map(PipePage::len).sum::<usize>();
+        if read_len == 0 {
+            break;
+        }
+        let written = match write_pages(
+            out_file,
+            write_offset.map(|offset| offset + total as u64),
+            &pages,
+        ) {
+            Ok(written) => written,
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        };
+        total += written;
+        if written < read_len {
+            break;
+        }
+    }
+    Ok(total)
+}
+
+/// The pipe inode of `file`, if it is an end of a pipe.
+fn pipe_inode(file: &Arc<dyn FileLike>) -> Option<Arc<dyn INode>> {
+    let inode = file.downcast_ref::<File>()?.inode();
+    inode.downcast_ref::<Pipe>()?;
+    Some(inode)
+}
+
+/// The pipe behind the inode got from [`pipe_inode`].
+fn as_pipe(inode: &Option<Arc<dyn INode>>) -> Option<&Pipe> {
+    inode.as_ref()?.downcast_ref::<Pipe>()
+}
+
+/// The data moved by `splice` and `sendfile` in a round, the default capacity of a Linux pipe.
+const SPLICE_CHUNK: usize = 0x10000;
+
+bitflags! {
+    /// flags of splice, tee and vmsplice
+    struct SpliceFlags: usize {
+        /// move pages instead of copying, a hint
+        const MOVE = 1;
+        /// do not block on the pipe
+        const NONBLOCK = 2;
+        /// more data will be coming, a hint
+        const MORE = 4;
+        /// the user pages are a gift to the kernel, a hint
+        const GIFT = 8;
+    }
+}
+
 numeric_enum_macro::numeric_enum! {
     #[repr(usize)]
     #[allow(non_camel_case_types)]
@@ -529,7 +1003,17 @@ numeric_enum_macro::numeric_enum! {
         SETLK = 6,
         /// Set record locking info (blocking).
         SETLKW = 7,
+        /// Get open file description locking info.
+        OFD_GETLK = 36,
+        /// Set open file description locking info (non-blocking).
+        OFD_SETLK = 37,
+        /// Set open file description locking info (blocking).
+        OFD_SETLKW = 38,
         /// like F_DUPFD, but additionally set the close-on-exec flag
         DUPFD_CLOEXEC = F_LINUX_SPECIFIC_BASE + 6,
+        /// add seals to a memfd
+        ADD_SEALS = F_LINUX_SPECIFIC_BASE + 9,
+        /// get the seals of a memfd
+        GET_SEALS = F_LINUX_SPECIFIC_BASE + 10,
     }
 }
@@ -2,13 +2,15 @@
 #![deny(missing_docs)]
 use super::*;
 use bitflags::bitflags;
-use linux_object::fs::vfs::{FileType, FsError};
+use linux_object::fs::vfs::{FileType, FsError, INode};
 use linux_object::fs::*;
 
 mod dir;
+mod epoll;
 mod fd;
 #[allow(clippy::module_inception)]
 mod file;
+mod mount;
 mod poll;
 mod stat;
 
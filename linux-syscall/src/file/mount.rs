@@ -0,0 +1,97 @@
+//! Mounting file systems
+//!
+//! - mount
+//! - umount2
+
+use super::*;
+
+/// The magic number callers used to put in the top 16 bits of the flags
+const MS_MGC_VAL: usize = 0xC0ED_0000;
+/// The bits the magic number is in
+const MS_MGC_MSK: usize = 0xffff_0000;
+
+/// Convert the flags of `mount`, dropping the magic number if it is there.
+fn mount_flags(flags: usize) -> MountFlags {
+    let flags = if flags & MS_MGC_MSK == MS_MGC_VAL {
+        flags & !MS_MGC_MSK
+    } else {
+        flags
+    };
+    MountFlags::from_bits_truncate(flags)
+}
+
+impl Syscall<'_> {
+    /// Attach the file system from `source` on the directory `target`
+    /// (see [linux man mount(2)](https://man7.org/linux/man-pages/man2/mount.2.html)).
+    ///
+    /// `fstype` is one of `tmpfs`, `ramfs`, `devtmpfs`, `proc` and `sfs`, which
+    /// is mounted from a block device. `source` and `fstype` are ignored by
+    /// remounts and changes of the propagation type. `data` is not used.
+    pub fn sys_mount(
+        &self,
+        source: UserInPtr<u8>,
+        target: UserInPtr<u8>,
+        fstype: UserInPtr<u8>,
+        flags: usize,
+        data: usize,
+    ) -> SysResult {
+        let source = if source.is_null() {
+            ""
+        } else {
+            source.as_c_str()?
+        };
+        let target = target.as_c_str()?;
+        let fstype = if fstype.is_null() {
+            ""
+        } else {
+            fstype.as_c_str()?
+        };
+        let flags = mount_flags(flags);
+        info!(
+            "mount: source={:?}, target={:?}, fstype={:?}, flags={:?}, data={:#x}",
+            source, target, fstype, flags, data
+        );
+        self.linux_process().mount(source, target, fstype, flags)?;
+        Ok(0)
+    }
+
+    /// Detach the file system mounted on `target`
+    /// (see [linux man umount(2)](https://man7.org/linux/man-pages/man2/umount.2.html)).
+    ///
+    /// The mount is detached at once, the open files keep using the file system.
+    /// `MNT_DETACH` detaches the mounts under `target` together.
+    pub fn sys_umount2(&self, target: UserInPtr<u8>, flags: usize) -> SysResult {
+        let target = target.as_c_str()?;
+        let flags = UmountFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        info!("umount2: target={:?}, flags={:?}", target, flags);
+        self.linux_process().umount(target, flags)?;
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcore_fs_ramfs::RamFS;
+    use zircon_object::task::Job;
+
+    #[test]
+    fn magic_flags() {
+        let flags = mount_flags(MS_MGC_VAL | MountFlags::RDONLY.bits());
+        assert_eq!(flags, MountFlags::RDONLY);
+        // the bits are flags without the magic number
+        let flags = mount_flags(MountFlags::PRIVATE.bits());
+        assert_eq!(flags, MountFlags::PRIVATE);
+
+        let proc = Process::create_linux(&Job::root(), RamFS::new()).unwrap();
+        let proc = proc.linux();
+        proc.root_inode()
+            .create("mnt", FileType::Dir, 0o755)
+            .unwrap();
+        proc.mount("none", "/mnt", "tmpfs", mount_flags(MS_MGC_VAL))
+            .unwrap();
+        let (inode, mount) = proc.lookup_mount("/mnt").unwrap();
+        assert_eq!(mount.fstype(), "tmpfs");
+        assert!(mount.is_root(&inode));
+    }
+}
@@ -13,6 +13,8 @@ use core::task::{Context, Poll};
 use core::time::Duration;
 use kernel_hal::timer;
 use linux_object::fs::{FileDesc, PollEvents};
+use linux_object::signal::Sigset;
+use linux_object::thread::ThreadExt;
 use linux_object::time::*;
 
 impl Syscall<'_> {
@@ -137,7 +139,10 @@ impl Syscall<'_> {
             begin_time_ms,
             syscall: self,
         };
-        let result = future.await;
+        // never restarted after a signal handler
+        let result = interruptible(self.thread, future)
+            .await
+            .unwrap_or(Err(LxError::EINTR));
         ufds.write_array(&polls)?;
         info!("return ufds: {:?}", polls);
         result
@@ -151,19 +156,25 @@ impl Syscall<'_> {
         ufds: UserInOutPtr<PollFd>,
         nfds: usize,
         timeout: UserInPtr<TimeSpec>,
+        sigmask: UserInPtr<Sigset>,
     ) -> SysResult {
         let timeout_msecs = if timeout.is_null() {
             -1
         } else {
-            let timeout = timeout.read().unwrap();
+            let timeout = timeout.read()?;
             info!("sys_ppoll: timeout: {:?}", timeout);
             timeout.to_msec() as isize
         };
-
+        if let Some(mask) = sigmask.read_if_not_null()? {
+            // restored when returning to user space, as `epoll_pwait` does
+            self.thread.lock_linux().set_temporary_mask(mask);
+        }
         self.sys_poll(ufds, nfds, timeout_msecs).await
     }
 
     /// similar to select, but have sigmask argument
+    ///
+    /// `sigset` points to the address and the size of the signal mask to use while waiting.
     pub async fn sys_pselect6(
         &mut self,
         nfds: usize,
@@ -171,8 +182,14 @@ impl Syscall<'_> {
         write: UserInOutPtr<u32>,
         err: UserInOutPtr<u32>,
         timeout: UserInPtr<TimeVal>,
-        _sigset: usize,
+        sigset: UserInPtr<[usize; 2]>,
     ) -> SysResult {
+        if let Some([mask, _size]) = sigset.read_if_not_null()? {
+            if let Some(mask) = UserInPtr::<Sigset>::from(mask).read_if_not_null()? {
+                // restored when returning to user space, as `epoll_pwait` does
+                self.thread.lock_linux().set_temporary_mask(mask);
+            }
+        }
         self.sys_select(nfds, read, write, err, timeout).await
     }
 
@@ -286,7 +303,10 @@ impl Syscall<'_> {
             begin_time_ms,
             syscall: self,
         };
-        future.await
+        // never restarted after a signal handler
+        interruptible(self.thread, future)
+            .await
+            .unwrap_or(Err(LxError::EINTR))
     }
 }
 
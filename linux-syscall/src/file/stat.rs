@@ -27,7 +27,11 @@ impl Syscall<'_> {
     pub fn sys_fstat(&self, fd: FileDesc, mut stat_ptr: UserOutPtr<Stat>) -> SysResult {
         info!("fstat: fd={:?}, stat_ptr={:?}", fd, stat_ptr);
 
-        let meta = self.linux_process().get_file(fd)?.metadata()?;
+        let proc = self.linux_process();
+        let meta = match proc.get_file_like(fd)?.downcast_ref::<MemFd>() {
+            Some(memfd) => memfd.metadata(),
+            None => proc.get_file(fd)?.metadata()?,
+        };
         stat_ptr.write(meta.into())?;
         Ok(0)
     }
@@ -1,21 +1,41 @@
 use super::*;
-use bitflags::*;
+use core::future::Future;
+use core::time::Duration;
+use kernel_hal::timer::timer_now;
+use linux_object::error::LxResult;
+use linux_object::fs::{FileLike, OpenFlags};
+use linux_object::signal::SigEvent;
+use linux_object::time::TimeSpec;
 use numeric_enum_macro::numeric_enum;
+use zircon_object::task::ThreadState;
 use zircon_object::vm::*;
+use zircon_object::ZxError;
 
 pub use linux_object::ipc::*;
 
-/// Syscalls of inter-process communication and System V semaphore Set operation.
+/// Syscalls of inter-process communication: System V semaphore sets, shared memory
+/// and message queues, and POSIX message queues.
 ///
 /// # Menu
 ///
 /// - [`semget`](Self::sys_semget)
 /// - [`semop`](Self::sys_semop)
+/// - [`semtimedop`](Self::sys_semtimedop)
 /// - [`semctl`](Self::sys_semctl)
 /// - [`shmget`](Self::sys_shmget)
 /// - [`shmat`](Self::sys_shmat)
 /// - [`shmdt`](Self::sys_shmdt)
 /// - [`shmctl`](Self::sys_shmctl)
+/// - [`msgget`](Self::sys_msgget)
+/// - [`msgsnd`](Self::sys_msgsnd)
+/// - [`msgrcv`](Self::sys_msgrcv)
+/// - [`msgctl`](Self::sys_msgctl)
+/// - [`mq_open`](Self::sys_mq_open)
+/// - [`mq_unlink`](Self::sys_mq_unlink)
+/// - [`mq_timedsend`](Self::sys_mq_timedsend)
+/// - [`mq_timedreceive`](Self::sys_mq_timedreceive)
+/// - [`mq_notify`](Self::sys_mq_notify)
+/// - [`mq_getsetattr`](Self::sys_mq_getsetattr)
 impl Syscall<'_> {
     /// Get a System V semaphore set identifier
     /// (see [linux man semget(2)](https://www.man7.org/linux/man-pages/man2/semget.2.html)).
@@ -57,9 +77,6 @@ impl Syscall<'_> {
     pub fn sys_semget(&self, key: usize, nsems: usize, flags: usize) -> SysResult {
         info!("semget: key: {} nsems: {} flags: {:#x}", key, nsems, flags);
 
-        /// The maximum semaphores per semaphore set
-        const SEMMSL: usize = 256;
-
         if nsems > SEMMSL {
             return Err(LxError::EINVAL);
         }
@@ -89,36 +106,80 @@ impl Syscall<'_> {
     ///
     /// Each operation is performed on the `SemBuf::num`-th semaphore of the semaphore set,
     /// where the first semaphore of the set is numbered 0.
-    /// There are two types of operation, distinguished by the value of `SemBuf::op`.
+    /// There are three types of operation, distinguished by the value of `SemBuf::op`.
+    ///
+    /// - If `op` is positive, it is added to the semaphore value.
+    /// - If `op` is negative, the call blocks until the semaphore value is
+    ///   greater than or equal to the absolute value of `op`, which is then subtracted from it.
+    /// - If `op` is zero, the call blocks until the semaphore value becomes zero.
     ///
-    /// - If `op` is +1, see [`acquire`](linux_object::sync::Semaphore::acquire).
-    /// - If `op` is -1, see [`release`](linux_object::sync::Semaphore::release).
+    /// The operations are performed atomically: either all or none of them are performed.
+    /// If an operation would block and has `IPC_NOWAIT`, the call fails with [`EAGAIN`](LxError::EAGAIN).
     pub async fn sys_semop(&self, id: usize, ops: UserInPtr<SemBuf>, num_ops: usize) -> SysResult {
-        info!("semop: id: {}", id);
-        let ops = ops.as_slice(num_ops)?;
+        info!("semop: id: {}, num_ops: {}", id, num_ops);
+        self.semop(id, ops, num_ops, None).await
+    }
+
+    /// System V semaphore operations with a timeout
+    /// (see [linux man semtimedop(2)](https://www.man7.org/linux/man-pages/man2/semtimedop.2.html)).
+    ///
+    /// `semtimedop` behaves identically to [`sys_semop`](Self::sys_semop),
+    /// except that the blocking is limited by the relative `timeout`.
+    /// If `timeout` is null, it may block indefinitely.
+    /// If the time limit expires, the call fails with [`EAGAIN`](LxError::EAGAIN).
+    pub async fn sys_semtimedop(
+        &self,
+        id: usize,
+        ops: UserInPtr<SemBuf>,
+        num_ops: usize,
+        timeout: UserInPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "semtimedop: id: {}, num_ops: {}, timeout: {:?}",
+            id, num_ops, timeout
+        );
+        let deadline = match timeout.read_if_not_null()? {
+            Some(timeout) if !timeout.is_valid() => return Err(LxError::EINVAL),
+            timeout => timeout.map(|timeout| timer_now() + Duration::from(timeout)),
+        };
+        self.semop(id, ops, num_ops, deadline).await
+    }
 
+    async fn semop(
+        &self,
+        id: usize,
+        ops: UserInPtr<SemBuf>,
+        num_ops: usize,
+        deadline: Option<Duration>,
+    ) -> SysResult {
+        if num_ops == 0 {
+            return Err(LxError::EINVAL);
+        }
+        if num_ops > SEMOPM {
+            return Err(LxError::E2BIG);
+        }
+        let ops = ops.read_array(num_ops)?;
         let sem_array = self
             .linux_process()
             .semaphores_get(id)
             .ok_or(LxError::EINVAL)?;
-        sem_array.otime();
-        for &SemBuf { num, op, flags } in ops {
-            let flags = SemFlags::from_bits_truncate(flags);
-            if flags.contains(SemFlags::IPC_NOWAIT) {
-                unimplemented!("Semaphore: semop.IPC_NOWAIT");
-            }
-            let sem = &sem_array[num as usize];
-
-            match op {
-                1 => sem.release(),
-                -1 => sem.acquire().await?,
-                _ => unimplemented!("Semaphore: semop.(Not 1/-1)"),
-            }
-            sem.set_pid(self.zircon_process().id() as usize);
-            if flags.contains(SemFlags::SEM_UNDO) {
-                self.linux_process().semaphores_add_undo(id, num, op);
+        let pid = self.zircon_process().id() as usize;
+        // never restarted after a signal handler
+        let future = interruptible(self.thread, sem_array.operate(&ops, pid));
+        let ret = match deadline {
+            Some(deadline) => {
+                let ret = self
+                    .thread
+                    .blocking_run(future, ThreadState::BlockedFutex, deadline, None)
+                    .await;
+                match ret {
+                    Err(ZxError::TIMED_OUT) => return Err(LxError::EAGAIN),
+                    ret => ret?,
+                }
             }
-        }
+            None => future.await,
+        };
+        ret.map_err(|_| LxError::EINTR)??;
         Ok(0)
     }
 
@@ -130,27 +191,30 @@ impl Syscall<'_> {
     /// or on the `num`-th semaphore of that set
     /// (The semaphores in a set are numbered starting at 0).
     ///
-    /// TODO
+    /// The argument `arg` is a value for `SETVAL`, or a pointer to
+    /// a `semid_ds` for `IPC_STAT` and `IPC_SET`, an array of `u16` for `GETALL` and `SETALL`,
+    /// or a `seminfo` for `IPC_INFO` and `SEM_INFO`.
     pub fn sys_semctl(&self, id: usize, num: usize, cmd: usize, arg: usize) -> SysResult {
         info!(
             "semctl: id: {}, num: {}, cmd: {} arg: {:#x}",
             id, num, cmd, arg
         );
-        let sem_array = self
-            .linux_process()
-            .semaphores_get(id)
-            .ok_or(LxError::EINVAL)?;
-
-        let cmd = match SemctlCmds::try_from(cmd) {
+        let cmd = match SemctlCmds::try_from(cmd & !IPC_64) {
             Ok(t) => t,
             Err(_) => {
                 error!("invalid semctl cmd: {}", cmd);
                 return Err(LxError::EINVAL);
             }
         };
+        let sem_array = || {
+            self.linux_process()
+                .semaphores_get(id)
+                .ok_or(LxError::EINVAL)
+        };
+        let pid = self.zircon_process().id() as usize;
         match cmd {
             SemctlCmds::IPC_RMID => {
-                sem_array.remove();
+                sem_array()?.remove();
                 self.linux_process().semaphores_remove(id);
                 Ok(0)
             }
@@ -159,31 +223,57 @@ impl Syscall<'_> {
                 let ptr = UserInPtr::from(arg);
                 let ds: SemidDs = ptr.read()?;
                 // update IpcPerm
+                let sem_array = sem_array()?;
                 sem_array.set(&ds);
                 sem_array.ctime();
                 Ok(0)
             }
-            SemctlCmds::IPC_STAT => {
+            SemctlCmds::IPC_STAT | SemctlCmds::SEM_STAT => {
                 // arg is struct semid_ds
                 let mut ptr = UserOutPtr::from(arg);
-                ptr.write(*sem_array.semid_ds.lock())?;
-                Ok(0)
+                ptr.write(*sem_array()?.semid_ds.lock())?;
+                Ok(if cmd == SemctlCmds::SEM_STAT { id } else { 0 })
             }
-            _ => {
-                let sem = &sem_array[num as usize];
-                match cmd {
-                    SemctlCmds::GETPID => Ok(sem.get_pid()),
-                    SemctlCmds::GETVAL => Ok(sem.get() as usize),
-                    SemctlCmds::GETNCNT => Ok(sem.get_ncnt()),
-                    SemctlCmds::GETZCNT => Ok(0),
-                    SemctlCmds::SETVAL => {
-                        sem.set(arg as isize);
-                        sem.set_pid(self.zircon_process().id() as usize);
-                        sem_array.ctime();
-                        Ok(0)
-                    }
-                    _ => unimplemented!("Semaphore Semctl cmd: {:?}", cmd),
+            SemctlCmds::IPC_INFO | SemctlCmds::SEM_INFO => {
+                let mut info = SemInfo {
+                    semmap: SEMMSL as i32,
+                    semmni: SEMMNI as i32,
+                    semmns: (SEMMNI * SEMMSL) as i32,
+                    semmnu: SEMMSL as i32,
+                    semmsl: SEMMSL as i32,
+                    semopm: SEMOPM as i32,
+                    semume: SEMOPM as i32,
+                    semusz: 0,
+                    semvmx: SEMVMX,
+                    semaem: SEMVMX,
+                };
+                if cmd == SemctlCmds::SEM_INFO {
+                    let (sets, sems) = SemArray::used();
+                    info.semusz = sets as i32;
+                    info.semaem = sems as i32;
                 }
+                UserOutPtr::<SemInfo>::from(arg).write(info)?;
+                Ok(0)
+            }
+            SemctlCmds::GETPID => sem_array()?.get_pid(num),
+            SemctlCmds::GETVAL => sem_array()?.get_val(num),
+            SemctlCmds::GETNCNT => sem_array()?.get_ncnt(num),
+            SemctlCmds::GETZCNT => sem_array()?.get_zcnt(num),
+            SemctlCmds::GETALL => {
+                let mut ptr = UserOutPtr::<u16>::from(arg);
+                ptr.write_array(&sem_array()?.get_all())?;
+                Ok(0)
+            }
+            SemctlCmds::SETVAL => {
+                sem_array()?.set_val(num, arg as i32, pid)?;
+                Ok(0)
+            }
+            SemctlCmds::SETALL => {
+                let sem_array = sem_array()?;
+                let ptr = UserInPtr::<u16>::from(arg);
+                let values = ptr.read_array(sem_array.nsems())?;
+                sem_array.set_all(&values, pid)?;
+                Ok(0)
             }
         }
     }
@@ -282,41 +372,413 @@ impl Syscall<'_> {
     /// performs the control operation specified by cmd on the shared memory segment whose identifier is given in id
     pub fn sys_shmctl(&self, id: usize, cmd: usize, buffer: usize) -> SysResult {
         info!("shmctl: id: {}, cmd: {} buffer: {:#x}", id, cmd, buffer);
-        let shm_identifier = self.linux_process().shm_get(id).ok_or(LxError::EINVAL)?;
-        let shm_guard = shm_identifier.guard.lock();
-        let cmd = match ShmctlCmds::try_from(cmd) {
+        let cmd = match ShmctlCmds::try_from(cmd & !IPC_64) {
             Ok(t) => t,
             Err(_) => {
-                error!("invalid semctl cmd: {}", cmd);
+                error!("invalid shmctl cmd: {}", cmd);
                 return Err(LxError::EINVAL);
             }
         };
+        let shm_guard = || {
+            let shm_identifier = self.linux_process().shm_get(id);
+            shm_identifier.map(|shm| shm.guard).ok_or(LxError::EINVAL)
+        };
         match cmd {
+            ShmctlCmds::IPC_INFO => {
+                let mut buffer: UserOutPtr<ShmidInfo> = buffer.into();
+                buffer.write(ShmidInfo {
+                    shmmax: SHMMAX,
+                    shmmin: 1,
+                    shmmni: SHMMNI,
+                    shmseg: SHMMNI,
+                    shmall: SHMMAX / PAGE_SIZE,
+                    __unused: [0; 4],
+                })?;
+                Ok(0)
+            }
+            ShmctlCmds::SHM_INFO => {
+                let mut buffer: UserOutPtr<ShmInfo> = buffer.into();
+                buffer.write(ShmInfo::default())?;
+                Ok(0)
+            }
             ShmctlCmds::IPC_RMID => {
-                shm_guard.remove();
+                shm_guard()?.lock().remove();
                 self.linux_process().shm_pop(id);
                 Ok(0)
             }
             ShmctlCmds::IPC_SET => {
                 let buffer: UserInPtr<ShmidDs> = buffer.into();
                 let set_ds = buffer.read()?;
+                let shm_guard = shm_guard()?;
+                let shm_guard = shm_guard.lock();
                 shm_guard.set(&set_ds);
                 shm_guard.ctime();
                 Ok(0)
             }
             ShmctlCmds::IPC_STAT | ShmctlCmds::SHM_STAT => {
-                let shmid_ds = shm_guard.shmid_ds.lock();
+                let shmid_ds = *shm_guard()?.lock().shmid_ds.lock();
                 let mut buffer: UserOutPtr<ShmidDs> = buffer.into();
-                buffer.write(*shmid_ds)?;
+                buffer.write(shmid_ds)?;
+                Ok(if cmd == ShmctlCmds::SHM_STAT { id } else { 0 })
+            }
+            ShmctlCmds::SHM_LOCK | ShmctlCmds::SHM_UNLOCK => {
+                // the segment is never swapped out, only record the state
+                let shm_guard = shm_guard()?;
+                let shm_guard = shm_guard.lock();
+                let mut shmid_ds = shm_guard.shmid_ds.lock();
+                if cmd == ShmctlCmds::SHM_LOCK {
+                    shmid_ds.perm.mode |= SHM_LOCKED;
+                } else {
+                    shmid_ds.perm.mode &= !SHM_LOCKED;
+                }
                 Ok(0)
             }
-            ShmctlCmds::SHM_INFO => {
-                let mut buffer: UserOutPtr<ShmInfo> = buffer.into();
-                buffer.write(ShmInfo::default())?;
+        }
+    }
+
+    /// Get a System V message queue identifier
+    /// (see [linux man msgget(2)](https://www.man7.org/linux/man-pages/man2/msgget.2.html)).
+    ///
+    /// `msgget` returns the identifier of the message queue associated with `key`.
+    /// A new queue is created if `key` is `IPC_PRIVATE` (zero),
+    /// or no queue is associated with `key` and `IpcGetFlag::CREAT` is in `flags`.
+    /// The least significant 9 bits of `flags` define the permissions of a new queue.
+    ///
+    /// Unlike semaphore sets and shared memory, the identifier is valid in all processes,
+    /// and the queue exists until it is removed by `IPC_RMID`.
+    pub fn sys_msgget(&self, key: usize, flags: usize) -> SysResult {
+        info!("msgget: key: {}, flags: {:#x}", key, flags);
+        let cred = self.linux_process().credentials();
+        MsgQueue::get_or_create(key as u32, flags, cred.euid, cred.egid)
+    }
+
+    /// Send a message to a System V message queue
+    /// (see [linux man msgsnd(2)](https://www.man7.org/linux/man-pages/man2/msgsnd.2.html)).
+    ///
+    /// `msgp` points to a `long` message type followed by `size` bytes of message text.
+    /// The call blocks until there is enough space in the queue,
+    /// or fails with [`EAGAIN`](LxError::EAGAIN) if `MsgFlags::IPC_NOWAIT` is in `flags`.
+    pub async fn sys_msgsnd(
+        &self,
+        id: usize,
+        msgp: VirtAddr,
+        size: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "msgsnd: id: {}, msgp: {:#x}, size: {}, flags: {:#x}",
+            id, msgp, size, flags
+        );
+        let queue = MsgQueue::get(id)?;
+        if size > MSGMAX {
+            return Err(LxError::EINVAL);
+        }
+        let mtype = UserInPtr::<isize>::from(msgp).read()?;
+        if mtype < 1 {
+            return Err(LxError::EINVAL);
+        }
+        let text = UserInPtr::<u8>::from(msgp + core::mem::size_of::<isize>()).read_array(size)?;
+        let flags = MsgFlags::from_bits_truncate(flags);
+        let pid = self.zircon_process().id() as usize;
+        // never restarted after a signal handler
+        let future = queue.send(mtype as usize, text, flags, pid);
+        interruptible(self.thread, future)
+            .await
+            .map_err(|_| LxError::EINTR)??;
+        Ok(0)
+    }
+
+    /// Receive a message from a System V message queue
+    /// (see [linux man msgrcv(2)](https://www.man7.org/linux/man-pages/man2/msgrcv.2.html)).
+    ///
+    /// The message is selected by `msgtyp` (see [`MsgQueue::receive`]),
+    /// its type and at most `size` bytes of text are stored at `msgp`,
+    /// and the length of the text is returned.
+    ///
+    /// - If the text is longer than `size`, it is truncated with `MsgFlags::MSG_NOERROR`,
+    ///   otherwise the call fails with [`E2BIG`](LxError::E2BIG).
+    /// - If there is no such message, the call blocks,
+    ///   or fails with [`ENOMSG`](LxError::ENOMSG) if `MsgFlags::IPC_NOWAIT` is in `flags`.
+    pub async fn sys_msgrcv(
+        &self,
+        id: usize,
+        msgp: VirtAddr,
+        size: usize,
+        msgtyp: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "msgrcv: id: {}, msgp: {:#x}, size: {}, msgtyp: {}, flags: {:#x}",
+            id, msgp, size, msgtyp as isize, flags
+        );
+        let queue = MsgQueue::get(id)?;
+        if (size as isize) < 0 {
+            return Err(LxError::EINVAL);
+        }
+        let flags = MsgFlags::from_bits_truncate(flags);
+        if flags.contains(MsgFlags::MSG_COPY) {
+            return Err(LxError::ENOSYS);
+        }
+        let pid = self.zircon_process().id() as usize;
+        // never restarted after a signal handler
+        let future = queue.receive(msgtyp as isize, size, flags, pid);
+        let (mtype, text) = interruptible(self.thread, future)
+            .await
+            .map_err(|_| LxError::EINTR)??;
+        UserOutPtr::<isize>::from(msgp).write(mtype as isize)?;
+        UserOutPtr::<u8>::from(msgp + core::mem::size_of::<isize>()).write_array(&text)?;
+        Ok(text.len())
+    }
+
+    /// System V message queue control operations
+    /// (see [linux man msgctl(2)](https://www.man7.org/linux/man-pages/man2/msgctl.2.html)).
+    ///
+    /// `msgctl` performs the control operation specified by `cmd` on the queue `id`.
+    /// Only the owner, the creator or a privileged process may set or remove the queue,
+    /// and only a privileged process may raise `msg_qbytes` over `MSGMNB`.
+    pub fn sys_msgctl(&self, id: usize, cmd: usize, buffer: usize) -> SysResult {
+        info!("msgctl: id: {}, cmd: {}, buffer: {:#x}", id, cmd, buffer);
+        let cmd = match MsgctlCmds::try_from(cmd & !IPC_64) {
+            Ok(t) => t,
+            Err(_) => {
+                error!("invalid msgctl cmd: {}", cmd);
+                return Err(LxError::EINVAL);
+            }
+        };
+        let queue = MsgQueue::get(id)?;
+        let ds = queue.stat();
+        let cred = self.linux_process().credentials();
+        let is_owner = cred.is_root() || cred.euid == ds.perm.uid || cred.euid == ds.perm.cuid;
+        match cmd {
+            MsgctlCmds::IPC_RMID => {
+                if !is_owner {
+                    return Err(LxError::EPERM);
+                }
+                MsgQueue::remove(id)?;
+                Ok(0)
+            }
+            MsgctlCmds::IPC_SET => {
+                let buffer: UserInPtr<MsqidDs> = buffer.into();
+                let set_ds = buffer.read()?;
+                if !is_owner || (set_ds.qbytes > MSGMNB.max(ds.qbytes) && !cred.is_root()) {
+                    return Err(LxError::EPERM);
+                }
+                queue.set(&set_ds)?;
                 Ok(0)
             }
-            _ => unimplemented!("Semaphore Semctl cmd: {:?}", cmd),
+            MsgctlCmds::IPC_STAT | MsgctlCmds::MSG_STAT => {
+                let mut buffer: UserOutPtr<MsqidDs> = buffer.into();
+                buffer.write(ds)?;
+                Ok(if cmd == MsgctlCmds::MSG_STAT { id } else { 0 })
+            }
+        }
+    }
+
+    /// Open a POSIX message queue
+    /// (see [linux man mq_open(3)](https://www.man7.org/linux/man-pages/man3/mq_open.3.html)).
+    ///
+    /// The queue `name` is given without the leading slash.
+    /// With `OpenFlags::CREATE` in `oflag`, a new queue is created with the attributes
+    /// in `attr`, or the default attributes if `attr` is NULL.
+    /// Returns a message queue descriptor, which is also a pollable file descriptor.
+    pub fn sys_mq_open(
+        &self,
+        name: UserInPtr<u8>,
+        oflag: usize,
+        mode: usize,
+        attr: UserInPtr<MqAttr>,
+    ) -> SysResult {
+        let name = name.as_c_str()?;
+        info!(
+            "mq_open: name: {:?}, oflag: {:#x}, mode: {:#o}, attr: {:?}",
+            name, oflag, mode, attr
+        );
+        let flags = OpenFlags::from_bits_truncate(oflag);
+        let attr = if flags.contains(OpenFlags::CREATE) {
+            attr.read_if_not_null()?
+        } else {
+            None
+        };
+        let queue = Mqueue::open(name, flags, attr)?;
+        // the message queue descriptors are closed on exec
+        let file_flags = (flags & (OpenFlags::WRONLY | OpenFlags::RDWR | OpenFlags::NON_BLOCK))
+            | OpenFlags::CLOEXEC;
+        let fd = self
+            .linux_process()
+            .add_file(MqueueFile::new(queue, file_flags))?;
+        Ok(fd.into())
+    }
+
+    /// Remove a POSIX message queue
+    /// (see [linux man mq_unlink(3)](https://www.man7.org/linux/man-pages/man3/mq_unlink.3.html)).
+    ///
+    /// The queue is destroyed after all its descriptors are closed.
+    pub fn sys_mq_unlink(&self, name: UserInPtr<u8>) -> SysResult {
+        let name = name.as_c_str()?;
+        info!("mq_unlink: name: {:?}", name);
+        Mqueue::unlink(name)?;
+        Ok(0)
+    }
+
+    /// Send a message to a POSIX message queue
+    /// (see [linux man mq_send(3)](https://www.man7.org/linux/man-pages/man3/mq_send.3.html)).
+    ///
+    /// If the queue is full, the call blocks until there is space or the absolute time
+    /// `abs_timeout` is reached, unless the descriptor is non-blocking.
+    pub async fn sys_mq_timedsend(
+        &self,
+        mqdes: FileDesc,
+        msg: UserInPtr<u8>,
+        len: usize,
+        prio: u32,
+        abs_timeout: UserInPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "mq_timedsend: mqdes: {:?}, msg: {:?}, len: {}, prio: {}, abs_timeout: {:?}",
+            mqdes, msg, len, prio, abs_timeout
+        );
+        let file = self.linux_process().get_file_like(mqdes)?;
+        let mqueue = file.downcast_ref::<MqueueFile>().ok_or(LxError::EBADF)?;
+        let flags = mqueue.flags();
+        if !flags.writable() {
+            return Err(LxError::EBADF);
         }
+        let msg = msg.read_array(len)?;
+        let deadline = mq_deadline(abs_timeout)?;
+        let nonblock = flags.contains(OpenFlags::NON_BLOCK);
+        let pid = self.zircon_process().id() as usize;
+        let uid = self.linux_process().credentials().uid;
+        let send = mqueue.queue().send(&msg, prio, nonblock, pid, uid);
+        self.mq_block(send, deadline).await?;
+        Ok(0)
+    }
+
+    /// Receive a message from a POSIX message queue
+    /// (see [linux man mq_receive(3)](https://www.man7.org/linux/man-pages/man3/mq_receive.3.html)).
+    ///
+    /// The oldest message of the highest priority is stored in `msg`,
+    /// and its priority is stored in `prio` if it is not NULL.
+    /// If the queue is empty, the call blocks until a message arrives or the absolute time
+    /// `abs_timeout` is reached, unless the descriptor is non-blocking.
+    pub async fn sys_mq_timedreceive(
+        &self,
+        mqdes: FileDesc,
+        mut msg: UserOutPtr<u8>,
+        len: usize,
+        mut prio: UserOutPtr<u32>,
+        abs_timeout: UserInPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "mq_timedreceive: mqdes: {:?}, msg: {:?}, len: {}, prio: {:?}, abs_timeout: {:?}",
+            mqdes, msg, len, prio, abs_timeout
+        );
+        let file = self.linux_process().get_file_like(mqdes)?;
+        let mqueue = file.downcast_ref::<MqueueFile>().ok_or(LxError::EBADF)?;
+        let flags = mqueue.flags();
+        if !flags.readable() {
+            return Err(LxError::EBADF);
+        }
+        let deadline = mq_deadline(abs_timeout)?;
+        let nonblock = flags.contains(OpenFlags::NON_BLOCK);
+        let receive = mqueue.queue().receive(len, nonblock);
+        let (data, priority) = self.mq_block(receive, deadline).await?;
+        msg.write_array(&data)?;
+        prio.write_if_not_null(priority)?;
+        Ok(data.len())
+    }
+
+    /// Register for notification when a message is available
+    /// (see [linux man mq_notify(3)](https://www.man7.org/linux/man-pages/man3/mq_notify.3.html)).
+    ///
+    /// The calling process is notified as `sevp` describes,
+    /// when a message arrives at the empty queue and no one is blocked to receive it.
+    /// The registration is removed after the notification, or if `sevp` is NULL.
+    pub fn sys_mq_notify(&self, mqdes: FileDesc, sevp: UserInPtr<SigEvent>) -> SysResult {
+        info!("mq_notify: mqdes: {:?}, sevp: {:?}", mqdes, sevp);
+        let file = self.linux_process().get_file_like(mqdes)?;
+        let mqueue = file.downcast_ref::<MqueueFile>().ok_or(LxError::EBADF)?;
+        let event = sevp.read_if_not_null()?;
+        mqueue.queue().set_notify(self.zircon_process(), event)?;
+        Ok(0)
+    }
+
+    /// Get and set the attributes of a POSIX message queue
+    /// (see [linux man mq_getsetattr(2)](https://www.man7.org/linux/man-pages/man2/mq_getsetattr.2.html)).
+    ///
+    /// Only `O_NONBLOCK` in the flags of the descriptor can be changed.
+    pub fn sys_mq_getsetattr(
+        &self,
+        mqdes: FileDesc,
+        newattr: UserInPtr<MqAttr>,
+        mut oldattr: UserOutPtr<MqAttr>,
+    ) -> SysResult {
+        info!(
+            "mq_getsetattr: mqdes: {:?}, newattr: {:?}, oldattr: {:?}",
+            mqdes, newattr, oldattr
+        );
+        let file = self.linux_process().get_file_like(mqdes)?;
+        let mqueue = file.downcast_ref::<MqueueFile>().ok_or(LxError::EBADF)?;
+        let mut flags = mqueue.flags();
+        let mut attr = mqueue.queue().attr();
+        attr.flags = (flags & OpenFlags::NON_BLOCK).bits() as isize;
+        if let Some(new) = newattr.read_if_not_null()? {
+            let nonblock = OpenFlags::NON_BLOCK.bits() as isize;
+            if new.flags & !nonblock != 0 {
+                return Err(LxError::EINVAL);
+            }
+            flags.set(OpenFlags::NON_BLOCK, new.flags != 0);
+            mqueue.set_flags(flags)?;
+        }
+        oldattr.write_if_not_null(attr)?;
+        Ok(0)
+    }
+
+    /// Run `future` until it completes, or fails with `ETIMEDOUT` at `deadline`,
+    /// or is interrupted by a signal
+    async fn mq_block<T>(
+        &self,
+        future: impl Future<Output = LxResult<T>>,
+        deadline: Option<Duration>,
+    ) -> LxResult<T> {
+        let future = interruptible(self.thread, future);
+        let ret = match deadline {
+            Some(deadline) => {
+                self.thread
+                    .blocking_run(future, ThreadState::BlockedChannel, deadline, None)
+                    .await?
+            }
+            None => future.await,
+        };
+        ret?
+    }
+}
+
+/// The C library may ask for the 64-bit structures of `*ctl` with this bit
+const IPC_64: usize = 0x100;
+
+/// Get the deadline from the absolute timeout of `mq_timedsend` and `mq_timedreceive`
+fn mq_deadline(abs_timeout: UserInPtr<TimeSpec>) -> LxResult<Option<Duration>> {
+    match abs_timeout.read_if_not_null()? {
+        Some(timeout) if !timeout.is_valid() => Err(LxError::EINVAL),
+        timeout => Ok(timeout.map(Duration::from)),
+    }
+}
+
+numeric_enum! {
+    #[repr(usize)]
+    #[derive(Debug, Eq, PartialEq)]
+    #[allow(non_camel_case_types)]
+    /// for the second argument of msgctl(), specified the control operation
+    pub enum MsgctlCmds {
+        /// Immediately remove the message queue, awakening all waiting processes
+        IPC_RMID = 0,
+        /// Write the values of some members of the msqid_ds structure pointed to by buf
+        IPC_SET = 1,
+        /// Copy information from the kernel data structure associated with
+        /// msqid into the msqid_ds structure pointed to by buf.
+        IPC_STAT = 2,
+        /// Returns a msqid_ds structure as for IPC_STAT, with an index instead of msqid
+        MSG_STAT = 11,
     }
 }
 
@@ -333,6 +795,8 @@ numeric_enum! {
         /// Copy information from the kernel data structure associated with
         /// semid into the semid_ds structure pointed to by arg.buf.
         IPC_STAT = 2,
+        /// Return the system-wide semaphore limits in the seminfo structure pointed to by arg.__buf
+        IPC_INFO = 3,
         /// Get the value of sempid
         GETPID = 11,
         /// Get the value of semval
@@ -347,6 +811,10 @@ numeric_enum! {
         SETVAL = 16,
         /// Set semval for all semaphores of the set using arg.array
         SETALL = 17,
+        /// Returns a semid_ds structure as for IPC_STAT, with an index instead of semid
+        SEM_STAT = 18,
+        /// Returns a seminfo structure as for IPC_INFO, with the resources used by semaphores
+        SEM_INFO = 19,
     }
 }
 
@@ -363,6 +831,8 @@ numeric_enum! {
         /// Copy information from the kernel data structure associated with
         /// shmid into the shmid_ds structure pointed to by arg.buf.
         IPC_STAT = 2,
+        /// Return the system-wide shared memory limits in the shminfo structure pointed to by buf
+        IPC_INFO = 3,
         /// Prevent swapping of the shared memory segment
         SHM_LOCK = 11,
         /// Unlock the segment, allowing it to be swapped out.
@@ -375,14 +845,54 @@ numeric_enum! {
     }
 }
 
-/// An operation to be performed on a single semaphore
-///
-/// Ref: <http://man7.org/linux/man-pages/man2/semop.2.html>
+/// The maximum number of semaphore sets
+const SEMMNI: usize = 32000;
+/// The maximum size of a shared memory segment
+const SHMMAX: usize = usize::MAX - (1 << 24);
+/// The maximum number of shared memory segments
+const SHMMNI: usize = 4096;
+/// The segment is locked by `SHM_LOCK`, in `shm_perm.mode`
+const SHM_LOCKED: u32 = 0o2000;
+
+/// seminfo structure for semctl
 #[repr(C)]
-pub struct SemBuf {
-    num: u16,
-    op: i16,
-    flags: i16,
+struct SemInfo {
+    /// Number of entries in semaphore map, unused
+    semmap: i32,
+    /// Maximum number of semaphore sets
+    semmni: i32,
+    /// Maximum number of semaphores in all semaphore sets
+    semmns: i32,
+    /// Maximum number of undo structures, unused
+    semmnu: i32,
+    /// Maximum number of semaphores in a set
+    semmsl: i32,
+    /// Maximum number of operations for semop
+    semopm: i32,
+    /// Maximum number of undo entries per process, unused
+    semume: i32,
+    /// Number of existing semaphore sets with SEM_INFO
+    semusz: i32,
+    /// Maximum semaphore value
+    semvmx: i32,
+    /// Number of existing semaphores with SEM_INFO, or the maximum value of undo
+    semaem: i32,
+}
+
+/// shminfo structure for shmctl
+#[repr(C)]
+struct ShmidInfo {
+    /// Maximum segment size
+    shmmax: usize,
+    /// Minimum segment size
+    shmmin: usize,
+    /// Maximum number of segments
+    shmmni: usize,
+    /// Maximum number of segments that a process can attach
+    shmseg: usize,
+    /// Maximum number of pages of shared memory
+    shmall: usize,
+    __unused: [usize; 4],
 }
 
 /// shm_info structure for shmctl
@@ -398,12 +908,3 @@ struct ShmInfo {
     /// of swapped shared memory pages
     shm_swp: usize,
 }
-
-bitflags! {
-    pub struct SemFlags: i16 {
-        /// For SemOP
-        const IPC_NOWAIT = 0x800;
-        /// it will be automatically undone when the process terminates.
-        const SEM_UNDO = 0x1000;
-    }
-}
@@ -30,7 +30,8 @@ use core::convert::TryFrom;
 use kernel_hal::user::{IoVecIn, IoVecOut, UserInOutPtr, UserInPtr, UserOutPtr};
 use linux_object::error::{LxError, SysResult};
 use linux_object::fs::FileDesc;
-use linux_object::process::{wait_child, wait_child_any, LinuxProcess, ProcessExt, RLimit};
+use linux_object::process::{LinuxProcess, ProcessExt, RLimit};
+use linux_object::thread::interruptible;
 use zircon_object::object::{KernelObject, KoID, Signal};
 use zircon_object::task::{CurrentThread, Process, Thread, ThreadFn};
 use zircon_object::vm::VirtAddr;
@@ -91,8 +92,8 @@ impl Syscall<'_> {
             Sys::READV => self.sys_readv(a0.into(), a1.into(), a2).await,
             Sys::WRITEV => self.sys_writev(a0.into(), a1.into(), a2),
             Sys::SENDFILE => self.sys_sendfile(a0.into(), a1.into(), a2.into(), a3).await,
-            Sys::FCNTL => self.sys_fcntl(a0.into(), a1, a2),
-            Sys::FLOCK => self.sys_flock(a0.into(), a1),
+            Sys::FCNTL => self.sys_fcntl(a0.into(), a1, a2).await,
+            Sys::FLOCK => self.sys_flock(a0.into(), a1).await,
             Sys::FSYNC => self.sys_fsync(a0.into()),
             Sys::FDATASYNC => self.sys_fdatasync(a0.into()),
             Sys::TRUNCATE => self.sys_truncate(a0.into(), a1),
@@ -106,10 +107,10 @@ impl Syscall<'_> {
             Sys::UNLINKAT => self.sys_unlinkat(a0.into(), a1.into(), a2),
             Sys::SYMLINKAT => self.unimplemented("symlinkat", Err(LxError::EACCES)),
             Sys::READLINKAT => self.sys_readlinkat(a0.into(), a1.into(), a2.into(), a3),
-            Sys::FCHMOD => self.unimplemented("fchmod", Ok(0)),
-            Sys::FCHMODAT => self.unimplemented("fchmodat", Ok(0)),
-            Sys::FCHOWN => self.unimplemented("fchown", Ok(0)),
-            Sys::FCHOWNAT => self.unimplemented("fchownat", Ok(0)),
+            Sys::FCHMOD => self.sys_fchmod(a0.into(), a1),
+            Sys::FCHMODAT => self.sys_fchmodat(a0.into(), a1.into(), a2),
+            Sys::FCHOWN => self.sys_fchown(a0.into(), a1, a2),
+            Sys::FCHOWNAT => self.sys_fchownat(a0.into(), a1.into(), a2, a3, a4),
             Sys::FACCESSAT => self.sys_faccessat(a0.into(), a1.into(), a2, a3),
             Sys::DUP => self.sys_dup(a0.into()),
             Sys::DUP3 => self.sys_dup2(a0.into(), a1.into()), // TODO: handle `flags`
@@ -119,36 +120,50 @@ impl Syscall<'_> {
                 self.sys_copy_file_range(a0.into(), a1.into(), a2.into(), a3.into(), a4, a5)
                     .await
             }
+            Sys::SPLICE => {
+                self.sys_splice(a0.into(), a1.into(), a2.into(), a3.into(), a4, a5)
+                    .await
+            }
+            Sys::TEE => self.sys_tee(a0.into(), a1.into(), a2, a3).await,
+            Sys::VMSPLICE => self.sys_vmsplice(a0.into(), a1.into(), a2, a3).await,
 
             // io multiplexing
             Sys::PSELECT6 => {
-                self.sys_pselect6(a0, a1.into(), a2.into(), a3.into(), a4.into(), a5)
+                self.sys_pselect6(a0, a1.into(), a2.into(), a3.into(), a4.into(), a5.into())
+                    .await
+            }
+            Sys::PPOLL => self.sys_ppoll(a0.into(), a1, a2.into(), a3.into()).await,
+            Sys::EPOLL_CREATE1 => self.sys_epoll_create1(a0),
+            Sys::EPOLL_CTL => self.sys_epoll_ctl(a0.into(), a1, a2.into(), a3.into()),
+            Sys::EPOLL_PWAIT => {
+                self.sys_epoll_pwait(a0.into(), a1.into(), a2, a3 as _, a4.into())
                     .await
             }
-            Sys::PPOLL => self.sys_ppoll(a0.into(), a1, a2.into()).await, // ignore sigmask
-            //            Sys::EPOLL_CREATE1 => self.sys_epoll_create1(a0),
-            //            Sys::EPOLL_CTL => self.sys_epoll_ctl(a0, a1, a2, a3.into()),
-            //            Sys::EPOLL_PWAIT => self.sys_epoll_pwait(a0, a1.into(), a2, a3, a4),
-            //            Sys::EVENTFD2 => self.unimplemented("eventfd2", Err(LxError::EACCES)),
+            Sys::EVENTFD2 => self.sys_eventfd2(a0, a1),
+            Sys::INOTIFY_INIT1 => self.sys_inotify_init1(a0),
+            Sys::MEMFD_CREATE => self.sys_memfd_create(a0.into(), a1),
+            Sys::INOTIFY_ADD_WATCH => self.sys_inotify_add_watch(a0.into(), a1.into(), a2 as _),
+            Sys::INOTIFY_RM_WATCH => self.sys_inotify_rm_watch(a0.into(), a1 as _),
+            Sys::SIGNALFD4 => self.sys_signalfd4(a0 as _, a1.into(), a2, a3),
+            Sys::TIMERFD_CREATE => self.sys_timerfd_create(a0, a1),
+            Sys::TIMERFD_SETTIME => self.sys_timerfd_settime(a0.into(), a1, a2.into(), a3.into()),
+            Sys::TIMERFD_GETTIME => self.sys_timerfd_gettime(a0.into(), a1.into()),
 
-            //            Sys::SOCKETPAIR => self.unimplemented("socketpair", Err(LxError::EACCES)),
             // file system
             Sys::STATFS => self.sys_statfs(a0.into(), a1.into()),
             Sys::FSTATFS => self.sys_fstatfs(a0.into(), a1.into()),
             Sys::SYNC => self.sys_sync(),
-            Sys::MOUNT => self.unimplemented("mount", Err(LxError::EACCES)),
-            Sys::UMOUNT2 => self.unimplemented("umount2", Err(LxError::EACCES)),
+            Sys::MOUNT => self.sys_mount(a0.into(), a1.into(), a2.into(), a3, a4),
+            Sys::UMOUNT2 => self.sys_umount2(a0.into(), a1),
 
             // memory
-            Sys::BRK => self.unimplemented("brk", Err(LxError::ENOMEM)),
+            Sys::BRK => self.sys_brk(a0),
             Sys::MMAP => self.sys_mmap(a0, a1, a2, a3, a4.into(), a5 as _).await,
             Sys::MPROTECT => self.sys_mprotect(a0, a1, a2),
             Sys::MUNMAP => self.sys_munmap(a0, a1),
-            Sys::MADVISE => {
-                info!("madvise unimplemented");
-                Ok(0)
-            }
-            Sys::MREMAP => self.unimplemented("mremap", Err(LxError::ENOMEM)),
+            Sys::MADVISE => self.sys_madvise(a0, a1, a2),
+            Sys::MSYNC => self.sys_msync(a0, a1, a2),
+            Sys::MREMAP => self.sys_mremap(a0, a1, a2, a3, a4),
 
             // signal
             Sys::RT_SIGACTION => self.sys_rt_sigaction(a0, a1.into(), a2.into(), a3),
@@ -156,6 +171,7 @@ impl Syscall<'_> {
             Sys::RT_SIGRETURN => self.sys_rt_sigreturn(),
             Sys::SIGALTSTACK => self.sys_sigaltstack(a0.into(), a1.into()),
             Sys::KILL => self.sys_kill(a0 as isize, a1),
+            Sys::RT_SIGQUEUEINFO => self.sys_rt_sigqueueinfo(a0, a1, a2.into()),
 
             // schedule
             Sys::SCHED_YIELD => self.unimplemented("yield", Ok(0)),
@@ -164,15 +180,16 @@ impl Syscall<'_> {
 
             // socket
             Sys::SOCKET => self.sys_socket(a0, a1, a2),
+            Sys::SOCKETPAIR => self.sys_socketpair(a0, a1, a2, a3.into()),
             Sys::CONNECT => self.sys_connect(a0, a1.into(), a2).await,
             Sys::ACCEPT => self.sys_accept(a0, a1.into(), a2.into()).await,
             //            Sys::ACCEPT4 => self.sys_accept(a0, a1.into(), a2.into()), // use accept for accept4
-            Sys::SENDTO => self.sys_sendto(a0, a1.into(), a2, a3, a4.into(), a5),
+            Sys::SENDTO => self.sys_sendto(a0, a1.into(), a2, a3, a4.into(), a5).await,
             Sys::RECVFROM => {
                 self.sys_recvfrom(a0, a1.into(), a2, a3, a4.into(), a5.into())
                     .await
             }
-            Sys::SENDMSG => self.unimplemented("sys_sendmsg(),", Ok(0)),
+            Sys::SENDMSG => self.sys_sendmsg(a0, a1.into(), a2).await,
             Sys::RECVMSG => self.sys_recvmsg(a0, a1.into(), a2).await,
             Sys::SHUTDOWN => self.sys_shutdown(a0, a1),
             Sys::BIND => self.sys_bind(a0, a1.into(), a2),
@@ -184,10 +201,12 @@ impl Syscall<'_> {
             Sys::GETSOCKOPT => self.sys_getsockopt(a0, a1, a2, a3.into(), a4.into()),
 
             // process
-            Sys::EXECVE => self.sys_execve(a0.into(), a1.into(), a2.into()),
+            Sys::CLONE3 => self.sys_clone3(a0.into(), a1).await,
+            Sys::EXECVE => self.sys_execve(a0.into(), a1.into(), a2.into()).await,
             Sys::EXIT => self.sys_exit(a0 as _),
             Sys::EXIT_GROUP => self.sys_exit_group(a0 as _),
             Sys::WAIT4 => self.sys_wait4(a0 as _, a1.into(), a2 as _).await,
+            Sys::PTRACE => self.sys_ptrace(a0, a1, a2, a3),
             Sys::SET_TID_ADDRESS => self.sys_set_tid_address(a0.into()),
             Sys::FUTEX => self.sys_futex(a0, a1 as _, a2 as _, a3, a4, a5 as _).await,
             Sys::GET_ROBUST_LIST => self.sys_get_robust_list(a0 as _, a1.into(), a2.into()),
@@ -196,12 +215,18 @@ impl Syscall<'_> {
             Sys::TGKILL => self.sys_tgkill(a0, a1, a2),
 
             // time
-            Sys::NANOSLEEP => self.sys_nanosleep(a0.into()).await,
+            Sys::NANOSLEEP => self.sys_nanosleep(a0.into(), a1.into()).await,
             Sys::CLOCK_NANOSLEEP => self.sys_clock_nanosleep(a0, a1, a2.into(), a3.into()).await,
-            Sys::SETITIMER => self.unimplemented("setitimer", Ok(0)),
+            Sys::GETITIMER => self.sys_getitimer(a0, a1.into()),
+            Sys::SETITIMER => self.sys_setitimer(a0, a1.into(), a2.into()),
             Sys::GETTIMEOFDAY => self.sys_gettimeofday(a0.into(), a1.into()),
             Sys::CLOCK_GETTIME => self.sys_clock_gettime(a0, a1.into()),
-            Sys::CLOCK_GETRES => self.unimplemented("clock_getres", Ok(0)),
+            Sys::CLOCK_GETRES => self.sys_clock_getres(a0, a1.into()),
+            Sys::TIMER_CREATE => self.sys_timer_create(a0, a1.into(), a2.into()),
+            Sys::TIMER_SETTIME => self.sys_timer_settime(a0, a1, a2.into(), a3.into()),
+            Sys::TIMER_GETTIME => self.sys_timer_gettime(a0, a1.into()),
+            Sys::TIMER_GETOVERRUN => self.sys_timer_getoverrun(a0),
+            Sys::TIMER_DELETE => self.sys_timer_delete(a0),
 
             // sem
             #[cfg(not(target_arch = "mips"))]
@@ -209,6 +234,8 @@ impl Syscall<'_> {
             #[cfg(not(target_arch = "mips"))]
             Sys::SEMOP => self.sys_semop(a0, a1.into(), a2).await,
             #[cfg(not(target_arch = "mips"))]
+            Sys::SEMTIMEDOP => self.sys_semtimedop(a0, a1.into(), a2, a3.into()).await,
+            #[cfg(not(target_arch = "mips"))]
             Sys::SEMCTL => self.sys_semctl(a0, a1, a2, a3),
 
             // shm
@@ -221,34 +248,65 @@ impl Syscall<'_> {
             #[cfg(not(target_arch = "mips"))]
             Sys::SHMCTL => self.sys_shmctl(a0, a1, a2),
 
+            // msg
+            #[cfg(not(target_arch = "mips"))]
+            Sys::MSGGET => self.sys_msgget(a0, a1),
+            #[cfg(not(target_arch = "mips"))]
+            Sys::MSGSND => self.sys_msgsnd(a0, a1, a2, a3).await,
+            #[cfg(not(target_arch = "mips"))]
+            Sys::MSGRCV => self.sys_msgrcv(a0, a1, a2, a3, a4).await,
+            #[cfg(not(target_arch = "mips"))]
+            Sys::MSGCTL => self.sys_msgctl(a0, a1, a2),
+
+            // mqueue
+            Sys::MQ_OPEN => self.sys_mq_open(a0.into(), a1, a2, a3.into()),
+            Sys::MQ_UNLINK => self.sys_mq_unlink(a0.into()),
+            Sys::MQ_TIMEDSEND => {
+                self.sys_mq_timedsend(a0.into(), a1.into(), a2, a3 as _, a4.into())
+                    .await
+            }
+            Sys::MQ_TIMEDRECEIVE => {
+                self.sys_mq_timedreceive(a0.into(), a1.into(), a2, a3.into(), a4.into())
+                    .await
+            }
+            Sys::MQ_NOTIFY => self.sys_mq_notify(a0.into(), a1.into()),
+            Sys::MQ_GETSETATTR => self.sys_mq_getsetattr(a0.into(), a1.into(), a2.into()),
+
             // system
             Sys::GETPID => self.sys_getpid(),
             Sys::GETTID => self.sys_gettid(),
             Sys::UNAME => self.sys_uname(a0.into()),
-            Sys::UMASK => self.unimplemented("umask", Ok(0o777)),
+            Sys::UMASK => self.sys_umask(a0),
             //            Sys::GETRLIMIT => self.sys_getrlimit(),
             //            Sys::SETRLIMIT => self.sys_setrlimit(),
             Sys::GETRUSAGE => self.sys_getrusage(a0, a1.into()),
             Sys::SYSINFO => self.sys_sysinfo(a0.into()),
             Sys::TIMES => self.sys_times(a0.into()),
-            Sys::GETUID => self.unimplemented("getuid", Ok(0)),
-            Sys::GETGID => self.unimplemented("getgid", Ok(0)),
-            Sys::SETUID => self.unimplemented("setuid", Ok(0)),
-            Sys::GETEUID => self.unimplemented("geteuid", Ok(0)),
-            Sys::GETEGID => self.unimplemented("getegid", Ok(0)),
-            Sys::SETPGID => self.unimplemented("setpgid", Ok(0)),
+            Sys::GETUID => self.sys_getuid(),
+            Sys::GETGID => self.sys_getgid(),
+            Sys::SETUID => self.sys_setuid(a0),
+            Sys::SETGID => self.sys_setgid(a0),
+            Sys::GETEUID => self.sys_geteuid(),
+            Sys::GETEGID => self.sys_getegid(),
+            Sys::SETREUID => self.sys_setreuid(a0, a1),
+            Sys::SETREGID => self.sys_setregid(a0, a1),
+            Sys::SETRESUID => self.sys_setresuid(a0, a1, a2),
+            Sys::GETRESUID => self.sys_getresuid(a0.into(), a1.into(), a2.into()),
+            Sys::SETRESGID => self.sys_setresgid(a0, a1, a2),
+            Sys::GETRESGID => self.sys_getresgid(a0.into(), a1.into(), a2.into()),
+            Sys::SETPGID => self.sys_setpgid(a0, a1),
             Sys::GETPPID => self.sys_getppid(),
-            Sys::SETSID => self.unimplemented("setsid", Ok(0)),
-            Sys::GETPGID => self.unimplemented("getpgid", Ok(0)),
-            Sys::GETGROUPS => self.unimplemented("getgroups", Ok(0)),
-            Sys::SETGROUPS => self.unimplemented("setgroups", Ok(0)),
+            Sys::SETSID => self.sys_setsid(),
+            Sys::GETSID => self.sys_getsid(a0),
+            Sys::GETPGID => self.sys_getpgid(a0),
+            Sys::GETGROUPS => self.sys_getgroups(a0, a1.into()),
+            Sys::SETGROUPS => self.sys_setgroups(a0, a1.into()),
             //            Sys::SETPRIORITY => self.sys_set_priority(a0),
             Sys::PRCTL => self.unimplemented("prctl", Ok(0)),
             Sys::MEMBARRIER => self.unimplemented("membarrier", Ok(0)),
             Sys::PRLIMIT64 => self.sys_prlimit64(a0, a1, a2.into(), a3.into()),
             //            Sys::REBOOT => self.sys_reboot(a0 as u32, a1 as u32, a2 as u32, a3.into()),
             Sys::GETRANDOM => self.sys_getrandom(a0.into(), a1 as usize, a2 as u32),
-            Sys::RT_SIGQUEUEINFO => self.unimplemented("rt_sigqueueinfo", Ok(0)),
 
             // kernel module
             //            Sys::INIT_MODULE => self.sys_init_module(a0.into(), a1 as usize, a2.into()),
@@ -277,7 +335,7 @@ impl Syscall<'_> {
         let [a0, a1, a2, a3, a4, _a5] = args;
         debug!("aarch6464_syscall: {:?}, args: {:?}", sys_type, args);
         match sys_type {
-            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a3, a4.into()),
+            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a3, a4.into()).await,
             _ => self.unknown_syscall(sys_type),
         }
     }
@@ -298,7 +356,7 @@ impl Syscall<'_> {
                     .await
             }
             Sys::DUP2 => self.sys_dup2(a0.into(), a1.into()),
-            //            Sys::ALARM => self.unimplemented("alarm", Ok(0)),
+            Sys::ALARM => self.sys_alarm(a0),
             Sys::FORK => self.sys_fork(),
             Sys::VFORK => self.sys_vfork().await,
             Sys::RENAME => self.sys_rename(a0.into(), a1.into()),
@@ -307,13 +365,18 @@ impl Syscall<'_> {
             Sys::LINK => self.sys_link(a0.into(), a1.into()),
             Sys::UNLINK => self.sys_unlink(a0.into()),
             Sys::READLINK => self.sys_readlink(a0.into(), a1.into(), a2),
-            Sys::CHMOD => self.unimplemented("chmod", Ok(0)),
-            Sys::CHOWN => self.unimplemented("chown", Ok(0)),
+            Sys::GETPGRP => self.sys_getpgrp(),
+            Sys::CHMOD => self.sys_chmod(a0.into(), a1),
+            Sys::CHOWN => self.sys_chown(a0.into(), a1, a2),
+            Sys::LCHOWN => self.sys_lchown(a0.into(), a1, a2),
             Sys::ARCH_PRCTL => self.sys_arch_prctl(a0 as _, a1),
             Sys::TIME => self.sys_time(a0.into()),
-            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a4, a3.into()),
-            //            Sys::EPOLL_CREATE => self.sys_epoll_create(a0),
-            //            Sys::EPOLL_WAIT => self.sys_epoll_wait(a0, a1.into(), a2, a3),
+            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a4, a3.into()).await,
+            Sys::EVENTFD => self.sys_eventfd(a0),
+            Sys::INOTIFY_INIT => self.sys_inotify_init(),
+            Sys::SIGNALFD => self.sys_signalfd4(a0 as _, a1.into(), a2, 0),
+            Sys::EPOLL_CREATE => self.sys_epoll_create(a0 as _),
+            Sys::EPOLL_WAIT => self.sys_epoll_wait(a0.into(), a1.into(), a2, a3 as _).await,
             _ => self.unknown_syscall(sys_type),
         }
     }
@@ -323,7 +386,7 @@ impl Syscall<'_> {
         let [a0, a1, a2, a3, a4, _a5] = args;
         match sys_type {
             //Sys::OPEN => self.sys_open(a0.into(), a1, a2),
-            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a3, a4.into()),
+            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a3, a4.into()).await,
             _ => self.unknown_syscall(sys_type),
         }
     }
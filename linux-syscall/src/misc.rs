@@ -1,9 +1,18 @@
 use super::*;
 use bitflags::bitflags;
+use core::future::Future;
+use core::sync::atomic::Ordering;
 use core::time::Duration;
 use kernel_hal::timer::timer_now;
+use linux_object::error::LxResult;
+use linux_object::sync::{
+    futex_word, get_futex, FutexWakeOp, FUTEX_OWNER_DIED, FUTEX_TID_MASK, FUTEX_WAITERS,
+};
 use linux_object::time::*;
+use numeric_enum_macro::numeric_enum;
+use zircon_object::signal::Futex;
 use zircon_object::task::ThreadState;
+use zircon_object::ZxResult;
 
 impl Syscall<'_> {
     #[cfg(target_arch = "x86_64")]
@@ -71,8 +80,8 @@ impl Syscall<'_> {
     /// - `op` -  the operation to perform on the futex
     /// - `val` -  a value whose meaning and purpose depends on op
     /// - `val2` - provides a timeout for the attempt or acts as val2 when op is REQUEUE
-    /// - `uaddr2` - when op is REQUEUE, points to the target futex
-    /// - `_val3` - is not used
+    /// - `uaddr2` - when op is REQUEUE or WAKE_OP, points to the second futex
+    /// - `val3` - the expected value, the bitset or the encoded operation, depending on op
     pub async fn sys_futex(
         &self,
         uaddr: usize,
@@ -80,56 +89,184 @@ impl Syscall<'_> {
         val: u32,
         val2: usize,
         uaddr2: usize,
-        _val3: u32,
+        val3: u32,
     ) -> SysResult {
         debug!(
-            "Futex uaddr: {:#x}, op: {:x}, val: {}, val2(timeout_addr): {:x}",
-            uaddr, op, val, val2,
+            "Futex uaddr: {:#x}, op: {:x}, val: {}, val2(timeout_addr): {:x}, uaddr2: {:#x}, val3: {:#x}",
+            uaddr, op, val, val2, uaddr2, val3,
         );
-        let op = FutexFlags::from_bits_truncate(op);
-        if !op.contains(FutexFlags::PRIVATE) {
-            warn!("process-shared futex is unimplemented");
-            // return Err(LxError::ENOSYS);
-        }
-        let op = op - FutexFlags::PRIVATE;
-        let futex = self.linux_process().get_futex(uaddr);
-        match op {
-            FutexFlags::WAIT => {
-                let future = futex.wait(val as _);
-                let timeout_addr: UserInPtr<TimeSpec> = val2.into();
-                let res = if let Some(timeout) = timeout_addr.read_if_not_null().unwrap() {
-                    self.thread
-                        .blocking_run(
-                            future,
-                            ThreadState::BlockedFutex,
-                            timer_now() + Duration::from(timeout),
-                            None,
-                        )
-                        .await
+        let flags = FutexFlags::from_bits_truncate(op);
+        let cmd = FutexCmd::try_from(op & !FutexFlags::all().bits()).map_err(|_| {
+            warn!("unsupported futex operation: {:#x}", op);
+            LxError::ENOSYS
+        })?;
+        let shared = !flags.contains(FutexFlags::PRIVATE);
+        let proc = self.zircon_process();
+        let futex = get_futex(proc, uaddr, shared)?;
+        match cmd {
+            FutexCmd::WAIT | FutexCmd::WAIT_BITSET => {
+                let bitset = if cmd == FutexCmd::WAIT {
+                    u32::MAX
+                } else {
+                    val3
+                };
+                if bitset == 0 {
+                    return Err(LxError::EINVAL);
+                }
+                // the timeout of FUTEX_WAIT is relative, the others are absolute
+                let deadline = futex_deadline(val2.into(), cmd == FutexCmd::WAIT)?;
+                let future = futex.wait_bitset(val as _, bitset);
+                self.futex_block(future, deadline).await?;
+                Ok(0)
+            }
+            FutexCmd::WAKE | FutexCmd::WAKE_BITSET => {
+                let bitset = if cmd == FutexCmd::WAKE {
+                    u32::MAX
                 } else {
-                    future.await
+                    val3
                 };
-                match res {
-                    Ok(_) => Ok(0),
-                    Err(e) => Err(e.into()),
+                if bitset == 0 {
+                    return Err(LxError::EINVAL);
                 }
+                Ok(futex.wake_bitset(val as _, bitset))
             }
-            FutexFlags::WAKE => Ok(futex.wake(val as _)),
-            FutexFlags::REQUEUE => {
-                let requeue_futex = self.linux_process().get_futex(uaddr2);
-                let res = futex.requeue(0, val as _, val2, &requeue_futex, None, false);
-                match res {
-                    Ok(_) => Ok(0),
-                    Err(e) => Err(e.into()),
+            FutexCmd::REQUEUE | FutexCmd::CMP_REQUEUE => {
+                if (val as i32) < 0 || (val2 as i32) < 0 {
+                    return Err(LxError::EINVAL);
+                }
+                let check_value = cmd == FutexCmd::CMP_REQUEUE;
+                let requeue_futex = get_futex(proc, uaddr2, shared)?;
+                if Arc::ptr_eq(&futex, &requeue_futex) {
+                    // requeueing to the same futex is just waking
+                    if check_value && futex_word(proc, uaddr)?.load(Ordering::SeqCst) != val3 as i32
+                    {
+                        return Err(LxError::EAGAIN);
+                    }
+                    return Ok(futex.wake(val as _));
                 }
+                let count = futex.requeue(
+                    val3 as _,
+                    val as _,
+                    val2 as u32 as _,
+                    &requeue_futex,
+                    None,
+                    check_value,
+                )?;
+                Ok(count)
+            }
+            FutexCmd::WAKE_OP => {
+                let wake_op = FutexWakeOp::new(val3)?;
+                let futex2 = get_futex(proc, uaddr2, shared)?;
+                let matched = wake_op.apply(futex_word(proc, uaddr2)?);
+                let mut count = futex.wake(val as _);
+                if matched {
+                    count += futex2.wake(val2 as u32 as _);
+                }
+                Ok(count)
+            }
+            FutexCmd::LOCK_PI | FutexCmd::TRYLOCK_PI => {
+                let deadline = if cmd == FutexCmd::LOCK_PI {
+                    futex_deadline(val2.into(), false)?
+                } else {
+                    None
+                };
+                self.futex_lock_pi(&futex, uaddr, deadline, cmd == FutexCmd::TRYLOCK_PI)
+                    .await
             }
-            _ => {
-                warn!("unsupported futex operation: {:?}", op);
-                Err(LxError::ENOSYS)
+            FutexCmd::UNLOCK_PI => {
+                let word = futex_word(proc, uaddr)?;
+                let tid = self.thread.id() as u32;
+                if word.load(Ordering::SeqCst) as u32 & FUTEX_TID_MASK != tid {
+                    return Err(LxError::EPERM);
+                }
+                // the woken waiter takes the lock in `futex_lock_pi`
+                word.store(0, Ordering::SeqCst);
+                futex.wake(1);
+                Ok(0)
             }
         }
     }
 
+    /// Acquire the PI futex at `uaddr` for the current thread.
+    ///
+    /// There is no priority to inherit by the owner here, so the waiters are simply
+    /// queued on the futex until the owner unlocks it by `FUTEX_UNLOCK_PI`.
+    async fn futex_lock_pi(
+        &self,
+        futex: &Arc<Futex>,
+        uaddr: usize,
+        deadline: Option<Duration>,
+        try_lock: bool,
+    ) -> SysResult {
+        let word = futex_word(self.zircon_process(), uaddr)?;
+        let tid = self.thread.id() as u32;
+        let mut waited = false;
+        loop {
+            let value = word.load(Ordering::SeqCst) as u32;
+            let owner = value & FUTEX_TID_MASK;
+            if owner == tid {
+                return Err(LxError::EDEADLK);
+            }
+            if owner == 0 {
+                // there may be other waiters after we have waited,
+                // so the owner has to unlock in the kernel
+                let waiters = if waited { FUTEX_WAITERS } else { 0 };
+                let new = tid | waiters | value & (FUTEX_WAITERS | FUTEX_OWNER_DIED);
+                if word
+                    .compare_exchange(value as i32, new as i32, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Ok(0);
+                }
+                continue;
+            }
+            if try_lock {
+                return Err(LxError::EAGAIN);
+            }
+            let expected = value | FUTEX_WAITERS;
+            if word
+                .compare_exchange(
+                    value as i32,
+                    expected as i32,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_err()
+            {
+                continue;
+            }
+            waited = true;
+            match self
+                .futex_block(futex.wait(expected as i32), deadline)
+                .await
+            {
+                // the value has been changed, try again
+                Ok(()) | Err(LxError::EAGAIN) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Block the current thread on the futex `future` until `deadline`,
+    /// or until a signal interrupts it.
+    async fn futex_block(
+        &self,
+        future: impl Future<Output = ZxResult> + Unpin,
+        deadline: Option<Duration>,
+    ) -> LxResult<()> {
+        let future = interruptible(self.thread, future);
+        let ret = match deadline {
+            Some(deadline) => {
+                self.thread
+                    .blocking_run(future, ThreadState::BlockedFutex, deadline, None)
+                    .await?
+            }
+            None => future.await,
+        };
+        ret??;
+        Ok(())
+    }
+
     /// Combines and extends the functionality of setrlimit() and getrlimit()
     pub fn sys_prlimit64(
         &mut self,
@@ -184,23 +321,55 @@ impl Syscall<'_> {
     }
 }
 
+/// Read the timeout of a futex operation at `timeout` as a deadline.
+fn futex_deadline(timeout: UserInPtr<TimeSpec>, relative: bool) -> LxResult<Option<Duration>> {
+    match timeout.read_if_not_null()? {
+        Some(timeout) if !timeout.is_valid() => Err(LxError::EINVAL),
+        Some(timeout) if relative => Ok(Some(timer_now() + Duration::from(timeout))),
+        Some(timeout) => Ok(Some(Duration::from(timeout))),
+        None => Ok(None),
+    }
+}
+
 bitflags! {
-    /// for op argument in futex()
+    /// flags in op argument of futex()
     struct FutexFlags: u32 {
+        /// can be employed with all futex operations, tells the kernel that the futex is process-private and not shared with another process
+        const PRIVATE        = 0x80;
+        /// the timeout is measured against CLOCK_REALTIME instead of CLOCK_MONOTONIC
+        const CLOCK_REALTIME = 0x100;
+    }
+}
+
+numeric_enum! {
+    #[repr(u32)]
+    #[allow(non_camel_case_types)]
+    #[derive(Eq, PartialEq, Debug, Copy, Clone)]
+    /// command in op argument of futex()
+    enum FutexCmd {
         /// tests that the value at the futex word pointed
         /// to by the address uaddr still contains the expected value val,
         /// and if so, then sleeps waiting for a FUTEX_WAKE operation on the futex word.
-        const WAIT      = 0;
+        WAIT = 0,
         /// wakes at most val of the waiters that are waiting on the futex word at the address uaddr.
-        const WAKE      = 1;
+        WAKE = 1,
         /// wakes up a maximum of val waiters that are waiting on the futex at uaddr.  If there are more than val waiters, then the remaining waiters are removed from the wait queue of the source futex at uaddr and added to the wait queue of the target futex at uaddr2.  The val2 argument specifies an upper limit on the number of waiters that are requeued to the futex at uaddr2.
-        const REQUEUE   = 3;
-        /// (unsupported) is used after an attempt to acquire the lock via an atomic user-mode instruction failed.
-        const LOCK_PI   = 6;
-        /// (unsupported) is called when the user-space value at uaddr cannot be changed atomically from a TID (of the owner) to 0.
-        const UNLOCK_PI = 7;
-        /// can be employed with all futex operations, tells the kernel that the futex is process-private and not shared with another process
-        const PRIVATE   = 0x80;
+        REQUEUE = 3,
+        /// same as REQUEUE, but first checks that the futex word at uaddr still contains val3.
+        CMP_REQUEUE = 4,
+        /// modifies the futex word at uaddr2 by the operation encoded in val3, wakes up val waiters
+        /// on uaddr, and also val2 waiters on uaddr2 if the old value satisfies the encoded comparison.
+        WAKE_OP = 5,
+        /// is used after an attempt to acquire the lock via an atomic user-mode instruction failed.
+        LOCK_PI = 6,
+        /// is called when the user-space value at uaddr cannot be changed atomically from a TID (of the owner) to 0.
+        UNLOCK_PI = 7,
+        /// same as LOCK_PI, but fails with EAGAIN instead of waiting.
+        TRYLOCK_PI = 8,
+        /// same as WAIT, but only woken up by the wakes whose bitset intersects val3.
+        WAIT_BITSET = 9,
+        /// same as WAKE, but only wakes up the waiters whose bitset intersects val3.
+        WAKE_BITSET = 10,
     }
 }
 
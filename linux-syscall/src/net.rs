@@ -1,11 +1,47 @@
 use super::*;
+use alloc::vec::Vec;
 use core::mem::size_of;
-use kernel_hal::user::{IoVecs, UserInOutPtr};
+use kernel_hal::user::UserInOutPtr;
 use linux_object::{
-    fs::{FileLike, OpenFlags},
+    error::LxResult,
+    fs::{split_path, vfs::FileType, Access, FileLike, OpenFlags},
     net::*,
 };
 
+/// `struct cmsghdr`, the header of a control message
+#[repr(C)]
+struct CMsgHdr {
+    /// data byte count, including header
+    len: usize,
+    /// originating protocol
+    level: i32,
+    /// protocol-specific type
+    type_: i32,
+}
+
+/// Level of control messages for unix sockets
+const SOL_SOCKET: i32 = 1;
+/// Transfer file descriptors
+const SCM_RIGHTS: i32 = 1;
+/// Transfer credentials
+const SCM_CREDENTIALS: i32 = 2;
+/// Maximum number of file descriptors in a SCM_RIGHTS message
+const SCM_MAX_FD: usize = 253;
+
+/// Round up the length of a control message
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
+/// The destination of a unix socket message, which must be a unix address
+fn unix_address(endpoint: Option<Endpoint>) -> LxResult<Option<UnixEndpoint>> {
+    match endpoint {
+        Some(Endpoint::Unix(addr)) => Ok(Some(addr)),
+        Some(_) => Err(LxError::EINVAL),
+        None => Ok(None),
+    }
+}
+
 impl Syscall<'_> {
     /// creates an endpoint for communication and returns a file descriptor that refers to that endpoint.
     pub fn sys_socket(&mut self, domain: usize, _type: usize, protocol: usize) -> SysResult {
@@ -45,12 +81,15 @@ impl Syscall<'_> {
             | (Domain::AF_INET, SocketType::SOCK_DGRAM, Protocol::IPPROTO_UDP) => {
                 Arc::new(UdpSocketState::new())
             }
+            (Domain::AF_UNIX, SocketType::SOCK_STREAM, Protocol::IPPROTO_IP)
+            | (Domain::AF_UNIX, SocketType::SOCK_DGRAM, Protocol::IPPROTO_IP)
+            | (Domain::AF_UNIX, SocketType::SOCK_SEQPACKET, Protocol::IPPROTO_IP) => {
+                UnixSocketState::new(socket_type, self.ucred())
+            }
             /*
             (AF_INET, SOCK_RAW, _) => {
                 Arc::new(RawSocketState::new(protocol as u8))
             }
-            // TODO, UnixSocket
-            (AF_UNIX, SOCK_STREAM, Protocol::IPPROTO_IP) => {}
             (AF_NETLINK, SOCK_RAW, _) => {
                 Arc::new(NetlinkSocketState::new())
             }
@@ -69,6 +108,55 @@ impl Syscall<'_> {
         Ok(fd.into())
     }
 
+    /// creates a pair of connected unix sockets, and returns their file descriptors in `sv`.
+    pub fn sys_socketpair(
+        &mut self,
+        domain: usize,
+        _type: usize,
+        protocol: usize,
+        mut sv: UserOutPtr<[i32; 2]>,
+    ) -> SysResult {
+        info!(
+            "sys_socketpair: domain:{}, type:{}, protocol:{}, sv:{:?}",
+            domain, _type, protocol, sv
+        );
+        match Domain::try_from(domain) {
+            Ok(Domain::AF_UNIX) => {}
+            Ok(_) => return Err(LxError::EOPNOTSUPP),
+            Err(_) => return Err(LxError::EAFNOSUPPORT),
+        }
+        let socket_type = match SocketType::try_from(_type & SOCKET_TYPE_MASK) {
+            Ok(t @ SocketType::SOCK_STREAM)
+            | Ok(t @ SocketType::SOCK_DGRAM)
+            | Ok(t @ SocketType::SOCK_SEQPACKET) => t,
+            _ => {
+                warn!("invalid socket type: {_type}");
+                return Err(LxError::EINVAL);
+            }
+        };
+        if protocol != 0 {
+            warn!("invalid protocol: {protocol}");
+            return Err(LxError::EINVAL);
+        }
+        sv.check()?;
+        // socket flags: SOCK_CLOEXEC SOCK_NONBLOCK
+        let flags = OpenFlags::from_bits_truncate(_type & !SOCKET_TYPE_MASK);
+        let (socket0, socket1) = UnixSocketState::pair(socket_type, self.ucred());
+        socket0.set_flags(flags)?;
+        socket1.set_flags(flags)?;
+        let proc = self.linux_process();
+        let fd0 = proc.add_socket(socket0)?;
+        let fd1 = match proc.add_socket(socket1) {
+            Ok(fd) => fd,
+            Err(err) => {
+                proc.close_file(fd0)?;
+                return Err(err);
+            }
+        };
+        sv.write([fd0.into(), fd1.into()])?;
+        Ok(0)
+    }
+
     ///  connects the socket referred to by the file descriptor sockfd to the address specified by addr.
     pub async fn sys_connect(
         &mut self,
@@ -81,8 +169,10 @@ impl Syscall<'_> {
             sockfd, addr, addrlen
         );
         let endpoint = sockaddr_to_endpoint(addr.read()?, addrlen)?;
+        let endpoint = self.resolve_unix_endpoint(endpoint, false)?;
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
-        file_like.clone().as_socket()?.connect(endpoint).await?;
+        let socket = file_like.as_socket()?;
+        interruptible(self.thread, socket.connect(endpoint)).await??;
         Ok(0)
     }
 
@@ -144,7 +234,7 @@ impl Syscall<'_> {
                     .clone()
                     .as_socket()?
                     .get_buffer_capacity()
-                    .unwrap();
+                    .ok_or(LxError::ENOPROTOOPT)?;
                 debug!("sys_getsockopt recv and send buffer capacity: {}, {}. optval: {:?}, optlen: {:?}", recv_buf_ca, send_buf_ca, optval.check(), optlen.check());
 
                 match optname {
@@ -189,7 +279,7 @@ impl Syscall<'_> {
     }
 
     /// transmit a message to another socket
-    pub fn sys_sendto(
+    pub async fn sys_sendto(
         &mut self,
         sockfd: usize,
         buf: UserInPtr<u8>,
@@ -206,9 +296,16 @@ impl Syscall<'_> {
             None
         } else {
             let endpoint = sockaddr_to_endpoint(dest_addr.read()?, addrlen)?;
-            Some(endpoint)
+            Some(self.resolve_unix_endpoint(endpoint, false)?)
         };
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
+        if let Some(socket) = file_like.downcast_ref::<UnixSocketState>() {
+            let to = unix_address(endpoint)?;
+            let flags = MsgFlags::from_bits_truncate(flags);
+            let data = buf.as_slice(len)?;
+            let send = socket.sendmsg(data, to, UnixAncillary::default(), flags);
+            return interruptible(self.thread, send).await?;
+        }
         file_like
             .clone()
             .as_socket()?
@@ -233,7 +330,8 @@ impl Syscall<'_> {
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
         debug!("FileLike {} flags: {:?}", sockfd, file_like.flags());
         let mut data = vec![0u8; len];
-        let (result, endpoint) = file_like.clone().as_socket()?.read(&mut data).await;
+        let socket = file_like.as_socket()?;
+        let (result, endpoint) = interruptible(self.thread, socket.read(&mut data)).await?;
         if result.is_ok() && !src_addr.is_null() {
             let sockaddr_in = SockAddr::from(endpoint);
             sockaddr_in.write_to(src_addr, addrlen)?;
@@ -242,35 +340,257 @@ impl Syscall<'_> {
         result
     }
 
+    /// send a message on a socket, with ancillary data for unix sockets
+    pub async fn sys_sendmsg(
+        &mut self,
+        sockfd: usize,
+        msg: UserInPtr<MsgHdr>,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "sys_sendmsg: sockfd:{}, msg:{:?}, flags:{}",
+            sockfd, msg, flags
+        );
+        let hdr = msg.read()?;
+        let data = if hdr.msg_iovlen == 0 {
+            Vec::new()
+        } else {
+            UserInPtr::<IoVecIn>::from(hdr.msg_iov.as_addr())
+                .read_iovecs(hdr.msg_iovlen)?
+                .read_to_vec()?
+        };
+        let endpoint = if hdr.msg_name.is_null() {
+            None
+        } else {
+            let addr = UserInPtr::<SockAddr>::from(hdr.msg_name.as_addr()).read()?;
+            let endpoint = sockaddr_to_endpoint(addr, hdr.msg_namelen as usize)?;
+            Some(self.resolve_unix_endpoint(endpoint, false)?)
+        };
+        let file_like = self.linux_process().get_file_like(sockfd.into())?;
+        match file_like.downcast_ref::<UnixSocketState>() {
+            Some(socket) => {
+                let to = unix_address(endpoint)?;
+                let ancillary = self.read_ancillary(hdr.msg_control, hdr.msg_controllen)?;
+                let flags = MsgFlags::from_bits_truncate(flags);
+                let send = socket.sendmsg(&data, to, ancillary, flags);
+                interruptible(self.thread, send).await?
+            }
+            None => file_like.as_socket()?.write(&data, endpoint),
+        }
+    }
+
     /// receive messages from a socket
     pub async fn sys_recvmsg(
         &mut self,
         sockfd: usize,
-        msg: UserInOutPtr<MsgHdr>,
+        mut msg: UserInOutPtr<MsgHdr>,
         flags: usize,
     ) -> SysResult {
         info!(
             "sys_recvmsg: sockfd:{}, msg:{:?}, flags:{}",
             sockfd, msg, flags
         );
-        let hdr = msg.read().unwrap();
-
-        let iov_ptr = hdr.msg_iov;
-        let iovlen = hdr.msg_iovlen;
-        let mut iovs = IoVecs::new(iov_ptr, iovlen);
-        let mut data = vec![0u8; 8192];
+        let hdr = msg.read()?;
+        let mut iovs = hdr.msg_iov.read_iovecs(hdr.msg_iovlen)?;
+        let mut data = vec![0u8; iovs.total_len()];
 
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
-        let (result, endpoint) = file_like.clone().as_socket()?.read(&mut data).await;
+        let socket = match file_like.downcast_ref::<UnixSocketState>() {
+            Some(socket) => socket,
+            None => {
+                let socket = file_like.as_socket()?;
+                let (result, endpoint) = interruptible(self.thread, socket.read(&mut data)).await?;
+                let len = result?;
+                iovs.write_from_buf(&data[..len])?;
+                if !hdr.msg_name.is_null() {
+                    SockAddr::from(endpoint).write_to_msg(msg)?;
+                }
+                return Ok(len);
+            }
+        };
+        let flags = MsgFlags::from_bits_truncate(flags);
+        let info = interruptible(self.thread, socket.recvmsg(&mut data, flags)).await??;
+        iovs.write_from_buf(&data[..info.len])?;
+        if !hdr.msg_name.is_null() {
+            SockAddr::from(Endpoint::Unix(info.from)).write_to_msg(msg)?;
+        }
+        let mut msg_flags = MsgFlags::empty();
+        if info.msg_len > info.len {
+            msg_flags |= MsgFlags::TRUNC;
+        }
+        let control_len = self.write_ancillary(
+            hdr.msg_control,
+            hdr.msg_controllen,
+            info.ancillary,
+            flags.contains(MsgFlags::CMSG_CLOEXEC),
+            &mut msg_flags,
+        )?;
+        // the name length may have been updated
+        let mut hdr = msg.read()?;
+        hdr.msg_controllen = control_len;
+        hdr.msg_flags = msg_flags.bits();
+        msg.write(hdr)?;
+        if flags.contains(MsgFlags::TRUNC) {
+            Ok(info.msg_len)
+        } else {
+            Ok(info.len)
+        }
+    }
 
-        let addr = hdr.msg_name;
-        if result.is_ok() && !addr.is_null() {
-            iovs.write_from_buf(&data).unwrap();
-            let sockaddr_in = SockAddr::from(endpoint);
-            sockaddr_in.write_to_msg(msg)?;
+    /// Credentials of the current process
+    fn ucred(&self) -> UCred {
+        let cred = self.linux_process().credentials();
+        UCred {
+            pid: self.zircon_process().id() as i32,
+            uid: cred.uid,
+            gid: cred.gid,
         }
+    }
 
-        result
+    /// Make the path of a unix socket address absolute.
+    ///
+    /// The socket file is created for `bind`, otherwise it must exist.
+    fn resolve_unix_endpoint(&self, endpoint: Endpoint, bind: bool) -> LxResult<Endpoint> {
+        let path = match endpoint {
+            Endpoint::Unix(UnixEndpoint::Path(path)) => path,
+            endpoint => return Ok(endpoint),
+        };
+        let proc = self.linux_process();
+        let path = proc.absolute_path(&path);
+        if bind {
+            let (dir_path, file_name) = split_path(&path);
+            let (dir_inode, dir_mount) = proc.lookup_mount(dir_path)?;
+            if dir_inode.find(file_name).is_ok() {
+                return Err(LxError::EADDRINUSE);
+            }
+            proc.create_inode(&dir_inode, &dir_mount, file_name, FileType::Socket, 0o777)?;
+        } else {
+            let inode = proc.lookup_inode(&path)?;
+            if inode.metadata()?.type_ != FileType::Socket {
+                return Err(LxError::ECONNREFUSED);
+            }
+            // connecting to a socket needs write permission of the socket file
+            proc.check_access(&inode, Access::WRITE)?;
+        }
+        Ok(Endpoint::Unix(UnixEndpoint::Path(path)))
+    }
+
+    /// Parse the control messages of `sendmsg`
+    fn read_ancillary(&self, control: usize, control_len: usize) -> LxResult<UnixAncillary> {
+        let mut ancillary = UnixAncillary::default();
+        if control == 0 {
+            return Ok(ancillary);
+        }
+        let hdr_len = size_of::<CMsgHdr>();
+        let mut offset = 0;
+        while offset + hdr_len <= control_len {
+            let cmsg = UserInPtr::<CMsgHdr>::from(control + offset).read()?;
+            if cmsg.len < hdr_len || offset + cmsg.len > control_len {
+                return Err(LxError::EINVAL);
+            }
+            let data = control + offset + hdr_len;
+            let data_len = cmsg.len - hdr_len;
+            match (cmsg.level, cmsg.type_) {
+                (SOL_SOCKET, SCM_RIGHTS) => {
+                    let count = data_len / size_of::<i32>();
+                    if ancillary.files.len() + count > SCM_MAX_FD {
+                        return Err(LxError::EINVAL);
+                    }
+                    let proc = self.linux_process();
+                    for fd in UserInPtr::<i32>::from(data).read_array(count)? {
+                        ancillary.files.push(proc.get_file_like(fd.into())?);
+                    }
+                }
+                (SOL_SOCKET, SCM_CREDENTIALS) => {
+                    if data_len < size_of::<UCred>() {
+                        return Err(LxError::EINVAL);
+                    }
+                    let cred = UserInPtr::<UCred>::from(data).read()?;
+                    // only root can send credentials other than its own IDs
+                    let own = self.linux_process().credentials();
+                    let pid = self.zircon_process().id() as i32;
+                    if !own.is_root()
+                        && (cred.pid != pid
+                            || ![own.uid, own.euid, own.suid].contains(&cred.uid)
+                            || ![own.gid, own.egid, own.sgid].contains(&cred.gid))
+                    {
+                        return Err(LxError::EPERM);
+                    }
+                    ancillary.cred = Some(cred);
+                }
+                _ => return Err(LxError::EINVAL),
+            }
+            offset += cmsg_align(cmsg.len);
+        }
+        Ok(ancillary)
+    }
+
+    /// Write the control messages of `recvmsg`, returns the length written.
+    ///
+    /// Files which do not fit in the buffer are closed, and `MSG_CTRUNC` is set.
+    /// Received files are duplicated like `F_DUPFD`, with close-on-exec set if `cloexec`.
+    /// A socket is shared with the sender instead, so close-on-exec is only ever set on it.
+    fn write_ancillary(
+        &self,
+        control: usize,
+        control_len: usize,
+        ancillary: UnixAncillary,
+        cloexec: bool,
+        msg_flags: &mut MsgFlags,
+    ) -> LxResult<usize> {
+        let hdr_len = size_of::<CMsgHdr>();
+        let control_len = if control == 0 { 0 } else { control_len };
+        let mut offset = 0;
+        if let Some(cred) = ancillary.cred {
+            let len = hdr_len + size_of::<UCred>();
+            if offset + len > control_len {
+                msg_flags.insert(MsgFlags::CTRUNC);
+            } else {
+                UserOutPtr::<CMsgHdr>::from(control + offset).write(CMsgHdr {
+                    len,
+                    level: SOL_SOCKET,
+                    type_: SCM_CREDENTIALS,
+                })?;
+                UserOutPtr::<UCred>::from(control + offset + hdr_len).write(cred)?;
+                offset += cmsg_align(len);
+            }
+        }
+        if !ancillary.files.is_empty() {
+            let space = control_len.saturating_sub(offset + hdr_len) / size_of::<i32>();
+            let count = space.min(ancillary.files.len());
+            if count < ancillary.files.len() {
+                msg_flags.insert(MsgFlags::CTRUNC);
+            }
+            if count > 0 {
+                let proc = self.linux_process();
+                let mut fds = Vec::with_capacity(count);
+                for file in ancillary.files.into_iter().take(count) {
+                    let socket = file.as_socket().is_ok();
+                    // sockets are not duplicated, the sender keeps its close-on-exec flag
+                    let file = if socket { file } else { file.dup() };
+                    if cloexec || !socket {
+                        let mut flags = file.flags();
+                        flags.set(OpenFlags::CLOEXEC, cloexec);
+                        file.set_flags(flags)?;
+                    }
+                    let fd = if socket {
+                        proc.add_socket(file)?
+                    } else {
+                        proc.add_file(file)?
+                    };
+                    fds.push(i32::from(fd));
+                }
+                let len = hdr_len + count * size_of::<i32>();
+                UserOutPtr::<CMsgHdr>::from(control + offset).write(CMsgHdr {
+                    len,
+                    level: SOL_SOCKET,
+                    type_: SCM_RIGHTS,
+                })?;
+                UserOutPtr::<i32>::from(control + offset + hdr_len).write_array(&fds)?;
+                offset += cmsg_align(len);
+            }
+        }
+        Ok(offset.min(control_len))
     }
 
     /// assigns the address specified by addr to the socket referred to by the file descriptor sockfd
@@ -287,7 +607,17 @@ impl Syscall<'_> {
         let endpoint = sockaddr_to_endpoint(addr.read()?, addrlen)?;
         debug!("sys_bind: fd:{} bind to {:?}", sockfd, endpoint);
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
-        file_like.clone().as_socket()?.bind(endpoint)
+        let socket = file_like.as_socket()?;
+        let endpoint = self.resolve_unix_endpoint(endpoint, true)?;
+        let ret = socket.bind(endpoint.clone());
+        if let (Err(_), Endpoint::Unix(UnixEndpoint::Path(path))) = (&ret, &endpoint) {
+            // remove the socket file just created
+            let (dir_path, file_name) = split_path(path);
+            self.linux_process()
+                .lookup_inode(dir_path)?
+                .unlink(file_name)?;
+        }
+        ret
     }
 
     /// marks the socket referred to by sockfd as a passive socket,
@@ -327,7 +657,8 @@ impl Syscall<'_> {
         // smoltcp tcp sockets do not support backlog
         // open multiple sockets for each connection
         let file_like = self.linux_process().get_file_like(sockfd.into())?;
-        let (new_socket, remote_endpoint) = file_like.clone().as_socket()?.accept().await?;
+        let socket = file_like.as_socket()?;
+        let (new_socket, remote_endpoint) = interruptible(self.thread, socket.accept()).await??;
         debug!(
             "FileLike{} flags: {:?}, New flags: {:?}",
             sockfd,
@@ -4,11 +4,20 @@
 //! - rt_sigreturn
 //! - rt_sigprocmask
 //! - kill
+//! - rt_sigqueueinfo
 //! - tkill
 //! - sigaltstack
+//! - signalfd
 
 use super::*;
-use linux_object::signal::{Signal, SignalAction, SignalStack, SignalStackFlags, Sigset};
+use alloc::vec::Vec;
+use kernel_hal::context::UserContextField;
+use linux_object::fs::{SignalFd, SignalFdFlags};
+use linux_object::process::{job_processes, send_signal, send_signal_to_thread};
+use linux_object::signal::{
+    SigInfo, Signal, SignalAction, SignalCode, SignalStack, SignalStackFlags, SignalUserContext,
+    Sigset,
+};
 use linux_object::thread::ThreadExt;
 use numeric_enum_macro::numeric_enum;
 
@@ -96,98 +105,180 @@ impl Syscall<'_> {
         mut old_ss: UserOutPtr<SignalStack>,
     ) -> SysResult {
         info!("sigaltstack: ss={:?}, old_ss={:?}", ss, old_ss);
-        let mut thread = self.thread.lock_linux();
-        old_ss.write_if_not_null(thread.signal_alternate_stack)?;
+        let sp = self
+            .thread
+            .with_context(|ctx| ctx.get_field(UserContextField::StackPointer))?;
+        let mut old = self.thread.lock_linux().signal_alternate_stack;
+        let on_stack = old.contains(sp);
+        if on_stack {
+            old.flags.insert(SignalStackFlags::ONSTACK);
+        }
+        old_ss.write_if_not_null(old)?;
         if ss.is_null() {
             return Ok(0);
         }
         let ss = ss.read()?;
         // check stack size when not disable
         const MIN_SIGSTACK_SIZE: usize = 2048;
-        if ss.flags.contains(SignalStackFlags::DISABLE) && ss.size < MIN_SIGSTACK_SIZE {
+        if !ss.flags.contains(SignalStackFlags::DISABLE) && ss.size < MIN_SIGSTACK_SIZE {
             return Err(LxError::ENOMEM);
         }
         // only allow SS_AUTODISARM and SS_DISABLE
         if !(SignalStackFlags::AUTODISARM | SignalStackFlags::DISABLE).contains(ss.flags) {
             return Err(LxError::EINVAL);
         }
-        let old_ss = &mut thread.signal_alternate_stack;
-        if old_ss.flags.contains(SignalStackFlags::ONSTACK) {
+        if on_stack {
             // cannot change signal alternate stack when we are on it
             // see man sigaltstack(2)
             return Err(LxError::EPERM);
         }
-        *old_ss = ss;
+        self.thread.lock_linux().signal_alternate_stack = ss;
         Ok(0)
     }
 
-    /// Send a signal to a process specified by pid
-    /// TODO: support all the arguments
+    /// Send a signal to a process or a group of processes specified by `pid`
+    ///
+    /// - `pid` > 0: the process with the ID `pid`.
+    /// - `pid` = 0: every process in the process group of the caller.
+    /// - `pid` = -1: every process the caller has permission to send signals to,
+    ///   except the initial process and the caller itself.
+    /// - `pid` < -1: every process in the process group `-pid`.
+    ///
+    /// If `signum` is 0, no signal is sent, but the existence and the permission are checked.
+    ///
+    /// Note: only the processes in the same job as the calling thread can be found.
     pub fn sys_kill(&self, pid: isize, signum: usize) -> SysResult {
-        let signal = Signal::try_from(signum as u8).map_err(|_| LxError::EINVAL)?;
+        let signal = if signum == 0 {
+            None
+        } else {
+            Some(Signal::try_from(signum as u8).map_err(|_| LxError::EINVAL)?)
+        };
         info!(
             "kill: thread {} kill process {} with signal {:?}",
             self.thread.id(),
             pid,
             signal
         );
-        warn!(
-            "The sys_kill only supports killing a process (SIGKILL) or sending a signal to
-            an arbitrary thread of a process within the same job as the calling thread. 
-            As for the latter, the signal will be delivered to an arbitrarily selected thread 
-            in the target process that is not blocking the signal."
-        );
-        enum SendTarget {
-            EveryProcessInGroup,
-            EveryProcess,
-            EveryProcessInGroupByPID(KoID),
-            Pid(KoID),
-        }
-        let target = match pid {
-            p if p > 0 => SendTarget::Pid(p as KoID),
-            0 => SendTarget::EveryProcessInGroup,
-            -1 => SendTarget::EveryProcess,
-            p if p < -1 => SendTarget::EveryProcessInGroupByPID((-p) as KoID),
-            _ => unimplemented!(),
+        let proc = self.zircon_process();
+        let linux_proc = self.linux_process();
+        let targets: Vec<Arc<Process>> = match pid {
+            p if p > 0 => proc
+                .job()
+                .get_child(p as KoID)
+                .ok()
+                .and_then(|obj| obj.downcast_arc::<Process>().ok())
+                .into_iter()
+                .collect(),
+            -1 => job_processes(&proc.job())
+                .into_iter()
+                .filter(|p| p.id() != proc.id() && p.linux().parent().is_some())
+                .collect(),
+            p => {
+                let pgid = if p == 0 {
+                    linux_proc.pgid()
+                } else {
+                    (-p) as KoID
+                };
+                job_processes(&proc.job())
+                    .into_iter()
+                    .filter(|p| p.linux().pgid() == pgid)
+                    .collect()
+            }
         };
-        let parent = self.zircon_process().clone();
-        match target {
-            SendTarget::Pid(pid) => {
-                match parent.job().get_child(pid as u64) {
-                    Ok(obj) => {
-                        match signal {
-                            Signal::SIGKILL => {
-                                let current_pid = parent.id();
-                                if current_pid == (pid as u64) {
-                                    // killing myself
-                                    parent.exit((128 + Signal::SIGKILL as i32) as i64);
-                                } else {
-                                    let process: Arc<Process> = obj.downcast_arc().unwrap();
-                                    process.exit((128 + Signal::SIGKILL as i32) as i64);
-                                }
-                            }
-                            sig => {
-                                let process: Arc<Process> = obj.downcast_arc().unwrap();
-                                let tids = process.thread_ids();
-                                for tid in tids {
-                                    let thread = process.get_child(tid).unwrap();
-                                    let thread: Arc<Thread> = thread.downcast_arc().unwrap();
-                                    let mut thread_linux = thread.lock_linux();
-                                    if thread_linux.signal_mask.contains(sig) {
-                                        continue;
-                                    } else {
-                                        thread_linux.signals.insert(signal);
-                                        break;
-                                    }
-                                }
-                            }
-                        };
-                        Ok(0)
-                    }
-                    Err(_) => Err(LxError::EINVAL),
-                }
+        if targets.is_empty() {
+            return Err(LxError::ESRCH);
+        }
+        let cred = linux_proc.credentials();
+        let sid = linux_proc.sid();
+        let pid = proc.id() as usize;
+        let mut sent = false;
+        for target in targets {
+            // the real or effective user ID of the sender must match
+            // the real or saved set-user-ID of the target
+            let target_cred = target.linux().credentials();
+            let permitted = cred.is_root()
+                || [cred.uid, cred.euid]
+                    .iter()
+                    .any(|&id| id == target_cred.uid || id == target_cred.suid)
+                || (signal == Some(Signal::SIGCONT) && target.linux().sid() == sid);
+            if !permitted {
+                continue;
             }
-            _ => unimplemented!(),
+            sent = true;
+            if let Some(signal) = signal {
+                let info = SigInfo::kill(signal, SignalCode::USER as i32, pid, cred.uid);
+                send_signal(&target, info);
+            }
+        }
+        if sent {
+            Ok(0)
+        } else {
+            Err(LxError::EPERM)
+        }
+    }
+
+    /// Send a signal with the information `uinfo` to the process `tgid`
+    ///
+    /// The sender can not pretend to be the kernel or `kill(2)` when sending to other processes.
+    pub fn sys_rt_sigqueueinfo(
+        &self,
+        tgid: usize,
+        signum: usize,
+        uinfo: UserInPtr<SigInfo>,
+    ) -> SysResult {
+        let signal = Signal::try_from(signum as u8).map_err(|_| LxError::EINVAL)?;
+        let mut info = uinfo.read()?;
+        info.set_signal(signal);
+        info!(
+            "rt_sigqueueinfo: thread {} send {:?} to process {}: {:x?}",
+            self.thread.id(),
+            signal,
+            tgid,
+            info
+        );
+        let proc = self.zircon_process();
+        if tgid as KoID != proc.id() && (info.code >= 0 || info.code == SignalCode::TKILL as i32) {
+            return Err(LxError::EPERM);
+        }
+        let target = proc
+            .job()
+            .get_child(tgid as KoID)
+            .ok()
+            .and_then(|obj| obj.downcast_arc::<Process>().ok())
+            .ok_or(LxError::ESRCH)?;
+        send_signal(&target, info);
+        Ok(0)
+    }
+
+    /// Create a file descriptor that can be used to accept signals in `mask`,
+    /// or replace the mask of an existing signalfd if `fd` is not -1
+    pub fn sys_signalfd4(
+        &self,
+        fd: i32,
+        mask: UserInPtr<Sigset>,
+        sizemask: usize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "signalfd4: fd={}, mask={:?}, sizemask={}, flags={:#x}",
+            fd, mask, sizemask, flags
+        );
+        if sizemask != core::mem::size_of::<Sigset>() {
+            return Err(LxError::EINVAL);
+        }
+        let flags = SignalFdFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        let mask = mask.read()?;
+        let proc = self.linux_process();
+        if fd == -1 {
+            let signalfd = SignalFd::new(self.zircon_process(), mask, flags);
+            let fd = proc.add_file(signalfd)?;
+            Ok(fd.into())
+        } else {
+            let file = proc.get_file_like(fd.into())?;
+            file.downcast_ref::<SignalFd>()
+                .ok_or(LxError::EINVAL)?
+                .set_mask(mask);
+            Ok(fd as usize)
         }
     }
 
@@ -204,9 +295,7 @@ impl Syscall<'_> {
         match parent.get_child(tid as u64) {
             Ok(obj) => {
                 let thread: Arc<Thread> = obj.downcast_arc().unwrap();
-                let mut thread_linux = thread.lock_linux();
-                thread_linux.signals.insert(signal);
-                drop(thread_linux);
+                send_signal_to_thread(&thread, self.tkill_info(signal));
                 Ok(0)
             }
             Err(_) => Err(LxError::EINVAL),
@@ -236,32 +325,41 @@ impl Syscall<'_> {
         {
             Ok(Ok(obj)) => {
                 let thread: Arc<Thread> = obj.downcast_arc().unwrap();
-                let mut thread_linux = thread.lock_linux();
-                thread_linux.signals.insert(signal);
-                drop(thread_linux);
+                send_signal_to_thread(&thread, self.tkill_info(signal));
                 Ok(0)
             }
             _ => Err(LxError::EINVAL),
         }
     }
 
+    /// The information of `signal` sent by `tkill` or `tgkill` from the current thread
+    fn tkill_info(&self, signal: Signal) -> SigInfo {
+        let uid = self.linux_process().credentials().uid;
+        let pid = self.zircon_process().id() as usize;
+        SigInfo::kill(signal, SignalCode::TKILL as i32, pid, uid)
+    }
+
     /// Return from handling some signal
+    ///
+    /// The registers and the signal mask are restored from the `ucontext` on the user stack,
+    /// which may have been modified by the signal handler.
     pub fn sys_rt_sigreturn(&mut self) -> SysResult {
         info!(
             "sigreturn: thread {} returns from handling the signal",
             self.thread.id()
         );
-        let (old_ctx, siginfo_ptr, uctx_ptr) = self.thread.fetch_backup_context().unwrap();
-        self.thread
-            .with_context(|ctx| {
-                self.thread.lock_linux().restore_after_handle_signal(
-                    ctx,
-                    &old_ctx,
-                    siginfo_ptr,
-                    uctx_ptr,
-                )
-            })
-            .unwrap();
-        Ok(0)
+        let sp = self
+            .thread
+            .with_context(|ctx| ctx.get_field(UserContextField::StackPointer))?;
+        let uctx: UserInPtr<SignalUserContext> = sp.into();
+        let uctx = uctx.read()?;
+        // keep the restored return value register
+        let ret = self.thread.with_context(|ctx| {
+            self.thread
+                .lock_linux()
+                .restore_after_handle_signal(ctx, &uctx);
+            ctx.get_field(UserContextField::ReturnValue)
+        })?;
+        Ok(ret)
     }
 }
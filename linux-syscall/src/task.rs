@@ -2,14 +2,21 @@ use super::*;
 use core::fmt::Debug;
 use core::mem::size_of;
 
-use alloc::string::ToString;
-use bitflags::bitflags;
+use alloc::{string::ToString, vec::Vec};
 
 use kernel_hal::context::UserContextField;
+use linux_object::error::LxResult;
+use linux_object::fs::{check_permission, vfs::FileType, Access, INodeExt, S_ISGID, S_ISUID};
+use linux_object::loader::LinuxElfLoader;
+use linux_object::process::{
+    exit_by_signal, job_processes, wait_child, CloneFlags, WaitOptions, WaitTarget,
+};
+use linux_object::ptrace::{self, PtraceOptions, PtraceRegs, Resume};
+use linux_object::signal::{SigInfo, Signal as LinuxSignal};
 use linux_object::thread::{CurrentThreadExt, RobustList, ThreadExt};
 use linux_object::time::TimeSpec;
-use linux_object::{fs::INodeExt, loader::LinuxElfLoader};
-use zircon_object::vm::USER_STACK_PAGES;
+use numeric_enum_macro::numeric_enum;
+use zircon_object::vm::{VmAddressRegion, PAGE_SIZE, USER_STACK_PAGES};
 
 /// Syscalls for process.
 ///
@@ -17,12 +24,18 @@ use zircon_object::vm::USER_STACK_PAGES;
 ///
 /// - [`fork`](Self::sys_fork)
 /// - [`vfork`](Self::sys_vfork)
-/// - [`clone`](Self::sys_clone)
+/// - [`clone`](Self::sys_clone), [`clone3`](Self::sys_clone3)
 /// - [`wait4`](Self::sys_wait4)
+/// - [`ptrace`](Self::sys_ptrace)
 /// - [`execve`](Self::sys_execve)
 /// - [`gettid`](Self::sys_gettid)
 /// - [`getpid`](Self::sys_getpid)
 /// - [`getppid`](Self::sys_getppid)
+/// - [`setpgid`](Self::sys_setpgid), [`getpgid`](Self::sys_getpgid),
+///   [`setsid`](Self::sys_setsid), [`getsid`](Self::sys_getsid)
+/// - [`getuid`](Self::sys_getuid), [`setuid`](Self::sys_setuid),
+///   [`setreuid`](Self::sys_setreuid), [`setresuid`](Self::sys_setresuid) and the `gid` ones
+/// - [`getgroups`](Self::sys_getgroups), [`setgroups`](Self::sys_setgroups)
 /// - [`exit`](Self::sys_exit)
 /// - [`exit_group`](Self::sys_exit_group)
 /// - [`nanosleep`](Self::sys_nanosleep)
@@ -61,12 +74,9 @@ impl Syscall<'_> {
     ///   This means that the two file descriptors share open file status flags and file offset.
     pub fn sys_fork(&self) -> SysResult {
         info!("fork:");
-        let new_proc = Process::fork_from(self.zircon_process(), false)?; // old pt NULL here
-        let new_thread = Thread::create_linux(&new_proc)?;
-        let mut new_ctx = self.thread.context_cloned()?;
-        new_ctx.set_field(UserContextField::ReturnValue, 0);
-        new_thread.with_context(|ctx| *ctx = new_ctx)?;
-        new_thread.start(self.thread_fn)?;
+        let flags = CloneFlags::from_bits_truncate(LinuxSignal::SIGCHLD as usize);
+        let new_thread = self.clone_task(flags, 0, 0, 0.into(), 0.into())?;
+        let new_proc = new_thread.proc();
         info!("fork: {} -> {}", self.zircon_process().id(), new_proc.id());
         Ok(new_proc.id() as usize)
     }
@@ -78,71 +88,179 @@ impl Syscall<'_> {
     /// `sys_vfork` differs from [`Self::sys_fork`] in that the calling thread is suspended until the child terminates
     /// (either normally, by calling [`Self::sys_exit`], or abnormally, after delivery of a fatal signal),
     /// or it makes a call to [`Self::sys_execve`].
+    /// Until that point, the child shares all memory with its parent, including the stack.
     pub async fn sys_vfork(&self) -> SysResult {
         info!("vfork:");
-        let new_proc = Process::fork_from(self.zircon_process(), true)?;
-        let new_thread = Thread::create_linux(&new_proc)?;
-        let mut new_ctx = self.thread.context_cloned()?;
-        new_ctx.set_field(UserContextField::ReturnValue, 0);
-        new_thread.with_context(|ctx| *ctx = new_ctx)?;
-        new_thread.start(self.thread_fn)?;
-
-        let new_proc: Arc<dyn KernelObject> = new_proc;
-        info!(
-            "vfork: {} -> {}. Waiting for execve SIGNALED",
-            self.zircon_process().id(),
-            new_proc.id()
-        );
-        new_proc.wait_signal(Signal::SIGNALED).await; // wait for execve
-        Ok(new_proc.id() as usize)
+        let flags = CloneFlags::VM
+            | CloneFlags::VFORK
+            | CloneFlags::from_bits_truncate(LinuxSignal::SIGCHLD as usize);
+        self.sys_clone(flags.bits(), 0, 0.into(), 0, 0.into()).await
     }
 
-    /// `sys_clone` create a new thread in the current process.
-    /// The new thread's stack pointer will be set to `newsp`,
-    /// and thread pointer will be set to `newtls`.
-    /// The child TID will be stored at both `parent_tid` and `child_tid`.
+    /// `sys_clone` creates a new process or a new thread in the current process
+    /// (see [linux man clone(2)](https://www.man7.org/linux/man-pages/man2/clone.2.html)),
+    /// which resumes from the return of this syscall with 0 as the return value.
+    ///
+    /// The low byte of `flags` is the signal sent to the parent when the child process terminates.
+    /// The other bits of `flags` select what is shared between the caller and the child:
+    ///
+    /// - **CLONE_VM**: the child runs in the same memory space, otherwise in a copy of it.
+    /// - **CLONE_FS**: the child shares the current working directory and umask.
+    /// - **CLONE_FILES**: the child shares the file descriptor table, otherwise gets a copy of it.
+    /// - **CLONE_SIGHAND**: the child shares the table of signal handlers (requires CLONE_VM).
+    /// - **CLONE_THREAD**: the child is a thread in the same process (requires CLONE_SIGHAND).
+    /// - **CLONE_PARENT**: the parent of the child is the parent of the caller.
+    /// - **CLONE_VFORK**: the caller is suspended until the child calls [`Self::sys_execve`] or exits.
     ///
-    /// > **NOTE!** This system call is not exactly the same as `clone` in Linux.
+    /// If `newsp` is not zero, the child starts with the stack pointer `newsp`.
+    /// With **CLONE_SETTLS** the thread pointer of the child is set to `newtls`.
+    /// The child thread ID is stored at `parent_tid` with **CLONE_PARENT_SETTID**,
+    /// and at `child_tid` in the memory of the child with **CLONE_CHILD_SETTID**.
+    /// With **CLONE_CHILD_CLEARTID**, `child_tid` is cleared and the futex at it is woken up
+    /// when the child thread exits, see [`Self::sys_set_tid_address`].
     ///
-    /// > **NOTE!** This is partially implemented for `musl` only.
-    pub fn sys_clone(
+    /// Returns the process ID of the child, or the thread ID with CLONE_THREAD.
+    ///
+    /// > **NOTE!** The namespace flags are not supported.
+    pub async fn sys_clone(
         &self,
         flags: usize,
         newsp: usize,
-        mut parent_tid: UserOutPtr<i32>,
+        parent_tid: UserOutPtr<i32>,
         newtls: usize,
-        mut child_tid: UserOutPtr<i32>,
+        child_tid: UserOutPtr<i32>,
     ) -> SysResult {
-        let _flags = CloneFlags::from_bits_truncate(flags);
+        let flags = CloneFlags::from_bits_truncate(flags);
         info!(
-            "clone: flags={:#x}, newsp={:#x}, parent_tid={:?}, child_tid={:?}, newtls={:#x}",
+            "clone: flags={:?}, newsp={:#x}, parent_tid={:?}, child_tid={:?}, newtls={:#x}",
             flags, newsp, parent_tid, child_tid, newtls
         );
-        if flags == 0x4111 || flags == 0x11 {
-            // VFORK | VM | SIGCHILD
-            warn!("sys_clone is calling sys_fork instead, ignoring other args");
-            return self.sys_fork();
-        }
-        if flags != 0x7d_0f00 && flags != 0x5d_0f00 {
-            // 0x5d0f00: gcc of alpine linux
-            // 0x7d0f00: pthread_create of alpine linux
-            // warn!("sys_clone only support musl pthread_create");
-            panic!("unsupported sys_clone flags: {:#x}", flags);
-        }
-        let new_thread = Thread::create_linux(self.zircon_process())?;
+        if flags.contains(CloneFlags::PIDFD) {
+            warn!("clone: CLONE_PIDFD is not supported");
+            return Err(LxError::EINVAL);
+        }
+        let new_thread = self.clone_task(flags, newsp, newtls, parent_tid, child_tid)?;
+        let id = if flags.contains(CloneFlags::THREAD) {
+            new_thread.id()
+        } else {
+            new_thread.proc().id()
+        };
+        info!("clone: {} -> {}", self.thread.id(), id);
+        if flags.contains(CloneFlags::VFORK) {
+            let new_proc: Arc<dyn KernelObject> = new_thread.proc().clone();
+            info!(
+                "clone: waiting for the vfork child {} to execve or exit",
+                id
+            );
+            new_proc
+                .wait_signal(Signal::VFORK_DONE | Signal::PROCESS_TERMINATED)
+                .await;
+        }
+        Ok(id as usize)
+    }
+
+    /// `sys_clone3` is the extensible version of [`Self::sys_clone`]
+    /// (see [linux man clone3(2)](https://www.man7.org/linux/man-pages/man2/clone3.2.html)),
+    /// which reads the arguments from the structure at `args` of `size` bytes.
+    ///
+    /// The exit signal is given separately from the flags,
+    /// and the child stack is specified by its lowest address and size.
+    ///
+    /// An older version of the structure is extended with zeros,
+    /// and a newer one is accepted if the fields unknown to the kernel are all zero.
+    ///
+    /// > **NOTE!** Choosing the thread IDs by `set_tid` and CLONE_INTO_CGROUP are not supported.
+    pub async fn sys_clone3(&self, args: UserInPtr<u64>, size: usize) -> SysResult {
+        info!("clone3: args={:?}, size={}", args, size);
+        let args = CloneArgs::read(args, size)?;
+        debug!("clone3: {:x?}", args);
+        let flags = args.flags as usize;
+        if flags & !CloneFlags::all().bits() != 0
+            || flags & CloneFlags::CSIGNAL.bits() != 0
+            || args.exit_signal > LinuxSignal::RTMAX as u64
+            || args.set_tid != 0
+            || args.set_tid_size != 0
+            || args.cgroup != 0
+        {
+            return Err(LxError::EINVAL);
+        }
+        let newsp = match (args.stack, args.stack_size) {
+            (0, 0) => 0,
+            (0, _) | (_, 0) => return Err(LxError::EINVAL),
+            (stack, stack_size) => (stack + stack_size) as usize,
+        };
+        self.sys_clone(
+            flags | args.exit_signal as usize,
+            newsp,
+            (args.parent_tid as usize).into(),
+            args.tls as usize,
+            (args.child_tid as usize).into(),
+        )
+        .await
+    }
+
+    /// Create the child thread of [`Self::sys_clone`] and start it.
+    fn clone_task(
+        &self,
+        flags: CloneFlags,
+        newsp: usize,
+        newtls: usize,
+        mut parent_tid: UserOutPtr<i32>,
+        mut child_tid: UserOutPtr<i32>,
+    ) -> LxResult<Arc<Thread>> {
+        let namespaces = CloneFlags::NEWNS
+            | CloneFlags::NEWCGROUP
+            | CloneFlags::NEWUTS
+            | CloneFlags::NEWIPC
+            | CloneFlags::NEWUSER
+            | CloneFlags::NEWPID
+            | CloneFlags::NEWNET;
+        if flags.intersects(namespaces) {
+            warn!("clone: namespaces are not supported: {:?}", flags);
+            return Err(LxError::EINVAL);
+        }
+        if flags.contains(CloneFlags::THREAD) && !flags.contains(CloneFlags::SIGHAND)
+            || flags.contains(CloneFlags::SIGHAND) && !flags.contains(CloneFlags::VM)
+        {
+            return Err(LxError::EINVAL);
+        }
+
+        let new_thread = if flags.contains(CloneFlags::THREAD) {
+            Thread::create_linux(self.zircon_process())?
+        } else {
+            let new_proc = Process::fork_from(self.zircon_process(), flags)?;
+            Thread::create_linux(&new_proc)?
+        };
         let mut new_ctx = self.thread.context_cloned()?;
-        new_ctx.set_field(UserContextField::StackPointer, newsp);
-        new_ctx.set_field(UserContextField::ThreadPointer, newtls);
+        if newsp != 0 {
+            new_ctx.set_field(UserContextField::StackPointer, newsp);
+        }
+        if flags.contains(CloneFlags::SETTLS) {
+            new_ctx.set_field(UserContextField::ThreadPointer, newtls);
+        }
         new_ctx.set_field(UserContextField::ReturnValue, 0);
         new_thread.with_context(|ctx| *ctx = new_ctx)?;
-        new_thread.start(self.thread_fn)?;
 
-        let tid = new_thread.id();
-        info!("clone: {} -> {}", self.thread.id(), tid);
-        parent_tid.write(tid as i32)?;
-        child_tid.write(tid as i32)?;
-        new_thread.set_tid_address(child_tid);
-        Ok(tid as usize)
+        // like Linux, faults on storing the thread ID are ignored
+        let tid = new_thread.id() as i32;
+        if flags.contains(CloneFlags::PARENT_SETTID) {
+            parent_tid.write_if_not_null(tid).ok();
+        }
+        if flags.contains(CloneFlags::CHILD_SETTID) && !child_tid.is_null() {
+            if flags.contains(CloneFlags::VM) {
+                child_tid.write(tid).ok();
+            } else {
+                let vmar = new_thread.proc().vmar();
+                vmar.write_memory(child_tid.as_addr(), &tid.to_ne_bytes())
+                    .ok();
+            }
+        }
+        if flags.contains(CloneFlags::CHILD_CLEARTID) {
+            new_thread.set_tid_address(child_tid);
+        }
+        ptrace::trace_clone(self.thread, &new_thread, flags);
+        new_thread.start(self.thread_fn)?;
+        Ok(new_thread)
     }
 
     /// `sys_wait4` suspends execution of the calling thread
@@ -157,28 +275,14 @@ impl Syscall<'_> {
     /// - **0**: meaning wait for any child process whose process group ID is equal to
     ///          that of the calling process at the time of the call to `sys_wait4`.
     /// - **>0**: meaning wait for the child whose process ID is equal to the value of `pid`.
+    /// - **<-1**: meaning wait for any child process whose process group ID is equal to
+    ///          the absolute value of `pid`.
     ///
     /// The value of options is an OR of zero or more of the following constants:
     ///
-    /// - **NOHANG**    = 0x000_0001;
-    ///
-    ///   TODO
-    ///
-    /// - **STOPPED**   = 0x000_0002;
-    ///
-    ///   TODO
-    ///
-    /// - **EXITED**    = 0x000_0004;
-    ///
-    ///   TODO
-    ///
-    /// - **CONTINUED** = 0x000_0008;
-    ///
-    ///   TODO
-    ///
-    /// - **NOWAIT**    = 0x100_0000;
-    ///
-    ///   TODO
+    /// - **NOHANG**: return immediately if no child has changed state.
+    /// - **UNTRACED**: also return if a child has stopped.
+    /// - **CONTINUED**: also return if a stopped child has been resumed by delivery of `SIGCONT`.
     ///
     /// On success, returns the process ID of the child whose state has changed;
     /// if `NOHANG` flag was specified and one or more child(ren) specified by pid exist,
@@ -190,41 +294,130 @@ impl Syscall<'_> {
         mut wstatus: UserOutPtr<i32>,
         options: u32,
     ) -> SysResult {
-        #[derive(Debug)]
-        enum WaitTarget {
-            AnyChild,
-            AnyChildInGroup,
-            Pid(KoID),
-        }
-        bitflags! {
-            struct WaitFlags: u32 {
-                const NOHANG    = 1;
-                const STOPPED   = 2;
-                const EXITED    = 4;
-                const CONTINUED = 8;
-                const NOWAIT    = 0x100_0000;
-            }
-        }
         let target = match pid {
             -1 => WaitTarget::AnyChild,
-            0 => WaitTarget::AnyChildInGroup,
+            0 => WaitTarget::Group(self.linux_process().pgid()),
             p if p > 0 => WaitTarget::Pid(p as KoID),
-            _ => unimplemented!(),
+            p => WaitTarget::Group(-p as KoID),
         };
-        let flags = WaitFlags::from_bits_truncate(options);
-        let nohang = flags.contains(WaitFlags::NOHANG);
+        let options = WaitOptions::from_bits_truncate(options);
         info!(
             "wait4: target={:?}, wstatus={:?}, options={:?}",
-            target, wstatus, flags,
+            target, wstatus, options,
+        );
+        let future = wait_child(self.zircon_process(), target, options);
+        match interruptible(self.thread, future).await?? {
+            Some((pid, status)) => {
+                wstatus.write_if_not_null(status.to_wstatus())?;
+                Ok(pid as usize)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// `sys_ptrace` lets the calling process, the tracer, observe and control the execution
+    /// of another process, the tracee
+    /// (see [linux man ptrace(2)](https://www.man7.org/linux/man-pages/man2/ptrace.2.html)).
+    ///
+    /// The tracee stops when a signal is delivered to it, and at the syscalls and the events
+    /// of `fork`, `clone` and `execve` selected by the tracer, which learns the stops
+    /// by [`Self::sys_wait4`]. While the tracee is stopped, the tracer can read and write
+    /// its memory and registers, and then resume it.
+    ///
+    /// The tracee is identified by its process ID, and the requests act on the thread
+    /// in the stop reported to the tracer.
+    ///
+    /// > **NOTE!** `PTRACE_PEEKUSER`, `PTRACE_POKEUSER`, `PTRACE_INTERRUPT` and `PTRACE_LISTEN`
+    /// > are not supported, and single-stepping is only supported on x86_64.
+    pub fn sys_ptrace(&self, request: usize, pid: usize, addr: usize, data: usize) -> SysResult {
+        let request = PtraceRequest::try_from(request).map_err(|_| LxError::EIO)?;
+        info!(
+            "ptrace: request={:?}, pid={}, addr={:#x}, data={:#x}",
+            request, pid, addr, data
         );
-        let (pid, code) = match target {
-            WaitTarget::AnyChild | WaitTarget::AnyChildInGroup => {
-                wait_child_any(self.zircon_process(), nohang).await?
+        let proc = self.zircon_process();
+        let options = || {
+            let options = PtraceOptions::from_bits_truncate(data);
+            if options.bits() != data {
+                warn!("ptrace: unsupported options {:#x}", data & !options.bits());
             }
-            WaitTarget::Pid(pid) => (pid, wait_child(self.zircon_process(), pid, nohang).await?),
+            options
         };
-        wstatus.write_if_not_null(code)?;
-        Ok(pid as usize)
+        match request {
+            PtraceRequest::TRACEME => {
+                ptrace::trace_me(proc)?;
+                return Ok(0);
+            }
+            PtraceRequest::ATTACH | PtraceRequest::SEIZE => {
+                let tracee = proc
+                    .job()
+                    .get_child(pid as KoID)
+                    .ok()
+                    .and_then(|obj| obj.downcast_arc::<Process>().ok())
+                    .ok_or(LxError::ESRCH)?;
+                if request == PtraceRequest::SEIZE {
+                    ptrace::attach(proc, &tracee, options(), true)?;
+                } else {
+                    ptrace::attach(proc, &tracee, PtraceOptions::empty(), false)?;
+                }
+                return Ok(0);
+            }
+            _ => {}
+        }
+        let tracee = ptrace::tracee(proc, pid as KoID)?;
+        let signal = || match data {
+            0 => Ok(None),
+            signum => LinuxSignal::try_from(signum as u8)
+                .map(Some)
+                .map_err(|_| LxError::EIO),
+        };
+        match request {
+            PtraceRequest::PEEKTEXT | PtraceRequest::PEEKDATA => {
+                let word = ptrace::peek(&tracee, addr)?;
+                UserOutPtr::<usize>::from(data).write(word)?;
+            }
+            PtraceRequest::POKETEXT | PtraceRequest::POKEDATA => ptrace::poke(&tracee, addr, data)?,
+            PtraceRequest::GETREGS => {
+                UserOutPtr::<PtraceRegs>::from(data).write(ptrace::get_regs(&tracee)?)?;
+            }
+            PtraceRequest::SETREGS => {
+                let regs = UserInPtr::<PtraceRegs>::from(data).read()?;
+                ptrace::set_regs(&tracee, &regs)?;
+            }
+            PtraceRequest::GETREGSET | PtraceRequest::SETREGSET => {
+                // only the general registers, `NT_PRSTATUS`
+                const NT_PRSTATUS: usize = 1;
+                if addr != NT_PRSTATUS {
+                    return Err(LxError::EINVAL);
+                }
+                let mut iov = UserInOutPtr::<[usize; 2]>::from(data);
+                let [base, len] = iov.read()?;
+                if len < size_of::<PtraceRegs>() {
+                    return Err(LxError::EINVAL);
+                }
+                if request == PtraceRequest::GETREGSET {
+                    UserOutPtr::<PtraceRegs>::from(base).write(ptrace::get_regs(&tracee)?)?;
+                    iov.write([base, size_of::<PtraceRegs>()])?;
+                } else {
+                    let regs = UserInPtr::<PtraceRegs>::from(base).read()?;
+                    ptrace::set_regs(&tracee, &regs)?;
+                }
+            }
+            PtraceRequest::GETSIGINFO => {
+                UserOutPtr::<SigInfo>::from(data).write(ptrace::siginfo(&tracee)?)?;
+            }
+            PtraceRequest::GETEVENTMSG => {
+                UserOutPtr::<usize>::from(data).write(ptrace::event_msg(&tracee)?)?;
+            }
+            PtraceRequest::SETOPTIONS => ptrace::set_options(&tracee, options())?,
+            PtraceRequest::CONT => ptrace::resume(&tracee, Resume::Continue, signal()?)?,
+            PtraceRequest::SYSCALL => ptrace::resume(&tracee, Resume::Syscall, signal()?)?,
+            PtraceRequest::SINGLESTEP => ptrace::resume(&tracee, Resume::SingleStep, signal()?)?,
+            PtraceRequest::DETACH => ptrace::detach(proc, &tracee, signal()?)?,
+            PtraceRequest::KILL => exit_by_signal(&tracee, LinuxSignal::SIGKILL),
+            PtraceRequest::TRACEME | PtraceRequest::ATTACH | PtraceRequest::SEIZE => unreachable!(),
+        }
+        Ok(0)
     }
 
     /// `sys_execve` executes the program referred to by `path`
@@ -248,7 +441,7 @@ impl Syscall<'_> {
     ///             A call to any exec function from a process with more than one thread
     ///             shall result in all threads being terminated and the new executable image
     ///             being loaded and executed.
-    pub fn sys_execve(
+    pub async fn sys_execve(
         &mut self,
         path: UserInPtr<u8>,
         argv: UserInPtr<UserInPtr<u8>>,
@@ -271,31 +464,70 @@ impl Syscall<'_> {
         // Read program file
         let proc = self.linux_process();
         let inode = proc.lookup_inode(path)?;
+        let info = inode.metadata()?;
+        if info.type_ != FileType::File {
+            return Err(LxError::EACCES);
+        }
+        check_permission(&proc.credentials(), &info, Access::EXEC)?;
         let data = inode.read_as_vec()?;
 
+        // the new program gets its own tables even if they were shared by `clone`
+        proc.unshare_for_exec();
         proc.remove_cloexec_files();
 
         // 注意！即将销毁旧应用程序的用户空间，现在将必要的信息拷贝到内核！
         // Notice! About to destroy the user space of the old application, now copy the necessary information into kernel!
         let path = path.to_string();
-        let vmar = self.zircon_process().vmar();
-        vmar.clear()?;
+        proc.remove_file_mappings(0, usize::MAX)?;
+        let zircon_proc = self.zircon_process();
+        let mut vmar = zircon_proc.vmar();
+        let shared = job_processes(&zircon_proc.job())
+            .iter()
+            .any(|other| other.id() != zircon_proc.id() && Arc::ptr_eq(&other.vmar(), &vmar));
+        if shared {
+            // the memory still belongs to the processes created by `clone(CLONE_VM)`,
+            // e.g. the parent of `vfork`, so switch to a new address space instead
+            vmar = VmAddressRegion::new_root();
+            zircon_proc.replace_vmar(vmar.clone());
+            // take the new page table into use
+            kernel_hal::thread::yield_now().await;
+        } else {
+            vmar.clear()?;
+        }
 
         // Modify exec path
         proc.set_execute_path(&path);
+        proc.set_args(&args, &envs);
 
-        let (entry, sp) = LinuxElfLoader {
+        // Run as the owner of the file if it has the set-user-ID bit,
+        // and save the effective IDs for switching back and forth.
+        // The bits are ignored while an unprivileged tracer could write into the process.
+        let mut cred = proc.credentials();
+        let mode = info.mode as u32;
+        let gain = ptrace::may_gain_privileges(&zircon_proc);
+        if gain && mode & S_ISUID != 0 {
+            cred.euid = info.uid as u32;
+        }
+        if gain && mode & S_ISGID != 0 && mode & 0o010 != 0 {
+            cred.egid = info.gid as u32;
+        }
+        cred.suid = cred.euid;
+        cred.sgid = cred.egid;
+        proc.set_credentials(cred);
+
+        let (entry, sp, heap_start) = LinuxElfLoader {
             syscall_entry: self.syscall_entry,
             stack_pages: USER_STACK_PAGES,
-            root_inode: proc.root_inode().clone(),
+            proc: zircon_proc.clone(),
         }
         .load(&vmar, &data, args, envs, path)?;
+        proc.reset_heap(heap_start);
 
-        // TODO: use right signal
-        // self.zircon_process().signal_set(Signal::SIGNALED);
-        // Workaround, the child process could NOT exit correctly
+        // release the parent waiting in `vfork`
+        zircon_proc.signal_set(Signal::VFORK_DONE);
         self.thread
             .with_context(|ctx| ctx.setup_uspace(entry, sp, &[0, 0, 0]))?;
+        ptrace::trace_exec(self.thread);
         Ok(0)
     }
 
@@ -335,6 +567,326 @@ impl Syscall<'_> {
         Ok(ppid as usize)
     }
 
+    /// Sets the process group ID of the process specified by `pid` to `pgid`
+    /// (see [linux man setpgid(2)](https://www.man7.org/linux/man-pages/man2/setpgid.2.html)).
+    ///
+    /// If `pid` is 0, the calling process is used. If `pgid` is 0, the process ID
+    /// of the process is used, which makes it the leader of a new process group.
+    /// The process must be the caller or a child of it in the same session, and the
+    /// process group must exist in the same session unless it is newly created.
+    pub fn sys_setpgid(&self, pid: usize, pgid: usize) -> SysResult {
+        info!("setpgid: pid={}, pgid={}", pid as i32, pgid as i32);
+        if (pgid as i32) < 0 {
+            return Err(LxError::EINVAL);
+        }
+        let proc = self.zircon_process();
+        let linux_proc = self.linux_process();
+        let target = if pid == 0 || pid as KoID == proc.id() {
+            proc.clone()
+        } else {
+            linux_proc.child(pid as KoID).ok_or(LxError::ESRCH)?
+        };
+        let pgid = if pgid == 0 { target.id() } else { pgid as KoID };
+        let sid = linux_proc.sid();
+        let target_linux = target.linux();
+        if target_linux.sid() != sid || target_linux.sid() == target.id() {
+            return Err(LxError::EPERM);
+        }
+        if pgid != target.id()
+            && !job_processes(&proc.job())
+                .iter()
+                .any(|p| p.linux().pgid() == pgid && p.linux().sid() == sid)
+        {
+            return Err(LxError::EPERM);
+        }
+        target_linux.set_pgid(pgid);
+        Ok(0)
+    }
+
+    /// Returns the process group ID of the process specified by `pid`,
+    /// or the calling process if `pid` is 0.
+    pub fn sys_getpgid(&self, pid: usize) -> SysResult {
+        info!("getpgid: pid={}", pid as i32);
+        let proc = self.find_process(pid)?;
+        let pgid = proc.linux().pgid();
+        Ok(pgid as usize)
+    }
+
+    /// Returns the process group ID of the calling process.
+    pub fn sys_getpgrp(&self) -> SysResult {
+        self.sys_getpgid(0)
+    }
+
+    /// Creates a new session if the calling process is not a process group leader
+    /// (see [linux man setsid(2)](https://www.man7.org/linux/man-pages/man2/setsid.2.html)).
+    ///
+    /// The calling process is the leader of the new session and of a new process group,
+    /// both have the same ID as the process. Returns the new session ID.
+    pub fn sys_setsid(&self) -> SysResult {
+        info!("setsid:");
+        let proc = self.zircon_process();
+        let pid = proc.id();
+        if job_processes(&proc.job())
+            .iter()
+            .any(|p| p.linux().pgid() == pid)
+        {
+            return Err(LxError::EPERM);
+        }
+        self.linux_process().set_session(pid);
+        Ok(pid as usize)
+    }
+
+    /// Returns the session ID of the process specified by `pid`,
+    /// or the calling process if `pid` is 0.
+    pub fn sys_getsid(&self, pid: usize) -> SysResult {
+        info!("getsid: pid={}", pid as i32);
+        let proc = self.find_process(pid)?;
+        let sid = proc.linux().sid();
+        Ok(sid as usize)
+    }
+
+    /// Find the process with `pid` in the job of the calling process,
+    /// or the calling process itself if `pid` is 0.
+    fn find_process(&self, pid: usize) -> LxResult<Arc<Process>> {
+        let proc = self.zircon_process();
+        if pid == 0 {
+            return Ok(proc.clone());
+        }
+        proc.job()
+            .get_child(pid as KoID)
+            .ok()
+            .and_then(|obj| obj.downcast_arc::<Process>().ok())
+            .ok_or(LxError::ESRCH)
+    }
+
+    /// Returns the real user ID of the calling process.
+    pub fn sys_getuid(&self) -> SysResult {
+        Ok(self.linux_process().credentials().uid as usize)
+    }
+
+    /// Returns the effective user ID of the calling process.
+    pub fn sys_geteuid(&self) -> SysResult {
+        Ok(self.linux_process().credentials().euid as usize)
+    }
+
+    /// Returns the real group ID of the calling process.
+    pub fn sys_getgid(&self) -> SysResult {
+        Ok(self.linux_process().credentials().gid as usize)
+    }
+
+    /// Returns the effective group ID of the calling process.
+    pub fn sys_getegid(&self) -> SysResult {
+        Ok(self.linux_process().credentials().egid as usize)
+    }
+
+    /// Sets the effective user ID of the calling process
+    /// (see [linux man setuid(2)](https://www.man7.org/linux/man-pages/man2/setuid.2.html)).
+    ///
+    /// If the caller is privileged, the real and saved set-user-ID are also set.
+    /// Otherwise `uid` must be the real or saved set-user-ID.
+    pub fn sys_setuid(&self, uid: usize) -> SysResult {
+        info!("setuid: uid={}", uid as i32);
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let uid = uid as u32;
+        if uid == u32::MAX {
+            return Err(LxError::EINVAL);
+        }
+        if cred.is_root() {
+            cred.uid = uid;
+            cred.suid = uid;
+        } else if uid != cred.uid && uid != cred.suid {
+            return Err(LxError::EPERM);
+        }
+        cred.euid = uid;
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Sets the effective group ID of the calling process, see [`Self::sys_setuid`].
+    pub fn sys_setgid(&self, gid: usize) -> SysResult {
+        info!("setgid: gid={}", gid as i32);
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let gid = gid as u32;
+        if gid == u32::MAX {
+            return Err(LxError::EINVAL);
+        }
+        if cred.is_root() {
+            cred.gid = gid;
+            cred.sgid = gid;
+        } else if gid != cred.gid && gid != cred.sgid {
+            return Err(LxError::EPERM);
+        }
+        cred.egid = gid;
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Sets the real and effective user IDs of the calling process
+    /// (see [linux man setreuid(2)](https://www.man7.org/linux/man-pages/man2/setreuid.2.html)).
+    ///
+    /// An ID of -1 is not changed. If the real user ID is set, or the effective user ID
+    /// is set to a value other than the previous real user ID, the saved set-user-ID
+    /// is set to the new effective user ID.
+    pub fn sys_setreuid(&self, ruid: usize, euid: usize) -> SysResult {
+        info!("setreuid: ruid={}, euid={}", ruid as i32, euid as i32);
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let (ruid, euid) = (ruid as u32, euid as u32);
+        if !cred.is_root() {
+            let ruid_ok = ruid == u32::MAX || ruid == cred.uid || ruid == cred.euid;
+            let euid_ok = euid == u32::MAX || [cred.uid, cred.euid, cred.suid].contains(&euid);
+            if !ruid_ok || !euid_ok {
+                return Err(LxError::EPERM);
+            }
+        }
+        let old_uid = cred.uid;
+        if ruid != u32::MAX {
+            cred.uid = ruid;
+        }
+        if euid != u32::MAX {
+            cred.euid = euid;
+        }
+        if ruid != u32::MAX || (euid != u32::MAX && euid != old_uid) {
+            cred.suid = cred.euid;
+        }
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Sets the real and effective group IDs of the calling process, see [`Self::sys_setreuid`].
+    pub fn sys_setregid(&self, rgid: usize, egid: usize) -> SysResult {
+        info!("setregid: rgid={}, egid={}", rgid as i32, egid as i32);
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let (rgid, egid) = (rgid as u32, egid as u32);
+        if !cred.is_root() {
+            let rgid_ok = rgid == u32::MAX || rgid == cred.gid || rgid == cred.egid;
+            let egid_ok = egid == u32::MAX || [cred.gid, cred.egid, cred.sgid].contains(&egid);
+            if !rgid_ok || !egid_ok {
+                return Err(LxError::EPERM);
+            }
+        }
+        let old_gid = cred.gid;
+        if rgid != u32::MAX {
+            cred.gid = rgid;
+        }
+        if egid != u32::MAX {
+            cred.egid = egid;
+        }
+        if rgid != u32::MAX || (egid != u32::MAX && egid != old_gid) {
+            cred.sgid = cred.egid;
+        }
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Sets the real, effective and saved set-user-ID of the calling process
+    /// (see [linux man setresuid(2)](https://www.man7.org/linux/man-pages/man2/setresuid.2.html)).
+    ///
+    /// An ID of -1 is not changed. An unprivileged process can only set each ID
+    /// to one of its current real, effective and saved set-user-ID.
+    pub fn sys_setresuid(&self, ruid: usize, euid: usize, suid: usize) -> SysResult {
+        info!(
+            "setresuid: ruid={}, euid={}, suid={}",
+            ruid as i32, euid as i32, suid as i32
+        );
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let ids = [cred.uid, cred.euid, cred.suid];
+        let new_ids = set_res_ids(ids, [ruid, euid, suid], cred.is_root())?;
+        cred.uid = new_ids[0];
+        cred.euid = new_ids[1];
+        cred.suid = new_ids[2];
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Sets the real, effective and saved set-group-ID of the calling process,
+    /// see [`Self::sys_setresuid`].
+    pub fn sys_setresgid(&self, rgid: usize, egid: usize, sgid: usize) -> SysResult {
+        info!(
+            "setresgid: rgid={}, egid={}, sgid={}",
+            rgid as i32, egid as i32, sgid as i32
+        );
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        let ids = [cred.gid, cred.egid, cred.sgid];
+        let new_ids = set_res_ids(ids, [rgid, egid, sgid], cred.is_root())?;
+        cred.gid = new_ids[0];
+        cred.egid = new_ids[1];
+        cred.sgid = new_ids[2];
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
+    /// Gets the real, effective and saved set-user-ID of the calling process.
+    pub fn sys_getresuid(
+        &self,
+        mut ruid: UserOutPtr<u32>,
+        mut euid: UserOutPtr<u32>,
+        mut suid: UserOutPtr<u32>,
+    ) -> SysResult {
+        let cred = self.linux_process().credentials();
+        ruid.write(cred.uid)?;
+        euid.write(cred.euid)?;
+        suid.write(cred.suid)?;
+        Ok(0)
+    }
+
+    /// Gets the real, effective and saved set-group-ID of the calling process.
+    pub fn sys_getresgid(
+        &self,
+        mut rgid: UserOutPtr<u32>,
+        mut egid: UserOutPtr<u32>,
+        mut sgid: UserOutPtr<u32>,
+    ) -> SysResult {
+        let cred = self.linux_process().credentials();
+        rgid.write(cred.gid)?;
+        egid.write(cred.egid)?;
+        sgid.write(cred.sgid)?;
+        Ok(0)
+    }
+
+    /// Gets the supplementary group IDs of the calling process
+    /// (see [linux man getgroups(2)](https://www.man7.org/linux/man-pages/man2/getgroups.2.html)).
+    ///
+    /// If `size` is 0, only the number of groups is returned.
+    pub fn sys_getgroups(&self, size: usize, mut list: UserOutPtr<u32>) -> SysResult {
+        info!("getgroups: size={}, list={:?}", size as i32, list);
+        let groups = self.linux_process().credentials().groups;
+        if size == 0 {
+            return Ok(groups.len());
+        }
+        if size < groups.len() {
+            return Err(LxError::EINVAL);
+        }
+        list.write_array(&groups)?;
+        Ok(groups.len())
+    }
+
+    /// Sets the supplementary group IDs of the calling process, which must be privileged.
+    pub fn sys_setgroups(&self, size: usize, list: UserInPtr<u32>) -> SysResult {
+        info!("setgroups: size={}, list={:?}", size, list);
+        const NGROUPS_MAX: usize = 65536;
+        if size > NGROUPS_MAX {
+            return Err(LxError::EINVAL);
+        }
+        let proc = self.linux_process();
+        let mut cred = proc.credentials();
+        if !cred.is_root() {
+            return Err(LxError::EPERM);
+        }
+        cred.groups = if size == 0 {
+            Vec::new()
+        } else {
+            list.read_array(size)?
+        };
+        proc.set_credentials(cred);
+        Ok(0)
+    }
+
     /// `sys_exit` system call terminates only the calling thread
     /// (see [linux man _exit(2)](https://www.man7.org/linux/man-pages/man2/exit.2.html),
     /// this syscall is same as a raw `_exit` in glibc),
@@ -369,12 +921,16 @@ impl Syscall<'_> {
     /// in the calling thread or that terminates the process.
     ///
     /// To represent a duration, see TimeSpec.
-    pub async fn sys_nanosleep(&self, req: UserInPtr<TimeSpec>) -> SysResult {
-        info!("nanosleep: deadline={:?}", req);
-        let duration = req.read()?.into();
-        use kernel_hal::{thread, timer};
-        thread::sleep_until(timer::deadline_after(duration)).await;
-        Ok(0)
+    /// If interrupted by a signal, it fails with `EINTR`,
+    /// and the remaining time is written to `rem` if it is not null.
+    pub async fn sys_nanosleep(
+        &self,
+        req: UserInPtr<TimeSpec>,
+        rem: UserOutPtr<TimeSpec>,
+    ) -> SysResult {
+        info!("nanosleep: req={:?}, rem={:?}", req, rem);
+        // measured by `CLOCK_MONOTONIC`
+        self.sys_clock_nanosleep(1, 0, req, rem).await
     }
 
     //    pub fn sys_set_priority(&self, priority: usize) -> SysResult {
@@ -416,55 +972,156 @@ impl Syscall<'_> {
     }
 }
 
-bitflags! {
-    pub struct CloneFlags: usize {
-        ///
-        const CSIGNAL =         0xff;
-        /// the calling process and the child process run in the same memory space
-        const VM =              1 << 8;
-        /// the caller and the child process share the same filesystem information
-        const FS =              1 << 9;
-        /// the calling process and the child process share the same file descriptor table
-        const FILES =           1 << 10;
-        /// the calling process and the child process share the same table of signal handlers.
-        const SIGHAND =         1 << 11;
-        /// the calling process is being traced
-        const PTRACE =          1 << 13;
-        /// the execution of the calling process is suspended until the child releases its virtual memory resources
-        const VFORK =           1 << 14;
-        /// the parent of the new child will be the same as that of the call‐ing process.
-        const PARENT =          1 << 15;
-        /// the child is placed in the same thread group as the calling process.
-        const THREAD =          1 << 16;
-        /// cloned child is started in a new mount namespace
-        const NEWNS	=           1 << 17;
-        /// the child and the calling process share a single list of System V semaphore adjustment values.
-        const SYSVSEM =         1 << 18;
-        /// architecture dependent, The TLS (Thread Local Storage) descriptor is set to tls.
-        const SETTLS =          1 << 19;
-        /// Store the child thread ID at the location in the parent's memory.
-        const PARENT_SETTID =   1 << 20;
-        /// Clear (zero) the child thread ID
-        const CHILD_CLEARTID =  1 << 21;
-        /// the parent not to receive a signal when the child terminated
-        const DETACHED =        1 << 22;
-        /// a tracing process cannot force CLONE_PTRACE on this child process.
-        const UNTRACED =        1 << 23;
-        /// Store the child thread ID
-        const CHILD_SETTID =    1 << 24;
-        /// Create the process in a new cgroup namespace.
-        const NEWCGROUP =       1 << 25;
-        /// create the process in a new UTS namespace
-        const NEWUTS =          1 << 26;
-        /// create the process in a new IPC namespace.
-        const NEWIPC =          1 << 27;
-        /// create the process in a new user namespace
-        const NEWUSER =         1 << 28;
-        /// create the process in a new PID namespace
-        const NEWPID =          1 << 29;
-        /// create the process in a new net‐work namespace.
-        const NEWNET =          1 << 30;
-        /// the new process shares an I/O context with the calling process.
-        const IO =              1 << 31;
+/// Compute the new (real, effective, saved) IDs for `setresuid` and `setresgid`.
+///
+/// An ID of -1 in `new` is not changed.
+fn set_res_ids(ids: [u32; 3], new: [usize; 3], privileged: bool) -> LxResult<[u32; 3]> {
+    let mut result = ids;
+    for (id, &new) in result.iter_mut().zip(new.iter()) {
+        let new = new as u32;
+        if new == u32::MAX {
+            continue;
+        }
+        if !privileged && !ids.contains(&new) {
+            return Err(LxError::EPERM);
+        }
+        *id = new;
+    }
+    Ok(result)
+}
+
+/// Arguments of `clone3`
+#[derive(Debug, Default)]
+struct CloneArgs {
+    flags: u64,
+    child_tid: u64,
+    parent_tid: u64,
+    exit_signal: u64,
+    stack: u64,
+    stack_size: u64,
+    tls: u64,
+    set_tid: u64,
+    set_tid_size: u64,
+    cgroup: u64,
+}
+
+impl CloneArgs {
+    /// Size of the first published version of the structure
+    const SIZE_VER0: usize = 64;
+    /// Size of the structure known to the kernel
+    const SIZE: usize = 88;
+
+    /// Read the structure of `size` bytes at `ptr` from user space.
+    fn read(ptr: UserInPtr<u64>, size: usize) -> LxResult<Self> {
+        if size > PAGE_SIZE {
+            return Err(LxError::E2BIG);
+        }
+        if size < Self::SIZE_VER0 || size % size_of::<u64>() != 0 {
+            return Err(LxError::EINVAL);
+        }
+        let count = size / size_of::<u64>();
+        let mut fields = ptr.read_array(count)?;
+        if fields
+            .iter()
+            .skip(Self::SIZE / size_of::<u64>())
+            .any(|&x| x != 0)
+        {
+            return Err(LxError::E2BIG);
+        }
+        fields.resize(Self::SIZE / size_of::<u64>(), 0);
+        Ok(CloneArgs {
+            flags: fields[0],
+            // fields[1] is where to store the pidfd, which is not supported
+            child_tid: fields[2],
+            parent_tid: fields[3],
+            exit_signal: fields[4],
+            stack: fields[5],
+            stack_size: fields[6],
+            tls: fields[7],
+            set_tid: fields[8],
+            set_tid_size: fields[9],
+            cgroup: fields[10],
+        })
+    }
+}
+
+numeric_enum! {
+    #[repr(usize)]
+    #[allow(non_camel_case_types)]
+    #[derive(Eq, PartialEq, Debug, Copy, Clone)]
+    /// request of ptrace()
+    enum PtraceRequest {
+        /// be traced by the parent
+        TRACEME = 0,
+        /// read a word in the text of the tracee
+        PEEKTEXT = 1,
+        /// read a word in the data of the tracee
+        PEEKDATA = 2,
+        /// write a word in the text of the tracee
+        POKETEXT = 4,
+        /// write a word in the data of the tracee
+        POKEDATA = 5,
+        /// resume the tracee
+        CONT = 7,
+        /// kill the tracee
+        KILL = 8,
+        /// resume the tracee, and stop it after an instruction
+        SINGLESTEP = 9,
+        /// read the general registers of the tracee
+        GETREGS = 12,
+        /// write the general registers of the tracee
+        SETREGS = 13,
+        /// trace a process, and stop it by `SIGSTOP`
+        ATTACH = 16,
+        /// stop tracing the tracee, and resume it
+        DETACH = 17,
+        /// resume the tracee, and stop it at the next entry or exit of a syscall
+        SYSCALL = 24,
+        /// set the options of the tracee
+        SETOPTIONS = 0x4200,
+        /// read the message of the latest event stop
+        GETEVENTMSG = 0x4201,
+        /// read the signal of the tracee in a stop
+        GETSIGINFO = 0x4202,
+        /// read a set of registers of the tracee
+        GETREGSET = 0x4204,
+        /// write a set of registers of the tracee
+        SETREGSET = 0x4205,
+        /// trace a process without stopping it
+        SEIZE = 0x4206,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// -1, which keeps the ID
+    const KEEP: usize = usize::MAX;
+
+    #[test]
+    fn unprivileged_res_ids() {
+        let ids = [1000, 1001, 1002];
+        // any of the current IDs can be picked, in any position
+        let new = set_res_ids(ids, [1002, KEEP, 1000], false).unwrap();
+        assert_eq!(new, [1002, 1001, 1000]);
+        let new = set_res_ids(ids, [1001, 1002, 1000], false).unwrap();
+        assert_eq!(new, [1001, 1002, 1000]);
+        assert_eq!(set_res_ids(ids, [KEEP; 3], false).unwrap(), ids);
+        // but not other IDs
+        let ret = set_res_ids(ids, [KEEP, 0, KEEP], false);
+        assert!(matches!(ret, Err(LxError::EPERM)));
+        let ret = set_res_ids(ids, [1000, 1000, 2000], false);
+        assert!(matches!(ret, Err(LxError::EPERM)));
+    }
+
+    #[test]
+    fn privileged_res_ids() {
+        let ids = [0, 0, 0];
+        let new = set_res_ids(ids, [1000, 2000, KEEP], true).unwrap();
+        assert_eq!(new, [1000, 2000, 0]);
+        // -1 passed as a 32-bit integer also keeps the ID
+        let new = set_res_ids(ids, [u32::MAX as usize, 3000, 3000], true).unwrap();
+        assert_eq!(new, [0, 3000, 3000]);
     }
 }
@@ -1,11 +1,26 @@
 //! Syscalls for time
-//! - clock_gettime
+//! - clock_gettime, clock_getres
+//! - getitimer, setitimer, alarm
+//! - timer_create, timer_settime, timer_gettime, timer_getoverrun, timer_delete
+//! - timerfd_create, timerfd_settime, timerfd_gettime
 //!
 use crate::Syscall;
+use alloc::sync::Arc;
+use core::convert::TryFrom;
+use core::time::Duration;
 use kernel_hal::{user::UserInPtr, user::UserOutPtr};
 use linux_object::error::LxError;
+use linux_object::error::LxResult;
 use linux_object::error::SysResult;
+use linux_object::fs::{FileDesc, TimerFd, TimerFdFlags, TimerSetFlags};
+use linux_object::process::itimer;
+use linux_object::signal::{
+    ITimerWhich, SigEvent, Signal, SignalTimer, TimerTarget, SIGEV_NONE, SIGEV_SIGNAL,
+    SIGEV_THREAD, SIGEV_THREAD_ID,
+};
+use linux_object::thread::interruptible;
 use linux_object::time::*;
+use zircon_object::task::Thread;
 
 const USEC_PER_TICK: usize = 10000;
 
@@ -26,6 +41,19 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// finds the resolution (precision) of the specified clock clockid, and,
+    /// if `res` is non-NULL, stores it in the struct timespec pointed to by `res`
+    pub fn sys_clock_getres(&self, clock: usize, mut res: UserOutPtr<TimeSpec>) -> SysResult {
+        info!("clock_getres: id={:?} res={:?}", clock, res);
+        // CLOCK_REALTIME to CLOCK_BOOTTIME_ALARM
+        if clock > 9 {
+            return Err(LxError::EINVAL);
+        }
+        // all clocks are read from the hardware timer in nanoseconds
+        res.write_if_not_null(TimeSpec { sec: 0, nsec: 1 })?;
+        Ok(0)
+    }
+
     /// get the time with second and microseconds
     pub fn sys_gettimeofday(
         &mut self,
@@ -115,13 +143,18 @@ impl Syscall<'_> {
         Ok(tick as usize)
     }
 
-    /// clock nanosleep
+    /// Sleep for an interval, or until an absolute time with `TIMER_ABSTIME` in `flags`,
+    /// as measured by the clock `clockid`
+    /// (see [linux man clock_nanosleep(2)](https://www.man7.org/linux/man-pages/man2/clock_nanosleep.2.html)).
+    ///
+    /// A signal to be handled interrupts the sleep with `EINTR`, which is never restarted,
+    /// and the remaining time of an interval is written to `rem` if it is not null.
     pub async fn sys_clock_nanosleep(
         &self,
         clockid: usize,
         flags: usize,
         req: UserInPtr<TimeSpec>,
-        rem: UserOutPtr<TimeSpec>,
+        mut rem: UserOutPtr<TimeSpec>,
     ) -> SysResult {
         info!(
             "clock_nanosleep: clockid={:?}, flags={:?}, req={:?}, rem={:?}",
@@ -130,41 +163,258 @@ impl Syscall<'_> {
             req.read()?,
             rem
         );
-        use core::time::Duration;
         use kernel_hal::{thread, timer};
-        let duration: Duration = req.read()?.into();
-        let clockid = ClockId::from(clockid);
+        let req = req.read()?;
+        if !req.is_valid() {
+            return Err(LxError::EINVAL);
+        }
+        if clockid > 9 || matches!(ClockId::from(clockid), ClockId::ClockThreadCpuTimeId) {
+            return Err(LxError::EINVAL);
+        }
+        // only `TIMER_ABSTIME` is defined
+        if flags > ClockFlags::TimerAbsTime as usize {
+            return Err(LxError::EINVAL);
+        }
         let flags = ClockFlags::from(flags);
-        info!("clockid={:?}, flags={:?}", clockid, flags,);
-        match clockid {
-            ClockId::ClockRealTime => {
-                match flags {
-                    ClockFlags::ZeroFlag => {
-                        thread::sleep_until(timer::deadline_after(duration)).await;
-                    }
-                    ClockFlags::TimerAbsTime => {
-                        // 目前统一由nanosleep代替了、之后再修改
-                        thread::sleep_until(timer::deadline_after(duration)).await;
-                    }
-                }
-            }
-            ClockId::ClockMonotonic => match flags {
-                ClockFlags::ZeroFlag => {
-                    thread::sleep_until(timer::deadline_after(duration)).await;
+        // all clocks are read from the hardware timer
+        let duration: Duration = req.into();
+        let deadline = match flags {
+            ClockFlags::ZeroFlag => timer::deadline_after(duration),
+            ClockFlags::TimerAbsTime => duration,
+        };
+        if interruptible(self.thread, thread::sleep_until(deadline))
+            .await
+            .is_ok()
+        {
+            return Ok(0);
+        }
+        if let ClockFlags::ZeroFlag = flags {
+            let remaining = deadline.saturating_sub(timer::timer_now());
+            rem.write_if_not_null(remaining.into())?;
+        }
+        Err(LxError::EINTR)
+    }
+
+    /// create a timer that delivers timer expiration notifications via a file descriptor
+    pub fn sys_timerfd_create(&self, clockid: usize, flags: usize) -> SysResult {
+        info!("timerfd_create: clockid={}, flags={:#x}", clockid, flags);
+        // only CLOCK_REALTIME, CLOCK_MONOTONIC, CLOCK_BOOTTIME and the alarm clocks
+        if !matches!(clockid, 0 | 1 | 7 | 8 | 9) {
+            return Err(LxError::EINVAL);
+        }
+        let flags = TimerFdFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        let timerfd = TimerFd::new(flags);
+        let fd = self.linux_process().add_file(timerfd)?;
+        Ok(fd.into())
+    }
+
+    /// arm or disarm the timer referred to by the file descriptor `fd`
+    pub fn sys_timerfd_settime(
+        &self,
+        fd: FileDesc,
+        flags: usize,
+        new_value: UserInPtr<ITimerSpec>,
+        mut old_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timerfd_settime: fd={:?}, flags={:#x}, new_value={:?}, old_value={:?}",
+            fd, flags, new_value, old_value
+        );
+        let flags = TimerSetFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        let new_value = new_value.read()?;
+        if !new_value.value.is_valid() || !new_value.interval.is_valid() {
+            return Err(LxError::EINVAL);
+        }
+        let file = self.linux_process().get_file_like(fd)?;
+        let timerfd = file.downcast_ref::<TimerFd>().ok_or(LxError::EINVAL)?;
+        let old = timerfd.set(flags, new_value)?;
+        old_value.write_if_not_null(old)?;
+        Ok(0)
+    }
+
+    /// get the current setting of the timer referred to by the file descriptor `fd`
+    pub fn sys_timerfd_gettime(
+        &self,
+        fd: FileDesc,
+        mut curr_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!("timerfd_gettime: fd={:?}, curr_value={:?}", fd, curr_value);
+        let file = self.linux_process().get_file_like(fd)?;
+        let timerfd = file.downcast_ref::<TimerFd>().ok_or(LxError::EINVAL)?;
+        curr_value.write(timerfd.get())?;
+        Ok(0)
+    }
+
+    /// get the value of the interval timer `which`
+    pub fn sys_getitimer(&self, which: usize, mut curr_value: UserOutPtr<ITimerVal>) -> SysResult {
+        info!("getitimer: which={}, curr_value={:?}", which, curr_value);
+        let which = itimer_which(which)?;
+        let value = itimer(self.zircon_process(), which).get();
+        curr_value.write(value.into())?;
+        Ok(0)
+    }
+
+    /// arm or disarm the interval timer `which`, the old value is stored in `old_value`
+    pub fn sys_setitimer(
+        &self,
+        which: usize,
+        new_value: UserInPtr<ITimerVal>,
+        mut old_value: UserOutPtr<ITimerVal>,
+    ) -> SysResult {
+        info!(
+            "setitimer: which={}, new_value={:?}, old_value={:?}",
+            which, new_value, old_value
+        );
+        let which = itimer_which(which)?;
+        // a NULL `new_value` disarms the timer
+        let new_value = if new_value.is_null() {
+            ITimerVal::default()
+        } else {
+            new_value.read()?
+        };
+        if !new_value.value.is_valid() || !new_value.interval.is_valid() {
+            return Err(LxError::EINVAL);
+        }
+        let old = itimer(self.zircon_process(), which).set(new_value.into(), false);
+        old_value.write_if_not_null(old.into())?;
+        Ok(0)
+    }
+
+    /// deliver `SIGALRM` after `seconds`, returns the seconds remaining of the last alarm
+    #[cfg(target_arch = "x86_64")]
+    pub fn sys_alarm(&self, seconds: usize) -> SysResult {
+        info!("alarm: seconds={}", seconds);
+        let new_value = ITimerSpec {
+            interval: TimeSpec::default(),
+            value: TimeSpec {
+                sec: seconds,
+                nsec: 0,
+            },
+        };
+        let old = itimer(self.zircon_process(), ITimerWhich::Real).set(new_value, false);
+        // round to the nearest second, and a pending alarm returns at least 1
+        let remaining = old.value.sec + (old.value.nsec >= 500_000_000) as usize;
+        Ok(if old.value.nsec != 0 {
+            remaining.max(1)
+        } else {
+            remaining
+        })
+    }
+
+    /// create a POSIX per-process timer, the ID of the new timer is stored in `timerid`
+    pub fn sys_timer_create(
+        &self,
+        clockid: usize,
+        sevp: UserInPtr<SigEvent>,
+        mut timerid: UserOutPtr<i32>,
+    ) -> SysResult {
+        info!(
+            "timer_create: clockid={}, sevp={:?}, timerid={:?}",
+            clockid, sevp, timerid
+        );
+        if clockid > 9 {
+            return Err(LxError::EINVAL);
+        }
+        let proc = self.zircon_process();
+        let linux_proc = self.linux_process();
+        let id = if sevp.is_null() {
+            // SIGEV_SIGNAL with SIGALRM and the timer ID as the value
+            linux_proc.add_timer(|id| {
+                let target = TimerTarget::Process(Arc::downgrade(proc));
+                SignalTimer::new(id, Signal::SIGALRM, id, target)
+            })
+        } else {
+            let event = sevp.read()?;
+            let signal = || {
+                u8::try_from(event.signo)
+                    .ok()
+                    .and_then(|signo| Signal::try_from(signo).ok())
+                    .ok_or(LxError::EINVAL)
+            };
+            let (signal, target) = match event.notify {
+                SIGEV_NONE => (Signal::SIGALRM, TimerTarget::None),
+                SIGEV_SIGNAL | SIGEV_THREAD => {
+                    (signal()?, TimerTarget::Process(Arc::downgrade(proc)))
                 }
-                ClockFlags::TimerAbsTime => {
-                    thread::sleep_until(timer::deadline_after(duration)).await;
+                SIGEV_THREAD_ID => {
+                    let thread: Arc<Thread> = proc
+                        .get_child(event.tid as u64)
+                        .ok()
+                        .and_then(|obj| obj.downcast_arc().ok())
+                        .ok_or(LxError::EINVAL)?;
+                    (signal()?, TimerTarget::Thread(Arc::downgrade(&thread)))
                 }
-            },
-            ClockId::ClockProcessCpuTimeId => {}
-            ClockId::ClockThreadCpuTimeId => {}
-            ClockId::ClockMonotonicRaw => {}
-            ClockId::ClockRealTimeCoarse => {}
-            ClockId::ClockMonotonicCoarse => {}
-            ClockId::ClockBootTime => {}
-            ClockId::ClockRealTimeAlarm => {}
-            ClockId::ClockBootTimeAlarm => {}
+                _ => return Err(LxError::EINVAL),
+            };
+            linux_proc.add_timer(|id| SignalTimer::new(id, signal, event.value, target))
+        };
+        if let Err(err) = timerid.write(id as i32) {
+            linux_proc.remove_timer(id)?;
+            return Err(err.into());
         }
         Ok(0)
     }
+
+    /// arm or disarm the POSIX timer `timerid`, the old value is stored in `old_value`
+    pub fn sys_timer_settime(
+        &self,
+        timerid: usize,
+        flags: usize,
+        new_value: UserInPtr<ITimerSpec>,
+        mut old_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timer_settime: timerid={}, flags={:#x}, new_value={:?}, old_value={:?}",
+            timerid, flags, new_value, old_value
+        );
+        let flags = TimerSetFlags::from_bits_truncate(flags);
+        let timer = self.linux_process().get_timer(timerid)?;
+        let new_value = new_value.read()?;
+        if !new_value.value.is_valid() || !new_value.interval.is_valid() {
+            return Err(LxError::EINVAL);
+        }
+        let old = timer.set(new_value, flags.contains(TimerSetFlags::ABSTIME));
+        old_value.write_if_not_null(old)?;
+        Ok(0)
+    }
+
+    /// get the time until the next expiration and the interval of the POSIX timer `timerid`
+    pub fn sys_timer_gettime(
+        &self,
+        timerid: usize,
+        mut curr_value: UserOutPtr<ITimerSpec>,
+    ) -> SysResult {
+        info!(
+            "timer_gettime: timerid={}, curr_value={:?}",
+            timerid, curr_value
+        );
+        let timer = self.linux_process().get_timer(timerid)?;
+        curr_value.write(timer.get())?;
+        Ok(0)
+    }
+
+    /// get the overrun count of the POSIX timer `timerid`
+    pub fn sys_timer_getoverrun(&self, timerid: usize) -> SysResult {
+        info!("timer_getoverrun: timerid={}", timerid);
+        let timer = self.linux_process().get_timer(timerid)?;
+        Ok(timer.overrun().min(i32::MAX as usize))
+    }
+
+    /// delete the POSIX timer `timerid`
+    pub fn sys_timer_delete(&self, timerid: usize) -> SysResult {
+        info!("timer_delete: timerid={}", timerid);
+        self.linux_process().remove_timer(timerid)?;
+        Ok(0)
+    }
+}
+
+/// Parse the interval timer `which`.
+///
+/// The CPU time of processes is not accounted, so `ITIMER_VIRTUAL` and `ITIMER_PROF`
+/// are rejected rather than counting down in real time.
+fn itimer_which(which: usize) -> LxResult<ITimerWhich> {
+    match ITimerWhich::try_from(which) {
+        Ok(ITimerWhich::Real) => Ok(ITimerWhich::Real),
+        _ => Err(LxError::EINVAL),
+    }
 }
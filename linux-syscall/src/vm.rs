@@ -1,15 +1,66 @@
 use super::*;
 use bitflags::bitflags;
-use zircon_object::vm::{pages, MMUFlags, VmObject};
+use linux_object::fs::{vfs::FileType, File, FileLike, FileMapping, MemFd};
+use linux_object::loader::USER_HEAP_PAGES;
+use zircon_object::vm::{pages, roundup_pages, MMUFlags, VmObject, PAGE_SIZE};
+use zircon_object::ZxError;
 
 /// Syscalls for virtual memory.
 ///
 /// # Menu
 ///
+/// - [`brk`](Self::sys_brk)
 /// - [`mmap`](Self::sys_mmap)
 /// - [`mprotect`](Self::sys_mprotect)
+/// - [`mremap`](Self::sys_mremap)
+/// - [`msync`](Self::sys_msync)
+/// - [`madvise`](Self::sys_madvise)
 /// - [`munmap`](Self::sys_munmap)
 impl Syscall<'_> {
+    /// Change the location of the program break
+    /// (see [linux man brk(2)](https://www.man7.org/linux/man-pages/man2/brk.2.html)).
+    ///
+    /// The program break defines the end of the process's data segment (heap).
+    /// `sys_brk` sets the end of the data segment to `addr`,
+    /// when that value is reasonable and the process does not exceed its maximum data size.
+    ///
+    /// Return the new program break on success, or the current one on failure,
+    /// so `sys_brk(0)` can be used to get the current program break.
+    ///
+    /// The heap is reserved right after the loaded image,
+    /// and it can not be larger than [`USER_HEAP_PAGES`] pages.
+    /// Moving the program break only changes the protection of the reserved pages,
+    /// so the heap stays in one accessible mapping followed by the rest of the reservation.
+    pub fn sys_brk(&self, addr: usize) -> SysResult {
+        info!("brk: addr={:#x}", addr);
+        let proc = self.linux_process();
+        let (heap_start, brk) = proc.heap();
+        if addr < heap_start || addr > heap_start + USER_HEAP_PAGES * PAGE_SIZE {
+            return Ok(brk);
+        }
+        let vmar = self.zircon_process().vmar();
+        let old_end = roundup_pages(brk);
+        let new_end = roundup_pages(addr);
+        let res = if new_end > old_end {
+            let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
+            vmar.protect(old_end, new_end - old_end, flags)
+        } else if new_end < old_end {
+            // make the released pages inaccessible again and free them,
+            // so they are filled with zero when the heap grows again
+            let len = old_end - new_end;
+            vmar.protect(new_end, len, MMUFlags::USER)
+                .and_then(|_| vmar.decommit(new_end, len))
+        } else {
+            Ok(())
+        };
+        if let Err(err) = res {
+            warn!("brk: failed to move the program break: {:?}", err);
+            return Ok(brk);
+        }
+        proc.set_brk(addr);
+        Ok(addr)
+    }
+
     /// Map files or devices into memory
     /// (see [linux man mmap(2)](https://www.man7.org/linux/man-pages/man2/mmap.2.html)).
     ///
@@ -45,8 +96,9 @@ impl Syscall<'_> {
     ///
     ///   Share this mapping. Updates to the mapping are visible to other processes mapping the same region,
     ///   and (in the case of file-backed mappings) are carried through to the underlying file.
-    ///   (To precisely control when updates are carried through to the underlying file requires the use of `msync`,
-    ///   which has not been implemented in zcore).
+    ///   The mapping is shared with the child processes after `fork`, instead of copied on write.
+    ///   A regular file is mapped through its page cache, so `read` and `write` of the file are coherent
+    ///   with the mapping. The changes are written back to the file by `msync` and `munmap`.
     ///
     /// - **`MmapFlags::PRIVATE`**
     ///
@@ -69,8 +121,6 @@ impl Syscall<'_> {
     ///
     ///   The mapping is not backed by any file; its contents are initialized to zero.
     ///   Both `fd` and `offset` arguments are ignored.
-    ///   The use of `MmapFlags::ANONYMOUS` in conjunction with `MmapFlags::SHARED`
-    ///   causes an [`EINVAL`](LxError::EINVAL) to be returned.
     pub async fn sys_mmap(
         &self,
         addr: usize,
@@ -87,34 +137,116 @@ impl Syscall<'_> {
             addr, len, prot, flags, fd, offset
         );
 
+        if len == 0 {
+            return Err(LxError::EINVAL);
+        }
         let proc = self.zircon_process();
         let vmar = proc.vmar();
 
         if flags.contains(MmapFlags::FIXED) {
             // unmap first
+            self.linux_process().remove_file_mappings(addr, len)?;
             vmar.unmap(addr, len)?;
         }
         let vmar_offset = flags.contains(MmapFlags::FIXED).then(|| addr - vmar.addr());
-        if flags.contains(MmapFlags::ANONYMOUS) {
-            if flags.contains(MmapFlags::SHARED) {
-                return Err(LxError::EINVAL);
-            }
-            let vmo = VmObject::new_paged(pages(len));
-            let addr = vmar.map(vmar_offset, vmo.clone(), 0, vmo.len(), prot.to_flags())?;
-            Ok(addr)
+        let shared = flags.contains(MmapFlags::SHARED);
+        let vmo = if flags.contains(MmapFlags::ANONYMOUS) {
+            VmObject::new_paged(pages(len))
         } else {
             let file_like = self.linux_process().get_file_like(fd)?;
+            if let Some(file) = file_like.downcast_ref::<File>() {
+                if shared && file.metadata()?.type_ == FileType::File {
+                    return self.mmap_shared_file(file, vmar_offset, len, prot, offset as usize);
+                }
+            }
+            if let Some(memfd) = file_like.downcast_ref::<MemFd>() {
+                return self.mmap_memfd(memfd, vmar_offset, len, prot, shared, offset as usize);
+            }
             let vmo = file_like.get_vmo(offset as usize, len)?;
-            let addr = vmar.map(vmar_offset, vmo.clone(), 0, vmo.len(), prot.to_flags())?;
-            Ok(addr)
+            match file_like.downcast_ref::<File>() {
+                // a private copy of the file, read again after `MADV_DONTNEED`
+                Some(file) if !shared && file.metadata()?.type_ == FileType::File => {
+                    vmo.create_backed_child(0, vmo.len())?
+                }
+                _ => vmo,
+            }
+        };
+        let addr = if shared {
+            vmar.map_shared(vmar_offset, vmo.clone(), 0, vmo.len(), prot.to_flags())?
+        } else {
+            vmar.map(vmar_offset, vmo.clone(), 0, vmo.len(), prot.to_flags())?
+        };
+        Ok(addr)
+    }
+
+    /// Map a regular file shared through its page cache.
+    fn mmap_shared_file(
+        &self,
+        file: &File,
+        vmar_offset: Option<usize>,
+        len: usize,
+        prot: MmapProt,
+        offset: usize,
+    ) -> SysResult {
+        if offset % PAGE_SIZE != 0 {
+            return Err(LxError::EINVAL);
+        }
+        let open_flags = file.flags();
+        if !open_flags.readable() || (prot.contains(MmapProt::WRITE) && !open_flags.writable()) {
+            return Err(LxError::EACCES);
+        }
+        let len = roundup_pages(len);
+        let cache = file.page_cache(offset + len)?;
+        cache.vmo().set_name(file.path());
+        let vmar = self.zircon_process().vmar();
+        let addr = vmar.map_shared(
+            vmar_offset,
+            cache.vmo().clone(),
+            offset,
+            len,
+            prot.to_flags(),
+        )?;
+        self.linux_process().add_file_mapping(FileMapping {
+            addr,
+            len,
+            offset,
+            cache,
+        });
+        Ok(addr)
+    }
+
+    /// Map a memfd, whose VMO is mapped directly by the shared mappings,
+    /// and cloned copy-on-write by the private ones.
+    fn mmap_memfd(
+        &self,
+        memfd: &MemFd,
+        vmar_offset: Option<usize>,
+        len: usize,
+        prot: MmapProt,
+        shared: bool,
+        offset: usize,
+    ) -> SysResult {
+        if !memfd.flags().readable() {
+            return Err(LxError::EACCES);
+        }
+        if shared && prot.contains(MmapProt::WRITE) {
+            memfd.check_mmap_write()?;
         }
+        let vmo = memfd.get_vmo(offset, len)?;
+        let len = roundup_pages(len);
+        let vmar = self.zircon_process().vmar();
+        let addr = if shared {
+            vmar.map_shared(vmar_offset, vmo, offset, len, prot.to_flags())?
+        } else {
+            let child = vmo.create_backed_child(offset, len)?;
+            vmar.map(vmar_offset, child, 0, len, prot.to_flags())?
+        };
+        Ok(addr)
     }
 
     /// Set protection on a region of memory
     /// (see [linux man mprotect(2)](https://www.man7.org/linux/man-pages/man2/mprotect.2.html)).
     ///
-    /// **NOTE!** This syscall is now unimplemented. Calling it always return `Ok(0)`.
-    ///
     /// `sys_mprotect` changes the access protections for the calling process's memory pages
     /// containing any part of the address range in the interval `[addr, addr+len-1]`.
     /// `addr` must be aligned to a page boundary.
@@ -138,13 +270,157 @@ impl Syscall<'_> {
     ///   The memory can be executed.
     ///
     /// If `prot` is 0, the memory cannot be accessed at all.
+    ///
+    /// If some pages in the range are not mapped, an [`ENOMEM`](LxError::ENOMEM) is returned.
     pub fn sys_mprotect(&self, addr: usize, len: usize, prot: usize) -> SysResult {
         let prot = MmapProt::from_bits_truncate(prot);
         info!(
             "mprotect: addr={:#x}, size={:#x}, prot={:?}",
             addr, len, prot
         );
-        warn!("mprotect: unimplemented");
+        if addr % PAGE_SIZE != 0 {
+            return Err(LxError::EINVAL);
+        }
+        let len = roundup_pages(len);
+        if addr.checked_add(len).is_none() {
+            return Err(LxError::ENOMEM);
+        }
+        let vmar = self.zircon_process().vmar();
+        vmar.protect(addr, len, prot.to_flags())
+            .map_err(|err| match err {
+                ZxError::NOT_FOUND => LxError::ENOMEM,
+                _ => err.into(),
+            })?;
+        Ok(0)
+    }
+
+    /// Remap a virtual memory address
+    /// (see [linux man mremap(2)](https://www.man7.org/linux/man-pages/man2/mremap.2.html)).
+    ///
+    /// `sys_mremap` expands (or shrinks) an existing memory mapping `[old_addr, old_addr+old_size)`
+    /// to `new_size` bytes, potentially moving it at the same time.
+    /// The old range must be within a single mapping, otherwise an [`EFAULT`](LxError::EFAULT) is returned.
+    ///
+    /// The `flags` argument is a bitmask that may include:
+    ///
+    /// - **`MremapFlags::MAYMOVE`**
+    ///
+    ///   If the mapping can not be expanded at its current location,
+    ///   it may be moved to a new virtual address.
+    ///   Otherwise an [`ENOMEM`](LxError::ENOMEM) is returned.
+    ///
+    /// - **`MremapFlags::FIXED`**
+    ///
+    ///   Move the mapping to `new_addr`, any previous mapping at the range is unmapped.
+    ///   `MremapFlags::MAYMOVE` must also be specified.
+    ///
+    /// The expanded part of the mapping is filled with zero,
+    /// even if the mapping is backed by a file.
+    pub fn sys_mremap(
+        &self,
+        old_addr: usize,
+        old_size: usize,
+        new_size: usize,
+        flags: usize,
+        new_addr: usize,
+    ) -> SysResult {
+        let flags = MremapFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        info!(
+            "mremap: old_addr={:#x}, old_size={:#x}, new_size={:#x}, flags={:?}, new_addr={:#x}",
+            old_addr, old_size, new_size, flags, new_addr
+        );
+        if old_addr % PAGE_SIZE != 0
+            || old_size == 0
+            || new_size == 0
+            || (flags.contains(MremapFlags::FIXED) && !flags.contains(MremapFlags::MAYMOVE))
+        {
+            return Err(LxError::EINVAL);
+        }
+        let (old_size, new_size) = (roundup_pages(old_size), roundup_pages(new_size));
+        let new_addr = flags.contains(MremapFlags::FIXED).then(|| new_addr);
+        let proc = self.linux_process();
+        // a shared mapping of a file is expanded with the following pages of the file
+        let file_mapping = proc.file_mapping(old_addr).map(|map| FileMapping {
+            addr: old_addr,
+            len: new_size,
+            offset: map.offset + (old_addr - map.addr),
+            cache: map.cache,
+        });
+        if let Some(map) = &file_mapping {
+            map.cache.reserve(map.offset + new_size)?;
+        }
+        if let Some(new_addr) = new_addr {
+            proc.remove_file_mappings(new_addr, new_size)?;
+        }
+        let vmar = self.zircon_process().vmar();
+        let addr = vmar
+            .remap(
+                old_addr,
+                old_size,
+                new_size,
+                new_addr,
+                flags.contains(MremapFlags::MAYMOVE),
+            )
+            .map_err(|err| match err {
+                ZxError::NOT_FOUND => LxError::EFAULT,
+                _ => err.into(),
+            })?;
+        if let Some(map) = file_mapping {
+            proc.remove_file_mappings(old_addr, old_size)?;
+            proc.add_file_mapping(FileMapping { addr, ..map });
+        }
+        Ok(addr)
+    }
+
+    /// Synchronize a file with a memory map
+    /// (see [linux man msync(2)](https://www.man7.org/linux/man-pages/man2/msync.2.html)).
+    ///
+    /// `sys_msync` flushes changes made to the shared mappings of files
+    /// in the range `[addr, addr+len)` back to the files.
+    /// `addr` must be aligned to a page boundary.
+    ///
+    /// The `flags` argument should specify exactly one of `MsyncFlags::ASYNC` and `MsyncFlags::SYNC`,
+    /// and may additionally include `MsyncFlags::INVALIDATE`.
+    /// Since `read` and `write` of a mapped file go through the same page cache,
+    /// the changes are always written back synchronously and `MsyncFlags::INVALIDATE` does nothing.
+    pub fn sys_msync(&self, addr: usize, len: usize, flags: usize) -> SysResult {
+        let flags = MsyncFlags::from_bits(flags).ok_or(LxError::EINVAL)?;
+        info!(
+            "msync: addr={:#x}, size={:#x}, flags={:?}",
+            addr, len, flags
+        );
+        if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::ASYNC | MsyncFlags::SYNC) {
+            return Err(LxError::EINVAL);
+        }
+        self.linux_process()
+            .sync_file_mappings(addr, roundup_pages(len))?;
+        Ok(0)
+    }
+
+    /// Give advice about use of memory
+    /// (see [linux man madvise(2)](https://www.man7.org/linux/man-pages/man2/madvise.2.html)).
+    ///
+    /// `sys_madvise` advises the kernel about how to handle the pages in the range `[addr, addr+len)`.
+    /// `addr` must be aligned to a page boundary.
+    ///
+    /// Only `MADV_DONTNEED` is implemented: the pages are released,
+    /// the next access of an anonymous private mapping gets zero-filled pages,
+    /// a private mapping of a file or memfd gets the content of the file again,
+    /// and a shared mapping gets the content of the shared memory or file.
+    /// The content of a regular file is the one when it was mapped.
+    /// Other advices are ignored.
+    pub fn sys_madvise(&self, addr: usize, len: usize, advice: usize) -> SysResult {
+        info!(
+            "madvise: addr={:#x}, size={:#x}, advice={}",
+            addr, len, advice
+        );
+        if addr % PAGE_SIZE != 0 {
+            return Err(LxError::EINVAL);
+        }
+        if advice == MADV_DONTNEED {
+            let vmar = self.zircon_process().vmar();
+            vmar.decommit(addr, roundup_pages(len))?;
+        }
         Ok(0)
     }
 
@@ -163,6 +439,7 @@ impl Syscall<'_> {
     /// Otherwise, an [`EINVAL`](LxError::EINVAL) is returned.
     pub fn sys_munmap(&self, addr: usize, len: usize) -> SysResult {
         info!("munmap: addr={:#x}, size={:#x}", addr, len);
+        self.linux_process().remove_file_mappings(addr, len)?;
         let proc = self.thread.proc();
         let vmar = proc.vmar();
         vmar.unmap(addr, len)?;
@@ -191,6 +468,31 @@ const MMAP_ANONYMOUS: usize = 0x800;
 #[cfg(not(target_arch = "mips"))]
 const MMAP_ANONYMOUS: usize = 1 << 5;
 
+bitflags! {
+    /// for the flags argument in mremap()
+    pub struct MremapFlags: usize {
+        /// The mapping can be moved to a new address
+        const MAYMOVE = 1;
+        /// Move the mapping to the given address
+        const FIXED = 2;
+    }
+}
+
+bitflags! {
+    /// for the flags argument in msync()
+    pub struct MsyncFlags: usize {
+        /// Schedule an update and return immediately
+        const ASYNC = 1;
+        /// Invalidate other mappings of the same file
+        const INVALIDATE = 2;
+        /// Request an update and wait for it to complete
+        const SYNC = 4;
+    }
+}
+
+/// Advice of madvise(), do not expect access in the near future
+const MADV_DONTNEED: usize = 4;
+
 bitflags! {
     /// for the prot argument in mmap()
     pub struct MmapProt: usize {
@@ -217,10 +519,6 @@ impl MmapProt {
         if self.contains(MmapProt::EXEC) {
             flags |= MMUFlags::EXECUTE;
         }
-        // FIXME: hack for unimplemented mprotect
-        if self.is_empty() {
-            flags |= MMUFlags::READ | MMUFlags::WRITE;
-        }
         flags
     }
 }
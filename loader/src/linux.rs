@@ -2,15 +2,20 @@
 
 use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
 use core::{future::Future, pin::Pin};
+use linux_object::error::LxError;
 use linux_object::signal::{
-    MachineContext, SigInfo, Signal, SignalActionFlags, SignalUserContext, Sigset,
+    MachineContext, SigInfo, Signal, SignalAction, SignalActionFlags, SignalCode, SignalStack,
+    SignalStackFlags, SignalUserContext, BUS_ADRALN, FPE_FLTINV, FPE_INTDIV, ILL_ILLOPC,
+    SEGV_ACCERR, SEGV_MAPERR, SIG_DFL, SIG_IGN, TRAP_BRKPT, TRAP_HWBKPT,
 };
 
 use kernel_hal::context::{TrapReason, UserContext, UserContextField};
 use kernel_hal::interrupt::{intr_off, intr_on};
 use linux_object::fs::{vfs::FileSystem, INodeExt};
-use linux_object::thread::{CurrentThreadExt, ThreadExt};
-use linux_object::{loader::LinuxElfLoader, process::ProcessExt};
+use linux_object::loader::LinuxElfLoader;
+use linux_object::process::{exit_by_signal, stop_by_signal, ProcessExt};
+use linux_object::ptrace;
+use linux_object::thread::{restart_on_signal, CurrentThreadExt, ThreadExt};
 use zircon_object::task::{CurrentThread, Job, Process, Thread, ThreadState};
 use zircon_object::{object::KernelObject, vm::USER_STACK_PAGES, ZxError, ZxResult};
 
@@ -19,22 +24,25 @@ pub fn run(args: Vec<String>, envs: Vec<String>, rootfs: Arc<dyn FileSystem>) ->
     info!("Run Linux process: args={:?}, envs={:?}", args, envs);
 
     let job = Job::root();
-    let proc = Process::create_linux(&job, rootfs.clone()).unwrap();
+    let proc = Process::create_linux(&job, rootfs).unwrap();
     let thread = Thread::create_linux(&proc).unwrap();
     let loader = LinuxElfLoader {
         syscall_entry: kernel_hal::context::syscall_entry as usize,
         stack_pages: USER_STACK_PAGES,
-        root_inode: rootfs.root_inode(),
+        proc: proc.clone(),
     };
 
-    let inode = rootfs.root_inode().lookup(&args[0]).unwrap();
+    let inode = proc.linux().lookup_inode(&args[0]).unwrap();
     let data = inode.read_as_vec().unwrap();
     let path = args[0].clone();
+    proc.linux().set_execute_path(&path);
+    proc.linux().set_args(&args, &envs);
 
     let pg_token = kernel_hal::vm::current_vmtoken();
     debug!("current pgt = {:#x}", pg_token);
     //调用zircon-object/src/task/thread.start设置好要执行的thread
-    let (entry, sp) = loader.load(&proc.vmar(), &data, args, envs, path).unwrap();
+    let (entry, sp, heap_start) = loader.load(&proc.vmar(), &data, args, envs, path).unwrap();
+    proc.linux().reset_heap(heap_start);
 
     thread
         .start_with_entry(entry, sp, 0, 0, thread_fn)
@@ -64,10 +72,34 @@ async fn run_user(thread: CurrentThread) {
         }
 
         // check the signal and handle
-        if let Some((signal, sigmask)) = thread.inner().lock_linux().handle_signal() {
-            ctx = handle_signal(&thread, ctx, signal, sigmask);
+        let mut signal = thread.lock_linux().dequeue_signal();
+        if let Some(info) = signal {
+            // a traced thread stops for the tracer, which may change or discard the signal
+            if ptrace::is_traced(thread.proc()) {
+                thread.put_context(ctx);
+                signal = ptrace::signal_stop(&thread, info).await;
+                ctx = thread.wait_for_run().await;
+                if thread.state() == ThreadState::Dying {
+                    break;
+                }
+            }
+        }
+        if let Some(info) = signal {
+            ctx = handle_signal(&thread, ctx, info);
+            // terminated by the default action of the signal
+            if thread.state() == ThreadState::Dying {
+                break;
+            }
+            // stopped by the default action of the signal, wait for `SIGCONT`
+            if thread.proc().linux().is_stopped() {
+                thread.put_context(ctx);
+                continue;
+            }
         }
 
+        // the mask replaced by `epoll_pwait` and the like is back if no signal is delivered
+        thread.lock_linux().restore_saved_mask();
+
         // run
         debug!(
             "go to user: tid = {} pc = {:x}",
@@ -91,43 +123,89 @@ async fn run_user(thread: CurrentThread) {
     kernel_hal::thread::set_current_thread(None);
 }
 
+/// Set up the frame on the user stack and the context to run the signal handler.
+///
+/// The frame contains the `siginfo` and the `ucontext` of the interrupted code,
+/// which is restored by `rt_sigreturn` when the handler returns.
 fn handle_signal(
     thread: &CurrentThread,
     mut ctx: Box<UserContext>,
-    signal: Signal,
-    sigmask: Sigset,
+    info: SigInfo,
 ) -> Box<UserContext> {
+    let signal = info.signal();
     let user_sp = ctx.get_field(UserContextField::StackPointer);
-    let user_pc = ctx.get_field(UserContextField::InstrPointer);
-    let action = thread.proc().linux().signal_action(signal);
-    let signal_info = SigInfo::default();
+    let linux = thread.proc().linux();
+    let action = linux.signal_action(signal);
+    match action.handler {
+        SIG_IGN => return ctx,
+        SIG_DFL => {
+            if signal.is_stopping_by_default() {
+                // only the stop signals of a traced process get here, after the tracer
+                info!("thread {} is stopped by {:?}", thread.id(), signal);
+                stop_by_signal(thread.proc(), signal);
+            } else if !signal.is_ignored_by_default() {
+                info!("thread {} is terminated by {:?}", thread.id(), signal);
+                exit_by_signal(thread.proc(), signal);
+            }
+            return ctx;
+        }
+        _ => {}
+    }
+    let mut linux_thread = thread.lock_linux();
+    let mut stack = linux_thread.signal_alternate_stack;
+    let on_stack = stack.contains(user_sp);
+    // switch to the alternate signal stack if it is requested and not used yet
+    const RED_ZONE_MAX_SIZE: usize = 0x100; // 256Bytes
+    let sp = if action.flags.contains(SignalActionFlags::ONSTACK)
+        && !stack.flags.contains(SignalStackFlags::DISABLE)
+        && !on_stack
+    {
+        if stack.flags.contains(SignalStackFlags::AUTODISARM) {
+            linux_thread.signal_alternate_stack = SignalStack::default();
+        }
+        stack.sp + stack.size
+    } else {
+        user_sp - RED_ZONE_MAX_SIZE
+    };
+    if on_stack {
+        stack.flags.insert(SignalStackFlags::ONSTACK);
+    }
+    // the mask replaced by `epoll_pwait` and the like is restored when the handler returns
+    let old_mask = linux_thread
+        .take_saved_mask()
+        .unwrap_or(linux_thread.signal_mask);
     let signal_context = SignalUserContext {
-        sig_mask: sigmask,
-        context: MachineContext::new(user_pc),
+        stack,
+        sig_mask: old_mask,
+        context: MachineContext::from_context(&ctx),
         ..Default::default()
     };
+    // block the signals in `sa_mask` and the signal itself while handling
+    let mask = &mut linux_thread.signal_mask;
+    *mask = old_mask;
+    mask.insert_set(&action.mask);
+    if !action.flags.contains(SignalActionFlags::NODEFER) {
+        mask.insert(signal);
+    }
+    mask.remove(Signal::SIGKILL);
+    mask.remove(Signal::SIGSTOP);
+    drop(linux_thread);
+    if action.flags.contains(SignalActionFlags::RESETHAND) {
+        linux.set_signal_action(signal, SignalAction::default());
+    }
     // push `siginfo` `uctx` into user stack
-    const RED_ZONE_MAX_SIZE: usize = 0x100; // 256Bytes
-    let mut sp = user_sp - RED_ZONE_MAX_SIZE;
-    let (siginfo_ptr, uctx_ptr) = if action.flags.contains(SignalActionFlags::SIGINFO) {
-        sp = push_stack(sp & !0xF, signal_info); // & !0xF for 16 bytes aligned
-        let siginfo_ptr = sp;
-        sp = push_stack(sp & !0xF, signal_context);
-        (siginfo_ptr, sp)
+    let siginfo_ptr = push_stack(sp & !0xF, info); // & !0xF for 16 bytes aligned
+    const UCTX_SIZE: usize = core::mem::size_of::<SignalUserContext>();
+    let uctx_ptr = (siginfo_ptr - UCTX_SIZE) & !0xF;
+    push_stack(uctx_ptr + UCTX_SIZE, signal_context);
+    // set user return address as `action.restorer`,
+    // `rt_sigreturn` finds the `uctx` at the stack pointer then
+    let sp = if cfg!(target_arch = "x86_64") {
+        push_stack::<usize>(uctx_ptr, action.restorer)
     } else {
-        error!("unimplementd signal flags");
-        (0, 0)
+        ctx.set_ra(action.restorer);
+        uctx_ptr
     };
-    // backup current context
-    thread.backup_context(*ctx, siginfo_ptr, uctx_ptr);
-    // set user return address as `action.restorer`
-    cfg_if! {
-        if #[cfg(target_arch = "x86_64")] {
-            sp = push_stack::<usize>(sp & !0xF, action.restorer);
-        } else {
-            ctx.set_ra(action.restorer);
-        }
-    }
     // set trapframe
     ctx.setup_uspace(
         action.handler,
@@ -159,11 +237,20 @@ macro_rules! run_with_irq_enable {
 
 async fn handle_user_trap(thread: &CurrentThread, mut ctx: Box<UserContext>) -> ZxResult {
     let reason = ctx.trap_reason();
+    let pc = ctx.get_field(UserContextField::InstrPointer);
     if let TrapReason::Syscall = reason {
-        let num = syscall_num(&ctx);
-        let args = syscall_args(&ctx);
+        let mut num = syscall_num(&ctx);
+        let mut args = syscall_args(&ctx);
         ctx.advance_pc(reason);
         thread.put_context(ctx);
+        // the syscall-enter-stop, where the tracer may change the syscall and its arguments
+        if ptrace::traces_syscalls(thread.proc()) {
+            num = ptrace::syscall_stop(thread, num).await;
+            if thread.state() == ThreadState::Dying {
+                return Ok(());
+            }
+            args = thread.with_context(|ctx| syscall_args(ctx))?;
+        }
         let mut syscall = linux_syscall::Syscall {
             thread,
             thread_fn,
@@ -171,9 +258,23 @@ async fn handle_user_trap(thread: &CurrentThread, mut ctx: Box<UserContext>) ->
         };
         trace!("Syscall : {} {:x?}", num as u32, args);
         run_with_irq_enable! {
-            let ret = syscall.syscall(num as u32, args).await as usize
+            let mut ret = syscall.syscall(num as u32, args).await as usize
+        }
+        // the syscall interrupted by a signal is restarted after the handler with `SA_RESTART`,
+        // or fails with `EINTR`
+        if ret as isize == -(LxError::ERESTARTSYS as isize) {
+            if restart_on_signal(thread) {
+                thread.with_context(|ctx| restart_syscall(ctx, pc, num, args[0]))?;
+                return Ok(());
+            }
+            ret = -(LxError::EINTR as isize) as usize;
         }
         thread.with_context(|ctx| ctx.set_field(UserContextField::ReturnValue, ret))?;
+        // the event stop of `fork`, `clone` and `execve`, then the syscall-exit-stop
+        ptrace::event_stop(thread).await;
+        if ptrace::traces_syscalls(thread.proc()) {
+            ptrace::syscall_stop(thread, num).await;
+        }
         return Ok(());
     }
 
@@ -195,25 +296,82 @@ async fn handle_user_trap(thread: &CurrentThread, mut ctx: Box<UserContext>) ->
                 vaddr, flags, pid
             );
             let vmar = thread.proc().vmar();
-            vmar.handle_page_fault(vaddr, flags).map_err(|err| {
-                error!(
-                    "failed to handle page fault from user mode @ {:#x}({:?}): {:?}\n{:#x?}",
-                    vaddr,
-                    flags,
-                    err,
-                    thread.context_cloned(),
+            if let Err(err) = vmar.handle_page_fault(vaddr, flags) {
+                let code = if err == ZxError::ACCESS_DENIED {
+                    SEGV_ACCERR
+                } else {
+                    SEGV_MAPERR
+                };
+                info!(
+                    "deliver SIGSEGV for page fault @ {:#x}({:?}): {:?}",
+                    vaddr, flags, err
                 );
-                err
-            })
+                deliver_fault_signal(thread, SigInfo::fault(Signal::SIGSEGV, code, vaddr));
+            }
+            Ok(())
         }
         _ => {
-            error!(
-                "unsupported trap from user mode: {:x?}, pid={}, {:#x?}",
+            let info = fault_signal(&reason, pc);
+            warn!(
+                "deliver {:?} for trap from user mode: {:x?}, pid={}",
+                info.signal(),
                 reason,
                 pid,
-                thread.context_cloned(),
             );
-            Err(ZxError::NOT_SUPPORTED)
+            deliver_fault_signal(thread, info);
+            Ok(())
+        }
+    }
+}
+
+/// The signal raised by the trap `reason` at `pc`.
+fn fault_signal(reason: &TrapReason, pc: usize) -> SigInfo {
+    match *reason {
+        TrapReason::UndefinedInstruction => SigInfo::fault(Signal::SIGILL, ILL_ILLOPC, pc),
+        TrapReason::SoftwareBreakpoint => SigInfo::fault(Signal::SIGTRAP, TRAP_BRKPT, pc),
+        TrapReason::HardwareBreakpoint => SigInfo::fault(Signal::SIGTRAP, TRAP_HWBKPT, pc),
+        TrapReason::UnalignedAccess => SigInfo::fault(Signal::SIGBUS, BUS_ADRALN, pc),
+        TrapReason::GernelFault(trap) => {
+            // the divide error, x87 floating-point and SIMD floating-point exceptions on x86
+            if cfg!(target_arch = "x86_64") && trap == 0 {
+                SigInfo::fault(Signal::SIGFPE, FPE_INTDIV, pc)
+            } else if cfg!(target_arch = "x86_64") && (trap == 16 || trap == 19) {
+                SigInfo::fault(Signal::SIGFPE, FPE_FLTINV, pc)
+            } else {
+                SigInfo::fault(Signal::SIGSEGV, SignalCode::KERNEL as i32, 0)
+            }
+        }
+        _ => SigInfo::fault(Signal::SIGSEGV, SignalCode::KERNEL as i32, 0),
+    }
+}
+
+/// Queue a signal caused by the faulting instruction, which is taken before returning to user.
+///
+/// A fault signal can not be ignored or blocked, the default action is taken then,
+/// which terminates the process.
+fn deliver_fault_signal(thread: &CurrentThread, info: SigInfo) {
+    let signal = info.signal();
+    let linux = thread.proc().linux();
+    let blocked = thread.lock_linux().signal_mask.contains(signal);
+    if blocked || linux.signal_action(signal).handler == SIG_IGN {
+        linux.set_signal_action(signal, SignalAction::default());
+    }
+    let mut linux_thread = thread.lock_linux();
+    linux_thread.signal_mask.remove(signal);
+    linux_thread.queue_signal(info);
+}
+
+/// Rewind the context to execute the syscall `num` at `pc` again after the signal handler.
+fn restart_syscall(ctx: &mut UserContext, pc: usize, num: usize, arg0: usize) {
+    ctx.set_field(UserContextField::InstrPointer, pc);
+    // the return value register holds the syscall number or the first argument before
+    cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            let _ = arg0;
+            ctx.set_field(UserContextField::ReturnValue, num);
+        } else {
+            let _ = num;
+            ctx.set_field(UserContextField::ReturnValue, arg0);
         }
     }
 }
@@ -70,16 +70,43 @@ impl Futex {
     ///
     /// The owner of the futex is set to nothing, regardless of the wake count.
     pub fn wake(&self, wake_count: usize) -> usize {
+        self.wake_bitset(wake_count, u32::MAX)
+    }
+
+    /// Wait on a futex, like [`wait`], but only woken up by a [`wake_bitset`]
+    /// with a `bitset` which has a common bit with this one.
+    ///
+    /// [`wait`]: Futex::wait
+    /// [`wake_bitset`]: Futex::wake_bitset
+    pub fn wait_bitset(
+        self: &Arc<Self>,
+        current_value: i32,
+        bitset: u32,
+    ) -> impl Future<Output = ZxResult> {
+        self.wait_inner(current_value, None, None, bitset)
+    }
+
+    /// Wake at most `wake_count` of the waiters that are waiting with
+    /// a bitset which has a common bit with `bitset`.
+    /// Return the number of waiters that were woken up.
+    ///
+    /// # Ownership
+    ///
+    /// The owner of the futex is set to nothing, regardless of the wake count.
+    pub fn wake_bitset(&self, wake_count: usize, bitset: u32) -> usize {
         let mut inner = self.inner.lock();
         inner.set_owner(None);
-        for i in 0..wake_count {
-            if let Some(waiter) = inner.waiter_queue.pop_front() {
+        let mut count = 0;
+        inner.waiter_queue.retain(|waiter| {
+            if count < wake_count && waiter.bitset & bitset != 0 {
                 waiter.wake();
+                count += 1;
+                false
             } else {
-                return i;
+                true
             }
-        }
-        wake_count
+        });
+        count
     }
 
     // ------ Advanced APIs on Zircon ------
@@ -117,6 +144,16 @@ impl Futex {
         current_value: i32,
         thread: Option<Arc<Thread>>,
         new_owner: Option<Arc<Thread>>,
+    ) -> impl Future<Output = ZxResult> {
+        self.wait_inner(current_value, thread, new_owner, u32::MAX)
+    }
+
+    fn wait_inner(
+        self: &Arc<Self>,
+        current_value: i32,
+        thread: Option<Arc<Thread>>,
+        new_owner: Option<Arc<Thread>>,
+        bitset: u32,
     ) -> impl Future<Output = ZxResult> {
         #[must_use = "wait does nothing unless polled/`await`-ed"]
         struct FutexFuture {
@@ -172,6 +209,7 @@ impl Futex {
         FutexFuture {
             waiter: Arc::new(Waiter {
                 thread,
+                bitset,
                 inner: Mutex::new(WaiterInner {
                     waker: None,
                     woken: false,
@@ -215,6 +253,8 @@ impl Futex {
     ///
     /// The owner of this futex is set to nothing, regardless of the wake count.
     /// The owner of the `requeue_futex` is set to the thread `new_requeue_owner`.
+    ///
+    /// Return the number of waiters that were woken up or requeued.
     pub fn requeue(
         &self,
         current_value: i32,
@@ -223,7 +263,7 @@ impl Futex {
         requeue_futex: &Arc<Futex>,
         new_requeue_owner: Option<Arc<Thread>>,
         check_value: bool,
-    ) -> ZxResult {
+    ) -> ZxResult<usize> {
         let mut inner = self.inner.lock();
         if check_value {
             // check value
@@ -232,12 +272,9 @@ impl Futex {
             }
         }
         // wake
-        for _ in 0..wake_count {
-            if let Some(waiter) = inner.waiter_queue.pop_front() {
-                waiter.wake();
-            } else {
-                break;
-            }
+        let wake_count = wake_count.min(inner.waiter_queue.len());
+        for waiter in inner.waiter_queue.drain(..wake_count) {
+            waiter.wake();
         }
         // requeue
         let mut new_inner = requeue_futex.inner.lock();
@@ -249,7 +286,7 @@ impl Futex {
         // set owner
         inner.set_owner(None);
         new_inner.set_owner(new_requeue_owner);
-        Ok(())
+        Ok(wake_count + requeue_count)
     }
 }
 
@@ -278,6 +315,8 @@ impl FutexInner {
 struct Waiter {
     /// The thread waiting on the futex.
     thread: Option<Arc<Thread>>,
+    /// Only woken up by the wakes with a common bit in the bitset.
+    bitset: u32,
     inner: Mutex<WaiterInner>,
 }
 
@@ -335,6 +374,27 @@ mod tests {
         assert_eq!(futex.wake(1), 0);
     }
 
+    #[async_std::test]
+    async fn bitset() {
+        static VALUE: AtomicI32 = AtomicI32::new(1);
+        let futex = Futex::new(&VALUE);
+
+        // spawn a new task to wake me up.
+        {
+            let futex = futex.clone();
+            async_std::task::spawn(async move {
+                async_std::task::sleep(Duration::from_millis(10)).await;
+                // no common bit, nobody is woken up.
+                assert_eq!(futex.wake_bitset(1, 0b10), 0);
+                VALUE.store(2, Ordering::SeqCst);
+                assert_eq!(futex.wake_bitset(1, 0b11), 1);
+            });
+        }
+        // wait for wake.
+        futex.wait_bitset(1, 0b01).await.unwrap();
+        assert_eq!(VALUE.load(Ordering::SeqCst), 2);
+    }
+
     #[async_std::test]
     async fn requeue() {
         static VALUE: AtomicI32 = AtomicI32::new(1);
@@ -370,7 +430,7 @@ mod tests {
                     futex.requeue(1, 1, 1, &requeue_futex, None, true),
                     Err(ZxError::BAD_STATE)
                 );
-                assert!(futex.requeue(2, 1, 1, &requeue_futex, None, true).is_ok());
+                assert_eq!(futex.requeue(2, 1, 1, &requeue_futex, None, true), Ok(2));
                 // 1 waiter waken, 1 waiter moved into `requeue_futex`.
                 assert_eq!(futex.inner.lock().waiter_queue.len(), 0);
                 assert_eq!(requeue_futex.inner.lock().waiter_queue.len(), 1);
@@ -58,7 +58,7 @@ pub struct Process {
     _counter: CountHelper,
     job: Arc<Job>,
     policy: JobPolicy,
-    vmar: Arc<VmAddressRegion>,
+    vmar: Mutex<Arc<VmAddressRegion>>,
     ext: Box<dyn Any + Send + Sync>,
     exceptionate: Arc<Exceptionate>,
     debug_exceptionate: Arc<Exceptionate>,
@@ -119,13 +119,25 @@ impl Process {
         job: &Arc<Job>,
         name: &str,
         ext: impl Any + Send + Sync,
+    ) -> ZxResult<Arc<Self>> {
+        Self::create_with_vmar(job, name, ext, VmAddressRegion::new_root())
+    }
+
+    /// Create a new process with extension info, which runs in an existing address space `vmar`.
+    ///
+    /// The address space is shared with the other processes using it.
+    pub fn create_with_vmar(
+        job: &Arc<Job>,
+        name: &str,
+        ext: impl Any + Send + Sync,
+        vmar: Arc<VmAddressRegion>,
     ) -> ZxResult<Arc<Self>> {
         let proc = Arc::new(Process {
             base: KObjectBase::with_name(name),
             _counter: CountHelper::new(),
             job: job.clone(),
             policy: job.policy(),
-            vmar: VmAddressRegion::new_root(),
+            vmar: Mutex::new(vmar),
             ext: Box::new(ext),
             exceptionate: Exceptionate::new(ExceptionChannelType::Process),
             debug_exceptionate: Exceptionate::new(ExceptionChannelType::Debugger),
@@ -310,7 +322,14 @@ impl Process {
 
     /// Get the `VmAddressRegion` of the process.
     pub fn vmar(&self) -> Arc<VmAddressRegion> {
-        self.vmar.clone()
+        self.vmar.lock().clone()
+    }
+
+    /// Switch the process to a new address space `vmar`, return the old one.
+    ///
+    /// The new page table takes effect the next time a thread of the process is scheduled.
+    pub fn replace_vmar(&self, vmar: Arc<VmAddressRegion>) -> Arc<VmAddressRegion> {
+        core::mem::replace(&mut *self.vmar.lock(), vmar)
     }
 
     /// Get the job of the process.
@@ -647,6 +666,20 @@ mod tests {
         assert!(Arc::ptr_eq(&root_job, &proc.job()));
     }
 
+    #[test]
+    fn shared_vmar() {
+        let root_job = Job::root();
+        let proc1 = Process::create(&root_job, "proc1").expect("failed to create process");
+        let proc2 = Process::create_with_vmar(&root_job, "proc2", (), proc1.vmar())
+            .expect("failed to create process");
+        assert!(Arc::ptr_eq(&proc1.vmar(), &proc2.vmar()));
+
+        let vmar = VmAddressRegion::new_root();
+        let old = proc2.replace_vmar(vmar.clone());
+        assert!(Arc::ptr_eq(&old, &proc1.vmar()));
+        assert!(Arc::ptr_eq(&vmar, &proc2.vmar()));
+    }
+
     #[test]
     fn handle() {
         let root_job = Job::root();
@@ -203,6 +203,55 @@ impl VmAddressRegion {
         flags: MMUFlags,
         overwrite: bool,
         map_range: bool,
+    ) -> ZxResult<VirtAddr> {
+        self.map_inner(
+            vmar_offset,
+            vmo,
+            vmo_offset,
+            len,
+            permissions,
+            flags,
+            overwrite,
+            map_range,
+            false,
+        )
+    }
+
+    /// Map the `vmo` into this VMAR, the mapping is shared with the address spaces
+    /// forked from this one instead of being copied on write. (For Linux MAP_SHARED)
+    pub fn map_shared(
+        &self,
+        vmar_offset: Option<usize>,
+        vmo: Arc<VmObject>,
+        vmo_offset: usize,
+        len: usize,
+        flags: MMUFlags,
+    ) -> ZxResult<VirtAddr> {
+        self.map_inner(
+            vmar_offset,
+            vmo,
+            vmo_offset,
+            len,
+            MMUFlags::RXW,
+            flags,
+            false,
+            true,
+            true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn map_inner(
+        &self,
+        vmar_offset: Option<usize>,
+        vmo: Arc<VmObject>,
+        vmo_offset: usize,
+        len: usize,
+        permissions: MMUFlags,
+        flags: MMUFlags,
+        overwrite: bool,
+        map_range: bool,
+        shared: bool,
     ) -> ZxResult<VirtAddr> {
         if !page_aligned(vmo_offset) || !page_aligned(len) || vmo_offset.overflowing_add(len).1 {
             return Err(ZxError::INVALID_ARGS);
@@ -240,6 +289,7 @@ impl VmAddressRegion {
             vmo_offset,
             permissions,
             flags,
+            shared,
             self.page_table.clone(),
         );
         if map_range {
@@ -301,6 +351,9 @@ impl VmAddressRegion {
     /// Change protections on a subset of the region of memory in the containing
     /// address space.  If the requested range overlaps with a subregion,
     /// protect() will fail.
+    /// If a mapping is only partially in the range, the mapping is split and the
+    /// protections of the requested portion are changed.
+    /// The split parts are merged again with their neighbours once their flags match.
     pub fn protect(&self, addr: usize, len: usize, flags: MMUFlags) -> ZxResult {
         if !page_aligned(addr) || !page_aligned(len) {
             return Err(ZxError::INVALID_ARGS);
@@ -312,20 +365,15 @@ impl VmAddressRegion {
         if inner
             .children
             .iter()
-            .any(|child| child.end_addr() >= addr && child.addr() <= end_addr)
+            .any(|child| child.overlap(addr, end_addr))
         {
             return Err(ZxError::INVALID_ARGS);
         }
         let length: usize = inner
             .mappings
             .iter()
-            .filter_map(|map| {
-                if map.end_addr() >= addr && map.addr() <= end_addr {
-                    Some(end_addr.min(map.end_addr()) - addr.max(map.addr()))
-                } else {
-                    None
-                }
-            })
+            .filter(|map| map.overlap(addr, end_addr))
+            .map(|map| end_addr.min(map.end_addr()) - addr.max(map.addr()))
             .sum();
         if length != len {
             return Err(ZxError::NOT_FOUND);
@@ -334,20 +382,191 @@ impl VmAddressRegion {
         if inner
             .mappings
             .iter()
-            .filter(|map| map.end_addr() >= addr && map.addr() <= end_addr) // get mappings in range: [addr, end_addr]
+            .filter(|map| map.overlap(addr, end_addr))
             .any(|map| !map.is_valid_mapping_flags(flags))
         {
             return Err(ZxError::ACCESS_DENIED);
         }
+        inner.split_mapping_at(addr);
+        inner.split_mapping_at(end_addr);
         inner
             .mappings
             .iter()
-            .filter(|map| map.end_addr() >= addr && map.addr() <= end_addr)
-            .for_each(|map| {
-                let start_index = pages(addr.max(map.addr()) - map.addr());
-                let end_index = pages(end_addr.min(map.end_addr()) - map.addr());
-                map.protect(flags, start_index, end_index);
-            });
+            .filter(|map| map.overlap(addr, end_addr))
+            .for_each(|map| map.protect(flags, 0, pages(map.size())));
+        let bounds: Vec<_> = inner
+            .mappings
+            .iter()
+            .filter(|map| map.overlap(addr, end_addr))
+            .map(|map| map.addr())
+            .chain(core::iter::once(end_addr))
+            .collect();
+        for addr in bounds {
+            inner.merge_mappings_at(addr);
+        }
+        Ok(())
+    }
+
+    /// Resize the mapping in `[addr, addr + old_len)` to `new_len`,
+    /// and move it to `new_addr` if given. (For Linux mremap)
+    ///
+    /// The range must be within a single mapping.
+    /// If it can not be expanded in place, it is moved to a free area when `may_move` is set,
+    /// otherwise `NO_MEMORY` is returned.
+    /// The expanded part is backed by a new VMO filled with zero.
+    ///
+    /// Return the address of the resized mapping.
+    pub fn remap(
+        &self,
+        addr: VirtAddr,
+        old_len: usize,
+        new_len: usize,
+        new_addr: Option<VirtAddr>,
+        may_move: bool,
+    ) -> ZxResult<VirtAddr> {
+        if !page_aligned(addr)
+            || !page_aligned(old_len)
+            || !page_aligned(new_len)
+            || old_len == 0
+            || new_len == 0
+        {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut guard = self.inner.lock();
+        let inner = guard.as_mut().ok_or(ZxError::BAD_STATE)?;
+        let old_end = addr.checked_add(old_len).ok_or(ZxError::INVALID_ARGS)?;
+        if !inner
+            .mappings
+            .iter()
+            .any(|map| map.addr() <= addr && old_end <= map.end_addr())
+        {
+            return Err(ZxError::NOT_FOUND);
+        }
+        if let Some(new_addr) = new_addr {
+            let new_end = new_addr.checked_add(new_len).ok_or(ZxError::INVALID_ARGS)?;
+            if !page_aligned(new_addr)
+                || new_addr < self.addr
+                || new_end > self.end_addr()
+                || (new_addr < old_end && addr < new_end)
+            {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            self.unmap_inner(new_addr, new_len, inner)?;
+            return self.move_inner(addr, old_len, new_addr, new_len, inner);
+        }
+        if new_len <= old_len {
+            if new_len < old_len {
+                self.unmap_inner(addr + new_len, old_len - new_len, inner)?;
+            }
+            return Ok(addr);
+        }
+        if self.test_map(inner, old_end - self.addr, new_len - old_len, PAGE_SIZE) {
+            self.expand_inner(old_end, new_len - old_len, inner)?;
+            return Ok(addr);
+        }
+        if !may_move {
+            return Err(ZxError::NO_MEMORY);
+        }
+        let offset = self
+            .find_free_area(inner, 0, new_len, PAGE_SIZE)
+            .ok_or(ZxError::NO_MEMORY)?;
+        self.move_inner(addr, old_len, self.addr + offset, new_len, inner)
+    }
+
+    /// Move the part of a mapping in `[addr, addr + old_len)` to the free area at `new_addr`,
+    /// and resize it to `new_len`.
+    ///
+    /// Must hold self.inner.lock() before calling.
+    fn move_inner(
+        &self,
+        addr: VirtAddr,
+        old_len: usize,
+        new_addr: VirtAddr,
+        new_len: usize,
+        inner: &mut VmarInner,
+    ) -> ZxResult<VirtAddr> {
+        let len = old_len.min(new_len);
+        inner.split_mapping_at(addr);
+        inner.split_mapping_at(addr + len);
+        let mapping = inner
+            .mappings
+            .iter()
+            .find(|map| map.addr() == addr)
+            .ok_or(ZxError::NOT_FOUND)?
+            .clone();
+        mapping.relocate(new_addr)?;
+        if old_len > len {
+            self.unmap_inner(addr + len, old_len - len, inner)?;
+        }
+        if new_len > len {
+            self.expand_inner(new_addr + len, new_len - len, inner)?;
+        }
+        Ok(new_addr)
+    }
+
+    /// Back the free area `[addr, addr + len)` with the VMO of the mapping which ends at `addr`,
+    /// using the flags of its last page.
+    ///
+    /// A new VMO is used unless the mapping is shared and its VMO is long enough.
+    ///
+    /// Must hold self.inner.lock() before calling.
+    fn expand_inner(&self, addr: VirtAddr, len: usize, inner: &mut VmarInner) -> ZxResult {
+        let prev = inner
+            .mappings
+            .iter()
+            .find(|map| map.end_addr() == addr)
+            .ok_or(ZxError::NOT_FOUND)?
+            .clone();
+        let (flags, vmo_end) = {
+            let prev_inner = prev.inner.lock();
+            let flags = *prev_inner.flags.last().ok_or(ZxError::BAD_STATE)?;
+            (flags, prev_inner.vmo_offset + prev_inner.size)
+        };
+        let (vmo, vmo_offset) = if prev.shared && vmo_end + len <= prev.vmo.len() {
+            (prev.vmo.clone(), vmo_end)
+        } else {
+            (VmObject::new_paged(pages(len)), 0)
+        };
+        let mapping = VmMapping::new(
+            addr,
+            len,
+            vmo,
+            vmo_offset,
+            prev.permissions,
+            flags,
+            prev.shared,
+            self.page_table.clone(),
+        );
+        mapping.map()?;
+        inner.mappings.push(mapping);
+        Ok(())
+    }
+
+    /// Release the pages of the mappings in `[addr, addr + len)`. (For Linux MADV_DONTNEED)
+    ///
+    /// The pages of private mappings are reset by [`VmObject::reset`]: they read as
+    /// the backing VMO of a [`VmObject::create_backed_child`] again, or as zero.
+    /// Shared mappings are mapped again from their VMOs.
+    pub fn decommit(&self, addr: VirtAddr, len: usize) -> ZxResult {
+        if !page_aligned(addr) || !page_aligned(len) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let end_addr = addr.checked_add(len).ok_or(ZxError::INVALID_ARGS)?;
+        let guard = self.inner.lock();
+        let inner = guard.as_ref().ok_or(ZxError::BAD_STATE)?;
+        for child in inner.children.iter() {
+            if child.overlap(addr, end_addr) {
+                child.decommit(
+                    addr.max(child.addr),
+                    end_addr.min(child.end_addr()) - addr.max(child.addr),
+                )?;
+            }
+        }
+        for map in inner.mappings.iter() {
+            if map.overlap(addr, end_addr) {
+                map.decommit(addr.max(map.addr()), end_addr.min(map.end_addr()))?;
+            }
+        }
         Ok(())
     }
 
@@ -597,6 +816,33 @@ impl VmAddressRegion {
         task_stats
     }
 
+    /// Get the mappings in the address space, sorted by address.
+    ///
+    /// A mapping whose pages have different flags is split into runs of
+    /// pages with the same flags.
+    pub fn mappings(&self) -> Vec<MappingInfo> {
+        let mut mappings = Vec::new();
+        self.for_each_mapping(&mut |map| {
+            let inner = map.inner.lock();
+            let mut start = 0;
+            for (i, flags) in inner.flags.iter().enumerate() {
+                if i + 1 == inner.flags.len() || inner.flags[i + 1] != *flags {
+                    mappings.push(MappingInfo {
+                        addr: inner.addr + start * PAGE_SIZE,
+                        size: (i + 1 - start) * PAGE_SIZE,
+                        flags: *flags,
+                        vmo_offset: inner.vmo_offset + start * PAGE_SIZE,
+                        shared: map.shared,
+                        vmo: map.vmo.clone(),
+                    });
+                    start = i + 1;
+                }
+            }
+        });
+        mappings.sort_unstable_by_key(|m| m.addr);
+        mappings
+    }
+
     /// Read from address space.
     ///
     /// Return the actual number of bytes read.
@@ -638,6 +884,20 @@ impl VmAddressRegion {
         None
     }
 
+    /// Find the VMO of the shared mapping which contains `vaddr`,
+    /// return it with the offset of `vaddr` in it.
+    ///
+    /// Return `None` if `vaddr` is not mapped, or the mapping is copied on write by fork.
+    pub fn find_shared_vmo(&self, vaddr: usize) -> Option<(Arc<VmObject>, usize)> {
+        let map = self.find_mapping(vaddr)?;
+        if !map.shared {
+            return None;
+        }
+        let map_inner = map.inner.lock();
+        let vmo_offset = vaddr - map_inner.addr + map_inner.vmo_offset;
+        Some((map.vmo.clone(), vmo_offset))
+    }
+
     #[cfg(test)]
     fn count(&self) -> usize {
         let mut guard = self.inner.lock();
@@ -656,6 +916,31 @@ impl VmAddressRegion {
 }
 
 impl VmarInner {
+    /// Split the mapping which crosses `addr`, so that no mapping contains
+    /// both the pages before and after `addr`.
+    fn split_mapping_at(&mut self, addr: VirtAddr) {
+        let new = self
+            .mappings
+            .iter()
+            .find(|map| map.addr() < addr && addr < map.end_addr())
+            .map(|map| map.split(addr));
+        if let Some(new) = new {
+            self.mappings.push(new);
+        }
+    }
+
+    /// Merge the mappings which meet at `addr`, if they map adjacent parts of
+    /// the same VMO and the pages on both sides have the same flags.
+    fn merge_mappings_at(&mut self, addr: VirtAddr) {
+        let prev = self.mappings.iter().position(|map| map.end_addr() == addr);
+        let next = self.mappings.iter().position(|map| map.addr() == addr);
+        if let (Some(prev), Some(next)) = (prev, next) {
+            if self.mappings[prev].merge(&self.mappings[next]) {
+                self.mappings.swap_remove(next);
+            }
+        }
+    }
+
     /// Clone the entire address space and VMOs from source VMAR. (For Linux fork)
     fn fork_from(
         &mut self,
@@ -685,10 +970,29 @@ pub struct VmarInfo {
     // pg_token: usize,
 }
 
+/// Information of a range of pages in a mapping with the same flags.
+#[derive(Debug, Clone)]
+pub struct MappingInfo {
+    /// The start address
+    pub addr: VirtAddr,
+    /// The size in bytes
+    pub size: usize,
+    /// The flags of the pages
+    pub flags: MMUFlags,
+    /// The offset in the VMO of the start address
+    pub vmo_offset: usize,
+    /// Whether the VMO is shared with the forked address spaces
+    pub shared: bool,
+    /// The mapped VMO
+    pub vmo: Arc<VmObject>,
+}
+
 /// Virtual Memory Mapping
 pub struct VmMapping {
     /// The permission limitation of the vmar
     permissions: MMUFlags,
+    /// Whether the VMO is shared with the forked address spaces, instead of copied on write
+    shared: bool,
     vmo: Arc<VmObject>,
     page_table: Arc<Mutex<dyn GenericPageTable>>,
     inner: Mutex<VmMappingInner>,
@@ -707,10 +1011,14 @@ struct VmMappingInner {
 #[repr(C)]
 #[derive(Default)]
 pub struct TaskStatsInfo {
-    mapped_bytes: u64,
-    private_bytes: u64,
-    shared_bytes: u64,
-    scaled_shared_bytes: u64,
+    /// The total size of the mapped VMOs
+    pub mapped_bytes: u64,
+    /// The committed memory only mapped by this task
+    pub private_bytes: u64,
+    /// The committed memory shared with other tasks
+    pub shared_bytes: u64,
+    /// The shared memory divided by the number of tasks sharing it
+    pub scaled_shared_bytes: u64,
 }
 
 impl core::fmt::Debug for VmMapping {
@@ -735,6 +1043,7 @@ impl VmMapping {
         vmo_offset: usize,
         permissions: MMUFlags,
         flags: MMUFlags,
+        shared: bool,
         page_table: Arc<Mutex<dyn GenericPageTable>>,
     ) -> Arc<Self> {
         let mapping = Arc::new(VmMapping {
@@ -745,6 +1054,7 @@ impl VmMapping {
                 vmo_offset,
             }),
             permissions,
+            shared,
             page_table,
             vmo: vmo.clone(),
         });
@@ -763,6 +1073,10 @@ impl VmMapping {
             let page_num = inner.size / PAGE_SIZE;
             let vmo_offset = inner.vmo_offset / PAGE_SIZE;
             for i in 0..page_num {
+                // inaccessible pages are left unmapped, see `protect`
+                if !inner.flags[i].intersects(MMUFlags::RXW) {
+                    continue;
+                }
                 let paddr = commit(vmo_offset + i, inner.flags[i])?;
                 //通过GenericPageTable的hal_pt_map进行页表映射
                 page_table
@@ -839,7 +1153,7 @@ impl VmMapping {
                 .unmap_cont(begin, cut_len)
                 .expect("failed to unmap");
             inner.size = new_len;
-            inner.flags.truncate(new_len);
+            inner.flags.truncate(pages(new_len));
             None
         } else {
             // superset: [---xxxx---]
@@ -852,6 +1166,7 @@ impl VmMapping {
             let new_flags_range = (pages(inner.size) - pages(new_len2))..pages(inner.size);
             let new_mapping = Arc::new(VmMapping {
                 permissions: self.permissions,
+                shared: self.shared,
                 vmo: self.vmo.clone(),
                 page_table: self.page_table.clone(),
                 inner: Mutex::new(VmMappingInner {
@@ -862,11 +1177,90 @@ impl VmMapping {
                 }),
             });
             inner.size = new_len1;
-            inner.flags.truncate(new_len1);
+            inner.flags.truncate(pages(new_len1));
+            self.vmo.append_mapping(Arc::downgrade(&new_mapping));
             Some(new_mapping)
         }
     }
 
+    /// Split this mapping at `addr`, and return the part after `addr`.
+    ///
+    /// The pages stay mapped in the page table and are owned by the new mapping.
+    fn split(&self, addr: VirtAddr) -> Arc<Self> {
+        let mut inner = self.inner.lock();
+        debug_assert!(inner.addr < addr && addr < inner.end_addr());
+        let offset = addr - inner.addr;
+        let new_mapping = Arc::new(VmMapping {
+            permissions: self.permissions,
+            shared: self.shared,
+            vmo: self.vmo.clone(),
+            page_table: self.page_table.clone(),
+            inner: Mutex::new(VmMappingInner {
+                flags: inner.flags.split_off(pages(offset)),
+                addr,
+                size: inner.size - offset,
+                vmo_offset: inner.vmo_offset + offset,
+            }),
+        });
+        inner.size = offset;
+        drop(inner);
+        self.vmo.append_mapping(Arc::downgrade(&new_mapping));
+        new_mapping
+    }
+
+    /// Append `next`, the mapping right after this one, if it can be merged.
+    ///
+    /// `next` is left empty on success, so dropping it unmaps nothing.
+    fn merge(&self, next: &Self) -> bool {
+        if !Arc::ptr_eq(&self.vmo, &next.vmo)
+            || self.permissions != next.permissions
+            || self.shared != next.shared
+        {
+            return false;
+        }
+        let mut inner = self.inner.lock();
+        let mut next_inner = next.inner.lock();
+        if inner.end_addr() != next_inner.addr
+            || inner.vmo_offset + inner.size != next_inner.vmo_offset
+            || inner.flags.last() != next_inner.flags.first()
+        {
+            return false;
+        }
+        inner.size += next_inner.size;
+        inner.flags.append(&mut next_inner.flags);
+        next_inner.size = 0;
+        true
+    }
+
+    /// Move this mapping to `addr` and map it again.
+    fn relocate(self: &Arc<Self>, addr: VirtAddr) -> ZxResult {
+        {
+            let mut inner = self.inner.lock();
+            self.page_table
+                .lock()
+                .unmap_cont(inner.addr, inner.size)
+                .expect("failed to unmap");
+            inner.addr = addr;
+        }
+        self.map()
+    }
+
+    /// Unmap the pages in `[begin, end)`, and reset them if the mapping is private.
+    fn decommit(&self, begin: VirtAddr, end: VirtAddr) -> ZxResult {
+        let vmo_offset = {
+            let inner = self.inner.lock();
+            self.page_table
+                .lock()
+                .unmap_cont(begin, end - begin)
+                .expect("failed to unmap");
+            begin - inner.addr + inner.vmo_offset
+        };
+        if !self.shared {
+            self.vmo.reset(vmo_offset, end - begin)?;
+        }
+        Ok(())
+    }
+
     fn overlap(&self, begin: VirtAddr, end: VirtAddr) -> bool {
         let inner = self.inner.lock();
         !(inner.addr >= end || inner.end_addr() <= begin)
@@ -889,10 +1283,17 @@ impl VmMapping {
             new_flags.remove(MMUFlags::RXW);
             new_flags.insert(flags & MMUFlags::RXW);
             inner.flags[i] = new_flags;
-            pg_table
-                .update(inner.addr + i * PAGE_SIZE, None, Some(new_flags))
-                .ignore()
-                .unwrap();
+            let vaddr = inner.addr + i * PAGE_SIZE;
+            if new_flags.intersects(MMUFlags::RXW) {
+                // the page is mapped on the next page fault if it is not mapped now
+                pg_table
+                    .update(vaddr, None, Some(new_flags))
+                    .ignore()
+                    .unwrap();
+            } else {
+                // an entry without any access is not valid on all architectures
+                pg_table.unmap(vaddr).ignore().unwrap();
+            }
         }
     }
 
@@ -981,12 +1382,19 @@ impl VmMapping {
     }
 
     /// Clone VMO and map it to a new page table. (For Linux)
+    ///
+    /// The VMO of a shared mapping is not cloned, it is mapped into both page tables.
     fn clone_map(&self, page_table: Arc<Mutex<dyn GenericPageTable>>) -> ZxResult<Arc<Self>> {
         //这里调用 hal protect 后, protect() 好像会破坏页表
-        let new_vmo = self.vmo.create_child(false, 0, self.vmo.len())?;
+        let new_vmo = if self.shared {
+            self.vmo.clone()
+        } else {
+            self.vmo.create_child(false, 0, self.vmo.len())?
+        };
         let mapping = Arc::new(VmMapping {
             inner: Mutex::new(self.inner.lock().clone()),
             permissions: self.permissions,
+            shared: self.shared,
             page_table,
             vmo: new_vmo.clone(),
         });
@@ -1216,6 +1624,206 @@ mod tests {
         assert_eq!(vmar.used_size(), 0x1000);
     }
 
+    #[test]
+    fn protect_split_mapping() {
+        //   +--------+--------+--------+--------+
+        //   [rw------|r-------|rw-------------- ]
+        let vmar = VmAddressRegion::new_root();
+        let base = vmar.addr();
+        let vmo = VmObject::new_paged(4);
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        vmar.map_at(0, vmo, 0, 0x4000, flags).unwrap();
+        assert_eq!(vmar.count(), 1);
+
+        vmar.protect(base + 0x1000, 0x1000, MMUFlags::READ).unwrap();
+        assert_eq!(vmar.count(), 3);
+        assert_eq!(vmar.used_size(), 0x4000);
+        let flags_at = |addr| vmar.find_mapping(addr).unwrap().get_flags(addr).unwrap();
+        assert!(flags_at(base).contains(MMUFlags::WRITE));
+        assert!(!flags_at(base + 0x1000).contains(MMUFlags::WRITE));
+        assert!(flags_at(base + 0x2000).contains(MMUFlags::WRITE));
+        assert_eq!(
+            vmar.handle_page_fault(base + 0x1000, MMUFlags::WRITE),
+            Err(ZxError::ACCESS_DENIED)
+        );
+
+        // unmapped range
+        assert_eq!(
+            vmar.protect(base + 0x3000, 0x2000, MMUFlags::READ),
+            Err(ZxError::NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn protect_merge_mapping() {
+        let vmar = VmAddressRegion::new_root();
+        let base = vmar.addr();
+        let vmo = VmObject::new_paged(4);
+        vmar.map_at(0, vmo, 0, 0x4000, MMUFlags::READ).unwrap();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+
+        // growing a writable prefix keeps two mappings
+        vmar.protect(base, 0x1000, flags).unwrap();
+        vmar.protect(base + 0x1000, 0x1000, flags).unwrap();
+        assert_eq!(vmar.count(), 2);
+        vmar.write_memory(base + 0x1000, &[1]).unwrap();
+
+        vmar.protect(base, 0x4000, MMUFlags::READ).unwrap();
+        assert_eq!(vmar.count(), 1);
+        assert_eq!(vmar.used_size(), 0x4000);
+        let mut buf = [0];
+        vmar.read_memory(base + 0x1000, &mut buf).unwrap();
+        assert_eq!(buf, [1]);
+        assert_eq!(
+            vmar.handle_page_fault(base + 0x1000, MMUFlags::WRITE),
+            Err(ZxError::ACCESS_DENIED)
+        );
+    }
+
+    #[test]
+    fn mappings() {
+        let vmar = VmAddressRegion::new_root();
+        let base = vmar.addr();
+        let child = vmar
+            .allocate_at(0, 0x4000, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
+            .unwrap();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        vmar.map_at(0x8000, VmObject::new_paged(2), 0, 0x2000, MMUFlags::READ)
+            .unwrap();
+        let vmo = VmObject::new_paged(4);
+        child.map_at(0, vmo.clone(), 0x1000, 0x3000, flags).unwrap();
+
+        let mappings = vmar.mappings();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[0].addr, base);
+        assert_eq!(mappings[0].size, 0x3000);
+        assert_eq!(mappings[0].flags, flags);
+        assert_eq!(mappings[0].vmo_offset, 0x1000);
+        assert!(Arc::ptr_eq(&mappings[0].vmo, &vmo));
+        assert_eq!(mappings[1].addr, base + 0x8000);
+        assert_eq!(mappings[1].flags, MMUFlags::READ);
+    }
+
+    #[test]
+    fn decommit_mapping() {
+        let vmar = VmAddressRegion::new_root();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let private = vmar
+            .map(None, VmObject::new_paged(1), 0, PAGE_SIZE, flags)
+            .unwrap();
+        let shared = vmar
+            .map_shared(None, VmObject::new_paged(1), 0, PAGE_SIZE, flags)
+            .unwrap();
+        vmar.write_memory(private, &[1]).unwrap();
+        vmar.write_memory(shared, &[1]).unwrap();
+
+        vmar.decommit(private, PAGE_SIZE).unwrap();
+        vmar.decommit(shared, PAGE_SIZE).unwrap();
+        let mut buf = [0xff];
+        vmar.read_memory(private, &mut buf).unwrap();
+        assert_eq!(buf, [0]);
+        vmar.read_memory(shared, &mut buf).unwrap();
+        assert_eq!(buf, [1]);
+    }
+
+    #[test]
+    fn decommit_backed_mapping() {
+        let vmar = VmAddressRegion::new_root();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let file = VmObject::new_paged(2);
+        file.write(0, &[1]).unwrap();
+        file.write(PAGE_SIZE, &[2]).unwrap();
+        let private = vmar
+            .map(
+                None,
+                file.create_backed_child(0, 2 * PAGE_SIZE).unwrap(),
+                0,
+                2 * PAGE_SIZE,
+                flags,
+            )
+            .unwrap();
+        vmar.write_memory(private, &[3]).unwrap();
+        vmar.write_memory(private + PAGE_SIZE, &[3]).unwrap();
+        file.write(PAGE_SIZE, &[4]).unwrap();
+
+        // a forked mapping is backed by the same VMO
+        let forked = VmAddressRegion::new_root();
+        forked.fork_from(&vmar).unwrap();
+        let mut buf = [0xff];
+        for vmar in [&vmar, &forked] {
+            vmar.decommit(private, 2 * PAGE_SIZE).unwrap();
+            vmar.read_memory(private, &mut buf).unwrap();
+            assert_eq!(buf, [1]);
+            vmar.read_memory(private + PAGE_SIZE, &mut buf).unwrap();
+            assert_eq!(buf, [4]);
+        }
+    }
+
+    #[test]
+    fn find_shared_vmo() {
+        let vmar = VmAddressRegion::new_root();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let private = vmar
+            .map(None, VmObject::new_paged(1), 0, PAGE_SIZE, flags)
+            .unwrap();
+        let vmo = VmObject::new_paged(2);
+        let shared = vmar
+            .map_shared(None, vmo.clone(), PAGE_SIZE, PAGE_SIZE, flags)
+            .unwrap();
+
+        assert!(vmar.find_shared_vmo(private).is_none());
+        let (found, offset) = vmar.find_shared_vmo(shared + 4).unwrap();
+        assert!(Arc::ptr_eq(&found, &vmo));
+        assert_eq!(offset, PAGE_SIZE + 4);
+    }
+
+    #[test]
+    #[allow(unsafe_code)]
+    fn remap_mapping() {
+        let vmar = VmAddressRegion::new_root();
+        let base = vmar.addr();
+        let vmo = VmObject::new_paged(2);
+        vmo.test_write(0, 1);
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        vmar.map_at(0, vmo, 0, 0x2000, flags).unwrap();
+
+        // shrink in place
+        assert_eq!(vmar.remap(base, 0x2000, 0x1000, None, false), Ok(base));
+        assert_eq!(vmar.used_size(), 0x1000);
+
+        // expand in place
+        assert_eq!(vmar.remap(base, 0x1000, 0x3000, None, false), Ok(base));
+        assert_eq!(vmar.used_size(), 0x3000);
+
+        // can not expand in place
+        let vmo = VmObject::new_paged(1);
+        vmar.map_at(0x3000, vmo, 0, 0x1000, flags).unwrap();
+        assert_eq!(
+            vmar.remap(base, 0x3000, 0x4000, None, false),
+            Err(ZxError::NO_MEMORY)
+        );
+
+        // move to a free area
+        let addr = vmar.remap(base, 0x3000, 0x4000, None, true).unwrap();
+        assert_ne!(addr, base);
+        assert_eq!(vmar.used_size(), 0x5000);
+        assert!(vmar.find_mapping(base).is_none());
+        unsafe {
+            assert_eq!((addr as *const u8).read(), 1);
+        }
+
+        // move to a fixed address
+        let new_addr = base + 0x10000;
+        assert_eq!(
+            vmar.remap(addr, 0x4000, 0x1000, Some(new_addr), true),
+            Ok(new_addr)
+        );
+        assert_eq!(vmar.used_size(), 0x2000);
+        unsafe {
+            assert_eq!((new_addr as *const u8).read(), 1);
+        }
+    }
+
     #[test]
     #[allow(unsafe_code)]
     fn copy_on_write_update_mapping() {
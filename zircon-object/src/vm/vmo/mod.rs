@@ -4,6 +4,7 @@ use {
     crate::object::*,
     alloc::{
         sync::{Arc, Weak},
+        vec,
         vec::Vec,
     },
     bitflags::bitflags,
@@ -133,6 +134,8 @@ struct VmObjectInner {
     children: Vec<Weak<VmObject>>,
     mapping_count: usize,
     content_size: usize,
+    /// The VMO and the offset in it whose content is restored by `reset`.
+    backing: Option<(Arc<VmObject>, usize)>,
 }
 
 impl VmObject {
@@ -186,6 +189,8 @@ impl VmObject {
         let base = KObjectBase::with_signal(Signal::VMO_ZERO_CHILDREN);
         base.set_name(&self.base.name());
         let trait_ = self.trait_.create_child(offset, len)?;
+        let backing = self.inner.lock().backing.clone();
+        let backing = backing.map(|(vmo, backing_offset)| (vmo, backing_offset + offset));
         let child = Arc::new(VmObject {
             base,
             resizable,
@@ -193,6 +198,7 @@ impl VmObject {
             trait_,
             inner: Mutex::new(VmObjectInner {
                 parent: Arc::downgrade(self),
+                backing,
                 ..VmObjectInner::default()
             }),
         });
@@ -200,6 +206,38 @@ impl VmObject {
         Ok(child)
     }
 
+    /// Create a child VMO whose pages read as the content of this VMO again
+    /// after [`reset`](Self::reset), even if the child is cloned again.
+    pub fn create_backed_child(self: &Arc<Self>, offset: usize, len: usize) -> ZxResult<Arc<Self>> {
+        let child = self.create_child(false, offset, len)?;
+        child.inner.lock().backing = Some((self.clone(), offset));
+        Ok(child)
+    }
+
+    /// Discard the content of the pages in `[offset, offset + len)`.
+    ///
+    /// The pages of a VMO created by [`create_backed_child`](Self::create_backed_child)
+    /// (or cloned from one) read as the backing VMO again, other pages read as zero.
+    pub fn reset(&self, offset: usize, len: usize) -> ZxResult {
+        let backing = self.inner.lock().backing.clone();
+        let (backing, backing_offset) = match backing {
+            Some(backing) => backing,
+            None => return self.zero(offset, len),
+        };
+        let mut buf = vec![0u8; PAGE_SIZE];
+        for page_offset in (offset..offset + len).step_by(PAGE_SIZE) {
+            let src = backing_offset + page_offset;
+            let n = backing.len().saturating_sub(src).min(PAGE_SIZE);
+            if n != 0 {
+                backing.read(src, &mut buf[..n])?;
+            }
+            buf[n..].fill(0);
+            let n = (offset + len - page_offset).min(PAGE_SIZE);
+            self.write(page_offset, &buf[..n])?;
+        }
+        Ok(())
+    }
+
     /// Create a child slice as an VMO
     pub fn create_slice(self: &Arc<Self>, offset: usize, p_size: usize) -> ZxResult<Arc<Self>> {
         let size = roundup_pages(p_size);